@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::header;
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+pub const SOCKET: &str = "agent.sock";
+
+// Holds nothing but the socket, so rk can keep it for the user only
+const DIR: &str = "agent";
+
+// Seconds a key is kept without being asked for
+pub const DEFAULT_TIMEOUT: u64 = 900;
+
+// How often idle keys are looked for between requests
+const POLL: Duration = Duration::from_millis(100);
+
+// Clients are served one at a time, one that doesn't send its request
+// in time is dropped
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/* Request enum definition */
+
+// One json line per connection, answered by one `Response` line
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Request {
+    Unlock { vault: String, key: String },
+    Key { vault: String },
+    Lock { vault: Option<String> },
+    Status,
+    Stop
+}
+
+/* Response enum definition */
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Done,
+    Key(String),
+    Vaults(Vec<String>),
+    Locked,
+    Error(String)
+}
+
+/* Secret struct definition */
+
+// A key pinned in memory so it is never swapped out, and zeroed once dropped
+struct Secret {
+    bytes: Vec<u8>,
+    used: Instant
+}
+
+impl Secret {
+
+    /* Intialisers */
+
+    fn new(bytes: Vec<u8>) -> Secret {
+        memory_lock(&bytes);
+
+        Secret { bytes, used: Instant::now() }
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(&mut self.bytes);
+        memory_unlock(&self.bytes);
+    }
+}
+
+/* Agent struct definition */
+
+// Keeps the derived key of every unlocked vault, keyed by its canonical
+// locker path, until it has been idle for `timeout` or `rk lock` drops it.
+// The whole vault is sealed with that key, so it is handed back to rk
+// itself, never kept in a buffer that isn't wiped afterwards
+pub struct Agent {
+    socket: PathBuf,
+    timeout: Duration,
+    keys: HashMap<String, Secret>
+}
+
+impl Agent {
+
+    /* Intialisers */
+
+    pub fn new(socket: PathBuf, timeout: Duration) -> Agent {
+        Agent { socket, timeout, keys: HashMap::new() }
+    }
+
+    /* Methods */
+
+    // Serves requests until a `Stop`, refusing to take over the socket of
+    // an agent that is still running. Only this user is answered
+    #[cfg(unix)]
+    pub fn run(mut self) -> VaultResult<()> {
+        use std::os::unix::net::UnixListener;
+
+        if request(&self.socket, &Request::Status).is_ok() {
+            return Err(VaultError::Error(format!("An agent is already listening on {:?}", self.socket)));
+        }
+
+        if let Some(dir) = self.socket.parent() {
+            header::shared_dir(dir)?;
+        }
+
+        if self.socket.exists() {
+            fs::remove_file(&self.socket)?;
+        }
+
+        let listener = UnixListener::bind(&self.socket)
+            .map_err(|err| VaultError::Error(format!("Could not listen on {:?}: {}", self.socket, err)))?;
+
+        header::restrict(&self.socket, 0o600)?;
+        listener.set_nonblocking(true)?;
+
+        loop {
+            match listener.accept() {
+                Ok((mut stream, _)) => match admit(&stream, TIMEOUT) {
+                    Ok(true) => {
+                        if !self.serve(stream) {
+                            break;
+                        }
+                    },
+                    Ok(false) => respond(&mut stream, &Response::Error(String::from("The agent only answers the user running it"))),
+                    Err(err) => eprintln!("rk: could not set up an agent client: {}", err)
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+                // A client that is gone before being accepted
+                Err(err) => eprintln!("rk: could not accept an agent client: {}", err)
+            }
+
+            self.expire();
+        }
+
+        fs::remove_file(&self.socket)?;
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn run(self) -> VaultResult<()> {
+        Err(VaultError::Error("The agent is only supported on unix".to_string()))
+    }
+
+    // Answers one connection, false once the agent has to stop
+    fn serve<S: io::Read + Write>(&mut self, mut stream: S) -> bool {
+        let mut line = String::new();
+
+        if BufReader::new(&mut stream).read_line(&mut line).is_err() {
+            wipe_string(line);
+
+            return true;
+        }
+
+        let request: Result<Request, _> = serde_json::from_str(&line);
+
+        wipe_string(line);
+
+        let stop = request.as_ref().map(|request| *request == Request::Stop).unwrap_or(false);
+        let response = match request {
+            Ok(request) => self.answer(request),
+            Err(err) => Response::Error(format!("Invalid request: {}", err))
+        };
+
+        respond(&mut stream, &response);
+
+        if let Response::Key(key) = response {
+            wipe_string(key);
+        }
+
+        !stop
+    }
+
+    fn answer(&mut self, request: Request) -> Response {
+        self.expire();
+
+        match request {
+            Request::Unlock { vault, key } => {
+                let decoded = hex::decode(&key);
+
+                wipe_string(key);
+
+                match decoded {
+                    Ok(key) => {
+                        self.keys.insert(vault, Secret::new(key));
+
+                        Response::Done
+                    },
+                    Err(_) => Response::Error(String::from("Invalid key"))
+                }
+            },
+            Request::Key { vault } => match self.keys.get_mut(&vault) {
+                Some(secret) => {
+                    secret.used = Instant::now();
+
+                    Response::Key(hex::encode(&secret.bytes))
+                },
+                None => Response::Locked
+            },
+            Request::Lock { vault: Some(vault) } => {
+                self.keys.remove(&vault);
+
+                Response::Done
+            },
+            Request::Lock { vault: None } | Request::Stop => {
+                self.keys.clear();
+
+                Response::Done
+            },
+            Request::Status => {
+                let mut vaults: Vec<String> = self.keys.keys().cloned().collect();
+
+                vaults.sort();
+
+                Response::Vaults(vaults)
+            }
+        }
+    }
+
+    fn expire(&mut self) {
+        let timeout = self.timeout;
+
+        self.keys.retain(|_, secret| secret.used.elapsed() < timeout);
+    }
+}
+
+/* Functions */
+
+// `$RK_AGENT_SOCK`, or `agent/agent.sock` next to the settings
+pub fn socket(config: &Path) -> PathBuf {
+    match env::var_os("RK_AGENT_SOCK") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => config.join(DIR).join(SOCKET)
+    }
+}
+
+// The name a locker's key is cached under, the same however its path is
+// written. A locker that isn't there keeps the path as given
+pub fn vault(locker: &Path) -> String {
+    fs::canonicalize(locker)
+        .unwrap_or_else(|_| locker.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+// A client hanging up early only loses its own answer
+fn respond<W: Write>(stream: &mut W, response: &Response) {
+    if let Ok(json) = serde_json::to_string(response) {
+        let _ = writeln!(stream, "{}", json);
+
+        wipe_string(json);
+    }
+}
+
+// Overwrites a buffer that held a key, volatile writes so they are kept
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, exclusive reference into the slice
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+// Keys travel between rk and the agent as hex in json lines
+fn wipe_string(string: String) {
+    wipe(&mut string.into_bytes());
+}
+
+// Back to blocking with a deadline, and whether the client runs as the
+// user the agent runs as, whatever the mode of the socket lets through.
+// The ssh agent admits its clients the same way
+#[cfg(unix)]
+pub(crate) fn admit(stream: &std::os::unix::net::UnixStream, timeout: Duration) -> io::Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // SAFETY: `geteuid` has no preconditions and can't fail
+    Ok(peer_uid(stream)? == unsafe { libc::geteuid() })
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> io::Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut credentials = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut size = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    // SAFETY: `credentials` and `size` are valid for writes and `size`
+    // is the size of the buffer handed in
+    let failed = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut size
+        )
+    };
+
+    match failed {
+        0 => Ok(credentials.uid),
+        _ => Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> io::Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+
+    // SAFETY: both out pointers are valid for writes
+    match unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } {
+        0 => Ok(uid),
+        _ => Err(io::Error::last_os_error())
+    }
+}
+
+// Fails when no agent is listening on `socket`
+#[cfg(unix)]
+pub fn request(socket: &Path, request: &Request) -> VaultResult<Response> {
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|_| VaultError::Error(String::from("No agent is running, start one with `rk agent`")))?;
+
+    let json = serde_json::to_string(request).map_err(|err| VaultError::Error(err.to_string()))?;
+    let mut line = String::new();
+    let sent = writeln!(stream, "{}", json);
+
+    wipe_string(json);
+    sent?;
+
+    let read = BufReader::new(stream).read_line(&mut line);
+    let response = read.map_err(VaultError::from).and_then(|_| serde_json::from_str(&line)
+        .map_err(|err| VaultError::Error(format!("Invalid agent response: {}", err))));
+
+    wipe_string(line);
+
+    response
+}
+
+#[cfg(not(unix))]
+pub fn request(_socket: &Path, _request: &Request) -> VaultResult<Response> {
+    Err(VaultError::Error("The agent is only supported on unix".to_string()))
+}
+
+// Hands the key of `locker` to the agent
+pub fn unlock(socket: &Path, locker: &Path, key: &[u8]) -> VaultResult<Response> {
+    let unlocking = Request::Unlock { vault: vault(locker), key: hex::encode(key) };
+    let response = request(socket, &unlocking);
+
+    if let Request::Unlock { key, .. } = unlocking {
+        wipe_string(key);
+    }
+
+    response
+}
+
+// The cached key of `locker`, if an agent is running and has one
+pub fn key(socket: &Path, locker: &Path) -> Option<Vec<u8>> {
+    match request(socket, &Request::Key { vault: vault(locker) }) {
+        Ok(Response::Key(key)) => {
+            let decoded = hex::decode(&key).ok();
+
+            wipe_string(key);
+
+            decoded
+        },
+        _ => None
+    }
+}
+
+#[cfg(unix)]
+fn memory_lock(bytes: &[u8]) {
+    // SAFETY: the range is the vector's own allocation. Failing to lock,
+    // eg. over RLIMIT_MEMLOCK, still leaves a working agent
+    unsafe { libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+}
+
+#[cfg(unix)]
+fn memory_unlock(bytes: &[u8]) {
+    // SAFETY: same range as the `mlock` above
+    unsafe { libc::munlock(bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+}
+
+#[cfg(not(unix))]
+fn memory_lock(_bytes: &[u8]) {}
+
+#[cfg(not(unix))]
+fn memory_unlock(_bytes: &[u8]) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `agent.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn unlock(vault: &str, key: &[u8]) -> Request {
+        Request::Unlock { vault: vault.to_string(), key: hex::encode(key) }
+    }
+
+    #[test]
+    fn answer_requests() {
+        let mut agent = Agent::new(PathBuf::from(SOCKET), Duration::from_secs(60));
+        let key = |vault: &str| Request::Key { vault: vault.to_string() };
+
+        assert_eq!(agent.answer(key("personal")), Response::Locked);
+        assert_eq!(agent.answer(unlock("personal", &[1, 2])), Response::Done);
+        assert_eq!(agent.answer(unlock("team", &[3])), Response::Done);
+        assert_eq!(agent.answer(key("personal")), Response::Key(String::from("0102")));
+        assert_eq!(agent.answer(Request::Status), Response::Vaults(vec![String::from("personal"), String::from("team")]));
+        assert_eq!(agent.answer(Request::Lock { vault: Some(String::from("team")) }), Response::Done);
+        assert_eq!(agent.answer(key("team")), Response::Locked);
+        assert_eq!(agent.answer(Request::Lock { vault: None }), Response::Done);
+        assert_eq!(agent.answer(key("personal")), Response::Locked);
+    }
+
+    #[test]
+    fn expire_idle_keys() {
+        let mut agent = Agent::new(PathBuf::from(SOCKET), Duration::from_millis(50));
+
+        agent.answer(unlock("personal", &[1]));
+        thread::sleep(Duration::from_millis(80));
+
+        assert_eq!(agent.answer(Request::Key { vault: String::from("personal") }), Response::Locked);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_and_request() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::os::unix::fs::PermissionsExt;
+
+                let (config, _) = this.as_path_buf();
+                let socket = config.join(SOCKET);
+                let locker = config.join("personal");
+                let agent = Agent::new(socket.clone(), Duration::from_secs(60));
+                let running = thread::spawn(move || agent.run());
+
+                while request(&socket, &Request::Status).is_err() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+
+                let second = Agent::new(socket.clone(), Duration::from_secs(60)).run();
+                let mode = fs::metadata(&socket).unwrap().permissions().mode();
+                // Never sends its request, the agent drops it after `TIMEOUT`
+                let _silent = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+
+                fs::create_dir_all(&locker).unwrap();
+                // Written another way, still the same locker
+                super::unlock(&socket, &locker.join("..").join("personal"), &[7; 32]).unwrap();
+
+                let cached = key(&socket, &locker);
+                let missing = key(&socket, &config.join("team"));
+
+                request(&socket, &Request::Stop).unwrap();
+                running.join().unwrap().unwrap();
+
+                assert!(second.is_err());
+                assert_eq!(mode & 0o777, 0o600);
+                assert_eq!(cached, Some(vec![7; 32]));
+                assert_eq!(missing, None);
+                assert!(!socket.exists());
+                assert!(key(&socket, &locker).is_none());
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn admit_the_user() {
+        use std::os::unix::net::UnixStream;
+
+        let (client, _) = UnixStream::pair().unwrap();
+
+        assert!(admit(&client, TIMEOUT).unwrap());
+        assert_eq!(client.read_timeout().unwrap(), Some(TIMEOUT));
+    }
+}
@@ -12,6 +12,217 @@ pub fn execute() -> ArgMatches<'static> {
         .author("Diego Braga <dsbrgg@gmail.com>")
         .about("Local password manager")
         .setting(AppSettings::SubcommandRequired)
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["table", "json", "yaml", "tsv", "csv"])
+                .global(true)
+                .help("Output format [default: table]")
+        )
+        .arg(
+            Arg::with_name("field")
+                .long("field")
+                .takes_value(true)
+                .global(true)
+                .help("Print only the raw value of this field, eg. `password`")
+        )
+        .arg(
+            Arg::with_name("locker")
+                .long("locker")
+                .takes_value(true)
+                .global(true)
+                .help("Locker directory, overrides RK_LOCKER and `paths.locker`")
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .global(true)
+                .help("Settings file, overrides RK_CONFIG and `paths.config`")
+        )
+        .arg(
+            Arg::with_name("algorithm")
+                .long("algorithm")
+                .takes_value(true)
+                .global(true)
+                .help("Encryption algorithm, overrides RK_ALGORITHM and `crypto.algorithm`")
+        )
+        .arg(
+            Arg::with_name("vault")
+                .long("vault")
+                .takes_value(true)
+                .global(true)
+                .help("Named vault to use, overrides RK_VAULT and `vault.default`")
+        )
+        .arg(
+            Arg::with_name("master-password-fd")
+                .long("master-password-fd")
+                .takes_value(true)
+                .global(true)
+                .help("Read the master password of the vault from the first line of this file descriptor")
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Create a new vault protected by a master password")
+                .arg(
+                    Arg::with_name("password-stdin")
+                        .long("password-stdin")
+                        .conflicts_with("password-fd")
+                        .help("Read the master password from the first line of stdin")
+                )
+                .arg(
+                    Arg::with_name("password-fd")
+                        .long("password-fd")
+                        .takes_value(true)
+                        .help("Read the master password from the first line of this file descriptor")
+                )
+                .arg(
+                    Arg::with_name("kdf-time")
+                        .long("kdf-time")
+                        .takes_value(true)
+                        .help("Milliseconds one key derivation should take on this machine [default: 500]")
+                )
+                .arg(
+                    Arg::with_name("kdf-memory")
+                        .long("kdf-memory")
+                        .takes_value(true)
+                        .help("Memory in KiB used by the key derivation [default: 65536]")
+                )
+                .arg(
+                    Arg::with_name("git")
+                        .long("git")
+                        .help("Keep the locker in a git repository, committing every change")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Write every entry to an archive encrypted under its own passphrase")
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .short("o")
+                        .takes_value(true)
+                        .required_unless_one(&["to", "plaintext"])
+                        .conflicts_with("to")
+                        .help("Archive to create, eg. backup.rkx, or the --plaintext file [default: stdout]")
+                )
+                .arg(
+                    Arg::with_name("plaintext")
+                        .long("plaintext")
+                        .conflicts_with_all(&["to", "passphrase-stdin", "passphrase-fd", "kdf-time", "kdf-memory"])
+                        .help("Write every account unencrypted, as --format csv or json")
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .requires("plaintext")
+                        .help("Write the --plaintext export to a terminal")
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .requires("plaintext")
+                        .help("Skip the typed confirmation of a --plaintext export")
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .possible_values(&["pass", "kdbx"])
+                        .requires("dir")
+                        .help("Write a store or database another password manager can read instead of an rk archive")
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .index(1)
+                        .requires("to")
+                        .help("Directory or database written by --to, eg. ~/.password-store or vault.kdbx")
+                )
+                .arg(
+                    Arg::with_name("gpg-id")
+                        .long("gpg-id")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("to")
+                        .help("Key to encrypt pass entries for, defaults to the store's .gpg-id")
+                )
+                .arg(
+                    Arg::with_name("passphrase-stdin")
+                        .long("passphrase-stdin")
+                        .conflicts_with("passphrase-fd")
+                        .help("Read the archive or kdbx database passphrase from the first line of stdin")
+                )
+                .arg(
+                    Arg::with_name("passphrase-fd")
+                        .long("passphrase-fd")
+                        .takes_value(true)
+                        .help("Read the archive or kdbx database passphrase from the first line of this file descriptor")
+                )
+                .arg(
+                    Arg::with_name("kdf-time")
+                        .long("kdf-time")
+                        .takes_value(true)
+                        .help("Milliseconds one key derivation should take on this machine [default: 500]")
+                )
+                .arg(
+                    Arg::with_name("kdf-memory")
+                        .long("kdf-memory")
+                        .takes_value(true)
+                        .help("Memory in KiB used by the key derivation [default: 65536]")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Restore the entries of an archive written by `rk export`, or import another password manager's")
+                .arg(
+                    Arg::with_name("file")
+                        .index(1)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("mode")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(&["merge", "replace"])
+                        .conflicts_with("from")
+                        .help("Keep the entries missing from the archive, or remove them first [default: merge]")
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .possible_values(&["bitwarden-json", "keepass-xml", "kdbx", "1password-1pux", "chrome-csv", "firefox-csv", "pass"])
+                        .help("Read an export of another password manager instead of an rk archive")
+                )
+                .arg(
+                    Arg::with_name("duplicates")
+                        .long("duplicates")
+                        .takes_value(true)
+                        .possible_values(&["skip", "overwrite", "rename"])
+                        .requires("from")
+                        .help("What to do with accounts already in the vault [default: skip]")
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .requires("from")
+                        .help("Only show what would be imported")
+                )
+                .arg(
+                    Arg::with_name("passphrase-stdin")
+                        .long("passphrase-stdin")
+                        .conflicts_with("passphrase-fd")
+                        .help("Read the archive or kdbx database passphrase from the first line of stdin")
+                )
+                .arg(
+                    Arg::with_name("passphrase-fd")
+                        .long("passphrase-fd")
+                        .takes_value(true)
+                        .help("Read the archive or kdbx database passphrase from the first line of this file descriptor")
+                )
+        )
         .subcommand(
             SubCommand::with_name("list")
                 .about("List entities or entity accounts")
@@ -37,6 +248,19 @@ pub fn execute() -> ArgMatches<'static> {
                         .takes_value(true)
                         .required(false)
                 )
+                .arg(
+                    Arg::with_name("clip")
+                        .long("clip")
+                        .short("c")
+                        .help("Copy the password to the clipboard instead of printing it")
+                )
+                .arg(
+                    Arg::with_name("clip-timeout")
+                        .long("clip-timeout")
+                        .takes_value(true)
+                        .requires("clip")
+                        .help("Seconds before the clipboard is cleared, 0 keeps it [default: 45]")
+                )
         )
         .subcommand(
             SubCommand::with_name("add")
@@ -58,6 +282,36 @@ pub fn execute() -> ArgMatches<'static> {
                         .short("p")
                         .takes_value(true)
                         .required(false)
+                        .help("Password to store, `-` prompts for it")
+                )
+                .arg(
+                    Arg::with_name("password-stdin")
+                        .long("password-stdin")
+                        .conflicts_with_all(&["password", "password-fd"])
+                        .help("Read the password from the first line of stdin")
+                )
+                .arg(
+                    Arg::with_name("password-fd")
+                        .long("password-fd")
+                        .takes_value(true)
+                        .conflicts_with("password")
+                        .help("Read the password from the given file descriptor")
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .long("url")
+                        .takes_value(true)
+                        .requires("account")
+                        .help("URL the account is used on")
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("account")
+                        .help("Tag the account, can be repeated")
                 )
         )
         .subcommand(
@@ -76,5 +330,419 @@ pub fn execute() -> ArgMatches<'static> {
                         .required(false)
                 )
         )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Fuzzy search entities, accounts, urls and tags")
+                .arg(
+                    Arg::with_name("query")
+                        .index(1)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("clip")
+                        .long("clip")
+                        .short("c")
+                        .help("Copy the password to the clipboard instead of printing it")
+                )
+                .arg(
+                    Arg::with_name("clip-timeout")
+                        .long("clip-timeout")
+                        .takes_value(true)
+                        .requires("clip")
+                        .help("Seconds before the clipboard is cleared, 0 keeps it [default: 45]")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("rename")
+                .about("Rename an entity or account")
+                .arg(
+                    Arg::with_name("entity")
+                        .short("e")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .short("a")
+                        .takes_value(true)
+                        .required(false)
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("move")
+                .about("Move an account to another entity")
+                .arg(
+                    Arg::with_name("entity")
+                        .short("e")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .short("a")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("to-entity")
+                        .long("to-entity")
+                        .takes_value(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("View and change settings")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the value of a setting")
+                        .arg(
+                            Arg::with_name("key")
+                                .index(1)
+                                .required(true)
+                                .help("Setting as `section.name`, eg. `paths.locker`")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Change a setting")
+                        .arg(
+                            Arg::with_name("key")
+                                .index(1)
+                                .required(true)
+                                .help("Setting as `section.name`, eg. `paths.locker`")
+                        )
+                        .arg(
+                            Arg::with_name("value")
+                                .index(2)
+                                .required(true)
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("unset")
+                        .about("Remove a setting so its default is used")
+                        .arg(
+                            Arg::with_name("key")
+                                .index(1)
+                                .required(true)
+                                .help("Setting as `section.name`, eg. `paths.locker`")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List every setting and its effective value")
+                        .arg(
+                            Arg::with_name("origin")
+                                .long("origin")
+                                .help("Show whether each value came from a flag, env, the file or a default")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("edit")
+                        .about("Open the settings file in $VISUAL or $EDITOR")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("Manage named vaults")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the configured vaults")
+                )
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Add a named vault to the settings")
+                        .arg(
+                            Arg::with_name("name")
+                                .index(1)
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .index(2)
+                                .required(true)
+                                .help("Locker directory of the vault, or the database of a kdbx vault")
+                        )
+                        .arg(
+                            Arg::with_name("backend")
+                                .long("backend")
+                                .takes_value(true)
+                                .possible_values(&["directory", "kdbx"])
+                                .help("Storage backend [default: directory]")
+                        )
+                        .arg(
+                            Arg::with_name("default")
+                                .long("default")
+                                .help("Use this vault when no --vault or RK_VAULT is given")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove a named vault from the settings, its locker is kept")
+                        .arg(
+                            Arg::with_name("name")
+                                .index(1)
+                                .required(true)
+                        )
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("Keep unlocked vault keys in memory so kdbx vaults don't ask for the master password")
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .help("Seconds an unused key is kept [default: 900]")
+                )
+                .arg(
+                    Arg::with_name("detach")
+                        .long("detach")
+                        .help("Run the agent in the background")
+                )
+                .arg(
+                    Arg::with_name("stop")
+                        .long("stop")
+                        .conflicts_with_all(&["timeout", "detach"])
+                        .help("Stop the running agent, forgetting every key")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Hand the vault's key to the running agent")
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Make the running agent forget the vault's key")
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Forget the keys of every vault")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("git-credential")
+                .about("Git credential helper, set with `git config credential.helper \"rk git-credential\"`")
+                .arg(
+                    Arg::with_name("operation")
+                        .index(1)
+                        .required(true)
+                        .help("get, store or erase, as called by git")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("ssh-agent")
+                .about("Serve the SSH keys of accounts tagged `ssh` to ssh clients, through SSH_AUTH_SOCK")
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("Socket to listen on [default: ssh-agent.sock next to the settings]")
+                )
+                .arg(
+                    Arg::with_name("confirm")
+                        .long("confirm")
+                        .help("Ask before every use of a key")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run a command with passwords as environment variables")
+                .arg(
+                    Arg::with_name("env")
+                        .long("env")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .help("NAME=entity/account, can be repeated")
+                )
+                .arg(
+                    Arg::with_name("mask")
+                        .long("mask")
+                        .help("Replace the passwords in the command's output")
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple(true)
+                        .required(true)
+                        .last(true)
+                        .help("Command to run, after `--`")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("inject")
+                .about("Fill the {{ rk://entity/account/field }} references of a template")
+                .arg(
+                    Arg::with_name("in")
+                        .long("in")
+                        .short("i")
+                        .takes_value(true)
+                        .help("Template to read [default: stdin]")
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .short("o")
+                        .takes_value(true)
+                        .conflicts_with("check")
+                        .help("File to write, only readable by you [default: stdout]")
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Only check that every reference resolves, printing no value")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("native-messaging")
+                .about("Browser extension backend, started by the browser once installed")
+                .arg(
+                    Arg::with_name("install")
+                        .long("install")
+                        .takes_value(true)
+                        .possible_values(&["firefox", "chrome", "chromium"])
+                        .requires("extension-id")
+                        .help("Register rk with the browser for the extension")
+                )
+                .arg(
+                    Arg::with_name("extension-id")
+                        .long("extension-id")
+                        .takes_value(true)
+                        .help("Id of the extension allowed to use rk")
+                )
+                .arg(
+                    Arg::with_name("caller")
+                        .multiple(true)
+                        .hidden(true)
+                        .help("What the browser passes, the extension's origin or id")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve a JSON API over HTTP to local tools, authenticated with client tokens")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .conflicts_with("socket")
+                        .help("Loopback address to listen on, eg. 127.0.0.1:8750")
+                )
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("Unix socket to listen on, only usable by you")
+                )
+                .subcommand(
+                    SubCommand::with_name("token")
+                        .about("Manage the clients allowed to use the API")
+                        .setting(AppSettings::SubcommandRequired)
+                        .subcommand(
+                            SubCommand::with_name("add")
+                                .about("Add a client, printing its token")
+                                .arg(
+                                    Arg::with_name("client")
+                                        .index(1)
+                                        .required(true)
+                                )
+                                .arg(
+                                    Arg::with_name("write")
+                                        .long("write")
+                                        .help("Allow adding and removing accounts, not only reading them")
+                                )
+                        )
+                        .subcommand(
+                            SubCommand::with_name("remove")
+                                .about("Remove a client, revoking its token")
+                                .arg(
+                                    Arg::with_name("client")
+                                        .index(1)
+                                        .required(true)
+                                )
+                        )
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("Show who added, found, read or removed which account, and when")
+                .arg(
+                    Arg::with_name("entity")
+                        .short("e")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("account")
+                        .short("a")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("Only from this UTC date or time on, eg. 2026-10-19 or 2026-10-19T08:30")
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("Only before this UTC date or time")
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Check that no record was edited, removed or reordered, and that none is missing at the end")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Pull and push the changes of a locker kept in git")
+                .arg(
+                    Arg::with_name("remote")
+                        .long("remote")
+                        .takes_value(true)
+                        .help("Repository to sync with from now on, cloned when the locker doesn't exist yet")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show how an entity changed over time in a locker kept in git")
+                .arg(
+                    Arg::with_name("entity")
+                        .short("e")
+                        .takes_value(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge the changes of another locker of the same vault, both end up alike")
+                .arg(
+                    Arg::with_name("other-locker")
+                        .required(true)
+                        .help("Path to the other locker")
+                )
+                .arg(
+                    Arg::with_name("newest")
+                        .long("newest")
+                        .help("Settle accounts changed on both sides with the newest change instead of asking")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("clip-clear")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("timeout")
+                        .index(1)
+                        .required(true)
+                )
+        )
         .get_matches()
 } 
@@ -0,0 +1,280 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, KeyInit};
+use chacha20poly1305::aead::{Aead, Payload};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rand::{Rng, OsRng};
+use serde::{Serialize, Deserialize};
+
+use crate::fields::Fields;
+use crate::header::{self, Kdf};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// `RKX` followed by the format version
+const MAGIC: &[u8; 4] = b"RKX\x01";
+const VERSION: u32 = 1;
+const NONCE_LEN: usize = 24;
+
+/* Item struct definition */
+
+// One entity, or one account of an entity, with everything stored for it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub entity: String,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub fields: Fields
+}
+
+/* Mode enum definition */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Merge,
+    Replace
+}
+
+impl FromStr for Mode {
+    type Err = VaultError;
+
+    fn from_str(mode: &str) -> VaultResult<Mode> {
+        match mode {
+            "merge" => Ok(Mode::Merge),
+            "replace" => Ok(Mode::Replace),
+            _ => Err(VaultError::Error(format!("Unknown import mode: {}", mode)))
+        }
+    }
+}
+
+/* Envelope struct definition */
+
+// Stored in the clear in front of the ciphertext, and authenticated with it
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    kdf: Kdf,
+    nonce: String
+}
+
+/* Archive struct definition */
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Archive {
+    pub version: u32,
+    pub items: Vec<Item>
+}
+
+impl Archive {
+
+    /* Intialisers */
+
+    pub fn new(items: Vec<Item>) -> Archive {
+        Archive { version: VERSION, items }
+    }
+
+    pub fn read(path: &Path, passphrase: &str) -> VaultResult<Archive> {
+        let bytes = fs::read(path)
+            .map_err(|err| VaultError::Error(format!("Could not read archive {:?}: {}", path, err)))?;
+
+        Archive::open(&bytes, passphrase)
+    }
+
+    pub fn open(bytes: &[u8], passphrase: &str) -> VaultResult<Archive> {
+        let invalid = || VaultError::Error(String::from("Not an rk archive"));
+
+        if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid());
+        }
+
+        let mut size = [0u8; 4];
+
+        size.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+
+        let start = MAGIC.len() + 4;
+        let end = start + u32::from_be_bytes(size) as usize;
+
+        if bytes.len() < end {
+            return Err(invalid());
+        }
+
+        let envelope: Envelope = serde_json::from_slice(&bytes[start..end])
+            .map_err(|_| invalid())?;
+        let nonce = hex::decode(&envelope.nonce).map_err(|_| invalid())?;
+
+        if nonce.len() != NONCE_LEN {
+            return Err(invalid());
+        }
+
+        let key = envelope.kdf.derive(passphrase)?;
+        let payload = Payload { msg: &bytes[end..], aad: &bytes[..end] };
+        let compressed = cipher(&key)?
+            .decrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| VaultError::Error(String::from("Wrong passphrase or damaged archive")))?;
+
+        let mut json = Vec::new();
+
+        GzDecoder::new(&compressed[..]).read_to_end(&mut json)?;
+
+        let archive: Archive = serde_json::from_slice(&json)
+            .map_err(|err| VaultError::Error(format!("Malformed archive: {}", err)))?;
+
+        if archive.version != VERSION {
+            return Err(VaultError::Error(format!("Unsupported archive version {}", archive.version)));
+        }
+
+        Ok(archive)
+    }
+
+    /* Methods */
+
+    pub fn seal(&self, passphrase: &str, kdf: Kdf) -> VaultResult<Vec<u8>> {
+        let json = serde_json::to_vec(self)
+            .map_err(|err| VaultError::Error(err.to_string()))?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+
+        encoder.write_all(&json)?;
+
+        let compressed = encoder.finish()?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        let mut rng = OsRng::new().expect("Could not open the OS random source");
+
+        rng.fill_bytes(&mut nonce);
+
+        let key = kdf.derive(passphrase)?;
+        let envelope = Envelope { kdf, nonce: hex::encode(nonce) };
+        let envelope = serde_json::to_vec(&envelope)
+            .map_err(|err| VaultError::Error(err.to_string()))?;
+
+        let mut bytes = MAGIC.to_vec();
+
+        bytes.extend_from_slice(&(envelope.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&envelope);
+
+        let payload = Payload { msg: &compressed, aad: &bytes };
+        let sealed = cipher(&key)?
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| VaultError::Error(String::from("Could not encrypt the archive")))?;
+
+        bytes.extend_from_slice(&sealed);
+
+        Ok(bytes)
+    }
+
+    pub fn write(&self, path: &Path, passphrase: &str, kdf: Kdf) -> VaultResult<()> {
+        let bytes = self.seal(passphrase, kdf)?;
+
+        fs::write(path, bytes)
+            .map_err(|err| VaultError::Error(format!("Could not write archive {:?}: {}", path, err)))?;
+
+        header::restrict(path, 0o600)
+    }
+}
+
+/* Functions */
+
+fn cipher(key: &[u8]) -> VaultResult<XChaCha20Poly1305> {
+    XChaCha20Poly1305::new_from_slice(key)
+        .map_err(|_| VaultError::Error(String::from("Invalid archive key")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `archive.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn archive() -> Archive {
+        let mut fields = Fields::new();
+
+        fields.insert(String::from("url"), String::from("https://mail.google.com"));
+
+        Archive::new(vec![
+            Item {
+                entity: String::from("google"),
+                account: Some(String::from("me@gmail.com")),
+                password: Some(String::from("secret")),
+                fields
+            },
+            Item {
+                entity: String::from("empty"),
+                account: None,
+                password: None,
+                fields: Fields::new()
+            }
+        ])
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let archive = archive();
+        let sealed = archive.seal("passphrase", Kdf::new(8, 1, 1)).unwrap();
+
+        assert_eq!(&sealed[..4], MAGIC);
+        assert_eq!(Archive::open(&sealed, "passphrase").unwrap(), archive);
+    }
+
+    #[test]
+    fn open_wrong_passphrase() {
+        let sealed = archive().seal("passphrase", Kdf::new(8, 1, 1)).unwrap();
+
+        assert!(Archive::open(&sealed, "wrong").is_err());
+    }
+
+    #[test]
+    fn open_tampered() {
+        let mut sealed = archive().seal("passphrase", Kdf::new(8, 1, 1)).unwrap();
+        let last = sealed.len() - 1;
+
+        sealed[last] ^= 1;
+
+        assert!(Archive::open(&sealed, "passphrase").is_err());
+        assert!(Archive::open(b"not an archive", "passphrase").is_err());
+    }
+
+    #[test]
+    fn mode_from_str() {
+        assert_eq!("merge".parse::<Mode>().unwrap(), Mode::Merge);
+        assert_eq!("replace".parse::<Mode>().unwrap(), Mode::Replace);
+        assert!("other".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn write_and_read() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (_, locker) = this.as_path_buf();
+                let path = locker.join("backup.rkx");
+                let archive = archive();
+
+                header::private_dir(&locker).unwrap();
+                archive.write(&path, "passphrase", Kdf::new(8, 1, 1)).unwrap();
+
+                assert_eq!(Archive::read(&path, "passphrase").unwrap(), archive);
+            }
+        };
+    }
+}
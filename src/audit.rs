@@ -0,0 +1,629 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::header;
+use crate::locker::{Encrypted, Locker, Seal};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+pub const DIR: &str = "audit";
+
+// What a KDBX backed vault notes the logs it keeps under, followed by their id
+pub const FLAG: &str = "rk.audit.";
+
+// What the first record follows
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const DAY: u64 = 24 * 60 * 60;
+
+/* Event struct definition */
+
+// Who did what to which account, and how it went
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub time: u64,
+    pub actor: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub outcome: String
+}
+
+impl Event {
+
+    /* Intialisers */
+
+    pub fn new(actor: &str, action: &str, entity: Option<&str>, account: Option<&str>, outcome: &str) -> Event {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        Event {
+            time,
+            actor: actor.to_string(),
+            action: action.to_string(),
+            entity: entity.map(String::from),
+            account: account.map(String::from),
+            outcome: outcome.to_string()
+        }
+    }
+}
+
+/* Record struct definition */
+
+// What each line holds once decrypted. `prev` is the hash of the record
+// before, so no record can be edited, removed or moved without breaking
+// the chain from there on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Record {
+    seq: u64,
+    prev: String,
+    #[serde(flatten)]
+    event: Event
+}
+
+/* Filter struct definition */
+
+// `since` is inclusive and `until` exclusive, both in unix seconds
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub entity: Option<String>,
+    pub account: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>
+}
+
+impl Filter {
+
+    /* Methods */
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let same = |wanted: &Option<String>, value: &Option<String>| wanted.is_none() || wanted == value;
+
+        same(&self.entity, &event.entity)
+            && same(&self.account, &event.account)
+            && self.since.map(|since| event.time >= since).unwrap_or(true)
+            && self.until.map(|until| event.time < until).unwrap_or(true)
+    }
+}
+
+/* Audit struct definition */
+
+// One record per line, only ever appended to and sealed under a key
+// derived from the master key of the vault, so that records can't be
+// read, forged or chained without it. The head, kept next to the log,
+// holds the number of records and the hash of the last one, keyed the
+// same way, so that a log cut short doesn't go unnoticed
+pub struct Audit {
+    path: PathBuf,
+    seal: Seal,
+    key: Vec<u8>
+}
+
+impl Audit {
+
+    /* Intialisers */
+
+    // `key` is the master key of the vault, as `Keeper::key` gives it
+    pub fn new(path: PathBuf, key: &[u8]) -> Audit {
+        let key = Locker::hmac(key, &[b"rk audit"]);
+
+        Audit { path, seal: Seal::new(&key), key }
+    }
+
+    /* Methods */
+
+    // Names the log in the vault, without giving away where it is kept
+    pub fn id(&self) -> String {
+        hex::encode(Locker::hmac(&self.key, &[b"rk log ", self.path.to_string_lossy().as_bytes()]))[..16].to_string()
+    }
+
+    // Starts the log with an empty head, unless the vault says it already
+    // keeps one: a log removed along with its head would otherwise just
+    // start over
+    pub fn begin(&self, kept: bool) -> VaultResult<()> {
+        let file = self.append()?;
+
+        lock(&file, true)?;
+
+        if self.head_path().exists() || !self.lines()?.is_empty() {
+            return Ok(());
+        }
+
+        if kept {
+            return Err(VaultError::Error(format!("The audit log {:?} of the vault is missing, it was removed along with its head", self.path)));
+        }
+
+        self.write_head(0, GENESIS)
+    }
+
+    // Locked from reading the head to writing the new one, so that rk
+    // processes running at once, eg. `rk serve` and a command, never
+    // write the same record
+    pub fn record(&mut self, event: &Event) -> VaultResult<()> {
+        let mut file = self.append()?;
+
+        lock(&file, true)?;
+
+        let (count, prev) = self.head()?
+            .ok_or_else(|| VaultError::Error(format!("The audit log {:?} has lost its head, check it with `rk log verify`", self.path)))?;
+
+        let record = Record { seq: count + 1, prev, event: event.clone() };
+        let json = serde_json::to_string(&record).map_err(|err| VaultError::Error(err.to_string()))?;
+        let line = self.seal.encrypt(&json);
+
+        writeln!(file, "{}", line.path())?;
+        file.sync_all()?;
+
+        self.write_head(count + 1, &line.hash())
+    }
+
+    // Every event, failing on the first record that can't be read
+    pub fn events(&self) -> VaultResult<Vec<Event>> {
+        self.lines()?
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                self.open(line)
+                    .map(|(record, _)| record.event)
+                    .ok_or_else(|| VaultError::Error(format!("Record {} of the audit log can't be read, check it with `rk log verify`", index + 1)))
+            })
+            .collect()
+    }
+
+    // Walks the whole chain and returns the number of records
+    pub fn verify(&self) -> VaultResult<u64> {
+        if !self.path.exists() && !self.head_path().exists() {
+            return Err(VaultError::Error(format!("The audit log {:?} is missing", self.path)));
+        }
+
+        if let Ok(file) = fs::File::open(&self.path) {
+            lock(&file, false)?;
+        }
+
+        let mut prev = GENESIS.to_string();
+        let mut count = 0;
+
+        for line in self.lines()? {
+            let (record, hash) = self.open(&line)
+                .ok_or_else(|| VaultError::Error(format!("Record {} was altered", count + 1)))?;
+
+            if record.seq != count + 1 || record.prev != prev {
+                let msg = format!("Record {} doesn't follow the one before, records were edited, removed or reordered", count + 1);
+
+                return Err(VaultError::Error(msg));
+            }
+
+            prev = hash;
+            count += 1;
+        }
+
+        match self.head()? {
+            None => Err(VaultError::Error(String::from("The head of the audit log is missing"))),
+            Some((written, _)) if written > count => {
+                Err(VaultError::Error(format!("The audit log was truncated, {} records were written but {} are left", written, count)))
+            },
+            Some((written, hash)) if written == count && hash == prev => Ok(count),
+            Some(_) => Err(VaultError::Error(String::from("The audit log doesn't end with the record its head points to")))
+        }
+    }
+
+    fn append(&self) -> VaultResult<fs::File> {
+        if let Some(dir) = self.path.parent() {
+            header::private_dir(dir)?;
+        }
+
+        let mut options = OpenOptions::new();
+
+        options.append(true).create(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(0o600);
+        }
+
+        options.open(&self.path)
+            .map_err(|err| VaultError::Error(format!("Could not open the audit log {:?}: {}", self.path, err)))
+    }
+
+    fn lines(&self) -> VaultResult<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let log = fs::read_to_string(&self.path)?;
+
+        Ok(log.lines().map(String::from).collect())
+    }
+
+    fn head_path(&self) -> PathBuf {
+        self.path.with_extension("head")
+    }
+
+    fn head(&self) -> VaultResult<Option<(u64, String)>> {
+        let path = self.head_path();
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let head = fs::read_to_string(&path)?;
+        let invalid = || VaultError::Error(format!("Invalid audit log head {:?}", path));
+        let (count, hash, mac) = match head.split_whitespace().collect::<Vec<&str>>()[..] {
+            [count, hash, mac] => (count, hash, mac),
+            _ => return Err(invalid())
+        };
+
+        if mac != self.head_mac(count, hash) {
+            return Err(VaultError::Error(format!("The audit log head {:?} was altered or belongs to another vault", path)));
+        }
+
+        Ok(Some((count.parse().map_err(|_| invalid())?, hash.to_string())))
+    }
+
+    // Replaced as a whole, a crash leaves either the old or the new head
+    fn write_head(&self, count: u64, hash: &str) -> VaultResult<()> {
+        let path = self.head_path();
+        let tmp = path.with_extension("head.tmp");
+
+        let count = count.to_string();
+
+        fs::write(&tmp, format!("{} {} {}\n", count, hash, self.head_mac(&count, hash)))?;
+        header::restrict(&tmp, 0o600)?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    fn head_mac(&self, count: &str, hash: &str) -> String {
+        hex::encode(Locker::hmac(&self.key, &[b"rk head ", count.as_bytes(), b" ", hash.as_bytes()]))
+    }
+
+    // The record of a line and its hash, `None` when the line was altered
+    // or sealed under another key
+    fn open(&self, line: &str) -> Option<(Record, String)> {
+        let encrypted = match line.split('$').collect::<Vec<&str>>()[..] {
+            [iv, key, dat, hash] => Encrypted::new(iv, key, dat, hash),
+            _ => return None
+        };
+        let json = self.seal.decrypt(&encrypted)?;
+
+        Some((serde_json::from_str(&json).ok()?, encrypted.hash()))
+    }
+}
+
+/* Functions */
+
+// One log per vault, next to the settings, named after its locker
+// wherever it is opened from
+pub fn path(config: &Path, locker: &Path) -> PathBuf {
+    let locker = fs::canonicalize(locker).unwrap_or_else(|_| locker.to_path_buf());
+    let name = Locker::hash(&locker.to_string_lossy());
+
+    config.join(DIR).join(format!("{}.log", &name[..16]))
+}
+
+// How an operation went, as the log keeps it
+pub fn outcome<T>(result: &VaultResult<T>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(VaultError::MissingEntity) | Err(VaultError::MissingAccount) | Err(VaultError::MissingField(_)) => "missing",
+        Err(VaultError::ExistingEntity) | Err(VaultError::ExistingAccount) => "exists",
+        Err(_) => "failed"
+    }
+}
+
+// `2026-10-19 05:17:26`, in UTC
+pub fn format_time(time: u64) -> String {
+    let (year, month, day) = civil((time / DAY) as i64);
+    let seconds = time % DAY;
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Unix seconds, or a UTC date with an optional time, eg. `2026-10-19`,
+// `2026-10-19 05:17` or `2026-10-19T05:17:26`
+pub fn parse_time(text: &str) -> VaultResult<u64> {
+    let invalid = || VaultError::Error(format!("Invalid time {:?}, expected eg. 2026-10-19, 2026-10-19 05:17 or unix seconds", text));
+    let text = text.trim();
+
+    if !text.is_empty() && text.chars().all(|character| character.is_ascii_digit()) {
+        return text.parse().map_err(|_| invalid());
+    }
+
+    let (date, time) = text.split_once(['T', ' ']).unwrap_or((text, "00:00"));
+    let date: Vec<u64> = date.split('-').map(|part| part.parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+    let time: Vec<u64> = time.split(':').map(|part| part.parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+
+    let (year, month, day) = match date[..] {
+        [year, month, day] if year >= 1970 && (1..=12).contains(&month) && (1..=31).contains(&day) => (year, month, day),
+        _ => return Err(invalid())
+    };
+
+    let (hours, minutes, seconds) = match time[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return Err(invalid())
+    };
+
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+
+    Ok(days(year as i64, month as u32, day as u32) as u64 * DAY + hours * 3600 + minutes * 60 + seconds)
+}
+
+// Held until `file` is closed, shared while the log is only read
+#[cfg(unix)]
+fn lock(file: &fs::File, exclusive: bool) -> VaultResult<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+
+    // SAFETY: the descriptor stays open for as long as `file` lives
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(VaultError::Error(format!("Could not lock the audit log: {}", io::Error::last_os_error())));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &fs::File, _exclusive: bool) -> VaultResult<()> {
+    Ok(())
+}
+
+// Days since 1970-01-01 and back, in the proleptic Gregorian calendar.
+// See http://howardhinnant.github.io/date_algorithms.html
+fn days(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `audit.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    const KEY: &[u8] = &[1; 32];
+
+    fn event(time: u64, entity: &str, account: &str) -> Event {
+        Event { time, ..Event::new("alice", "read", Some(entity), Some(account), "ok") }
+    }
+
+    #[test]
+    fn append_events() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let log = path(&config, &locker);
+                let mut audit = Audit::new(log.clone(), KEY);
+
+                audit.begin(false).unwrap();
+                audit.record(&Event::new("api:ci", "read", Some("github"), Some("me"), "200")).unwrap();
+                Audit::new(log.clone(), KEY).record(&Event::new("api:ci", "list", None, None, "200")).unwrap();
+
+                let events = audit.events().unwrap();
+                let other = Audit::new(log.clone(), &[2; 32]);
+                let log = fs::read_to_string(&log).unwrap();
+
+                assert_eq!(events.len(), 2);
+                assert_eq!(events[0].entity.as_deref(), Some("github"));
+                assert_eq!(events[1].action, "list");
+                assert_eq!(events[1].account, None);
+                assert_eq!(audit.verify().unwrap(), 2);
+                assert!(!log.contains("github"));
+                assert!(other.events().is_err());
+                assert!(other.verify().is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn detect_tampering() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let log = path(&config, &locker);
+                let mut audit = Audit::new(log.clone(), KEY);
+
+                audit.begin(false).unwrap();
+
+                for account in ["me", "work", "ci"] {
+                    audit.record(&Event::new("alice", "read", Some("github"), Some(account), "ok")).unwrap();
+                }
+
+                let original = fs::read_to_string(&log).unwrap();
+                let lines: Vec<&str> = original.lines().collect();
+                let record = Audit::new(log.clone(), KEY).open(lines[1]).unwrap().0;
+                let json = serde_json::to_string(&record).unwrap().replace("work", "home");
+                let forged = Locker::new().encrypt(&json);
+                let head = fs::read_to_string(log.with_extension("head")).unwrap();
+                let check = |contents: String| {
+                    fs::write(&log, contents).unwrap();
+                    audit.verify().unwrap_err().to_str()
+                };
+
+                let truncated = check(format!("{}\n{}\n", lines[0], lines[1]));
+                let removed = check(format!("{}\n{}\n", lines[0], lines[2]));
+                let reordered = check(format!("{}\n{}\n{}\n", lines[1], lines[0], lines[2]));
+                let edited = check(format!("{}\n{}\n{}\n", lines[0], forged.path(), lines[2]));
+                let resealed = check(format!("{}\n{}\n{}\n", lines[0], Seal::new(&[2; 32]).encrypt(&json).path(), lines[2]));
+                let corrupted = check(format!("{}\n{}\n{}\n", lines[0], lines[1].replace('a', "b"), lines[2]));
+
+                fs::write(&log, &original).unwrap();
+
+                assert_eq!(audit.verify().unwrap(), 3);
+                assert_eq!(truncated, "The audit log was truncated, 3 records were written but 2 are left");
+                assert!(removed.starts_with("Record 2 doesn't follow"));
+                assert!(reordered.starts_with("Record 1 doesn't follow"));
+                assert_eq!(edited, "Record 2 was altered");
+                assert_eq!(resealed, "Record 2 was altered");
+                assert_eq!(corrupted, "Record 2 was altered");
+
+                fs::write(log.with_extension("head"), head.replacen('3', "2", 1)).unwrap();
+                assert!(audit.verify().unwrap_err().to_str().contains("was altered"));
+
+                fs::remove_file(log.with_extension("head")).unwrap();
+
+                assert!(audit.verify().is_err());
+                assert!(audit.record(&Event::new("alice", "read", None, None, "ok")).is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn missing_log() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let log = path(&config, &locker);
+                let mut audit = Audit::new(log.clone(), KEY);
+
+                let unbegun = audit.record(&Event::new("alice", "read", None, None, "ok"));
+
+                audit.begin(false).unwrap();
+
+                let empty = audit.verify().unwrap();
+
+                audit.record(&Event::new("alice", "read", None, None, "ok")).unwrap();
+                audit.begin(true).unwrap();
+                fs::remove_file(&log).unwrap();
+                fs::remove_file(log.with_extension("head")).unwrap();
+
+                assert!(unbegun.is_err());
+                assert_eq!(empty, 0);
+                assert!(audit.verify().unwrap_err().to_str().contains("is missing"));
+                assert!(audit.begin(true).unwrap_err().to_str().contains("is missing"));
+            }
+        };
+    }
+
+    #[test]
+    fn record_at_once() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let log = path(&config, &locker);
+
+                Audit::new(log.clone(), KEY).begin(false).unwrap();
+
+                let writers: Vec<_> = (0..4)
+                    .map(|_| {
+                        let log = log.clone();
+
+                        std::thread::spawn(move || {
+                            let mut audit = Audit::new(log, KEY);
+
+                            for _ in 0..10 {
+                                audit.record(&Event::new("alice", "read", None, None, "ok")).unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+
+                for writer in writers {
+                    writer.join().unwrap();
+                }
+
+                assert_eq!(Audit::new(log, KEY).verify().unwrap(), 40);
+            }
+        };
+    }
+
+    #[test]
+    fn path_of_a_locker() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fs::create_dir_all(&locker).unwrap();
+
+                let dotted = locker.join("..").join(locker.file_name().unwrap());
+
+                assert_eq!(path(&config, &dotted), path(&config, &locker));
+                assert!(path(&config, &locker).starts_with(config.join(DIR)));
+            }
+        };
+    }
+
+    #[test]
+    fn filter_events() {
+        let since = parse_time("2026-10-19").unwrap();
+        let filter = Filter { entity: Some(String::from("github")), since: Some(since), until: Some(since + DAY), ..Filter::default() };
+
+        assert!(filter.matches(&event(since, "github", "me")));
+        assert!(!filter.matches(&event(since - 1, "github", "me")));
+        assert!(!filter.matches(&event(since + DAY, "github", "me")));
+        assert!(!filter.matches(&event(since, "gitlab", "me")));
+        assert!(Filter::default().matches(&event(0, "gitlab", "me")));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(1792387046), "2026-10-19 05:17:26");
+        assert_eq!(format_time(951782400), "2000-02-29 00:00:00");
+        assert_eq!(parse_time("2026-10-19 05:17:26").unwrap(), 1792387046);
+        assert_eq!(parse_time("2026-10-19T05:17:26").unwrap(), 1792387046);
+        assert_eq!(parse_time("2026-10-19 05:17").unwrap(), 1792387020);
+        assert_eq!(parse_time("2000-02-29").unwrap(), 951782400);
+        assert_eq!(parse_time("1792387046").unwrap(), 1792387046);
+        assert!(parse_time("2026-13-01").is_err());
+        assert!(parse_time("2026-10-19 25:00").is_err());
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("").is_err());
+    }
+}
@@ -1,346 +1,2956 @@
-use std::io;
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
 
 use clap::ArgMatches;
-use copypasta::ClipboardContext;
-use copypasta::ClipboardProvider;
-use dialoguer::{theme::ColorfulTheme, Select};
+use dialoguer::{theme::ColorfulTheme, Confirmation, Input, PasswordInput, Select};
 
 use rk::{
+    Action,
+    Archive,
     Args, 
+    Duplicates,
     Encrypted,
+    Fields,
+    Format,
+    Gpg,
+    Header,
+    Kdbx,
+    Kdf,
+    Locker,
+    Mode,
+    Record,
     Resolve, 
     Keeper,
+    Profile,
+    Settings,
+    SettingsOpts,
+    Source,
+    VaultError,
     VaultResult,
+    URL,
+    TAGS,
+    pass,
+    plaintext,
     list_table,
-    find_table
+    find_table,
+    settings_table
 };
+use rk::agent::{self, Agent, Request, Response};
+use rk::clipboard::{self, Clipboard};
+use rk::credential::{self, Credential};
+use rk::git::Git;
+use rk::inject::{self, Template};
+use rk::merge::{Conflict, Entry, Merge, Side, SyncLog};
+use rk::native::{self, Browser};
+use rk::output;
+use rk::run::{self, Variable};
+use rk::server::{self, Scope, Server};
+use rk::audit::{self, Audit, Event, Filter};
+use rk::ssh::{self, SshAgent};
+
+fn pick(items: &[String]) -> Option<usize> {
+    if items.is_empty() { return None; }
+    if items.len() == 1 { return Some(0); }
 
-fn select(found: Vec<(String, String)>) -> Option<String> {
-    if found.len() == 0 { return None; }
-    if found.len() == 1 { return Some(found[0].1.clone()); }
-    
-    let accounts: Vec<&str> = found.iter().map(|(acc, _)| &acc[..]).collect();
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Pick account")
         .default(0)
-        .items(&accounts[..])
+        .items(items)
         .interact()
         .unwrap();
 
-    Some(found[selection].1.clone())
-}
+    Some(selection)
+}
+
+fn select(found: Vec<(String, String)>) -> Option<String> {
+    let accounts: Vec<String> = found.iter().map(|(acc, _)| acc.clone()).collect();
+
+    pick(&accounts).map(|selection| found[selection].1.clone())
+}
+
+fn prompt_password() -> io::Result<String> {
+    PasswordInput::with_theme(&ColorfulTheme::default())
+        .with_prompt("Password")
+        .with_confirmation("Confirm password", "Passwords do not match")
+        .interact()
+}
+
+fn prompt_master_password() -> io::Result<String> {
+    PasswordInput::with_theme(&ColorfulTheme::default())
+        .with_prompt("Master password")
+        .with_confirmation("Confirm master password", "Passwords do not match")
+        .interact()
+}
+
+// Unlocks an existing vault, so there is nothing to confirm
+fn prompt_unlock() -> io::Result<String> {
+    PasswordInput::with_theme(&ColorfulTheme::default())
+        .with_prompt("Master password")
+        .interact()
+}
+
+fn prompt_database_password(confirm: bool) -> io::Result<String> {
+    let theme = ColorfulTheme::default();
+    let mut input = PasswordInput::with_theme(&theme);
+
+    input.with_prompt("Database password");
+
+    if confirm {
+        input.with_confirmation("Confirm database password", "Passwords do not match");
+    }
+
+    input.interact()
+}
+
+fn prompt_passphrase(confirm: bool) -> io::Result<String> {
+    let theme = ColorfulTheme::default();
+    let mut input = PasswordInput::with_theme(&theme);
+
+    input.with_prompt("Archive passphrase");
+
+    if confirm {
+        input.with_confirmation("Confirm archive passphrase", "Passphrases do not match");
+    }
+
+    input.interact()
+}
+
+// Typing the word out, rather than answering y/n, is
+// what makes a plaintext export a deliberate choice
+fn confirm_plaintext() -> VaultResult<()> {
+    let typed: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Every password will be written unencrypted, type \"plaintext\" to continue")
+        .interact()?;
+
+    if typed.trim() != "plaintext" {
+        return Err(VaultError::Error(String::from("Plaintext export cancelled")));
+    }
+
+    Ok(())
+}
+
+// Asked on the terminal the ssh agent runs in, anything but a yes refuses
+fn confirm_ssh(comment: &str) -> bool {
+    Confirmation::with_theme(&ColorfulTheme::default())
+        .with_text(&format!("Allow a signature with {}?", comment))
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+// Who vault access is recorded for, looked up by the real uid since
+// `$USER` is whatever the caller sets
+#[cfg(unix)]
+fn user() -> String {
+    use std::ffi::CStr;
+
+    // SAFETY: `getuid` has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    // SAFETY: `passwd` is plain data, all zeroes is a valid value
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+
+    // SAFETY: `entry`, `buffer` and `found` are valid for writes and the
+    // length handed in is the one of `buffer`
+    let failed = unsafe { libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+
+    if failed != 0 || found.is_null() {
+        return format!("uid:{}", uid);
+    }
+
+    // SAFETY: on success `pw_name` points to a NUL terminated name in `buffer`
+    unsafe { CStr::from_ptr(entry.pw_name) }.to_string_lossy().to_string()
+}
+
+#[cfg(not(unix))]
+fn user() -> String {
+    env::var("USERNAME").unwrap_or_else(|_| String::from("unknown"))
+}
+
+// Single quoted for the shell, for paths and names with spaces
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn read_secret<R: Read>(reader: R) -> io::Result<String> {
+    let mut line = String::new();
+
+    BufReader::new(reader).read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(|c| c == '\n' || c == '\r').to_string())
+}
+
+#[cfg(unix)]
+fn read_fd(fd: &str) -> VaultResult<String> {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    let fd: i32 = fd
+        .parse()
+        .map_err(|_| VaultError::Error(format!("Invalid file descriptor: {}", fd)))?;
+
+    // SAFETY: the descriptor is handed to us by the caller and owned
+    // by this process for the rest of its lifetime
+    let file = unsafe { File::from_raw_fd(fd) };
+
+    Ok(read_secret(file)?)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: &str) -> VaultResult<String> {
+    Err(VaultError::Error("Reading from a file descriptor is only supported on unix".to_string()))
+}
+
+// eg. `3 added, 1 overwritten, 0 renamed, 2 skipped`
+fn summary(actions: &[Action]) -> String {
+    let count = |matches: &dyn Fn(&Action) -> bool| actions.iter().filter(|action| matches(action)).count();
+
+    format!(
+        "{} added, {} overwritten, {} renamed, {} skipped",
+        count(&|action| *action == Action::Add),
+        count(&|action| *action == Action::Overwrite),
+        count(&|action| matches!(action, Action::Rename(_))),
+        count(&|action| *action == Action::Skip)
+    )
+}
+
+// Global args can land on any level of the subcommand chain,
+// the deepest one wins
+fn global_value(args: &ArgMatches, name: &str) -> Option<String> {
+    let mut value = args.value_of(name).map(|value| value.to_string());
+    let mut current = args;
+
+    while let (_, Some(sub)) = current.subcommand() {
+        if let Some(found) = sub.value_of(name) {
+            value = Some(found.to_string());
+        }
+
+        current = sub;
+    }
+
+    value
+}
+
+// Settings given as global flags, eg. `--locker <path>`
+pub fn overrides(args: &ArgMatches) -> Vec<(SettingsOpts, String)> {
+    SettingsOpts::all()
+        .iter()
+        .filter_map(|opt| {
+            let name = opt.flag();
+
+            global_value(args, name.trim_start_matches('-')).map(|value| (*opt, value))
+        })
+        .collect()
+}
+
+fn args_value(args: &ArgMatches, name: &str) -> Option<String> {
+    args.value_of(name).map(|value| value.to_string())
+}
+
+struct Params<'p> { 
+    entity: Option<&'p str>,
+    account: Option<&'p str>,
+    password: Option<&'p str>
+}
+
+pub struct CLI { 
+    config: PathBuf,
+    locker: PathBuf,
+    settings: PathBuf,
+    flags: Vec<(SettingsOpts, String)>,
+    backend: String,
+    master_password_fd: Option<String>,
+    // Kept once read, `rk merge` opens two lockers with it
+    master_password: Option<String>,
+    socket: PathBuf,
+    keeper: Option<Keeper>,
+    interactive: bool,
+    clipboard: Option<Box<dyn Clipboard>>,
+    format: Format,
+    field: Option<String>,
+    gpg: Gpg
+}
+
+impl<'p> CLI {
+    pub fn start(config: PathBuf, locker: PathBuf) -> CLI {
+        let interactive = io::stdin().is_terminal();
+        let settings = config.join("settings.yml");
+        let socket = agent::socket(&config);
+
+        CLI {
+            config,
+            locker,
+            settings,
+            flags: Vec::new(),
+            backend: String::from("directory"),
+            master_password_fd: None,
+            master_password: None,
+            socket,
+            keeper: None,
+            interactive,
+            clipboard: None,
+            format: Format::Table,
+            field: None,
+            gpg: Gpg::new("gpg")
+        }
+    }
+
+    pub fn with_settings(mut self, settings: PathBuf) -> CLI {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: &str) -> CLI {
+        self.backend = backend.to_string();
+        self
+    }
+
+    // The vault is only opened by the commands that need it,
+    // so `rk config` still works when the locker path is broken
+    fn keeper(&mut self) -> VaultResult<&mut Keeper> {
+        if self.keeper.is_none() {
+            let (config, locker, socket) = (self.config.clone(), self.locker.clone(), self.socket.clone());
+            let keeper = match self.backend == "kdbx" {
+                true => Keeper::unlock_kdbx(locker, &socket, || self.master_password())?,
+                false => Keeper::unlock(config, locker, &socket, || self.master_password())?
+            };
+
+            self.keeper = Some(self.audited(keeper, &self.locker)?);
+        }
+
+        Ok(self.keeper.as_mut().unwrap())
+    }
+
+    fn audited(&self, keeper: Keeper, locker: &Path) -> VaultResult<Keeper> {
+        let audit = self.audit(&keeper, locker)?;
+
+        keeper.with_audit(audit, &user())
+    }
+
+    // The log of the vault in `locker`, sealed under its master key
+    fn audit(&self, keeper: &Keeper, locker: &Path) -> VaultResult<Audit> {
+        let key = keeper.key()
+            .ok_or_else(|| VaultError::Error(String::from("The vault has no master key to seal its audit log under")))?;
+
+        Ok(Audit::new(audit::path(&self.config, locker), key))
+    }
+
+    // Vaults can't be opened without the master password, unless an agent
+    // keeps their key
+    fn master_password(&mut self) -> VaultResult<String> {
+        if let Some(password) = &self.master_password {
+            return Ok(password.clone());
+        }
+
+        let password = match &self.master_password_fd {
+            Some(fd) => read_fd(fd)?,
+            None if self.interactive => prompt_unlock()?,
+            None => {
+                let msg = "The master password of the vault is required, use --master-password-fd or `rk unlock`";
+
+                return Err(VaultError::Error(msg.to_string()));
+            }
+        };
+
+        self.master_password = Some(password.clone());
+
+        Ok(password)
+    }
+
+    pub fn operation(&mut self, args: ArgMatches) -> VaultResult<Resolve> {
+        self.extract_output(&args)?;
+        self.flags = overrides(&args);
+        self.master_password_fd = global_value(&args, "master-password-fd");
+
+        match args.subcommand() {
+            ("init", Some(init)) => { self.handle_init(init) },
+            ("export", Some(export)) => { self.handle_export(export) },
+            ("import", Some(import)) => { self.handle_import(import) },
+            ("add", Some(add)) => { self.handle_add(add).and_then(|r| self.done(r)) },
+            ("find", Some(find)) => { self.handle_find(find) },
+            ("list", Some(list)) => { self.handle_list(list) },
+            ("remove", Some(remove)) => { self.handle_remove(remove).and_then(|r| self.done(r)) },
+            ("search", Some(search)) => { self.handle_search(search) },
+            ("rename", Some(rename)) => { self.handle_rename(rename).and_then(|r| self.done(r)) },
+            ("move", Some(move_account)) => { self.handle_move(move_account).and_then(|r| self.done(r)) },
+            ("config", Some(config)) => { self.handle_config(config) },
+            ("vault", Some(vault)) => { self.handle_vault(vault) },
+            ("agent", Some(agent)) => { self.handle_agent(agent) },
+            ("unlock", Some(_)) => { self.handle_unlock() },
+            ("lock", Some(lock)) => { self.handle_lock(lock) },
+            ("git-credential", Some(git)) => { self.handle_git_credential(git, &mut io::stdin(), &mut io::stdout()) },
+            ("ssh-agent", Some(ssh)) => { self.handle_ssh_agent(ssh) },
+            ("run", Some(run)) => { self.handle_run(run) },
+            ("inject", Some(inject)) => { self.handle_inject(inject) },
+            ("native-messaging", Some(native)) => { self.handle_native_messaging(native, &mut io::stdin(), &mut io::stdout()) },
+            ("serve", Some(serve)) => { self.handle_serve(serve) },
+            ("log", Some(log)) => { self.handle_log(log) },
+            ("sync", Some(sync)) => { self.handle_sync(sync) },
+            ("history", Some(history)) => { self.handle_history(history) },
+            ("merge", Some(merge)) => { self.handle_merge(merge) },
+            ("clip-clear", Some(clear)) => { self.handle_clip_clear(clear) },
+            (_, _) => { panic!("Unknown operation in CLI"); }
+        }
+    }
+
+    fn extract_output(&mut self, args: &ArgMatches) -> VaultResult<()> {
+        if let Some(format) = global_value(args, "format") {
+            self.format = format.parse()?;
+        }
+
+        self.field = global_value(args, "field");
+
+        Ok(())
+    }
+
+    fn machine(&self) -> bool {
+        self.format != Format::Table || self.field.is_some()
+    }
+
+    fn show<F>(&self, records: &[Record], table: F) -> VaultResult<()> 
+    where F: FnOnce() -> io::Result<()> {
+        if let Some(name) = &self.field {
+            print!("{}", output::field(records, name)?);
+
+            return Ok(());
+        }
+
+        match self.format {
+            Format::Table => table()?,
+            format => print!("{}", output::render(records, format)?)
+        }
+
+        Ok(())
+    }
+
+    fn done(&mut self, resolve: Resolve) -> VaultResult<Resolve> {
+        if self.machine() {
+            self.show(&[output::record(&[("status", "done")])], || Ok(()))?;
+        }
+
+        Ok(resolve)
+    }
+
+    fn account_record(&mut self, entity: &str, account: &str, password: Option<&str>) -> VaultResult<Record> {
+        let args = Args::new(Some(entity), Some(account), None);
+        let mut record = self.keeper()?.fields(args)?.to_fields();
+
+        record.insert("entity".to_string(), entity.to_string());
+        record.insert("account".to_string(), account.to_string());
+
+        if let Some(password) = password {
+            record.insert("password".to_string(), password.to_string());
+        }
+
+        Ok(record)
+    }
+
+    fn extract_values(args: &'p ArgMatches) -> Params<'p> {
+        let password = args.value_of("password");
+        let account = args.value_of("account");
+        let entity = args.value_of("entity");
+        
+        Params {
+            entity,
+            account,
+            password
+        }
+    }
+
+    fn extract_password(&self, args: &'p ArgMatches) -> VaultResult<Option<String>> {
+        let Params { 
+            account, 
+            password,
+            .. 
+        } = CLI::extract_values(args);
+
+        if let Some(fd) = args.value_of("password-fd") {
+            return Ok(Some(read_fd(fd)?));
+        }
+
+        if args.is_present("password-stdin") {
+            return Ok(Some(read_secret(io::stdin())?));
+        }
+
+        match password {
+            Some("-") => Ok(Some(prompt_password()?)),
+            Some(password) => Ok(Some(password.to_string())),
+            None if account.is_some() && self.interactive => Ok(Some(prompt_password()?)),
+            None => Ok(None)
+        }
+    }
+
+    // Reads `--<name>-fd` or `--<name>-stdin`, falling back to `prompt`
+    // when there is someone to answer it
+    fn extract_secret<F>(&self, args: &'p ArgMatches, name: &str, label: &str, prompt: F) -> VaultResult<String>
+    where F: FnOnce() -> io::Result<String> {
+        let fd = format!("{}-fd", name);
+        let stdin = format!("{}-stdin", name);
+
+        let secret = if let Some(fd) = args.value_of(&fd) {
+            read_fd(fd)?
+        } else if args.is_present(&stdin) {
+            read_secret(io::stdin())?
+        } else if self.interactive {
+            prompt()?
+        } else {
+            let msg = format!("A {} is required, use --{} or --{}", label, stdin, fd);
+
+            return Err(VaultError::Error(msg));
+        };
+
+        if secret.is_empty() {
+            return Err(VaultError::Error(format!("The {} can't be empty", label)));
+        }
+
+        Ok(secret)
+    }
+
+    fn extract_kdf(args: &'p ArgMatches) -> VaultResult<Kdf> {
+        let number = |name: &str, default: u32| -> VaultResult<u32> {
+            let value = args.value_of(name).unwrap_or("");
+
+            if value.is_empty() { return Ok(default); }
+
+            value.parse().map_err(|_| VaultError::Error(format!("Invalid --{}: {}", name, value)))
+        };
+
+        let target = Duration::from_millis(number("kdf-time", 500)? as u64);
+
+        Kdf::calibrate(target, number("kdf-memory", 64 * 1024)?)
+    }
+
+    fn handle_init(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let password = self.extract_secret(args, "password", "master password", prompt_master_password)?;
+        let kdf = CLI::extract_kdf(args)?;
+        let keeper = match self.backend.as_str() {
+            "kdbx" => Keeper::init_kdbx(self.locker.clone(), &password, kdf)?,
+            _ => Keeper::init(self.config.clone(), self.locker.clone(), &password, kdf)?
+        };
+
+        // Reopened so that the keeper commits to the new repository
+        let keeper = match args.is_present("git") {
+            true if self.backend == "kdbx" => return Err(VaultError::Error(String::from("Only directory lockers can be kept in git"))),
+            true => {
+                Git::init(&self.locker)?;
+                Keeper::open(self.config.clone(), self.locker.clone(), &password)?
+            },
+            false => keeper
+        };
+
+        self.keeper = Some(self.audited(keeper, &self.locker)?);
+
+        if !self.settings.exists() {
+            let locker = self.locker.to_string_lossy().to_string();
+
+            Settings::set(&self.settings, "paths.locker", &locker)?;
+        }
+
+        if !self.machine() {
+            eprintln!("Initialised vault at {:?}", self.locker);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_export(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        if args.is_present("plaintext") {
+            return self.handle_export_plaintext(args);
+        }
+
+        match args.value_of("to") {
+            Some("pass") => return self.handle_export_pass(args),
+            Some("kdbx") => return self.handle_export_kdbx(args),
+            _ => {}
+        }
+
+        let out = PathBuf::from(args.value_of("out").unwrap());
+
+        if out.exists() {
+            return Err(VaultError::Error(format!("{:?} already exists", out)));
+        }
+
+        let archive = self.keeper()?.export()?;
+        let passphrase = self.extract_secret(args, "passphrase", "passphrase", || prompt_passphrase(true))?;
+
+        archive.write(&out, &passphrase, CLI::extract_kdf(args)?)?;
+
+        if !self.machine() {
+            eprintln!("Exported {} entries to {:?}", archive.items.len(), out);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_export_pass(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let store = PathBuf::from(args.value_of("dir").unwrap());
+        let recipients: Vec<String> = args.values_of("gpg-id")
+            .map(|ids| ids.map(|id| id.to_string()).collect())
+            .unwrap_or_default();
+
+        let archive = self.keeper()?.export()?;
+        let count = pass::write(&store, &archive.items, &recipients, &self.gpg)?;
+
+        if !self.machine() {
+            eprintln!("Exported {} entries to {:?}", count, store);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_export_kdbx(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let out = PathBuf::from(args.value_of("dir").unwrap());
+
+        if out.exists() {
+            return Err(VaultError::Error(format!("{:?} already exists", out)));
+        }
+
+        let archive = self.keeper()?.export()?;
+        let password = self.extract_secret(args, "passphrase", "database password", || prompt_database_password(true))?;
+        let mut database = Kdbx::new(&password, &CLI::extract_kdf(args)?)?;
+
+        database.update(&archive.items);
+        database.write(&out)?;
+
+        if !self.machine() {
+            eprintln!("Exported {} entries to {:?}", archive.items.len(), out);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_export_plaintext(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let out = args.value_of("out").map(PathBuf::from);
+
+        if let Some(out) = out.as_ref().filter(|out| out.exists()) {
+            return Err(VaultError::Error(format!("{:?} already exists", out)));
+        }
+
+        if out.is_none() && io::stdout().is_terminal() && !args.is_present("force") {
+            let msg = "Refusing to write a plaintext export to a terminal, use --out or --force";
+
+            return Err(VaultError::Error(msg.to_string()));
+        }
+
+        let archive = self.keeper()?.export()?;
+        let contents = match self.format {
+            Format::Csv => plaintext::csv(&archive.items)?,
+            Format::Json => plaintext::json(&archive.items)?,
+            _ => return Err(VaultError::Error(String::from("A plaintext export is written with --format csv or json")))
+        };
+
+        if !args.is_present("yes") {
+            if !self.interactive {
+                return Err(VaultError::Error(String::from("A plaintext export has to be confirmed, use --yes")));
+            }
+
+            confirm_plaintext()?;
+        }
+
+        match out {
+            Some(out) => {
+                plaintext::write(&out, &contents)?;
+                eprintln!("Exported every account unencrypted to {:?}, delete it once imported", out);
+
+                self.done(Resolve::Done)
+            },
+            // The dump is the output, a status record would corrupt it
+            None => {
+                print!("{}", contents);
+
+                Ok(Resolve::Done)
+            }
+        }
+    }
+
+    fn handle_import(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let file = PathBuf::from(args.value_of("file").unwrap());
+
+        if let Some(source) = args.value_of("from") {
+            return self.handle_import_from(args, source.parse()?, file);
+        }
+
+        let mode: Mode = args.value_of("mode").unwrap_or("merge").parse()?;
+
+        // Fail on an uninitialised vault before asking for anything
+        self.keeper()?;
+
+        let passphrase = self.extract_secret(args, "passphrase", "passphrase", || prompt_passphrase(false))?;
+        let archive = Archive::read(&file, &passphrase)?;
+        let count = archive.items.len();
+
+        self.keeper()?.import(archive, mode)?;
+
+        if !self.machine() {
+            eprintln!("Imported {} entries from {:?}", count, file);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_import_from(&mut self, args: &'p ArgMatches, source: Source, file: PathBuf) -> VaultResult<Resolve> {
+        let duplicates: Duplicates = args.value_of("duplicates").unwrap_or("skip").parse()?;
+        let password = match source {
+            Source::Kdbx => Some(self.extract_secret(args, "passphrase", "database password", || prompt_database_password(false))?),
+            _ => None
+        };
+
+        let items = source.read(&file, &self.gpg, password.as_deref())?;
+        let actions = self.keeper()?.plan(&items, duplicates)?;
+
+        if args.is_present("dry-run") {
+            let rows: Vec<Vec<String>> = items.iter()
+                .zip(actions.iter())
+                .map(|(item, action)| vec![
+                    item.entity.clone(),
+                    item.account.clone().unwrap_or_default(),
+                    action.to_str()
+                ])
+                .collect();
+
+            let records: Vec<Record> = rows.iter()
+                .map(|row| output::record(&[("entity", &row[0]), ("account", &row[1]), ("action", &row[2])]))
+                .collect();
+
+            self.show(&records, || settings_table(&["entity", "account", "action"], rows.clone()))?;
+
+            if !self.machine() {
+                eprintln!("{}, nothing was imported (dry run)", summary(&actions));
+            }
+
+            return Ok(Resolve::Done);
+        }
+
+        self.keeper()?.import_items(items, &actions)?;
+
+        if !self.machine() {
+            eprintln!("{} from {:?}", summary(&actions), file);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_add(&mut self, matches: &'p ArgMatches) -> VaultResult<Resolve> {
+        let password = self.extract_password(matches)?;
+        let Params { 
+            entity, 
+            account, 
+            .. 
+        } = CLI::extract_values(matches);
+
+        let args = Args::new(
+            entity,
+            account,
+            password.as_deref()
+        );
+
+        let mut fields = Fields::new();
+
+        if let Some(url) = args_value(matches, "url") {
+            fields.insert(URL.to_string(), url);
+        }
+
+        if let Some(tags) = matches.values_of("tag") {
+            fields.insert(TAGS.to_string(), tags.collect::<Vec<&str>>().join(","));
+        }
+
+        self.keeper()?.add(args.clone())?;
+
+        if !fields.is_empty() {
+            self.keeper()?.annotate(args, fields)?;
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    fn handle_find(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let Params { 
+            entity, 
+            account, 
+            .. 
+        } = CLI::extract_values(args);
+
+        let find = Args::new(
+            entity,
+            account,
+            None 
+        );
+
+        let found = self.keeper()?.find(find)?;
+
+        if self.machine() && !args.is_present("clip") {
+            let entity = entity.unwrap_or("");
+            let accounts = match found {
+                Resolve::Find(vec) => vec,
+                Resolve::Read(password) => vec![(account.unwrap_or("").to_string(), password)],
+                _ => Vec::new()
+            };
+
+            let records = accounts.iter()
+                .map(|(account, password)| self.account_record(entity, account, Some(password)))
+                .collect::<VaultResult<Vec<Record>>>()?;
+
+            self.show(&records, || Ok(()))?;
+
+            return Ok(Resolve::Done);
+        }
+
+        let password = match &found {
+            Resolve::Find(vec) => select(vec.to_owned()),
+            Resolve::Read(password) => Some(password.to_owned()),
+            _ => None
+        };
+
+        if let Some(password) = password {
+            if args.is_present("clip") {
+                self.copy(args, &password)?;
+            } else {
+                find_table(password.to_string());
+            }
+
+            if let Resolve::Find(_) = found {
+                return Ok(Resolve::Read(password));
+            }
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    fn copy(&mut self, args: &'p ArgMatches, password: &str) -> VaultResult<()> {
+        let timeout = args
+            .value_of("clip-timeout")
+            .unwrap_or("45");
+
+        let seconds: u64 = timeout
+            .parse()
+            .map_err(|_| VaultError::Error(format!("Invalid clipboard timeout: {}", timeout)))?;
+
+        let mut clipboard = match self.clipboard.take() {
+            Some(clipboard) => clipboard,
+            None => clipboard::detect()?
+        };
+
+        let copied = clipboard::copy(clipboard.as_mut(), password, Duration::from_secs(seconds));
+
+        self.clipboard = Some(clipboard);
+        copied?;
+
+        if seconds > 0 {
+            eprintln!("Copied to clipboard, clearing in {} seconds", seconds);
+        } else {
+            eprintln!("Copied to clipboard");
+        }
+
+        Ok(())
+    }
+
+    fn handle_config(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        match args.subcommand() {
+            ("get", Some(get)) => {
+                let key = get.value_of("key").unwrap_or("");
+                let value = Settings::load_from(&self.settings, &self.flags)?.value(key)?;
+                let records = [output::record(&[("key", key), ("value", &value)])];
+
+                self.show(&records, || { println!("{}", value); Ok(()) })?;
+
+                Ok(Resolve::Read(value))
+            },
+            ("list", Some(list)) => {
+                let settings = Settings::load_from(&self.settings, &self.flags)?;
+                let origin = list.is_present("origin");
+                let rows: Vec<Vec<String>> = settings.entries()
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let mut row = vec![key.clone(), value];
+
+                        if origin {
+                            row.push(settings.origin(&key).to_string());
+                        }
+
+                        row
+                    })
+                    .collect();
+
+                let headers: &[&str] = if origin { &["key", "value", "origin"] } else { &["key", "value"] };
+                let records: Vec<Record> = rows.iter()
+                    .map(|row| {
+                        let pairs: Vec<(&str, &str)> = headers.iter()
+                            .zip(row.iter())
+                            .map(|(header, value)| (*header, value.as_str()))
+                            .collect();
+
+                        output::record(&pairs)
+                    })
+                    .collect();
+
+                self.show(&records, || settings_table(headers, rows.clone()))?;
+
+                Ok(Resolve::Done)
+            },
+            ("set", Some(set)) => {
+                let key = set.value_of("key").unwrap_or("");
+                let value = set.value_of("value").unwrap_or("");
+
+                Settings::set(&self.settings, key, value)?;
+
+                self.done(Resolve::Done)
+            },
+            ("unset", Some(unset)) => {
+                Settings::unset(&self.settings, unset.value_of("key").unwrap_or(""))?;
+
+                self.done(Resolve::Done)
+            },
+            ("edit", Some(_)) => {
+                self.edit_settings()?;
+
+                self.done(Resolve::Done)
+            },
+            (_, _) => { panic!("Unknown config operation in CLI"); }
+        }
+    }
+
+    fn handle_vault(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        match args.subcommand() {
+            ("list", Some(_)) => {
+                let settings = Settings::load_from(&self.settings, &self.flags)?;
+                let current = settings.vault()?.map(|(name, _)| name.clone());
+                let headers: &[&str] = &["name", "locker", "backend", "algorithm", "default"];
+                let rows: Vec<Vec<String>> = settings.vaults()
+                    .iter()
+                    .map(|(name, profile)| vec![
+                        name.clone(),
+                        profile.locker.clone(),
+                        profile.backend.clone(),
+                        profile.algorithm.clone().unwrap_or_default(),
+                        (current.as_ref() == Some(name)).to_string()
+                    ])
+                    .collect();
+
+                let records: Vec<Record> = rows.iter()
+                    .map(|row| {
+                        let pairs: Vec<(&str, &str)> = headers.iter()
+                            .zip(row.iter())
+                            .map(|(header, value)| (*header, value.as_str()))
+                            .collect();
+
+                        output::record(&pairs)
+                    })
+                    .collect();
+
+                self.show(&records, || settings_table(headers, rows.clone()))?;
+
+                Ok(Resolve::List(settings.vaults().keys().cloned().collect()))
+            },
+            ("create", Some(create)) => {
+                let name = create.value_of("name").unwrap_or("");
+                let locker = create.value_of("path").unwrap_or("");
+                let locker = if locker.starts_with('~') || locker.starts_with('$') {
+                    locker.to_string()
+                } else {
+                    env::current_dir()?.join(locker).to_string_lossy().to_string()
+                };
+
+                let profile = Profile {
+                    locker,
+                    backend: create.value_of("backend").unwrap_or("directory").to_string(),
+                    algorithm: global_value(create, "algorithm")
+                };
+
+                Settings::create_vault(&self.settings, name, &profile)?;
+
+                if create.is_present("default") {
+                    Settings::set(&self.settings, "vault.default", name)?;
+                }
+
+                self.done(Resolve::Done)
+            },
+            ("remove", Some(remove)) => {
+                Settings::remove_vault(&self.settings, remove.value_of("name").unwrap_or(""))?;
+
+                self.done(Resolve::Done)
+            },
+            (_, _) => { panic!("Unknown vault operation in CLI"); }
+        }
+    }
+
+    // Edits a copy of the settings file so a broken edit never
+    // replaces the one in use
+    fn edit_settings(&mut self) -> VaultResult<()> {
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| String::from("vi"));
+
+        let mut copy = self.settings.clone().into_os_string();
+
+        copy.push(".edit");
+
+        let copy = PathBuf::from(copy);
+        let current = if self.settings.is_file() { fs::read_to_string(&self.settings)? } else { String::new() };
+
+        if let Some(dir) = copy.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&copy, &current)?;
+
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+        let status = Command::new(program).args(words).arg(&copy).status();
+        let edited = fs::read_to_string(&copy);
+
+        fs::remove_file(&copy)?;
+
+        if !status?.success() {
+            return Err(VaultError::Error(format!("Editor {:?} exited with an error, settings were not changed", editor)));
+        }
+
+        let edited = edited?;
+
+        if edited != current {
+            Settings::replace(&self.settings, &edited)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_agent(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        if args.is_present("stop") {
+            agent::request(&self.socket, &Request::Stop)?;
+
+            return self.done(Resolve::Done);
+        }
+
+        let timeout: u64 = args
+            .value_of("timeout")
+            .map(|timeout| timeout.parse())
+            .unwrap_or(Ok(agent::DEFAULT_TIMEOUT))
+            .map_err(|_| VaultError::Error(String::from("The agent timeout is a number of seconds")))?;
+
+        // The detached agent is told where to listen, it may not
+        // see the same flags and settings as this process
+        if args.is_present("detach") {
+            std::process::Command::new(env::current_exe()?)
+                .args(["agent", "--timeout", &timeout.to_string()])
+                .env("RK_AGENT_SOCK", &self.socket)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()?;
+
+            if !self.machine() {
+                eprintln!("Agent listening on {:?}", self.socket);
+            }
+
+            return self.done(Resolve::Done);
+        }
+
+        if !self.machine() {
+            eprintln!("Agent listening on {:?}", self.socket);
+        }
+
+        Agent::new(self.socket.clone(), Duration::from_secs(timeout)).run()?;
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_unlock(&mut self) -> VaultResult<Resolve> {
+        // Fails before asking for the password when no agent is running
+        agent::request(&self.socket, &Request::Status)?;
+
+        let password = self.master_password()?;
+        let keeper = match self.backend == "kdbx" {
+            true => Keeper::open_kdbx(self.locker.clone(), &password)?,
+            false => Keeper::open(self.config.clone(), self.locker.clone(), &password)?
+        };
+        if let Response::Error(err) = agent::unlock(&self.socket, &self.locker, keeper.key().unwrap_or_default())? {
+            return Err(VaultError::Error(err));
+        }
+
+        self.keeper = Some(keeper);
+
+        if !self.machine() {
+            eprintln!("Unlocked {:?}", self.locker);
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    fn handle_lock(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let vault = match args.is_present("all") {
+            true => None,
+            false => Some(agent::vault(&self.locker))
+        };
+
+        agent::request(&self.socket, &Request::Lock { vault })?;
+
+        if !self.machine() {
+            eprintln!("Locked {}", if args.is_present("all") { String::from("every vault") } else { format!("{:?}", self.locker) });
+        }
+
+        self.done(Resolve::Done)
+    }
+
+    // Git reads whatever is printed, so nothing but the credential is.
+    // Operations a later git may add are ignored, as the protocol asks
+    fn handle_git_credential<R: Read, W: Write>(&mut self, args: &'p ArgMatches, input: &mut R, output: &mut W) -> VaultResult<Resolve> {
+        let operation = args.value_of("operation").unwrap_or("");
+
+        if !["get", "store", "erase"].contains(&operation) {
+            return Ok(Resolve::Done);
+        }
+
+        let mut attributes = String::new();
+
+        input.read_to_string(&mut attributes)?;
+
+        let request = Credential::parse(&attributes);
+        let settings = Settings::load_from(&self.settings, &self.flags)?;
+        let mapping = request.key().ok().and_then(|key| settings.git(&key));
+        let keeper = self.keeper()?;
+
+        match operation {
+            "get" => {
+                if let Some(credential) = credential::get(keeper, &request, mapping)? {
+                    write!(output, "{}", credential)?;
+                }
+            },
+            "store" => credential::store(keeper, &request, mapping)?,
+            _ => credential::erase(keeper, &request, mapping)?
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    // Runs until it is killed. The keys are listed once, a key is only
+    // decrypted while it signs and each signature is recorded like a read
+    fn handle_ssh_agent(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let confirm = args.is_present("confirm");
+        let socket = args
+            .value_of("socket")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| self.config.join(ssh::SOCKET));
+
+        if confirm && !self.interactive {
+            return Err(VaultError::Error(String::from("Confirming each use of a key needs a terminal")));
+        }
+
+        if !self.machine() {
+            eprintln!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+        }
+
+        let keeper = self.keeper()?;
+
+        SshAgent::new(keeper, |comment: &str| !confirm || confirm_ssh(comment)).run(&socket)?;
+
+        Ok(Resolve::Done)
+    }
+
+    // The passwords only live in the child's environment, nothing is
+    // written to disk. rk exits with the child's code once it is done
+    fn handle_run(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let variables = args
+            .values_of("env")
+            .map(|specs| specs.map(Variable::parse).collect::<VaultResult<Vec<Variable>>>())
+            .unwrap_or_else(|| Ok(Vec::new()))?;
+
+        let command: Vec<String> = args
+            .values_of("command")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+
+        let env = run::resolve(self.keeper()?, &variables)?;
+        let code = run::exec(&command, &env, args.is_present("mask"))?;
+
+        Ok(Resolve::Exit(code))
+    }
+
+    // Nothing is rendered unless every reference resolves, and `--check`
+    // never prints a value
+    fn handle_inject(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let text = match args.value_of("in") {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|err| VaultError::Error(format!("Could not read {:?}: {}", path, err)))?,
+            None => {
+                let mut text = String::new();
+
+                io::stdin().read_to_string(&mut text)?;
+                text
+            }
+        };
+
+        let template = Template::parse(&text)?;
+
+        if args.is_present("check") {
+            template.check(self.keeper()?)?;
+
+            if !self.machine() {
+                eprintln!("{} references resolve", template.references().len());
+            }
+
+            return self.done(Resolve::Done);
+        }
+
+        let rendered = template.render(self.keeper()?)?;
+
+        match args.value_of("out") {
+            Some(out) => {
+                inject::write(&PathBuf::from(out), &rendered)?;
+
+                if !self.machine() {
+                    eprintln!("Wrote {} references to {:?}", template.references().len(), out);
+                }
+
+                self.done(Resolve::Done)
+            },
+            None => {
+                print!("{}", rendered);
+
+                Ok(Resolve::Done)
+            }
+        }
+    }
+
+    // Started by the browser, which owns stdin and stdout, so nothing but
+    // messages is printed there. `--install` registers rk with a browser
+    fn handle_native_messaging<R: Read, W: Write>(&mut self, args: &'p ArgMatches, input: &mut R, output: &mut W) -> VaultResult<Resolve> {
+        if let Some(browser) = args.value_of("install") {
+            let browser = Browser::parse(browser)?;
+            let extension = args.value_of("extension-id").unwrap_or("");
+            let home = dirs::home_dir()
+                .ok_or_else(|| VaultError::Error(String::from("Could not find the home directory")))?;
+
+            // The browser starts the host with none of the flags used here
+            let mut command = quote(&env::current_exe()?.to_string_lossy());
+
+            for name in ["config", "locker", "vault"] {
+                if let Some(value) = args.value_of(name) {
+                    command.push_str(&format!(" --{} {}", name, quote(value)));
+                }
+            }
+
+            let manifest = native::install(browser, extension, &home, &command)?;
+
+            if !self.machine() {
+                eprintln!("Installed {:?}", manifest);
+            }
+
+            return self.done(Resolve::Done);
+        }
+
+        native::serve(self.keeper()?, input, output)?;
+
+        Ok(Resolve::Done)
+    }
+
+    // Serves until killed. Clients are managed with `serve token`, a token
+    // being printed once, when its client is added
+    fn handle_serve(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        if let ("token", Some(token)) = args.subcommand() {
+            return match token.subcommand() {
+                ("add", Some(add)) => {
+                    let name = add.value_of("client").unwrap_or("");
+                    let scope = if add.is_present("write") { Scope::Write } else { Scope::Read };
+                    let token = server::add_client(self.keeper()?, name, scope)?;
+
+                    if self.machine() {
+                        self.show(&[output::record(&[("client", name), ("scope", scope.as_str()), ("token", &token)])], || Ok(()))?;
+                    } else {
+                        println!("{}", token);
+                    }
+
+                    Ok(Resolve::Read(token))
+                },
+                ("remove", Some(remove)) => {
+                    server::remove_client(self.keeper()?, remove.value_of("client").unwrap_or(""))?;
+
+                    self.done(Resolve::Done)
+                },
+                (_, _) => { panic!("Unknown serve token operation in CLI"); }
+            };
+        }
+
+        match (args.value_of("listen"), args.value_of("socket")) {
+            (Some(address), _) => {
+                if !self.machine() {
+                    eprintln!("Listening on http://{}", address);
+                }
+
+                Server::new(self.keeper()?)?.listen(address)?;
+            },
+            (None, Some(socket)) => {
+                if !self.machine() {
+                    eprintln!("Listening on {}", socket);
+                }
+
+                Server::new(self.keeper()?)?.listen_unix(&PathBuf::from(socket))?;
+            },
+            (None, None) => {
+                return Err(VaultError::Error(String::from("Either --listen or --socket is required")));
+            }
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    // The log is sealed under the master key, so the vault is opened to
+    // read it. Times are shown in UTC
+    fn handle_log(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        self.keeper()?;
+
+        let log = self.audit(self.keeper.as_ref().unwrap(), &self.locker)?;
+
+        if let ("verify", Some(_)) = args.subcommand() {
+            let count = log.verify()?;
+
+            if !self.machine() {
+                eprintln!("The audit log is intact, {} records", count);
+            }
+
+            return self.done(Resolve::Done);
+        }
+
+        let time = |name: &str| args.value_of(name).map(audit::parse_time).transpose();
+        let filter = Filter {
+            entity: args.value_of("entity").map(String::from),
+            account: args.value_of("account").map(String::from),
+            since: time("since")?,
+            until: time("until")?
+        };
+
+        let headers: &[&str] = &["time", "actor", "action", "entity", "account", "outcome"];
+        let rows: Vec<Vec<String>> = log.events()?
+            .into_iter()
+            .filter(|event| filter.matches(event))
+            .map(|Event { time, actor, action, entity, account, outcome }| vec![
+                audit::format_time(time),
+                actor,
+                action,
+                entity.unwrap_or_default(),
+                account.unwrap_or_default(),
+                outcome
+            ])
+            .collect();
+
+        let records: Vec<Record> = rows.iter()
+            .map(|row| {
+                let pairs: Vec<(&str, &str)> = headers.iter()
+                    .zip(row.iter())
+                    .map(|(header, value)| (*header, value.as_str()))
+                    .collect();
+
+                output::record(&pairs)
+            })
+            .collect();
+
+        self.show(&records, || settings_table(headers, rows.clone()))?;
+
+        Ok(Resolve::List(rows.iter().map(|row| row[1..].join(" ")).collect()))
+    }
+
+    // Works on the locker itself, without opening the vault. A locker
+    // that isn't kept in git yet starts being, or is cloned when missing
+    fn handle_sync(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        if self.backend == "kdbx" {
+            return Err(VaultError::Error(String::from("Only directory lockers can be synced with git")));
+        }
+
+        let remote = args.value_of("remote");
+        let git = match (Git::open(&self.locker), remote) {
+            (Some(git), _) => git,
+            (None, Some(url)) if !Header::path(&self.locker).exists() => Git::clone(url, &self.locker)?,
+            (None, None) if !Header::path(&self.locker).exists() => {
+                return Err(VaultError::MissingVault(self.locker.to_string_lossy().to_string()));
+            },
+            (None, _) => Git::init(&self.locker)?
+        };
+
+        let synced = git.sync(remote)?;
+
+        // Read again on the next operation, with what was pulled
+        self.keeper = None;
+        let (pulled, pushed) = (synced.pulled.to_string(), synced.pushed.to_string());
+
+        if self.machine() {
+            self.show(&[output::record(&[("pulled", pulled.as_str()), ("pushed", pushed.as_str())])], || Ok(()))?;
+        } else {
+            eprintln!("Pulled {} and pushed {} changes", pulled, pushed);
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    // Only says what kind of change each commit made, and to which accounts
+    fn handle_history(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let entity = args.value_of("entity").unwrap_or("");
+        let git = Git::open(&self.locker)
+            .ok_or_else(|| VaultError::Error(String::from("The locker isn't kept in git, see `rk init --git` and `rk sync`")))?;
+        let seal = self.keeper()?.seal().cloned().ok_or("Only directory lockers have a history")?;
+
+        let headers: &[&str] = &["time", "commit", "change", "accounts"];
+        let rows: Vec<Vec<String>> = git.history(entity, &seal)?
+            .into_iter()
+            .map(|change| vec![
+                audit::format_time(change.time),
+                change.commit,
+                change.message,
+                change.accounts.join(", ")
+            ])
+            .collect();
+
+        let records: Vec<Record> = rows.iter()
+            .map(|row| {
+                let pairs: Vec<(&str, &str)> = headers.iter()
+                    .zip(row.iter())
+                    .map(|(header, value)| (*header, value.as_str()))
+                    .collect();
+
+                output::record(&pairs)
+            })
+            .collect();
+
+        self.show(&records, || settings_table(headers, rows.clone()))?;
+
+        Ok(Resolve::List(rows.iter().map(|row| row[2..].join(" ")).collect()))
+    }
+
+    // Both lockers end up with the merged vault, and the same record in
+    // their sync logs as the base of the next merge
+    fn handle_merge(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        if self.backend == "kdbx" {
+            return Err(VaultError::Error(String::from("Only directory lockers can be merged")));
+        }
+
+        let path = PathBuf::from(args.value_of("other-locker").unwrap_or(""));
+
+        if fs::canonicalize(&path).ok() == fs::canonicalize(&self.locker).ok() {
+            return Err(VaultError::Error(String::from("A locker can't be merged with itself")));
+        }
+
+        let (config, socket) = (self.config.clone(), self.socket.clone());
+        let other = Keeper::unlock(config, path.clone(), &socket, || self.master_password())?;
+        let mut other = self.audited(other, &path)?;
+        let ours = self.keeper()?.snapshot()?;
+        let seal = self.keeper()?.seal().cloned().ok_or("Only directory lockers can be merged")?;
+        let other_seal = other.seal().cloned().ok_or("Only directory lockers can be merged")?;
+        let (log, other_log) = (SyncLog::new(&self.locker, seal), SyncLog::new(&path, other_seal));
+        let theirs = other.snapshot()?;
+        let plan = Merge::plan(&ours, &theirs, &log.base(&other_log)?);
+        let sides = self.settle(&plan.conflicts, &path, args.is_present("newest"))?;
+        let settled = sides.len();
+        let merged = plan.resolve(&sides)?;
+        let (here, there) = (merged.changes(&ours), merged.changes(&theirs));
+
+        // Each log only records a merge its locker went through. If the
+        // other locker fails, the next merge starts again from the last one
+        // both went through and carries over what is still missing
+        self.keeper()?.apply(&here)?;
+        log.record(&merged)?;
+
+        other.apply(&there)
+            .and_then(|_| other_log.record(&merged))
+            .map_err(|err| VaultError::Error(format!("Merged into this locker but not into {}, merge again to finish: {}", path.display(), err.to_str())))?;
+
+        let (here, there, settled) = (here.len().to_string(), there.len().to_string(), settled.to_string());
+
+        if self.machine() {
+            self.show(&[output::record(&[("here", here.as_str()), ("there", there.as_str()), ("conflicts", settled.as_str())])], || Ok(()))?;
+        } else {
+            eprintln!("Merged {} changes here and {} into {}, {} conflicts settled", here, there, path.display(), settled);
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    // Asks which side to keep for each conflict, the newest one preselected.
+    // Without a terminal the conflicts are listed unless `--newest` is given
+    fn settle(&self, conflicts: &[Conflict], other: &PathBuf, newest: bool) -> VaultResult<Vec<Side>> {
+        if newest {
+            return Ok(conflicts.iter().map(Conflict::newest).collect());
+        }
+
+        if !conflicts.is_empty() && (!self.interactive || self.machine()) {
+            let names: Vec<String> = conflicts.iter()
+                .map(|conflict| format!("{}/{}", conflict.entity, conflict.account))
+                .collect();
+            let msg = format!("Changed on both sides: {}. Merge from a terminal to pick a side, or pass --newest", names.join(", "));
+
+            return Err(VaultError::Error(msg));
+        }
+
+        let describe = |name: String, entry: &Option<Entry>| match entry {
+            Some(entry) if entry.modified > 0 => format!("{}, changed {}", name, audit::format_time(entry.modified)),
+            Some(_) => name,
+            None => format!("{}, removed", name)
+        };
+
+        conflicts.iter()
+            .map(|conflict| {
+                let items = [
+                    describe(String::from("Keep this locker's"), &conflict.ours),
+                    describe(format!("Take {}'s", other.display()), &conflict.theirs)
+                ];
+                let prompt = format!("{}/{} differs in {}", conflict.entity, conflict.account, conflict.differs().join(", "));
+                let default = match conflict.newest() {
+                    Side::Ours => 0,
+                    Side::Theirs => 1
+                };
+
+                let selection = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(&prompt)
+                    .default(default)
+                    .items(&items)
+                    .interact()?;
+
+                Ok(if selection == 0 { Side::Ours } else { Side::Theirs })
+            })
+            .collect()
+    }
+
+    fn handle_clip_clear(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let mut hash = String::new();
+        let timeout: u64 = args
+            .value_of("timeout")
+            .unwrap_or("0")
+            .parse()
+            .unwrap_or(0);
+
+        io::stdin().read_to_string(&mut hash)?;
+        std::thread::sleep(Duration::from_secs(timeout));
+
+        let mut clipboard = clipboard::detect()?;
+
+        clipboard::clear_if_unchanged(clipboard.as_mut(), hash.trim())?;
+
+        Ok(Resolve::Done)
+    }
+
+    fn handle_search(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let query = args.value_of("query").unwrap_or("");
+        let matches = self.keeper()?.search(query)?.to_matches();
+
+        if self.machine() {
+            let records = matches.iter()
+                .map(|(entity, account)| self.account_record(entity, account, None))
+                .collect::<VaultResult<Vec<Record>>>()?;
+
+            self.show(&records, || Ok(()))?;
+
+            return Ok(Resolve::Done);
+        }
+
+        let labels: Vec<String> = matches.iter()
+            .map(|(entity, account)| format!("{} / {}", entity, account))
+            .collect();
+
+        let (entity, account) = match pick(&labels) {
+            Some(selection) => matches[selection].clone(),
+            None => {
+                eprintln!("No matches for {:?}", query);
+
+                return Ok(Resolve::Done);
+            }
+        };
+
+        let read = Args::new(Some(&entity), Some(&account), None);
+        let password = self.keeper()?.read(read)?.to_string();
+
+        if args.is_present("clip") {
+            self.copy(args, &password)?;
+        } else {
+            find_table(password.clone());
+        }
+
+        Ok(Resolve::Read(password))
+    }
+
+    fn handle_list(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let Params { 
+            entity, 
+            ..
+        } = CLI::extract_values(args);
+
+        if let Some(name) = entity {
+            let args = Args::new(entity, None, None);
+            let list = self.keeper()?.list(Some(args))?.to_list();
+            let records: Vec<Record> = list.iter()
+                .map(|account| output::record(&[("entity", name), ("account", account)]))
+                .collect();
+
+            self.show(&records, || list_table(list.clone(), true))?;
+
+            return Ok(Resolve::Done);
+        }
+
+        let list = self.keeper()?.list(None)?.to_list();
+        let records: Vec<Record> = list.iter()
+            .map(|entity| output::record(&[("entity", entity)]))
+            .collect();
+
+        self.show(&records, || list_table(list.clone(), false))?;
+
+        Ok(Resolve::Done)
+    }
+    
+    fn handle_remove(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let Params { 
+            entity, 
+            account, 
+            .. 
+        } = CLI::extract_values(args);
+       
+        let args = Args::new(
+            entity,
+            account,
+            None 
+        );
+
+        self.keeper()?.remove(args)
+    }
+
+    fn handle_rename(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let Params { 
+            entity, 
+            account, 
+            .. 
+        } = CLI::extract_values(args);
+
+        let to = Locker::new().encrypt(args.value_of("to").unwrap_or(""));
+        let args = Args::new(
+            entity,
+            account,
+            None 
+        );
+
+        self.keeper()?.rename(args, to)
+    }
+
+    fn handle_move(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
+        let Params { 
+            entity, 
+            account, 
+            .. 
+        } = CLI::extract_values(args);
+
+        let to_entity = Locker::new().encrypt(args.value_of("to-entity").unwrap_or(""));
+        let args = Args::new(
+            entity,
+            account,
+            None 
+        );
+
+        self.keeper()?.move_account(args, to_entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Path, PathBuf};
+    use std::fs::{remove_dir_all, remove_file};
+ 
+    use crate::mocks::Setup; 
+    use crate::locker::Locker;
+    use crate::cli::commands::{command, Commands};
+
+    use rk::clipboard::Memory;
+
+    use Commands::*;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let p = Path::new(path);
+
+            let exists = &p.exists();
+            let is_dir = &p.is_dir();
+            let is_file = &p.is_file();
+
+            let remove = if *is_dir { "dir" } else { "file" };
+            let msg = format!("Could not remove {} {:?} in `handler.rs` test", remove, path);
+
+            if *exists { 
+                if *is_file { remove_file(path).expect(&msg); }
+                if *is_dir { remove_dir_all(path).expect(&msg); }
+            } 
+        }
+    }
+
+    fn start(config: PathBuf, locker: PathBuf) -> CLI {
+        Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+        let mut cli = CLI::start(config, locker);
+
+        cli.interactive = false;
+        cli.master_password = Some(String::from("master"));
+        cli
+    }
+
+    #[test]
+    fn operation_add_entity() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+                let args = vec![ "test", "add", "-e", "add_entity" ];
+                let results = command(Add, args);
+                let add = cli.operation(results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_add_account() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+                let args = vec![ "test", "add", "-a", "add_account", "-e", "add_account_entity" ];
+                let results = command(Add, args);
+                let add = cli.operation(results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_add_password() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+
+                let args = vec![ 
+                    "test", "add", "-p", "very_good_password_1", 
+                    "-a", "account_for_password", "-e", "entity_for_password" 
+                ];
+
+                let results = command(Add, args);
+                let add = cli.operation(results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_find_entity() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-e", "operation_find_entity" ];
+                let add_results = command(Add, add_args);
+                let add = cli.operation(add_results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+
+                let find_args = vec![ "test", "find", "-e", "operation_find_entity" ]; 
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results).unwrap();
+
+                assert_eq!(found, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_find_account() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-a", "account", "-e", "entity" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results);
+
+                let find_args = vec![ "test", "find", "-a", "account", "-e", "entity" ];
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results).unwrap();
+
+                assert_eq!(found, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_remove_entity() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-e", "entity" ];
+                let add_results = command(Add, add_args);
+                let add = cli.operation(add_results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+
+                let remove_args = vec![ "test", "remove", "-e", "entity" ];
+                let remove_results = command(Remove, remove_args);
+                let removed = cli.operation(remove_results).unwrap();
+
+                assert_eq!(removed, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_remove_account() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-a", "new_account", "-e", "new_entity" ];
+                let add_results = command(Add, add_args);
+                let add = cli.operation(add_results).unwrap();
+
+                assert_eq!(add, Resolve::Done);
+
+                let remove_args = vec![ "test", "remove", "-a", "new_account", "-e", "new_entity" ];
+                let remove_results = command(Remove, remove_args);
+                let removed = cli.operation(remove_results).unwrap();
+
+                assert_eq!(removed, Resolve::Done);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_rename_entity() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-e", "old_entity" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let rename_args = vec![ "test", "rename", "-e", "old_entity", "--to", "new_entity" ];
+                let rename_results = command(Rename, rename_args);
+                let renamed = cli.operation(rename_results).unwrap();
+
+                assert_eq!(renamed, Resolve::Done);
+
+                let find_args = vec![ "test", "find", "-e", "new_entity" ];
+                let find_results = command(Find, find_args);
+
+                assert!(cli.operation(find_results).is_ok());
+            }
+        };
+    }
+
+    #[test]
+    fn operation_move_account() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-p", "moved", "-a", "account", "-e", "entity" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let move_args = vec![ "test", "move", "-e", "entity", "-a", "account", "--to-entity", "other" ];
+                let move_results = command(Move, move_args);
+                let moved = cli.operation(move_results).unwrap();
+
+                assert_eq!(moved, Resolve::Done);
+
+                let find_args = vec![ "test", "find", "-e", "other", "-a", "account" ];
+                let find_results = command(Find, find_args);
+
+                assert!(cli.operation(find_results).is_ok());
+            }
+        };
+    }
+
+    #[test]
+    fn operation_search() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ 
+                    "test", "add", "-p", "router_password", "-a", "admin", "-e", "home",
+                    "--url", "http://192.168.0.1", "--tag", "network", "--tag", "admin"
+                ];
+
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let search_args = vec![ "test", "search", "netwrk" ];
+                let search_results = command(Search, search_args);
+                let found = cli.operation(search_results).unwrap();
+
+                assert_eq!(found, Resolve::Read(String::from("router_password")));
+
+                let fields = cli.keeper().unwrap().fields(Args::new(Some("home"), Some("admin"), None));
+                let fields = fields.unwrap().to_fields();
+
+                assert_eq!(fields.get(URL), Some(&String::from("http://192.168.0.1")));
+                assert_eq!(fields.get(TAGS), Some(&String::from("network,admin")));
+            }
+        };
+    }
+
+    #[test]
+    fn operation_find_json() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-p", "json_password", "-a", "account", "-e", "entity" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let find_args = vec![ "test", "--format", "json", "find", "-e", "entity" ];
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results).unwrap();
+
+                assert_eq!(found, Resolve::Done);
+                assert_eq!(cli.format, Format::Json);
+
+                let record = cli.account_record("entity", "account", Some("json_password")).unwrap();
+
+                assert_eq!(record.get("password"), Some(&String::from("json_password")));
+            }
+        };
+    }
+
+    #[test]
+    fn operation_find_missing_field() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let add_args = vec![ "test", "add", "-a", "account", "-e", "entity" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let find_args = vec![ "test", "find", "-e", "entity", "-a", "account", "--field", "url" ];
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results);
+
+                assert_eq!(found.unwrap_err().exit_code(), 3);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_unknown_format() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+               
+                let list_args = vec![ "test", "--format", "xml", "find", "-e", "entity" ];
+                let list_results = command(Find, list_args);
+
+                assert!(cli.operation(list_results).is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn operation_config() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config.clone(), locker.clone());
+                let locker = locker.to_str().unwrap();
+
+                let set_args = vec![ "test", "config", "set", "paths.locker", locker ];
+                let set_results = command(Config, set_args);
+                cli.operation(set_results).unwrap();
+
+                let get_args = vec![ "test", "config", "get", "paths.locker" ];
+                let get_results = command(Config, get_args);
+                let value = cli.operation(get_results).unwrap();
+
+                let unset_args = vec![ "test", "config", "unset", "paths.locker" ];
+                let unset_results = command(Config, unset_args);
+                cli.operation(unset_results).unwrap();
+
+                let get_args = vec![ "test", "config", "get", "paths.locker" ];
+                let get_results = command(Config, get_args);
+                let unset = cli.operation(get_results).unwrap();
+
+                assert_eq!(value, Resolve::Read(locker.to_string()));
+                assert_ne!(unset, Resolve::Read(locker.to_string()));
+                assert!(config.join("settings.yml").exists());
+            }
+        };
+    }
+
+    #[test]
+    fn operation_config_flags() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
+
+                let get_args = vec![ "test", "--locker", "/from/flag", "config", "get", "paths.locker" ];
+                let get_results = command(Config, get_args);
+                let value = cli.operation(get_results).unwrap();
+
+                let list_args = vec![ "test", "config", "list", "--origin", "--algorithm", "aes-128-cbc" ];
+                let list_results = command(Config, list_args);
+                let flags = overrides(&list_results);
+
+                cli.operation(list_results).unwrap();
+
+                assert_eq!(value, Resolve::Read(String::from("/from/flag")));
+                assert_eq!(flags, vec![(SettingsOpts::Algorithm, String::from("aes-128-cbc"))]);
+            }
+        };
+    }
+
+    #[test]
+    fn operation_vault() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker.clone());
+                let locker = locker.to_str().unwrap();
+
+                let create_args = vec![ "test", "vault", "create", "team", locker, "--default" ];
+                let create_results = command(Vault, create_args);
+                cli.operation(create_results).unwrap();
+
+                let create_args = vec![ "test", "vault", "create", "prod", locker ];
+                let create_results = command(Vault, create_args);
+                cli.operation(create_results).unwrap();
+
+                let remove_args = vec![ "test", "vault", "remove", "team" ];
+                let remove_results = command(Vault, remove_args);
+                let remove_default = cli.operation(remove_results);
+
+                let remove_args = vec![ "test", "vault", "remove", "prod" ];
+                let remove_results = command(Vault, remove_args);
+                cli.operation(remove_results).unwrap();
+
+                let list_args = vec![ "test", "vault", "list" ];
+                let list_results = command(Vault, list_args);
+                let list = cli.operation(list_results).unwrap();
+
+                let get_args = vec![ "test", "config", "get", "paths.locker" ];
+                let get_results = command(Config, get_args);
+                let value = cli.operation(get_results).unwrap();
+
+                assert!(remove_default.is_err());
+                assert_eq!(list, Resolve::List(vec![String::from("team")]));
+                assert_eq!(value, Resolve::Read(locker.to_string()));
+            }
+        };
+    }
+
+    #[test]
+    fn operation_config_invalid() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config.clone(), locker);
+
+                let set_args = vec![ "test", "config", "set", "crypto.algorithm", "rot13" ];
+                let set_results = command(Config, set_args);
+                let set = cli.operation(set_results);
+
+                let get_args = vec![ "test", "config", "get", "paths.lockr" ];
+                let get_results = command(Config, get_args);
+                let get = cli.operation(get_results);
+
+                assert!(set.is_err());
+                assert!(get.is_err());
+                assert!(!config.join("settings.yml").exists());
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn operation_init() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
+                let (config, locker) = this.as_path_buf();
+                let mut secret = this.dump_path();
+
+                secret.push("operation_init");
+                this.add_to_paths(&secret);
+                std::fs::write(&secret, "master_password\n").unwrap();
+
+                let mut cli = CLI::start(config.clone(), locker.clone());
+
+                cli.interactive = false;
+
+                let list_args = vec![ "test", "list" ];
+                let list_results = command(List, list_args);
+                let uninitialised = cli.operation(list_results);
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let init_args = vec![ "test", "init", "--password-fd", &fd, "--kdf-memory", "8", "--kdf-time", "1" ];
+                let init_results = command(Init, init_args);
+                cli.operation(init_results).unwrap();
+
+                let list_args = vec![ "test", "list" ];
+                let list_results = command(List, list_args);
+                let initialised = cli.operation(list_results);
+
+                let header = rk::Header::read(&locker).unwrap().unwrap();
+
+                assert_eq!(uninitialised.unwrap_err().exit_code(), 3);
+                assert!(initialised.is_ok());
+                assert!(header.unlock("master_password").is_ok());
+                assert!(config.join("settings.yml").exists());
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn operation_export_import() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
+                let (config, locker) = this.as_path_buf();
+                let mut secret = this.dump_path();
+                let mut out = this.dump_path();
+
+                secret.push("operation_export_import");
+                out.push("operation_export_import.rkx");
+                this.add_to_paths(&secret);
+                this.add_to_paths(&out);
+                std::fs::write(&secret, "passphrase\n").unwrap();
+
+                let mut cli = start(config, locker);
+                let out_path = out.to_str().unwrap();
+
+                let add_args = vec![ "test", "add", "-e", "entity", "-a", "account", "-p", "password" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let export_args = vec![ "test", "export", "--out", out_path, "--passphrase-fd", &fd, "--kdf-memory", "8", "--kdf-time", "1" ];
+                let export_results = command(Export, export_args);
+                cli.operation(export_results).unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let again_args = vec![ "test", "export", "--out", out_path, "--passphrase-fd", &fd ];
+                let again_results = command(Export, again_args);
+                let again = cli.operation(again_results);
+
+                let remove_args = vec![ "test", "remove", "-e", "entity" ];
+                let remove_results = command(Remove, remove_args);
+                cli.operation(remove_results).unwrap();
+
+                let import_args = vec![ "test", "import", out_path, "--mode", "replace" ];
+                let import_results = command(Import, import_args);
+                let missing_passphrase = cli.operation(import_results);
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let import_args = vec![ "test", "import", out_path, "--mode", "replace", "--passphrase-fd", &fd ];
+                let import_results = command(Import, import_args);
+                cli.operation(import_results).unwrap();
+
+                let password = cli.keeper().unwrap().read(Args::new(Some("entity"), Some("account"), None)).unwrap();
+
+                assert!(again.is_err());
+                assert!(missing_passphrase.is_err());
+                assert_eq!(password, Resolve::Read(String::from("password")));
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn operation_export_import_kdbx() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
+                let (config, locker) = this.as_path_buf();
+                let mut secret = this.dump_path();
+                let mut out = this.dump_path();
+
+                secret.push("operation_export_import_kdbx");
+                out.push("operation_export_import_kdbx.kdbx");
+                this.add_to_paths(&secret);
+                this.add_to_paths(&out);
+                std::fs::write(&secret, "database\n").unwrap();
+
+                let mut cli = start(config, locker);
+                let out_path = out.to_str().unwrap();
+
+                let add_args = vec![ "test", "add", "-e", "entity", "-a", "account", "-p", "password" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let export_args = vec![ "test", "export", "--to", "kdbx", out_path, "--passphrase-fd", &fd, "--kdf-memory", "8", "--kdf-time", "1" ];
+                let export_results = command(Export, export_args);
+                cli.operation(export_results).unwrap();
+
+                let remove_args = vec![ "test", "remove", "-e", "entity" ];
+                let remove_results = command(Remove, remove_args);
+                cli.operation(remove_results).unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let import_args = vec![ "test", "import", "--from", "kdbx", out_path, "--passphrase-fd", &fd ];
+                let import_results = command(Import, import_args);
+                cli.operation(import_results).unwrap();
+
+                let password = cli.keeper().unwrap().read(Args::new(Some("entity"), Some("account"), None)).unwrap();
+
+                assert_eq!(password, Resolve::Read(String::from("password")));
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn operation_kdbx_backend() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
+                let (config, locker) = this.as_path_buf();
+                let mut secret = this.dump_path();
+                let database = locker.join("vault.kdbx");
+
+                secret.push("operation_kdbx_backend");
+                this.add_to_paths(&secret);
+                std::fs::write(&secret, "master\n").unwrap();
+
+                let mut cli = CLI::start(config.clone(), database.clone()).with_backend("kdbx");
+
+                cli.interactive = false;
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let init_args = vec![ "test", "init", "--password-fd", &fd, "--kdf-memory", "8", "--kdf-time", "1" ];
+                let init_results = command(Init, init_args);
+                cli.operation(init_results).unwrap();
+
+                let add_args = vec![ "test", "add", "-e", "entity", "-a", "account", "-p", "password" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let mut cli = CLI::start(config.clone(), database.clone()).with_backend("kdbx");
+
+                cli.interactive = false;
+
+                let list_args = vec![ "test", "list" ];
+                let list_results = command(List, list_args);
+                let locked = cli.operation(list_results);
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let find_args = vec![ "test", "--master-password-fd", &fd, "find", "-e", "entity", "-a", "account" ];
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results);
+
+                let items = Kdbx::read(&database, "master").unwrap().items(false);
+
+                assert!(locked.is_err());
+                assert!(found.is_ok());
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].password.as_deref(), Some("password"));
+            }
+        };
+    }
+
+    #[test]
+    fn operation_export_plaintext() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut out = this.dump_path();
+
+                out.push("operation_export_plaintext.csv");
+                this.add_to_paths(&out);
+
+                let mut cli = start(config, locker);
+                let out_path = out.to_str().unwrap();
+
+                let add_args = vec![ "test", "add", "-e", "entity", "-a", "account", "-p", "password" ];
+                let add_results = command(Add, add_args);
+                cli.operation(add_results).unwrap();
+
+                let export_args = vec![ "test", "export", "--plaintext", "--out", out_path, "--yes" ];
+                let export_results = command(Export, export_args);
+                let table = cli.operation(export_results);
+
+                let export_args = vec![ "test", "--format", "csv", "export", "--plaintext", "--out", out_path ];
+                let export_results = command(Export, export_args);
+                let unconfirmed = cli.operation(export_results);
+
+                let export_args = vec![ "test", "--format", "csv", "export", "--plaintext", "--out", out_path, "--yes" ];
+                let export_results = command(Export, export_args);
+                cli.operation(export_results).unwrap();
+
+                let export_args = vec![ "test", "--format", "json", "export", "--plaintext", "--out", out_path, "--yes" ];
+                let export_results = command(Export, export_args);
+                let again = cli.operation(export_results);
+
+                assert!(unconfirmed.is_err());
+                assert!(table.is_err());
+                assert!(again.is_err());
+                assert_eq!(
+                    std::fs::read_to_string(&out).unwrap(),
+                    "name,url,username,password,note\nentity,,account,password,\n"
+                );
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn operation_agent_unlock() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
+                let (config, locker) = this.as_path_buf();
+                let mut secret = this.dump_path();
+                let database = locker.join("vault.kdbx");
+                let socket = config.join(agent::SOCKET);
+
+                secret.push("operation_agent_unlock");
+                this.add_to_paths(&secret);
+                std::fs::write(&secret, "master\n").unwrap();
+                Keeper::init_kdbx(database.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+                let cli = |socket: &PathBuf| {
+                    let mut cli = CLI::start(config.clone(), database.clone()).with_backend("kdbx");
+
+                    cli.interactive = false;
+                    cli.socket = socket.clone();
+                    cli
+                };
+
+                let unlock_results = command(Unlock, vec![ "test", "unlock" ]);
+                let no_agent = cli(&socket).operation(unlock_results);
+
+                let agent = agent::Agent::new(socket.clone(), Duration::from_secs(60));
+                let running = std::thread::spawn(move || agent.run());
+
+                while agent::request(&socket, &Request::Status).is_err() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let unlock_results = command(Unlock, vec![ "test", "--master-password-fd", &fd, "unlock" ]);
+                cli(&socket).operation(unlock_results).unwrap();
+
+                let list_results = command(List, vec![ "test", "list" ]);
+                let unlocked = cli(&socket).operation(list_results);
+
+                let lock_results = command(Lock, vec![ "test", "lock" ]);
+                cli(&socket).operation(lock_results).unwrap();
+
+                let list_results = command(List, vec![ "test", "list" ]);
+                let locked = cli(&socket).operation(list_results);
+
+                // Directory lockers are unlocked the same way
+                let directory = locker.join("directory");
+                let directory_cli = || {
+                    let mut cli = CLI::start(config.clone(), directory.clone());
+
+                    cli.interactive = false;
+                    cli.socket = socket.clone();
+                    cli
+                };
+
+                Keeper::init(config.clone(), directory.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let unlock_results = command(Unlock, vec![ "test", "--master-password-fd", &fd, "unlock" ]);
+                directory_cli().operation(unlock_results).unwrap();
+
+                let list_results = command(List, vec![ "test", "list" ]);
+                let directory_unlocked = directory_cli().operation(list_results);
+
+                let lock_results = command(Lock, vec![ "test", "lock" ]);
+                directory_cli().operation(lock_results).unwrap();
+
+                let list_results = command(List, vec![ "test", "list" ]);
+                let directory_locked = directory_cli().operation(list_results);
+
+                let stop_results = command(Agent, vec![ "test", "agent", "--stop" ]);
+                cli(&socket).operation(stop_results).unwrap();
+                running.join().unwrap().unwrap();
+
+                assert!(no_agent.is_err());
+                assert!(unlocked.is_ok());
+                assert!(locked.is_err());
+                assert!(directory_unlocked.is_ok());
+                assert!(directory_locked.is_err());
+                assert!(!socket.exists());
+            }
+        };
+    }
+
+    #[test]
+    fn operation_git_credential() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config.clone(), locker);
+
+                std::fs::write(config.join("settings.yml"), "git:\n  forge.internal:\n    entity: 'forge'\n").unwrap();
+
+                let mut git = |operation: &str, input: &str| {
+                    let matches = command(GitCredential, vec![ "test", "git-credential", operation ]);
+                    let args = matches.subcommand_matches("git-credential").unwrap().clone();
+                    let mut output = Vec::new();
+
+                    cli.handle_git_credential(&args, &mut input.as_bytes(), &mut output).unwrap();
 
-struct Params<'p> { 
-    entity: Option<&'p str>,
-    account: Option<&'p str>,
-    password: Option<&'p str>
-}
+                    String::from_utf8(output).unwrap()
+                };
 
-pub struct CLI { keeper: Keeper }
+                let missing = git("get", "protocol=https\nhost=forge.internal\n\n");
+                let stored = git("store", "protocol=https\nhost=forge.internal\nusername=ci\npassword=token\n\n");
+                let found = git("get", "protocol=https\nhost=forge.internal\n\n");
+                let unknown = git("capability", "");
 
-impl<'p> CLI {
-    pub fn start(config: PathBuf, locker: PathBuf) -> CLI {
-        let keeper = Keeper::new(config, locker).unwrap();
+                git("erase", "protocol=https\nhost=forge.internal\nusername=ci\npassword=token\n\n");
 
-        CLI {
-            keeper
-        }
-    }
+                let erased = git("get", "protocol=https\nhost=forge.internal\nusername=ci\n\n");
 
-    pub fn operation(&mut self, args: ArgMatches) -> VaultResult<Resolve> {
-        match args.subcommand() {
-            ("add", Some(add)) => { self.handle_add(add) },
-            ("find", Some(find)) => { self.handle_find(find) },
-            ("list", Some(list)) => { self.handle_list(list) },
-            ("remove", Some(remove)) => { self.handle_remove(remove) }
-            (_, _) => { panic!("Unknown operation in CLI"); }
-        }
+                assert_eq!(missing, "");
+                assert_eq!(stored, "");
+                assert_eq!(found, "username=ci\npassword=token\n");
+                assert_eq!(unknown, "");
+                assert_eq!(erased, "");
+            }
+        };
     }
 
-    fn extract_values(args: &'p ArgMatches) -> Params<'p> {
-        let password = args.value_of("password");
-        let account = args.value_of("account");
-        let entity = args.value_of("entity");
-        
-        Params {
-            entity,
-            account,
-            password
-        }
-    }
+    #[cfg(unix)]
+    #[test]
+    fn operation_run() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
 
-    fn handle_add(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
-        let Params { 
-            entity, 
-            account, 
-            password 
-        } = CLI::extract_values(args);
+                let add_results = command(Add, vec![ "test", "add", "-e", "prod-db", "-a", "admin", "-p", "secret" ]);
+                cli.operation(add_results).unwrap();
 
-        let args = Args::new(
-            entity,
-            account,
-            password
-        );
+                let run_results = command(Run, vec![ "test", "run", "--env", "DB_PASS=prod-db/admin", "--", "sh", "-c", "test \"$DB_PASS\" = secret" ]);
+                let matched = cli.operation(run_results).unwrap();
+
+                let run_results = command(Run, vec![ "test", "run", "--env", "DB_PASS=prod-db/admin", "--mask", "--", "sh", "-c", "exit 3" ]);
+                let failed = cli.operation(run_results).unwrap();
+
+                let run_results = command(Run, vec![ "test", "run", "--env", "DB_PASS=prod-db/root", "--", "true" ]);
+                let missing = cli.operation(run_results);
+
+                let run_results = command(Run, vec![ "test", "run", "--env", "prod-db/admin", "--", "true" ]);
+                let invalid = cli.operation(run_results);
 
-        self.keeper.add(args)
+                assert_eq!(matched, Resolve::Exit(0));
+                assert_eq!(failed, Resolve::Exit(3));
+                assert!(missing.is_err());
+                assert!(invalid.is_err());
+            }
+        };
     }
 
-    fn handle_find(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
-        let Params { 
-            entity, 
-            account, 
-            .. 
-        } = CLI::extract_values(args);
+    #[test]
+    fn operation_inject() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config.clone(), locker);
+                let template = config.join("database.yml.tpl");
+                let broken = config.join("broken.yml.tpl");
+                let out = config.join("database.yml");
 
-        let args = Args::new(
-            entity,
-            account,
-            None 
-        );
+                std::fs::write(&template, "password: {{ rk://prod-db/admin/password }}\n").unwrap();
+                std::fs::write(&broken, "password: {{ rk://prod-db/root/password }}\n").unwrap();
 
-        let found = self.keeper.find(args)?;
-        
-        if let Resolve::Find(vec) = &found {
-            let selected = select(vec.to_owned());
+                let add_results = command(Add, vec![ "test", "add", "-e", "prod-db", "-a", "admin", "-p", "secret" ]);
+                cli.operation(add_results).unwrap();
 
-            if let Some(option) = selected {
-                // NOTE: having issues on linux to copy/paste on clipboard:
-                // https://github.com/alacritty/alacritty/issues/2795
-                // let mut ctx = ClipboardContext::new().unwrap();
-                // ctx.set_contents(read).unwrap();
-                // let read = self.keeper.read(option)?.to_string();
-                
-                find_table(option.to_string());
+                let check_results = command(Inject, vec![ "test", "inject", "-i", template.to_str().unwrap(), "--check" ]);
+                let checked = cli.operation(check_results);
 
-                return Ok(Resolve::Read(option));
-            }
-        }
+                let check_results = command(Inject, vec![ "test", "inject", "-i", broken.to_str().unwrap(), "--check" ]);
+                let missing = cli.operation(check_results);
 
-        if let Resolve::Read(password) = found {
-            find_table(password);
-        }
+                let inject_results = command(Inject, vec![ "test", "inject", "-i", broken.to_str().unwrap(), "-o", out.to_str().unwrap() ]);
+                let not_written = cli.operation(inject_results);
+                let exists = out.exists();
 
-        Ok(Resolve::Done)
-    }
+                let inject_results = command(Inject, vec![ "test", "inject", "-i", template.to_str().unwrap(), "-o", out.to_str().unwrap() ]);
+                cli.operation(inject_results).unwrap();
 
-    fn handle_list(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
-        let Params { 
-            entity, 
-            ..
-        } = CLI::extract_values(args);
+                assert!(checked.is_ok());
+                assert!(missing.is_err());
+                assert!(not_written.is_err());
+                assert!(!exists);
+                assert_eq!(std::fs::read_to_string(&out).unwrap(), "password: secret\n");
+            }
+        };
+    }
 
-        if entity.is_some() {
-            let args = Args::new(entity, None, None);
-            let list = self.keeper.list(Some(args))?;
+    #[test]
+    fn operation_native_messaging() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
 
-            list_table(list.to_list(), true);
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "secret", "--url", "https://github.com" ]);
+                cli.operation(add_results).unwrap();
 
-            return Ok(Resolve::Done);
-        }
+                let mut input = Vec::new();
+                let mut output = Vec::new();
 
-        let list = self.keeper.list(None)?;
+                native::write_message(&mut input, &serde_json::json!({ "action": "find", "url": "https://github.com/login" })).unwrap();
+                native::write_message(&mut input, &serde_json::json!({ "action": "get", "url": "https://github.com/login", "entity": "github", "account": "me" })).unwrap();
 
-        list_table(list.to_list(), false);
+                let matches = command(NativeMessaging, vec![ "test", "native-messaging", "chrome-extension://abcdefghijklmnop/" ]);
+                let args = matches.subcommand_matches("native-messaging").unwrap().clone();
+                cli.handle_native_messaging(&args, &mut &input[..], &mut output).unwrap();
 
-        Ok(Resolve::Done)
-    }
-    
-    fn handle_remove(&mut self, args: &'p ArgMatches) -> VaultResult<Resolve> {
-        let Params { 
-            entity, 
-            account, 
-            .. 
-        } = CLI::extract_values(args);
-       
-        let args = Args::new(
-            entity,
-            account,
-            None 
-        );
+                let mut responses = &output[..];
+                let found = native::read_message(&mut responses).unwrap().unwrap();
+                let credentials = native::read_message(&mut responses).unwrap().unwrap();
 
-        self.keeper.remove(args)
+                assert_eq!(found["accounts"], serde_json::json!([{ "entity": "github", "account": "me" }]));
+                assert_eq!(credentials["password"], serde_json::json!("secret"));
+                assert_eq!(quote("/opt/my rk/it's"), "'/opt/my rk/it'\\''s'");
+            }
+        };
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn operation_serve() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut cli = start(config, locker);
 
-    use std::path::{Path, PathBuf};
-    use std::fs::{remove_dir_all, remove_file};
- 
-    use crate::mocks::Setup; 
-    use crate::locker::Locker;
-    use crate::cli::commands::{command, Commands};
+                let add_results = command(Commands::Serve, vec![ "test", "serve", "token", "add", "ci", "--write" ]);
+                let token = cli.operation(add_results).unwrap().to_string();
 
-    use Commands::*;
+                let public_results = command(Commands::Serve, vec![ "test", "serve", "--listen", "0.0.0.0:8750" ]);
+                let public = cli.operation(public_results);
 
-    fn after_each(this: &mut Setup) {
-        for path in this.paths.iter() {
-            let p = Path::new(path);
+                let nowhere_results = command(Commands::Serve, vec![ "test", "serve" ]);
+                let nowhere = cli.operation(nowhere_results);
 
-            let exists = &p.exists();
-            let is_dir = &p.is_dir();
-            let is_file = &p.is_file();
+                let remove_results = command(Commands::Serve, vec![ "test", "serve", "token", "remove", "ci" ]);
+                cli.operation(remove_results).unwrap();
 
-            let remove = if *is_dir { "dir" } else { "file" };
-            let msg = format!("Could not remove {} {:?} in `handler.rs` test", remove, path);
+                let again_results = command(Commands::Serve, vec![ "test", "serve", "token", "remove", "ci" ]);
+                let again = cli.operation(again_results);
 
-            if *exists { 
-                if *is_file { remove_file(path).expect(&msg); }
-                if *is_dir { remove_dir_all(path).expect(&msg); }
-            } 
-        }
+                assert_eq!(token.len(), 32);
+                assert!(public.unwrap_err().to_str().contains("only loopback"));
+                assert!(nowhere.is_err());
+                assert!(again.is_err());
+            }
+        };
     }
 
     #[test]
-    fn operation_add_entity() {
+    fn operation_log() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-                let args = vec![ "test", "add", "-e", "add_entity" ];
-                let results = command(Add, args);
-                let add = cli.operation(results).unwrap();
+                let mut cli = start(config.clone(), locker.clone());
 
-                assert_eq!(add, Resolve::Done);
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "secret" ]);
+                cli.operation(add_results).unwrap();
+
+                let find_results = command(Find, vec![ "test", "find", "-e", "github", "-a", "me" ]);
+                cli.operation(find_results).unwrap();
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "gitlab", "-a", "me", "-p", "secret" ]);
+                cli.operation(add_results).unwrap();
+
+                let log_results = command(Log, vec![ "test", "log", "-e", "github", "--since", "2020-01-01" ]);
+                let github = cli.operation(log_results).unwrap().to_list();
+
+                let future_results = command(Log, vec![ "test", "log", "--since", "2999-01-01" ]);
+                let future = cli.operation(future_results).unwrap().to_list();
+
+                let verify_results = command(Log, vec![ "test", "log", "verify" ]);
+                let verified = cli.operation(verify_results);
+
+                let head = audit::path(&config, &locker).with_extension("head");
+                fs::write(&head, fs::read_to_string(&head).unwrap().replacen('3', "4", 1)).unwrap();
+
+                let verify_results = command(Log, vec![ "test", "log", "verify" ]);
+                let truncated = cli.operation(verify_results);
+
+                let time_results = command(Log, vec![ "test", "log", "--until", "tomorrow" ]);
+                let time = cli.operation(time_results);
+
+                assert_eq!(github, vec![format!("{} add github me ok", user()), format!("{} find github me ok", user())]);
+                assert!(future.is_empty());
+                assert!(verified.is_ok());
+                assert!(truncated.is_err());
+                assert!(time.is_err());
             }
         };
     }
 
     #[test]
-    fn operation_add_account() {
+    fn operation_sync_history() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-                let args = vec![ "test", "add", "-a", "add_account", "-e", "add_account_entity" ];
-                let results = command(Add, args);
-                let add = cli.operation(results).unwrap();
+                let (other_config, other_locker) = this.as_path_buf();
+                let (_, remote) = this.as_path_buf();
+                let url = remote.to_str().unwrap();
 
-                assert_eq!(add, Resolve::Done);
+                Command::new("git").args(["init", "-q", "--bare"]).arg(&remote).output().unwrap();
+
+                let mut cli = start(config, locker);
+
+                let history_results = command(History, vec![ "test", "history", "-e", "github" ]);
+                let untracked = cli.operation(history_results);
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "secret" ]);
+                cli.operation(add_results).unwrap();
+
+                let sync_results = command(Sync, vec![ "test", "sync" ]);
+                let no_remote = cli.operation(sync_results);
+
+                let sync_results = command(Sync, vec![ "test", "sync", "--remote", url ]);
+                cli.operation(sync_results).unwrap();
+
+                let mut other = CLI::start(other_config.clone(), other_locker.clone());
+
+                other.interactive = false;
+                other.master_password = Some(String::from("master"));
+
+                let sync_results = command(Sync, vec![ "test", "sync", "--remote", url ]);
+                other.operation(sync_results).unwrap();
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "work" ]);
+                other.operation(add_results).unwrap();
+
+                let sync_results = command(Sync, vec![ "test", "sync" ]);
+                other.operation(sync_results).unwrap();
+
+                let sync_results = command(Sync, vec![ "test", "sync" ]);
+                cli.operation(sync_results).unwrap();
+
+                let history_results = command(History, vec![ "test", "history", "-e", "github" ]);
+                let history = cli.operation(history_results).unwrap().to_list();
+
+                let mut keeper = Keeper::open(other_config, other_locker, "master").unwrap();
+                let read = keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+
+                assert!(untracked.is_err());
+                assert!(no_remote.is_err());
+                assert_eq!(history, vec![
+                    String::from("Add an account work"),
+                    String::from("Start tracking the vault me")
+                ]);
+                assert_eq!(read, Resolve::Read(String::from("secret")));
             }
         };
     }
 
     #[test]
-    fn operation_add_password() {
+    fn operation_merge() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
+                let (other_config, other_locker) = this.as_path_buf();
+                let other = other_locker.to_str().unwrap();
+                let mut cli = start(config.clone(), locker.clone());
+                let mut laptop = start(other_config.clone(), other_locker.clone());
 
-                let args = vec![ 
-                    "test", "add", "-p", "very_good_password_1", 
-                    "-a", "account_for_password", "-e", "entity_for_password" 
-                ];
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "first" ]);
+                cli.operation(add_results).unwrap();
 
-                let results = command(Add, args);
-                let add = cli.operation(results).unwrap();
+                let add_results = command(Add, vec![ "test", "add", "-e", "gitlab", "-a", "me", "-p", "first" ]);
+                laptop.operation(add_results).unwrap();
 
-                assert_eq!(add, Resolve::Done);
+                let merge_results = command(Merge, vec![ "test", "merge", other ]);
+                cli.operation(merge_results).unwrap();
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "desktop" ]);
+                cli.operation(add_results).unwrap();
+
+                let remove_results = command(Remove, vec![ "test", "remove", "-e", "gitlab", "-a", "me" ]);
+                cli.operation(remove_results).unwrap();
+
+                let mut laptop = CLI::start(other_config.clone(), other_locker.clone());
+
+                laptop.interactive = false;
+                laptop.master_password = Some(String::from("master"));
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "github", "-a", "me", "-p", "laptop" ]);
+                laptop.operation(add_results).unwrap();
+
+                let add_results = command(Add, vec![ "test", "add", "-e", "aws", "-a", "root", "-p", "first" ]);
+                laptop.operation(add_results).unwrap();
+
+                let merge_results = command(Merge, vec![ "test", "merge", other ]);
+                let conflicted = cli.operation(merge_results);
+
+                let merge_results = command(Merge, vec![ "test", "merge", other, "--newest" ]);
+                cli.operation(merge_results).unwrap();
+
+                let merge_results = command(Merge, vec![ "test", "merge", locker.to_str().unwrap() ]);
+                let itself = cli.operation(merge_results);
+
+                let mut desktop = Keeper::open(config.clone(), locker.clone(), "master").unwrap();
+                let mut laptop = Keeper::open(other_config.clone(), other_locker.clone(), "master").unwrap();
+                let merged = |log: Audit| -> Vec<String> {
+                    log.events().unwrap().into_iter()
+                        .filter(|event| event.action == "merge")
+                        .map(|event| format!("{:?} {:?}", event.entity, event.account))
+                        .collect()
+                };
+                let here = merged(Audit::new(audit::path(&config, &locker), desktop.key().unwrap()));
+                let there = merged(Audit::new(audit::path(&config, &other_locker), laptop.key().unwrap()));
+
+                assert!(conflicted.unwrap_err().to_str().contains("github/me"));
+                assert!(here.contains(&String::from(r#"Some("aws") Some("root")"#)));
+                assert!(there.contains(&String::from(r#"Some("github") Some("me")"#)));
+                assert!(itself.is_err());
+                assert_eq!(desktop.snapshot().unwrap(), laptop.snapshot().unwrap());
+                assert!(desktop.read(Args::new(Some("gitlab"), Some("me"), None)).is_err());
+                assert_eq!(desktop.read(Args::new(Some("aws"), Some("root"), None)).unwrap(), Resolve::Read(String::from("first")));
+                assert!(desktop.read(Args::new(Some("github"), Some("me"), None)).is_ok());
             }
         };
     }
 
+    #[cfg(unix)]
     #[test]
-    fn operation_find_entity() {
+    fn operation_ssh_agent() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
+                use std::os::unix::net::UnixListener;
+
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-               
-                let add_args = vec![ "test", "add", "-e", "operation_find_entity" ];
-                let add_results = command(Add, add_args);
-                let add = cli.operation(add_results).unwrap();
+                let mut cli = start(config.clone(), locker);
+                let socket = config.join(ssh::SOCKET);
+                let busy = UnixListener::bind(&socket).unwrap();
 
-                assert_eq!(add, Resolve::Done);
+                let confirm_results = command(Commands::SshAgent, vec![ "test", "ssh-agent", "--confirm" ]);
+                let confirm = cli.operation(confirm_results);
 
-                let find_args = vec![ "test", "find", "-e", "operation_find_entity" ]; 
-                let find_results = command(Find, find_args);
-                let found = cli.operation(find_results).unwrap();
+                let agent_results = command(Commands::SshAgent, vec![ "test", "ssh-agent", "--socket", socket.to_str().unwrap() ]);
+                let running = cli.operation(agent_results);
 
-                assert_eq!(found, Resolve::Done);
+                drop(busy);
+
+                assert!(confirm.is_err());
+                assert!(running.is_err());
+                assert!(socket.exists());
             }
         };
     }
 
     #[test]
-    fn operation_find_account() {
+    fn operation_import_from() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-               
-                let add_args = vec![ "test", "add", "-a", "account", "-e", "entity" ];
+                let mut csv = this.dump_path();
+
+                csv.push("operation_import_from.csv");
+                this.add_to_paths(&csv);
+                std::fs::write(&csv, "name,url,username,password,note\ngithub.com,https://github.com,me,secret,\n").unwrap();
+
+                let mut cli = start(config, locker);
+                let csv_path = csv.to_str().unwrap();
+
+                let add_args = vec![ "test", "add", "-e", "github.com", "-a", "me", "-p", "old" ];
                 let add_results = command(Add, add_args);
-                cli.operation(add_results);
+                cli.operation(add_results).unwrap();
 
-                let find_args = vec![ "test", "find", "-a", "account", "-e", "entity" ];
-                let find_results = command(Find, find_args);
-                let found = cli.operation(find_results).unwrap();
+                let dry_args = vec![ "test", "import", csv_path, "--from", "chrome-csv", "--duplicates", "rename", "--dry-run" ];
+                let dry_results = command(Import, dry_args);
+                cli.operation(dry_results).unwrap();
 
-                assert_eq!(found, Resolve::Done);
+                let accounts = cli.keeper().unwrap().list(Some(Args::new(Some("github.com"), None, None))).unwrap();
+
+                assert_eq!(accounts, Resolve::List(vec![String::from("me")]));
+
+                let import_args = vec![ "test", "import", csv_path, "--from", "chrome-csv", "--duplicates", "overwrite" ];
+                let import_results = command(Import, import_args);
+                cli.operation(import_results).unwrap();
+
+                let password = cli.keeper().unwrap().read(Args::new(Some("github.com"), Some("me"), None)).unwrap();
+
+                assert_eq!(password, Resolve::Read(String::from("secret")));
             }
         };
     }
 
+    #[cfg(unix)]
     #[test]
-    fn operation_remove_entity() {
+    fn operation_add_password_from_fd() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
+                use std::fs::File;
+                use std::os::unix::io::IntoRawFd;
+
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-               
-                let add_args = vec![ "test", "add", "-e", "entity" ];
-                let add_results = command(Add, add_args);
-                let add = cli.operation(add_results).unwrap();
+                let mut secret = this.dump_path();
+
+                secret.push("operation_add_password_from_fd");
+                this.add_to_paths(&secret);
+                std::fs::write(&secret, "from_fd_password\n").unwrap();
+
+                let fd = File::open(&secret).unwrap().into_raw_fd().to_string();
+                let mut cli = start(config, locker);
+                let args = vec![ 
+                    "test", "add", "--password-fd", &fd, 
+                    "-a", "fd_account", "-e", "fd_entity" 
+                ];
+
+                let results = command(Add, args);
+                let add = cli.operation(results).unwrap();
 
                 assert_eq!(add, Resolve::Done);
 
-                let remove_args = vec![ "test", "remove", "-e", "entity" ];
-                let remove_results = command(Remove, remove_args);
-                let removed = cli.operation(remove_results).unwrap();
+                let read = cli.keeper().unwrap().read(Args::new(Some("fd_entity"), Some("fd_account"), None));
 
-                assert_eq!(removed, Resolve::Done);
+                assert_eq!(read.unwrap(), Resolve::Read(String::from("from_fd_password")));
             }
         };
     }
 
     #[test]
-    fn operation_remove_account() {
+    fn operation_find_account_clip() {
         Setup {
             paths: Vec::new(),
             after_each: &after_each,
             test: &|this| {
                 let (config, locker) = this.as_path_buf();
-                let mut cli = CLI::start(config, locker);
-               
-                let add_args = vec![ "test", "add", "-a", "new_account", "-e", "new_entity" ];
+                let clipboard = Memory::new();
+                let mut cli = start(config, locker);
+
+                cli.clipboard = Some(Box::new(clipboard.clone()));
+
+                let add_args = vec![ "test", "add", "-p", "clip_password", "-a", "account", "-e", "entity" ];
                 let add_results = command(Add, add_args);
-                let add = cli.operation(add_results).unwrap();
+                cli.operation(add_results).unwrap();
 
-                assert_eq!(add, Resolve::Done);
+                let find_args = vec![ 
+                    "test", "find", "--clip", "--clip-timeout", "1", 
+                    "-a", "account", "-e", "entity" 
+                ];
 
-                let remove_args = vec![ "test", "remove", "-a", "new_account", "-e", "new_entity" ];
-                let remove_results = command(Remove, remove_args);
-                let removed = cli.operation(remove_results).unwrap();
+                let find_results = command(Find, find_args);
+                let found = cli.operation(find_results).unwrap();
 
-                assert_eq!(removed, Resolve::Done);
+                assert_eq!(found, Resolve::Done);
+                assert_eq!(clipboard.clone().get().unwrap(), "clip_password");
+
+                std::thread::sleep(Duration::from_millis(1500));
+
+                assert_eq!(clipboard.clone().get().unwrap(), "");
             }
         };
     }
+
+    #[test]
+    fn read_secret_trims_line_ending() {
+        let secret = read_secret("strong_pass\r\nignored\n".as_bytes()).unwrap();
+
+        assert_eq!(secret, "strong_pass");
+    }
 }
@@ -9,16 +9,79 @@ use clap::{
 pub enum Commands {
     Add,
     Find,
-    Remove
+    Remove,
+    Rename,
+    Move,
+    Search,
+    Config,
+    Vault,
+    Init,
+    Export,
+    Import,
+    List,
+    Agent,
+    Unlock,
+    Lock,
+    GitCredential,
+    SshAgent,
+    Run,
+    Inject,
+    NativeMessaging,
+    Serve,
+    Log,
+    Sync,
+    History,
+    Merge
 }
 
 pub fn command(cmd: Commands, args: Vec<&str>) -> ArgMatches<'static> {
-   let app = App::new("test");
+    let app = App::new("test")
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .global(true)
+        )
+        .arg(
+            Arg::with_name("field")
+                .long("field")
+                .takes_value(true)
+                .global(true)
+        )
+        .arg(
+            Arg::with_name("master-password-fd")
+                .long("master-password-fd")
+                .takes_value(true)
+                .global(true)
+        )
+        .args(&settings());
 
     match cmd {
         Commands::Add => app.subcommand(add()).get_matches_from(args),
         Commands::Find => app.subcommand(find()).get_matches_from(args),
         Commands::Remove => app.subcommand(remove()).get_matches_from(args),
+        Commands::Rename => app.subcommand(rename()).get_matches_from(args),
+        Commands::Move => app.subcommand(move_account()).get_matches_from(args),
+        Commands::Search => app.subcommand(search()).get_matches_from(args),
+        Commands::Config => app.subcommand(config()).get_matches_from(args),
+        Commands::Vault => app.subcommand(vault()).get_matches_from(args),
+        Commands::Init => app.subcommand(init()).get_matches_from(args),
+        Commands::Export => app.subcommand(export()).get_matches_from(args),
+        Commands::Import => app.subcommand(import()).get_matches_from(args),
+        Commands::List => app.subcommand(list()).get_matches_from(args),
+        Commands::Agent => app.subcommand(agent()).get_matches_from(args),
+        Commands::Unlock => app.subcommand(SubCommand::with_name("unlock")).get_matches_from(args),
+        Commands::Lock => app.subcommand(lock()).get_matches_from(args),
+        Commands::GitCredential => app.subcommand(git_credential()).get_matches_from(args),
+        Commands::SshAgent => app.subcommand(ssh_agent()).get_matches_from(args),
+        Commands::Run => app.subcommand(run()).get_matches_from(args),
+        Commands::Inject => app.subcommand(inject()).get_matches_from(args),
+        Commands::NativeMessaging => app.subcommand(native_messaging()).get_matches_from(args),
+        Commands::Serve => app.subcommand(serve()).get_matches_from(args),
+        Commands::Log => app.subcommand(log()).get_matches_from(args),
+        Commands::Sync => app.subcommand(sync()).get_matches_from(args),
+        Commands::History => app.subcommand(history()).get_matches_from(args),
+        Commands::Merge => app.subcommand(merge()).get_matches_from(args),
     }
 }
 
@@ -42,6 +105,31 @@ fn add() -> App<'static, 'static> {
                 .takes_value(true)
                 .required(false)
         )
+        .arg(
+            Arg::with_name("password-stdin")
+                .long("password-stdin")
+                .conflicts_with_all(&["password", "password-fd"])
+        )
+        .arg(
+            Arg::with_name("password-fd")
+                .long("password-fd")
+                .takes_value(true)
+                .conflicts_with("password")
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .takes_value(true)
+                .requires("account")
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("account")
+        )
 }
 
 fn find() -> App<'static, 'static> {
@@ -58,6 +146,17 @@ fn find() -> App<'static, 'static> {
                 .takes_value(true)
                 .required(false)
         )
+        .arg(
+            Arg::with_name("clip")
+                .long("clip")
+                .short("c")
+        )
+        .arg(
+            Arg::with_name("clip-timeout")
+                .long("clip-timeout")
+                .takes_value(true)
+                .requires("clip")
+        )
 }
 
 fn remove() -> App<'static, 'static> {
@@ -75,3 +174,339 @@ fn remove() -> App<'static, 'static> {
                 .required(false)
         )
 }
+
+fn rename() -> App<'static, 'static> {
+    SubCommand::with_name("rename")
+        .arg(
+            Arg::with_name("entity")
+                .short("e")
+                .takes_value(true)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("account")
+                .short("a")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+        )
+}
+
+fn move_account() -> App<'static, 'static> {
+    SubCommand::with_name("move")
+        .arg(
+            Arg::with_name("entity")
+                .short("e")
+                .takes_value(true)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("account")
+                .short("a")
+                .takes_value(true)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("to-entity")
+                .long("to-entity")
+                .takes_value(true)
+                .required(true)
+        )
+}
+
+fn search() -> App<'static, 'static> {
+    SubCommand::with_name("search")
+        .arg(
+            Arg::with_name("query")
+                .index(1)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("clip")
+                .long("clip")
+                .short("c")
+        )
+        .arg(
+            Arg::with_name("clip-timeout")
+                .long("clip-timeout")
+                .takes_value(true)
+                .requires("clip")
+        )
+}
+
+fn config() -> App<'static, 'static> {
+    let key = || Arg::with_name("key").index(1).required(true);
+
+    SubCommand::with_name("config")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(SubCommand::with_name("get").arg(key()))
+        .subcommand(
+            SubCommand::with_name("set")
+                .arg(key())
+                .arg(
+                    Arg::with_name("value")
+                        .index(2)
+                        .required(true)
+                )
+        )
+        .subcommand(SubCommand::with_name("unset").arg(key()))
+        .subcommand(SubCommand::with_name("list").arg(Arg::with_name("origin").long("origin")))
+        .subcommand(SubCommand::with_name("edit"))
+}
+
+fn vault() -> App<'static, 'static> {
+    let name = || Arg::with_name("name").index(1).required(true);
+
+    SubCommand::with_name("vault")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(SubCommand::with_name("list"))
+        .subcommand(
+            SubCommand::with_name("create")
+                .arg(name())
+                .arg(
+                    Arg::with_name("path")
+                        .index(2)
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                )
+                .arg(Arg::with_name("default").long("default"))
+        )
+        .subcommand(SubCommand::with_name("remove").arg(name()))
+}
+
+fn init() -> App<'static, 'static> {
+    SubCommand::with_name("init")
+        .arg(Arg::with_name("password-stdin").long("password-stdin"))
+        .arg(
+            Arg::with_name("password-fd")
+                .long("password-fd")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("kdf-time")
+                .long("kdf-time")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("kdf-memory")
+                .long("kdf-memory")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("git").long("git"))
+}
+
+fn export() -> App<'static, 'static> {
+    SubCommand::with_name("export")
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("dir").index(1))
+        .arg(Arg::with_name("plaintext").long("plaintext"))
+        .arg(Arg::with_name("force").long("force"))
+        .arg(Arg::with_name("yes").long("yes"))
+        .args(&passphrase())
+        .arg(
+            Arg::with_name("kdf-time")
+                .long("kdf-time")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("kdf-memory")
+                .long("kdf-memory")
+                .takes_value(true)
+        )
+}
+
+fn import() -> App<'static, 'static> {
+    SubCommand::with_name("import")
+        .arg(
+            Arg::with_name("file")
+                .index(1)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("duplicates")
+                .long("duplicates")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("dry-run").long("dry-run"))
+        .args(&passphrase())
+}
+
+fn passphrase() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("passphrase-stdin").long("passphrase-stdin"),
+        Arg::with_name("passphrase-fd").long("passphrase-fd").takes_value(true)
+    ]
+}
+
+fn list() -> App<'static, 'static> {
+    SubCommand::with_name("list")
+        .arg(
+            Arg::with_name("entity")
+                .short("e")
+                .takes_value(true)
+        )
+}
+
+fn agent() -> App<'static, 'static> {
+    SubCommand::with_name("agent")
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("detach").long("detach"))
+        .arg(Arg::with_name("stop").long("stop"))
+}
+
+fn lock() -> App<'static, 'static> {
+    SubCommand::with_name("lock")
+        .arg(Arg::with_name("all").long("all"))
+}
+
+fn git_credential() -> App<'static, 'static> {
+    SubCommand::with_name("git-credential")
+        .arg(
+            Arg::with_name("operation")
+                .index(1)
+                .required(true)
+        )
+}
+
+fn ssh_agent() -> App<'static, 'static> {
+    SubCommand::with_name("ssh-agent")
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("confirm").long("confirm"))
+}
+
+fn run() -> App<'static, 'static> {
+    SubCommand::with_name("run")
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+        )
+        .arg(Arg::with_name("mask").long("mask"))
+        .arg(
+            Arg::with_name("command")
+                .multiple(true)
+                .required(true)
+                .last(true)
+        )
+}
+
+fn inject() -> App<'static, 'static> {
+    SubCommand::with_name("inject")
+        .arg(
+            Arg::with_name("in")
+                .long("in")
+                .short("i")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("check").long("check"))
+}
+
+fn native_messaging() -> App<'static, 'static> {
+    SubCommand::with_name("native-messaging")
+        .arg(
+            Arg::with_name("install")
+                .long("install")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("extension-id")
+                .long("extension-id")
+                .takes_value(true)
+        )
+        .arg(Arg::with_name("caller").multiple(true))
+}
+
+fn serve() -> App<'static, 'static> {
+    let client = || Arg::with_name("client").index(1).required(true);
+
+    SubCommand::with_name("serve")
+        .arg(Arg::with_name("listen").long("listen").takes_value(true))
+        .arg(Arg::with_name("socket").long("socket").takes_value(true))
+        .subcommand(
+            SubCommand::with_name("token")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(client())
+                        .arg(Arg::with_name("write").long("write"))
+                )
+                .subcommand(SubCommand::with_name("remove").arg(client()))
+        )
+}
+
+fn log() -> App<'static, 'static> {
+    SubCommand::with_name("log")
+        .arg(Arg::with_name("entity").short("e").takes_value(true))
+        .arg(Arg::with_name("account").short("a").takes_value(true))
+        .arg(Arg::with_name("since").long("since").takes_value(true))
+        .arg(Arg::with_name("until").long("until").takes_value(true))
+        .subcommand(SubCommand::with_name("verify"))
+}
+
+fn sync() -> App<'static, 'static> {
+    SubCommand::with_name("sync")
+        .arg(Arg::with_name("remote").long("remote").takes_value(true))
+}
+
+fn history() -> App<'static, 'static> {
+    SubCommand::with_name("history")
+        .arg(Arg::with_name("entity").short("e").takes_value(true).required(true))
+}
+
+fn merge() -> App<'static, 'static> {
+    SubCommand::with_name("merge")
+        .arg(Arg::with_name("other-locker").required(true))
+        .arg(Arg::with_name("newest").long("newest"))
+}
+
+fn settings() -> Vec<Arg<'static, 'static>> {
+    ["locker", "config", "algorithm", "vault"]
+        .iter()
+        .map(|name| Arg::with_name(name).long(name).takes_value(true).global(true))
+        .collect()
+}
@@ -1,5 +1,5 @@
 mod cli;
 mod commands;
 
-pub use cli::CLI;
+pub use cli::{CLI, overrides};
 pub use commands::{command, Commands};
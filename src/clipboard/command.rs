@@ -0,0 +1,91 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command as Process, Stdio};
+
+use crate::clipboard::Clipboard;
+
+/* Command struct */
+
+// Clipboard driven by external tools. On X11 and Wayland the selection
+// is owned by a process, so copying through `xclip`/`wl-copy` keeps the
+// value around after rk exits.
+#[derive(Debug)]
+pub struct Command {
+    copy: Vec<&'static str>,
+    paste: Vec<&'static str>,
+}
+
+impl Command {
+
+    /* Initialisers */
+
+    pub fn wayland() -> Command {
+        Command {
+            copy: vec!["wl-copy"],
+            paste: vec!["wl-paste", "--no-newline"]
+        }
+    }
+
+    pub fn xclip() -> Command {
+        Command {
+            copy: vec!["xclip", "-selection", "clipboard", "-in"],
+            paste: vec!["xclip", "-selection", "clipboard", "-out"]
+        }
+    }
+
+    /* Associated functions */
+
+    pub fn available(program: &str) -> bool {
+        let paths = match env::var_os("PATH") {
+            Some(paths) => paths,
+            None => return false
+        };
+
+        env::split_paths(&paths).any(|dir| dir.join(program).is_file())
+    }
+}
+
+impl Clipboard for Command {
+    fn get(&mut self) -> io::Result<String> {
+        let mut contents = String::new();
+        let mut child = Process::new(self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(stdout) = child.stdout.as_mut() {
+            stdout.read_to_string(&mut contents)?;
+        }
+
+        child.wait()?;
+
+        Ok(contents)
+    }
+
+    fn set(&mut self, contents: &str) -> io::Result<()> {
+        let mut child = Process::new(self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.write_all(contents.as_bytes())?;
+        }
+
+        drop(child.stdin.take());
+
+        let status = child.wait()?;
+
+        if !status.success() {
+            let msg = format!("`{}` exited with {}", self.copy[0], status);
+
+            return Err(io::Error::other(msg));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::clipboard::{Clipboard, clear_if_unchanged};
+
+/* Memory struct */
+
+// In-process stand-in for the system clipboard. Clones share the
+// same contents so a test can keep a handle on what the CLI copied.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    contents: Arc<Mutex<String>>
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, String>> {
+        self.contents
+            .lock()
+            .map_err(|_| io::Error::other("Memory clipboard poisoned"))
+    }
+}
+
+impl Clipboard for Memory {
+    fn get(&mut self) -> io::Result<String> {
+        Ok(self.lock()?.clone())
+    }
+
+    fn set(&mut self, contents: &str) -> io::Result<()> {
+        *self.lock()? = contents.to_string();
+
+        Ok(())
+    }
+
+    fn schedule_clear(&mut self, hash: String, timeout: Duration) -> io::Result<()> {
+        let mut clipboard = self.clone();
+
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            clear_if_unchanged(&mut clipboard, &hash).ok();
+        });
+
+        Ok(())
+    }
+}
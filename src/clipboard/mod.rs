@@ -0,0 +1,137 @@
+
+/* Dependencies */
+
+use std::env;
+use std::io::{self, Write};
+use std::time::Duration;
+use std::process::{Command as Process, Stdio};
+
+use crate::locker::Locker;
+
+mod command;
+mod memory;
+mod system;
+
+pub use command::Command;
+pub use memory::Memory;
+pub use system::System;
+
+/* Clipboard trait */
+
+pub trait Clipboard {
+    fn get(&mut self) -> io::Result<String>;
+
+    fn set(&mut self, contents: &str) -> io::Result<()>;
+
+    // Clearing is left to a detached `rk clip-clear` process so the
+    // timeout still fires after the current command has returned.
+    // Only the hash of the copied value is handed over, through stdin.
+    fn schedule_clear(&mut self, hash: String, timeout: Duration) -> io::Result<()> {
+        let exe = env::current_exe()?;
+        let mut child = Process::new(exe)
+            .arg("clip-clear")
+            .arg(timeout.as_secs().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.write_all(hash.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Functions */
+
+pub fn detect() -> io::Result<Box<dyn Clipboard>> {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = env::var_os("DISPLAY").is_some();
+
+    if wayland && Command::available("wl-copy") {
+        return Ok(Box::new(Command::wayland()));
+    }
+
+    if x11 && Command::available("xclip") {
+        return Ok(Box::new(Command::xclip()));
+    }
+
+    Ok(Box::new(System::new()?))
+}
+
+pub fn copy(clipboard: &mut dyn Clipboard, contents: &str, timeout: Duration) -> io::Result<()> {
+    clipboard.set(contents)?;
+
+    if timeout.as_secs() > 0 {
+        clipboard.schedule_clear(Locker::hash(contents), timeout)?;
+    }
+
+    Ok(())
+}
+
+pub fn clear_if_unchanged(clipboard: &mut dyn Clipboard, hash: &str) -> io::Result<bool> {
+    let contents = clipboard.get()?;
+
+    if Locker::hash(&contents) != hash {
+        return Ok(false);
+    }
+
+    clipboard.set("")?;
+
+    Ok(true)
+}
+
+/* Clipboard tests */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn copy_sets_contents() {
+        let mut clipboard = Memory::new();
+
+        copy(&mut clipboard, "secret", Duration::from_secs(0)).unwrap();
+
+        assert_eq!(clipboard.get().unwrap(), "secret");
+    }
+
+    #[test]
+    fn clear_if_unchanged_clears() {
+        let mut clipboard = Memory::new();
+        let hash = Locker::hash("secret");
+
+        clipboard.set("secret").unwrap();
+
+        assert!(clear_if_unchanged(&mut clipboard, &hash).unwrap());
+        assert_eq!(clipboard.get().unwrap(), "");
+    }
+
+    #[test]
+    fn clear_if_unchanged_keeps_other_contents() {
+        let mut clipboard = Memory::new();
+        let hash = Locker::hash("secret");
+
+        clipboard.set("copied afterwards").unwrap();
+
+        assert!(!clear_if_unchanged(&mut clipboard, &hash).unwrap());
+        assert_eq!(clipboard.get().unwrap(), "copied afterwards");
+    }
+
+    #[test]
+    fn copy_clears_after_timeout() {
+        let mut clipboard = Memory::new();
+
+        copy(&mut clipboard, "secret", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(clipboard.get().unwrap(), "secret");
+
+        thread::sleep(Duration::from_millis(1500));
+
+        assert_eq!(clipboard.get().unwrap(), "");
+    }
+}
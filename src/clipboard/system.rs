@@ -0,0 +1,38 @@
+use std::io;
+use std::error::Error;
+
+use copypasta::{ClipboardContext, ClipboardProvider};
+
+use crate::clipboard::Clipboard;
+
+/* System struct */
+
+pub struct System {
+    context: ClipboardContext
+}
+
+impl System {
+    pub fn new() -> io::Result<System> {
+        let context = ClipboardContext::new().map_err(to_io)?;
+
+        Ok(System { context })
+    }
+}
+
+impl Clipboard for System {
+    fn get(&mut self) -> io::Result<String> {
+        self.context
+            .get_contents()
+            .map_err(to_io)
+    }
+
+    fn set(&mut self, contents: &str) -> io::Result<()> {
+        self.context
+            .set_contents(contents.to_string())
+            .map_err(to_io)
+    }
+}
+
+fn to_io(err: Box<dyn Error>) -> io::Error {
+    io::Error::other(err.to_string())
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+
+use crate::{Args, Keeper, Resolve};
+use crate::settings::GitHost;
+use crate::vault::{VaultError, VaultResult};
+
+/* Credential struct definition */
+
+// What git hands a credential helper: `key=value` lines up to a blank one.
+// Only the attributes rk maps are kept, git ignores the ones it gets back
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credential {
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>
+}
+
+impl Credential {
+
+    /* Intialisers */
+
+    pub fn parse(input: &str) -> Credential {
+        let mut credential = Credential::default();
+
+        for line in input.lines() {
+            if line.is_empty() {
+                break;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => continue
+            };
+
+            match key {
+                "protocol" => credential.protocol = Some(value),
+                "host" => credential.host = Some(value),
+                "path" => credential.path = Some(value),
+                "username" => credential.username = Some(value),
+                "password" => credential.password = Some(value),
+                "url" => credential.merge_url(&value),
+                _ => {}
+            }
+        }
+
+        credential
+    }
+
+    /* Methods */
+
+    // `protocol://user@host/path`, the attributes given on their own win
+    fn merge_url(&mut self, url: &str) {
+        let (protocol, rest) = match url.split_once("://") {
+            Some((protocol, rest)) => (Some(protocol.to_string()), rest),
+            None => (None, url)
+        };
+
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, Some(path.to_string()).filter(|path| !path.is_empty())),
+            None => (rest, None)
+        };
+
+        let (username, host) = match authority.rsplit_once('@') {
+            Some((username, host)) => (Some(username.to_string()), host),
+            None => (None, authority)
+        };
+
+        self.protocol = self.protocol.take().or(protocol);
+        self.host = self.host.take().or_else(|| Some(host.to_string()).filter(|host| !host.is_empty()));
+        self.path = self.path.take().or(path);
+        self.username = self.username.take().or(username);
+    }
+
+    // What the credentials of a host are looked up by, in the vault and in
+    // the settings: the host for https, and `protocol://host` for anything
+    // else, so that a token kept for https never goes out over plain http
+    pub fn key(&self) -> VaultResult<String> {
+        let host = self.host.as_deref()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| VaultError::Error(String::from("Git sent no host")))?;
+
+        match self.protocol.as_deref() {
+            Some("https") => Ok(host.to_string()),
+            Some(protocol) if !protocol.is_empty() => Ok(format!("{}://{}", protocol, host)),
+            _ => Err(VaultError::Error(String::from("Git sent no protocol")))
+        }
+    }
+
+    // The entity a host's credentials are kept under, and the account
+    // when git says which user it wants or the mapping has a default
+    pub fn target(&self, mapping: Option<&GitHost>) -> VaultResult<(String, Option<String>)> {
+        let key = self.key()?;
+        let entity = mapping.map(|mapping| mapping.entity.clone()).unwrap_or(key);
+        let account = match (&self.username, mapping) {
+            (Some(username), Some(mapping)) => Some(mapping.usernames.get(username).cloned().unwrap_or_else(|| username.clone())),
+            (Some(username), None) => Some(username.clone()),
+            (None, Some(mapping)) => mapping.account.clone(),
+            (None, None) => None
+        };
+
+        Ok((entity, account))
+    }
+}
+
+// The answer to `get`, only what git asked for
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(username) = &self.username {
+            writeln!(f, "username={}", username)?;
+        }
+
+        if let Some(password) = &self.password {
+            writeln!(f, "password={}", password)?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Functions */
+
+// The stored credential, or `None` for git to ask the user. Without a
+// username the entity's only account is used, several ones are ambiguous
+pub fn get(keeper: &mut Keeper, request: &Credential, mapping: Option<&GitHost>) -> VaultResult<Option<Credential>> {
+    let (entity, account) = request.target(mapping)?;
+
+    let account = match account {
+        Some(account) => account,
+        None => match keeper.list(Some(Args::new(Some(&entity), None, None))) {
+            Ok(Resolve::List(accounts)) if accounts.len() == 1 => accounts[0].clone(),
+            _ => return Ok(None)
+        }
+    };
+
+    let password = match keeper.read(Args::new(Some(&entity), Some(&account), None)) {
+        Ok(Resolve::Read(password)) if !password.is_empty() => password,
+        _ => return Ok(None)
+    };
+
+    // Git gets back the username it knows, not the account it maps to
+    let username = request.username.clone().unwrap_or_else(|| {
+        mapping
+            .and_then(|mapping| mapping.usernames.iter().find(|(_, mapped)| **mapped == account))
+            .map(|(username, _)| username.clone())
+            .unwrap_or(account)
+    });
+
+    Ok(Some(Credential { username: Some(username), password: Some(password), ..request.clone() }))
+}
+
+// Called by git once a credential worked, replaces the stored password
+pub fn store(keeper: &mut Keeper, request: &Credential, mapping: Option<&GitHost>) -> VaultResult<()> {
+    let (entity, account) = request.target(mapping)?;
+
+    match (account, &request.password) {
+        (Some(account), Some(password)) => {
+            keeper.add(Args::new(Some(&entity), Some(&account), Some(password)))?;
+
+            Ok(())
+        },
+        _ => Ok(())
+    }
+}
+
+// Called by git once a credential was rejected. Only the account still
+// holding that password is removed, not one that was updated meanwhile
+pub fn erase(keeper: &mut Keeper, request: &Credential, mapping: Option<&GitHost>) -> VaultResult<()> {
+    let (entity, account) = request.target(mapping)?;
+    let account = match account {
+        Some(account) => account,
+        None => return Ok(())
+    };
+
+    let stored = match keeper.read(Args::new(Some(&entity), Some(&account), None)) {
+        Ok(Resolve::Read(password)) => password,
+        _ => return Ok(())
+    };
+
+    if request.password.as_ref().map(|password| *password == stored).unwrap_or(true) {
+        keeper.remove(Args::new(Some(&entity), Some(&account), None))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use crate::Kdf;
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `credential.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn forge() -> GitHost {
+        let mut usernames = BTreeMap::new();
+
+        usernames.insert(String::from("alice"), String::from("alice@corp.com"));
+
+        GitHost { entity: String::from("forge"), account: None, usernames }
+    }
+
+    #[test]
+    fn parse_attributes() {
+        let credential = Credential::parse("protocol=https\nhost=forge.internal:8443\nusername=alice\n\npassword=ignored\n");
+        let url = Credential::parse("url=https://bob@github.com/rk/rk.git\nhost=gitlab.com\n");
+
+        assert_eq!(credential.host.as_deref(), Some("forge.internal:8443"));
+        assert_eq!(credential.username.as_deref(), Some("alice"));
+        assert_eq!(credential.password, None);
+        assert_eq!(url.protocol.as_deref(), Some("https"));
+        assert_eq!(url.host.as_deref(), Some("gitlab.com"));
+        assert_eq!(url.path.as_deref(), Some("rk/rk.git"));
+        assert_eq!(url.username.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn target_mapping() {
+        let alice = Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\n");
+        let bob = Credential::parse("protocol=https\nhost=forge.internal\nusername=bob\n");
+        let nobody = Credential::parse("protocol=https\nhost=github.com\n");
+        let plain = Credential::parse("protocol=http\nhost=github.com\n");
+
+        assert_eq!(alice.target(Some(&forge())).unwrap(), (String::from("forge"), Some(String::from("alice@corp.com"))));
+        assert_eq!(bob.target(Some(&forge())).unwrap(), (String::from("forge"), Some(String::from("bob"))));
+        assert_eq!(alice.target(None).unwrap(), (String::from("forge.internal"), Some(String::from("alice"))));
+        assert_eq!(nobody.target(None).unwrap(), (String::from("github.com"), None));
+        assert_eq!(plain.target(None).unwrap(), (String::from("http://github.com"), None));
+        assert!(Credential::parse("protocol=https\n").target(None).is_err());
+        assert!(Credential::parse("host=github.com\n").target(None).is_err());
+    }
+
+    #[test]
+    fn store_get_erase() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config, locker, "master", Kdf::new(8, 1, 1)).unwrap();
+                let mapping = forge();
+
+                let missing = get(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\n"), Some(&mapping)).unwrap();
+
+                store(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\npassword=token\n"), Some(&mapping)).unwrap();
+
+                let only = get(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\n"), Some(&mapping)).unwrap().unwrap();
+                let named = get(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\n"), Some(&mapping)).unwrap().unwrap();
+                let plain = get(&mut keeper, &Credential::parse("protocol=http\nhost=forge.internal\nusername=alice\n"), None).unwrap();
+
+                erase(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\npassword=stale\n"), Some(&mapping)).unwrap();
+                let kept = get(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\n"), Some(&mapping)).unwrap();
+
+                erase(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\npassword=token\n"), Some(&mapping)).unwrap();
+                let erased = get(&mut keeper, &Credential::parse("protocol=https\nhost=forge.internal\nusername=alice\n"), Some(&mapping)).unwrap();
+
+                assert_eq!(missing, None);
+                assert_eq!(only.to_string(), "username=alice\npassword=token\n");
+                assert_eq!(named.password.as_deref(), Some("token"));
+                assert_eq!(plain, None);
+                assert!(kept.is_some());
+                assert_eq!(erased, None);
+            }
+        };
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use crate::locker::{Locker, Encrypted};
+use crate::vault::{VaultError, VaultResult};
+
+/* Custom types */
+
+pub type Fields = BTreeMap<String, String>;
+
+/* Well known fields */
+
+pub const URL: &str = "url";
+pub const TAGS: &str = "tags";
+pub const NOTES: &str = "notes";
+
+/* Functions */
+
+pub fn seal(fields: &Fields) -> VaultResult<Encrypted> {
+    if fields.is_empty() {
+        return Ok(Encrypted::empty());
+    }
+
+    let yaml = serde_yaml::to_string(fields)
+        .map_err(|err| VaultError::Error(err.to_string()))?;
+
+    Ok(Locker::new().encrypt(&yaml))
+}
+
+pub fn open(encrypted: &Encrypted) -> VaultResult<Fields> {
+    if encrypted.is_empty() {
+        return Ok(Fields::new());
+    }
+
+    let yaml = Locker::from_encrypted(encrypted).decrypt();
+
+    serde_yaml::from_str(&yaml)
+        .map_err(|err| VaultError::Error(format!("Malformed account fields: {}", err)))
+}
+
+pub fn tags(fields: &Fields) -> Vec<String> {
+    fields
+        .get(TAGS)
+        .map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// `url`, and the `url2`, `url3`... of accounts used on several sites
+pub fn is_url(key: &str) -> bool {
+    key.strip_prefix(URL)
+        .map(|rest| rest.is_empty() || rest.parse::<usize>().is_ok())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), "https://mail.google.com".to_string());
+        fields.insert(TAGS.to_string(), "mail,personal".to_string());
+
+        let sealed = seal(&fields).unwrap();
+
+        assert_eq!(open(&sealed).unwrap(), fields);
+    }
+
+    #[test]
+    fn seal_empty() {
+        let sealed = seal(&Fields::new()).unwrap();
+
+        assert!(sealed.is_empty());
+        assert_eq!(open(&sealed).unwrap(), Fields::new());
+    }
+
+    #[test]
+    fn tags_split() {
+        let mut fields = Fields::new();
+
+        fields.insert(TAGS.to_string(), "mail, personal,,work".to_string());
+
+        assert_eq!(tags(&fields), vec!["mail", "personal", "work"]);
+    }
+}
@@ -0,0 +1,457 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::locker::{Encrypted, Seal};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// Placed in empty entity and account directories, which git doesn't keep
+pub const KEEP: &str = ".keep";
+
+const REMOTE: &str = "origin";
+
+/* Change struct definition */
+
+// A commit touching an entity, with the accounts it touched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub commit: String,
+    pub time: u64,
+    pub message: String,
+    pub accounts: Vec<String>
+}
+
+/* Synced struct definition */
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Synced {
+    pub pulled: usize,
+    pub pushed: usize
+}
+
+/* Git struct definition */
+
+// A locker kept in a git repository, through the local git binary. Commit
+// messages only say what kind of change was made, entries stay encrypted
+#[derive(Clone, Debug)]
+pub struct Git {
+    dir: PathBuf
+}
+
+impl Git {
+
+    /* Intialisers */
+
+    // `None` when the locker isn't a repository
+    pub fn open(dir: &Path) -> Option<Git> {
+        if !dir.join(".git").exists() {
+            return None;
+        }
+
+        Some(Git { dir: dir.to_path_buf() })
+    }
+
+    // Turns an existing locker into a repository, committing what it holds
+    pub fn init(dir: &Path) -> VaultResult<Git> {
+        if let Some(git) = Git::open(dir) {
+            return Ok(git);
+        }
+
+        let git = Git { dir: dir.to_path_buf() };
+
+        git.run(&["init", "-q"])?;
+        git.identify()?;
+        git.commit("Start tracking the vault")?;
+
+        Ok(git)
+    }
+
+    pub fn clone(url: &str, dir: &Path) -> VaultResult<Git> {
+        let output = Command::new("git")
+            .args(["clone", "-q", "-o", REMOTE, url])
+            .arg(dir)
+            .output()
+            .map_err(|err| VaultError::Error(format!("Could not run git: {}", err)))?;
+
+        if !output.status.success() {
+            let msg = format!("Could not clone {}: {}", url, String::from_utf8_lossy(&output.stderr).trim());
+
+            return Err(VaultError::Error(msg));
+        }
+
+        let git = Git { dir: dir.to_path_buf() };
+
+        git.identify()?;
+
+        Ok(git)
+    }
+
+    /* Methods */
+
+    // Nothing is committed when nothing changed
+    pub fn commit(&self, message: &str) -> VaultResult<()> {
+        self.keep_empty_dirs()?;
+        self.run(&["add", "-A"])?;
+
+        if self.succeeds(&["diff", "--cached", "--quiet"])? {
+            return Ok(());
+        }
+
+        self.run(&["commit", "-q", "--no-verify", "-m", message])?;
+
+        Ok(())
+    }
+
+    // Pulls then pushes the current branch. A merge git can't make on its
+    // own, or one leaving an entry twice, is undone
+    pub fn sync(&self, remote: Option<&str>) -> VaultResult<Synced> {
+        if let Some(url) = remote {
+            match self.succeeds(&["remote", "get-url", REMOTE])? {
+                true => self.run(&["remote", "set-url", REMOTE, url])?,
+                false => self.run(&["remote", "add", REMOTE, url])?
+            };
+        }
+
+        if !self.succeeds(&["remote", "get-url", REMOTE])? {
+            return Err(VaultError::Error(String::from("No remote to sync with, set one with `rk sync --remote <url>`")));
+        }
+
+        let branch = self.run(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+        let upstream = format!("{}/{}", REMOTE, branch);
+        let mut synced = Synced::default();
+
+        self.run(&["fetch", "-q", REMOTE])?;
+
+        if self.succeeds(&["rev-parse", "--verify", "-q", &upstream])? {
+            synced.pulled = self.count(&format!("HEAD..{}", upstream))?;
+
+            if synced.pulled > 0 {
+                let merged = self.succeeds(&["merge", "-q", "--no-edit", &upstream])?;
+
+                if !merged || !self.consistent()? {
+                    match merged {
+                        true => self.run(&["reset", "-q", "--hard", "ORIG_HEAD"])?,
+                        false => self.run(&["merge", "--abort"])?
+                    };
+
+                    return Err(VaultError::Error(String::from("Both sides changed the same entries, merge them with `rk merge`")));
+                }
+            }
+
+            synced.pushed = self.count(&format!("{}..HEAD", upstream))?;
+        } else {
+            synced.pushed = self.count("HEAD")?;
+        }
+
+        if synced.pushed > 0 {
+            self.run(&["push", "-q", "-u", REMOTE, &branch])?;
+        }
+
+        Ok(synced)
+    }
+
+    // Newest first. Entity directories end with the hash of the name, so
+    // changes are found even once the entity is renamed away or removed
+    pub fn history(&self, entity: &str, seal: &Seal) -> VaultResult<Vec<Change>> {
+        let hash = seal.hash(entity);
+        let pathspec = format!(":(glob)*${}/**", hash);
+        let log = self.run(&["log", "--no-renames", "--format=%x1e%h%x1f%at%x1f%s", "--name-only", "--", &pathspec])?;
+
+        let changes = log.split('\u{1e}')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let mut lines = entry.lines();
+                let mut header = lines.next()?.split('\u{1f}');
+                let commit = header.next()?.to_string();
+                let time = header.next()?.parse().ok()?;
+                let message = header.next().unwrap_or("").to_string();
+                let mut accounts: Vec<String> = lines
+                    .filter_map(|path| path.split('/').nth(1))
+                    .filter_map(|account| Encrypted::from(account).ok())
+                    .filter_map(|account| seal.decrypt(&account))
+                    .collect();
+
+                accounts.sort();
+                accounts.dedup();
+
+                Some(Change { commit, time, message, accounts })
+            })
+            .collect();
+
+        Ok(changes)
+    }
+
+    // Commits that still need setting up a name get a local one
+    fn identify(&self) -> VaultResult<()> {
+        if !self.succeeds(&["config", "user.email"])? {
+            self.run(&["config", "user.name", "rk"])?;
+            self.run(&["config", "user.email", "rk@localhost"])?;
+        }
+
+        Ok(())
+    }
+
+    fn keep_empty_dirs(&self) -> VaultResult<()> {
+        for entity in subdirs(&self.dir)? {
+            keep(&entity)?;
+
+            for account in subdirs(&entity)? {
+                keep(&account)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Each entity and account once, and at most one password per account
+    fn consistent(&self) -> VaultResult<bool> {
+        if !unique(&self.dir)? {
+            return Ok(false);
+        }
+
+        for entity in subdirs(&self.dir)? {
+            if !unique(&entity)? {
+                return Ok(false);
+            }
+
+            for account in subdirs(&entity)? {
+                let passwords = fs::read_dir(&account)?
+                    .filter_map(|file| file.ok())
+                    .filter(|file| !is_dotfile(&file.path()))
+                    .count();
+
+                if passwords > 1 {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn count(&self, range: &str) -> VaultResult<usize> {
+        self.run(&["rev-list", "--count", range])?
+            .parse()
+            .map_err(|_| VaultError::Error(String::from("Unexpected output from git rev-list")))
+    }
+
+    fn run(&self, args: &[&str]) -> VaultResult<String> {
+        let output = self.git(args)?;
+
+        if !output.status.success() {
+            let msg = format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+
+            return Err(VaultError::Error(msg));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn succeeds(&self, args: &[&str]) -> VaultResult<bool> {
+        Ok(self.git(args)?.status.success())
+    }
+
+    fn git(&self, args: &[&str]) -> VaultResult<std::process::Output> {
+        Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()
+            .map_err(|err| VaultError::Error(format!("Could not run git: {}", err)))
+    }
+}
+
+/* Functions */
+
+fn is_dotfile(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with('.'))
+        .unwrap_or(false)
+}
+
+// The entity or account directories in `dir`
+fn subdirs(dir: &Path) -> VaultResult<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() && !is_dotfile(&path) {
+            dirs.push(path);
+        }
+    }
+
+    Ok(dirs)
+}
+
+// Directory names end with the hash of the name they hold
+fn unique(dir: &Path) -> VaultResult<bool> {
+    let mut hashes: Vec<String> = subdirs(dir)?
+        .iter()
+        .filter_map(|path| path.file_name()?.to_str()?.rsplit('$').next().map(String::from))
+        .collect();
+
+    let count = hashes.len();
+
+    hashes.sort();
+    hashes.dedup();
+
+    Ok(hashes.len() == count)
+}
+
+// Adds the placeholder to an empty directory, drops it once there is more
+fn keep(dir: &Path) -> VaultResult<()> {
+    let placeholder = dir.join(KEEP);
+    let others = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name() != KEEP);
+
+    match (others, placeholder.exists()) {
+        (false, false) => fs::write(&placeholder, "")?,
+        (true, true) => fs::remove_file(&placeholder)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::{Args, Kdf, Keeper, Resolve};
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `git.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    // A locker kept in git from the start, reopened so the keeper commits
+    fn tracked(config: PathBuf, locker: PathBuf) -> Keeper {
+        Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+        Git::init(&locker).unwrap();
+        Keeper::open(config, locker, "master").unwrap()
+    }
+
+    fn messages(git: &Git) -> Vec<String> {
+        git.run(&["log", "--format=%s"]).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn commit_changes() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = tracked(config, locker.clone());
+                let git = Git::open(&locker).unwrap();
+
+                keeper.add(Args::new(Some("github"), None, None)).unwrap();
+                keeper.add(Args::new(Some("gitlab"), Some("me"), Some("secret"))).unwrap();
+                keeper.remove(Args::new(Some("gitlab"), Some("me"), None)).unwrap();
+
+                let files = git.run(&["ls-files"]).unwrap();
+
+                assert_eq!(messages(&git), vec![
+                    "Remove an account",
+                    "Save the password of an account",
+                    "Add an entity",
+                    "Start tracking the vault"
+                ]);
+                assert!(!files.contains("github") && !files.contains("secret"));
+                assert_eq!(files.matches(KEEP).count(), 2);
+                assert!(Git::open(&locker.join("missing")).is_none());
+            }
+        };
+    }
+
+    #[test]
+    fn sync_clones() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let (other_config, other_locker) = this.as_path_buf();
+                let (_, remote) = this.as_path_buf();
+                let url = remote.to_str().unwrap();
+
+                Command::new("git").args(["init", "-q", "--bare"]).arg(&remote).output().unwrap();
+
+                let mut keeper = tracked(config.clone(), locker.clone());
+                let git = Git::open(&locker).unwrap();
+
+                keeper.add(Args::new(Some("github"), Some("me"), Some("secret"))).unwrap();
+
+                let first = git.sync(Some(url)).unwrap();
+                let other = Git::clone(url, &other_locker).unwrap();
+                let mut other_keeper = Keeper::open(other_config, other_locker.clone(), "master").unwrap();
+                let cloned = other_keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+
+                other_keeper.add(Args::new(Some("github"), Some("work"), Some("secret"))).unwrap();
+
+                let pushed = other.sync(None).unwrap();
+                let pulled = git.sync(None).unwrap();
+                let mut keeper = Keeper::open(config.clone(), locker.clone(), "master").unwrap();
+                let work = keeper.read(Args::new(Some("github"), Some("work"), None)).unwrap();
+
+                keeper.add(Args::new(Some("github"), Some("me"), Some("mine"))).unwrap();
+                other_keeper.add(Args::new(Some("github"), Some("me"), Some("theirs"))).unwrap();
+                other.sync(None).unwrap();
+
+                let conflict = git.sync(None);
+                let mut keeper = Keeper::open(config, locker, "master").unwrap();
+                let kept = keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+
+                assert_eq!(first, Synced { pulled: 0, pushed: 2 });
+                assert_eq!(cloned, Resolve::Read(String::from("secret")));
+                assert_eq!(pushed, Synced { pulled: 0, pushed: 1 });
+                assert_eq!(pulled, Synced { pulled: 1, pushed: 0 });
+                assert_eq!(work, Resolve::Read(String::from("secret")));
+                assert!(conflict.is_err());
+                assert_eq!(kept, Resolve::Read(String::from("mine")));
+            }
+        };
+    }
+
+    #[test]
+    fn entity_history() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = tracked(config, locker.clone());
+                let git = Git::open(&locker).unwrap();
+
+                keeper.add(Args::new(Some("github"), Some("me"), Some("secret"))).unwrap();
+                keeper.add(Args::new(Some("gitlab"), Some("me"), Some("secret"))).unwrap();
+                keeper.add(Args::new(Some("github"), Some("work"), None)).unwrap();
+                keeper.remove(Args::new(Some("github"), None, None)).unwrap();
+
+                let history: Vec<(String, String)> = git.history("github", keeper.seal().unwrap()).unwrap()
+                    .into_iter()
+                    .map(|change| (change.message, change.accounts.join(" ")))
+                    .collect();
+
+                assert_eq!(history, vec![
+                    (String::from("Remove an entity"), String::from("me work")),
+                    (String::from("Add an account"), String::from("work")),
+                    (String::from("Save the password of an account"), String::from("me"))
+                ]);
+                assert!(git.history("bitbucket", keeper.seal().unwrap()).unwrap().is_empty());
+            }
+        };
+    }
+}
@@ -0,0 +1,435 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use crypto_hash::{hex_digest, Algorithm as Hash};
+use rand::{Rng, OsRng};
+use serde::{Serialize, Deserialize};
+
+use crate::locker::Locker;
+use crate::settings::ALGORITHMS;
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// Written at the root of the locker, `Vault` skips dotfiles
+pub const HEADER: &str = ".header";
+
+// Version 1 checked the key against a plain hash of it, and left the
+// entries of the locker unsealed
+pub const VERSION: u32 = 2;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+// Calibration keeps memory fixed and only scales the iterations
+const PARALLELISM: u32 = 1;
+const MIN_ITERATIONS: u32 = 2;
+const MAX_ITERATIONS: u32 = 32;
+
+/* Kdf struct definition */
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Kdf {
+    pub name: String,
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub salt: String
+}
+
+impl Kdf {
+
+    /* Intialisers */
+
+    pub fn new(memory: u32, iterations: u32, parallelism: u32) -> Kdf {
+        let mut salt = [0u8; SALT_LEN];
+        let mut rng = OsRng::new().expect("Could not open the OS random source");
+
+        rng.fill_bytes(&mut salt);
+
+        Kdf {
+            name: String::from("argon2id"),
+            memory,
+            iterations,
+            parallelism,
+            salt: hex::encode(salt)
+        }
+    }
+
+    // Times a single pass on this machine and picks enough
+    // iterations for one derivation to take about `target`
+    pub fn calibrate(target: Duration, memory: u32) -> VaultResult<Kdf> {
+        let mut kdf = Kdf::new(memory, 1, PARALLELISM);
+        let started = Instant::now();
+
+        kdf.derive("calibration")?;
+
+        let pass = started.elapsed().as_millis().max(1);
+        let iterations = (target.as_millis() / pass) as u32;
+
+        kdf.iterations = iterations.clamp(MIN_ITERATIONS, MAX_ITERATIONS);
+
+        Ok(kdf)
+    }
+
+    /* Methods */
+
+    pub fn derive(&self, password: &str) -> VaultResult<Vec<u8>> {
+        let invalid = |err: String| VaultError::Error(format!("Invalid key derivation parameters: {}", err));
+
+        if self.name != "argon2id" {
+            return Err(invalid(format!("unsupported function {:?}", self.name)));
+        }
+
+        let salt = hex::decode(&self.salt).map_err(|err| invalid(err.to_string()))?;
+        let params = Params::new(self.memory, self.iterations, self.parallelism, Some(KEY_LEN))
+            .map_err(|err| invalid(err.to_string()))?;
+
+        let mut key = vec![0u8; KEY_LEN];
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|err| invalid(err.to_string()))?;
+
+        Ok(key)
+    }
+}
+
+/* Header struct definition */
+
+// `audits` names the audit logs kept of the vault, one per machine it is
+// used on. The check covers them, so none can be dropped without the key
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub algorithm: String,
+    pub kdf: Kdf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audits: Vec<String>,
+    check: String
+}
+
+impl Header {
+
+    /* Intialisers */
+
+    pub fn new(password: &str, kdf: Kdf) -> VaultResult<Header> {
+        let key = kdf.derive(password)?;
+
+        Ok(Header::for_key(&key, kdf))
+    }
+
+    // For a `key` already derived with `kdf`
+    pub fn for_key(key: &[u8], kdf: Kdf) -> Header {
+        Header {
+            version: VERSION,
+            algorithm: ALGORITHMS[0].to_string(),
+            kdf,
+            audits: Vec::new(),
+            check: verifier(key, &[])
+        }
+    }
+
+    pub fn read(locker: &Path) -> VaultResult<Option<Header>> {
+        let path = Header::path(locker);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let yaml = fs::read_to_string(&path)?;
+        let header = serde_yaml::from_str(&yaml)
+            .map_err(|err| format!("Invalid vault header {:?}: {}", path, err))?;
+
+        Ok(Some(header))
+    }
+
+    /* Methods */
+
+    pub fn path(locker: &Path) -> PathBuf {
+        locker.join(HEADER)
+    }
+
+    // Derives the master key, failing when the password is wrong
+    pub fn unlock(&self, password: &str) -> VaultResult<Vec<u8>> {
+        let key = self.kdf.derive(password)?;
+
+        if !self.fits(&key) {
+            return Err(VaultError::Error(String::from("Wrong master password")));
+        }
+
+        Ok(key)
+    }
+
+    // Whether `key` is the master key, eg. the one an agent keeps
+    pub fn fits(&self, key: &[u8]) -> bool {
+        let check = match self.version {
+            1 => hex_digest(Hash::SHA256, key),
+            _ => verifier(key, &self.audits)
+        };
+
+        check == self.check
+    }
+
+    // Brings the header of a locker sealed with `key` to this version
+    pub fn upgrade(&mut self, key: &[u8]) {
+        self.version = VERSION;
+        self.check = verifier(key, &self.audits);
+    }
+
+    // From now on the vault keeps the audit log `id`
+    pub fn keep_audit(&mut self, key: &[u8], id: &str) {
+        self.audits.push(id.to_string());
+        self.check = verifier(key, &self.audits);
+    }
+
+    pub fn write(&self, locker: &Path) -> VaultResult<()> {
+        let path = Header::path(locker);
+        let yaml = serde_yaml::to_string(self)
+            .map_err(|err| VaultError::Error(err.to_string()))?;
+
+        fs::write(&path, yaml)?;
+        restrict(&path, 0o600)?;
+
+        Ok(())
+    }
+}
+
+/* Functions */
+
+// Keyed, so the check gives nothing away about the key itself
+fn verifier(key: &[u8], audits: &[String]) -> String {
+    let mut parts: Vec<&[u8]> = vec![b"rk check"];
+
+    for id in audits {
+        parts.push(b" ");
+        parts.push(id.as_bytes());
+    }
+
+    hex::encode(Locker::hmac(key, &parts))
+}
+
+// Creates `dir` if needed and leaves it readable by the user only
+pub fn private_dir(dir: &Path) -> VaultResult<()> {
+    fs::create_dir_all(dir)?;
+    restrict(dir, 0o700)
+}
+
+// For directories rk doesn't own, eg. the one holding a KDBX database or
+// a socket the user named. A missing one is created for the user only, an
+// existing one keeps its mode and is only checked
+pub fn shared_dir(dir: &Path) -> VaultResult<()> {
+    if !dir.exists() {
+        return private_dir(dir);
+    }
+
+    if writable_by_others(dir)? {
+        eprintln!("rk: warning: other users can write to {:?}", dir);
+    }
+
+    Ok(())
+}
+
+// Directories with the sticky bit, eg. `/tmp`, don't let others replace
+// what is in them
+#[cfg(unix)]
+pub(crate) fn writable_by_others(dir: &Path) -> VaultResult<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(dir)?.permissions().mode();
+
+    Ok(mode & 0o022 != 0 && mode & 0o1000 == 0)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn writable_by_others(_dir: &Path) -> VaultResult<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+pub(crate) fn restrict(path: &Path, mode: u32) -> VaultResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn restrict(_path: &Path, _mode: u32) -> VaultResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `header.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn fast() -> Kdf {
+        Kdf::new(8, 1, 1)
+    }
+
+    #[test]
+    fn derive() {
+        let kdf = fast();
+        let other = fast();
+
+        let key = kdf.derive("master").unwrap();
+
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(key, kdf.derive("master").unwrap());
+        assert_ne!(key, kdf.derive("other").unwrap());
+        assert_ne!(key, other.derive("master").unwrap());
+    }
+
+    #[test]
+    fn calibrate() {
+        let kdf = Kdf::calibrate(Duration::from_millis(1), 8).unwrap();
+
+        assert_eq!(kdf.memory, 8);
+        assert_eq!(kdf.iterations, MIN_ITERATIONS);
+    }
+
+    #[test]
+    fn derive_invalid() {
+        let mut kdf = fast();
+
+        kdf.name = String::from("scrypt");
+
+        assert!(kdf.derive("master").is_err());
+    }
+
+    #[test]
+    fn unlock() {
+        let header = Header::new("master", fast()).unwrap();
+
+        assert_eq!(header.unlock("master").unwrap(), header.kdf.derive("master").unwrap());
+        assert!(header.unlock("wrong").is_err());
+    }
+
+    #[test]
+    fn upgrade() {
+        let mut header = Header::new("master", fast()).unwrap();
+        let key = header.kdf.derive("master").unwrap();
+
+        header.version = 1;
+        header.check = hex_digest(Hash::SHA256, &key);
+
+        assert!(header.fits(&key));
+        assert!(!header.fits(&[0; KEY_LEN]));
+
+        header.upgrade(&key);
+
+        assert_eq!(header, Header::new("master", header.kdf.clone()).unwrap());
+        assert!(header.fits(&key));
+    }
+
+    #[test]
+    fn keep_audit() {
+        let mut header = Header::new("master", fast()).unwrap();
+        let key = header.kdf.derive("master").unwrap();
+
+        header.keep_audit(&key, "laptop");
+        header.keep_audit(&key, "desktop");
+
+        let mut dropped = header.clone();
+
+        dropped.audits.pop();
+
+        assert!(header.fits(&key));
+        assert!(!dropped.fits(&key));
+        assert_eq!(header.audits, vec!["laptop", "desktop"]);
+        assert!(!serde_yaml::to_string(&Header::new("master", fast()).unwrap()).unwrap().contains("audits"));
+    }
+
+    #[test]
+    fn write_and_read() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (_, locker) = this.as_path_buf();
+                let header = Header::new("master", fast()).unwrap();
+
+                assert_eq!(Header::read(&locker).unwrap(), None);
+
+                private_dir(&locker).unwrap();
+                header.write(&locker).unwrap();
+
+                assert_eq!(Header::read(&locker).unwrap(), Some(header));
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (_, locker) = this.as_path_buf();
+                let header = Header::new("master", fast()).unwrap();
+
+                private_dir(&locker).unwrap();
+                header.write(&locker).unwrap();
+
+                let dir = fs::metadata(&locker).unwrap().permissions().mode();
+                let file = fs::metadata(Header::path(&locker)).unwrap().permissions().mode();
+
+                assert_eq!(dir & 0o777, 0o700);
+                assert_eq!(file & 0o777, 0o600);
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (existing, missing) = this.as_path_buf();
+
+                fs::create_dir_all(&existing).unwrap();
+                fs::set_permissions(&existing, fs::Permissions::from_mode(0o755)).unwrap();
+
+                shared_dir(&existing).unwrap();
+                shared_dir(&missing.join("nested")).unwrap();
+
+                let kept = fs::metadata(&existing).unwrap().permissions().mode();
+                let created = fs::metadata(missing.join("nested")).unwrap().permissions().mode();
+
+                assert_eq!(kept & 0o777, 0o755);
+                assert_eq!(created & 0o777, 0o700);
+                assert!(!writable_by_others(&existing).unwrap());
+
+                fs::set_permissions(&existing, fs::Permissions::from_mode(0o1777)).unwrap();
+
+                assert!(!writable_by_others(&existing).unwrap());
+
+                fs::set_permissions(&existing, fs::Permissions::from_mode(0o777)).unwrap();
+
+                assert!(writable_by_others(&existing).unwrap());
+            }
+        };
+    }
+}
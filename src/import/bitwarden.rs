@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::archive::Item;
+use crate::vault::{VaultError, VaultResult};
+
+use super::{item, fields};
+
+/* Export structure, only what is mapped */
+
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Entry>
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    name: String,
+    #[serde(default)]
+    folder_id: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    fields: Vec<Field>,
+    #[serde(default)]
+    login: Option<Login>
+}
+
+#[derive(Deserialize)]
+struct Field {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    value: Option<String>
+}
+
+#[derive(Deserialize, Default)]
+struct Login {
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    totp: Option<String>,
+    #[serde(default)]
+    uris: Vec<Uri>
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    #[serde(default)]
+    uri: Option<String>
+}
+
+/* Functions */
+
+// Unencrypted `bitwarden_export_*.json`, every item type becomes an
+// account, the ones without a login only carry their notes and fields
+pub fn parse(json: &str) -> VaultResult<Vec<Item>> {
+    let export: Export = serde_json::from_str(json)
+        .map_err(|err| VaultError::Error(format!("Invalid Bitwarden export: {}", err)))?;
+
+    if export.encrypted {
+        return Err(VaultError::Error(String::from("Encrypted Bitwarden exports are not supported, export as unencrypted json")));
+    }
+
+    let folders: HashMap<String, String> = export.folders
+        .into_iter()
+        .map(|folder| (folder.id, folder.name))
+        .collect();
+
+    let items = export.items
+        .into_iter()
+        .map(|entry| {
+            let login = entry.login.unwrap_or_default();
+            let uris: Vec<String> = login.uris.into_iter().filter_map(|uri| uri.uri).collect();
+            let tags: Vec<String> = entry.folder_id
+                .and_then(|id| folders.get(&id).cloned())
+                .into_iter()
+                .collect();
+
+            let mut extra = fields(
+                uris.first().map(|uri| uri.as_str()).unwrap_or(""),
+                entry.notes.as_deref().unwrap_or(""),
+                &tags
+            );
+
+            for (index, uri) in uris.iter().enumerate().skip(1) {
+                extra.insert(format!("url{}", index + 1), uri.clone());
+            }
+
+            if let Some(totp) = login.totp {
+                extra.insert(String::from("totp"), totp);
+            }
+
+            for field in entry.fields {
+                if let (Some(name), Some(value)) = (field.name, field.value) {
+                    extra.insert(name, value);
+                }
+            }
+
+            item(
+                &entry.name,
+                login.username.as_deref().unwrap_or(""),
+                login.password.as_deref().unwrap_or(""),
+                extra
+            )
+        })
+        .collect();
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fields::{URL, NOTES, TAGS};
+    use crate::import::NO_ACCOUNT;
+
+    const EXPORT: &str = r#"{
+        "encrypted": false,
+        "folders": [{ "id": "f1", "name": "Work" }],
+        "items": [
+            {
+                "id": "1",
+                "folderId": "f1",
+                "type": 1,
+                "name": "GitHub",
+                "notes": "2fa on phone",
+                "fields": [{ "name": "pin", "value": "1234", "type": 1 }],
+                "login": {
+                    "uris": [{ "match": null, "uri": "https://github.com" }, { "uri": "https://gist.github.com" }],
+                    "username": "octocat",
+                    "password": "hunter2",
+                    "totp": null
+                }
+            },
+            {
+                "id": "2",
+                "folderId": null,
+                "type": 2,
+                "name": "Wifi",
+                "notes": "the password is on the router",
+                "secureNote": { "type": 0 }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_export() {
+        let items = parse(EXPORT).unwrap();
+        let github = &items[0];
+        let wifi = &items[1];
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(github.entity, "GitHub");
+        assert_eq!(github.account, Some(String::from("octocat")));
+        assert_eq!(github.password, Some(String::from("hunter2")));
+        assert_eq!(github.fields[URL], "https://github.com");
+        assert_eq!(github.fields["url2"], "https://gist.github.com");
+        assert_eq!(github.fields[NOTES], "2fa on phone");
+        assert_eq!(github.fields[TAGS], "Work");
+        assert_eq!(github.fields["pin"], "1234");
+        assert_eq!(wifi.account, Some(NO_ACCOUNT.to_string()));
+        assert_eq!(wifi.password, None);
+    }
+
+    #[test]
+    fn parse_encrypted() {
+        assert!(parse(r#"{ "encrypted": true, "items": [] }"#).is_err());
+        assert!(parse("not json").is_err());
+    }
+}
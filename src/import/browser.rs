@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::archive::Item;
+use crate::vault::{VaultError, VaultResult};
+
+use super::{item, fields, host};
+
+/* Functions */
+
+// `name,url,username,password,note` from chrome://password-manager
+pub fn chrome(csv: &str) -> VaultResult<Vec<Item>> {
+    rows(csv, "Chrome", &["url", "username", "password"])?
+        .into_iter()
+        .map(|row| {
+            let url = value(&row, "url");
+            let name = value(&row, "name");
+            let entity = if name.is_empty() { host(url) } else { name.to_string() };
+
+            Ok(item(&entity, value(&row, "username"), value(&row, "password"), fields(url, value(&row, "note"), &[])))
+        })
+        .collect()
+}
+
+// `url,username,password,httpRealm,formActionOrigin,guid,...` from about:logins
+pub fn firefox(csv: &str) -> VaultResult<Vec<Item>> {
+    rows(csv, "Firefox", &["url", "username", "password"])?
+        .into_iter()
+        .map(|row| {
+            let url = value(&row, "url");
+
+            Ok(item(&host(url), value(&row, "username"), value(&row, "password"), fields(url, "", &[])))
+        })
+        .collect()
+}
+
+// Rows keyed by their lowercased header, after checking `required` is there
+fn rows(csv: &str, source: &str, required: &[&str]) -> VaultResult<Vec<HashMap<String, String>>> {
+    let invalid = |err: String| VaultError::Error(format!("Invalid {} csv: {}", source, err));
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+
+    let headers: Vec<String> = reader.headers()
+        .map_err(|err| invalid(err.to_string()))?
+        .iter()
+        .map(|header| header.trim().to_lowercase())
+        .collect();
+
+    if let Some(missing) = required.iter().find(|name| !headers.iter().any(|header| header == *name)) {
+        return Err(invalid(format!("missing the {:?} column", missing)));
+    }
+
+    reader.records()
+        .map(|record| {
+            let record = record.map_err(|err| invalid(err.to_string()))?;
+
+            Ok(headers.iter().cloned().zip(record.iter().map(|field| field.to_string())).collect())
+        })
+        .collect()
+}
+
+fn value<'r>(row: &'r HashMap<String, String>, name: &str) -> &'r str {
+    row.get(name).map(|value| value.as_str()).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fields::{URL, NOTES};
+
+    #[test]
+    fn chrome_csv() {
+        let csv = "name,url,username,password,note\n\
+                   accounts.google.com,https://accounts.google.com/signin,me@gmail.com,\"pa,ss\",\"multi\nline\"\n\
+                   ,https://www.example.com/login,bob,secret,\n";
+
+        let items = chrome(csv).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].entity, "accounts.google.com");
+        assert_eq!(items[0].password, Some(String::from("pa,ss")));
+        assert_eq!(items[0].fields[NOTES], "multi\nline");
+        assert_eq!(items[1].entity, "example.com");
+        assert_eq!(items[1].fields[URL], "https://www.example.com/login");
+    }
+
+    #[test]
+    fn firefox_csv() {
+        let csv = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+                   \"https://github.com\",\"octocat\",\"hunter2\",,\"https://github.com\",\"{guid}\",\"1\",\"1\",\"1\"\n";
+
+        let items = firefox(csv).unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].entity, "github.com");
+        assert_eq!(items[0].account, Some(String::from("octocat")));
+        assert_eq!(items[0].password, Some(String::from("hunter2")));
+    }
+
+    #[test]
+    fn missing_column() {
+        assert!(firefox("url,user\nhttps://github.com,octocat\n").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use roxmltree::{Document, Node};
+
+use crate::archive::Item;
+use crate::fields::Fields;
+use crate::vault::{VaultError, VaultResult};
+
+use super::{item, fields};
+
+/* Constants */
+
+pub const STANDARD: [&str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+
+/* Functions */
+
+// KeePass 2 / KeePassXC xml export. Groups below the root one
+// become tags, entries in the recycle bin and history are left out
+pub fn parse(xml: &str) -> VaultResult<Vec<Item>> {
+    let document = Document::parse(xml)
+        .map_err(|err| VaultError::Error(format!("Invalid KeePass xml: {}", err)))?;
+
+    let file = document.root_element();
+    let root = child(file, "Root")
+        .and_then(|root| child(root, "Group"))
+        .ok_or_else(|| VaultError::Error(String::from("Invalid KeePass xml: no root group")))?;
+
+    let meta = child(file, "Meta");
+    let recycle_bin = meta
+        .filter(|meta| text(*meta, "RecycleBinEnabled") != "False")
+        .map(|meta| text(meta, "RecycleBinUUID"))
+        .filter(|uuid| !uuid.is_empty());
+
+    let mut items = Vec::new();
+
+    group(root, &[], recycle_bin.as_deref(), &mut items);
+
+    Ok(items)
+}
+
+fn group(node: Node, path: &[String], recycle_bin: Option<&str>, items: &mut Vec<Item>) {
+    if recycle_bin.is_some() && recycle_bin == Some(text(node, "UUID").as_str()) {
+        return;
+    }
+
+    for entry in node.children().filter(|child| child.has_tag_name("Entry")) {
+        items.push(entry_item(entry, path));
+    }
+
+    for sub in node.children().filter(|child| child.has_tag_name("Group")) {
+        let mut sub_path = path.to_vec();
+
+        sub_path.push(text(sub, "Name"));
+        group(sub, &sub_path, recycle_bin, items);
+    }
+}
+
+fn entry_item(entry: Node, path: &[String]) -> Item {
+    let mut strings = Fields::new();
+
+    for string in entry.children().filter(|child| child.has_tag_name("String")) {
+        strings.insert(text(string, "Key"), text(string, "Value"));
+    }
+
+    self::entry(&strings, &text(entry, "Tags"), path)
+}
+
+// Shared with the KDBX reader, `path` holds the names of the groups
+// above the entry and ends up in front of its own tags
+pub fn entry(strings: &Fields, tags: &str, path: &[String]) -> Item {
+    let get = |key: &str| strings.get(key).map(|value| value.as_str()).unwrap_or("");
+    let mut all_tags = path.to_vec();
+
+    all_tags.extend(
+        tags.split([';', ','])
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+    );
+
+    let mut extra = fields(get("URL"), get("Notes"), &all_tags);
+
+    for (key, value) in strings.iter().filter(|(key, _)| !STANDARD.contains(&key.as_str())) {
+        extra.insert(key.clone(), value.clone());
+    }
+
+    item(get("Title"), get("UserName"), get("Password"), extra)
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn text(node: Node, name: &str) -> String {
+    child(node, name)
+        .and_then(|child| child.text())
+        .unwrap_or("")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fields::{URL, NOTES, TAGS};
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <RecycleBinEnabled>True</RecycleBinEnabled>
+        <RecycleBinUUID>YmlufQ==</RecycleBinUUID>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdA==</UUID>
+            <Name>Database</Name>
+            <Entry>
+                <UUID>ZW50cnk=</UUID>
+                <Tags>mail;personal</Tags>
+                <String><Key>Title</Key><Value>Google</Value></String>
+                <String><Key>UserName</Key><Value>me@gmail.com</Value></String>
+                <String><Key>Password</Key><Value ProtectMemory="True">s3cr&amp;t</Value></String>
+                <String><Key>URL</Key><Value>https://mail.google.com</Value></String>
+                <String><Key>Notes</Key><Value>recovery codes in the safe</Value></String>
+                <String><Key>pin</Key><Value>0000</Value></String>
+                <History>
+                    <Entry>
+                        <String><Key>Title</Key><Value>Old</Value></String>
+                    </Entry>
+                </History>
+            </Entry>
+            <Group>
+                <UUID>d29yaw==</UUID>
+                <Name>Work</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Jira</Value></String>
+                    <String><Key>UserName</Key><Value>me</Value></String>
+                    <String><Key>Password</Key><Value>token</Value></String>
+                </Entry>
+            </Group>
+            <Group>
+                <UUID>YmlufQ==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                </Entry>
+            </Group>
+        </Group>
+    </Root>
+</KeePassFile>"#;
+
+    #[test]
+    fn parse_export() {
+        let items = parse(EXPORT).unwrap();
+        let google = &items[0];
+        let jira = &items[1];
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(google.entity, "Google");
+        assert_eq!(google.account, Some(String::from("me@gmail.com")));
+        assert_eq!(google.password, Some(String::from("s3cr&t")));
+        assert_eq!(google.fields[URL], "https://mail.google.com");
+        assert_eq!(google.fields[NOTES], "recovery codes in the safe");
+        assert_eq!(google.fields[TAGS], "mail,personal");
+        assert_eq!(google.fields["pin"], "0000");
+        assert_eq!(jira.fields[TAGS], "Work");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse("<KeePassFile></KeePassFile>").is_err());
+        assert!(parse("<KeePassFile>").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+/* Dependencies */
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::archive::Item;
+use crate::fields::{Fields, URL, NOTES, TAGS};
+use crate::kdbx::Kdbx;
+use crate::vault::{VaultError, VaultResult};
+
+mod bitwarden;
+mod browser;
+mod onepassword;
+
+pub(crate) mod keepass;
+
+pub mod pass;
+pub mod plaintext;
+
+pub use pass::Gpg;
+
+/* Constants */
+
+// Used when a source entry has a password but no username
+pub const NO_ACCOUNT: &str = "default";
+
+pub const SOURCES: [&str; 7] = [
+    "bitwarden-json",
+    "keepass-xml",
+    "kdbx",
+    "1password-1pux",
+    "chrome-csv",
+    "firefox-csv",
+    "pass"
+];
+
+/* Source enum definition */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Bitwarden,
+    KeePass,
+    Kdbx,
+    OnePassword,
+    Chrome,
+    Firefox,
+    Pass
+}
+
+impl FromStr for Source {
+    type Err = VaultError;
+
+    fn from_str(source: &str) -> VaultResult<Source> {
+        match source {
+            "bitwarden-json" => Ok(Source::Bitwarden),
+            "keepass-xml" => Ok(Source::KeePass),
+            "kdbx" => Ok(Source::Kdbx),
+            "1password-1pux" => Ok(Source::OnePassword),
+            "chrome-csv" => Ok(Source::Chrome),
+            "firefox-csv" => Ok(Source::Firefox),
+            "pass" => Ok(Source::Pass),
+            _ => Err(VaultError::Error(format!("Unknown import source {:?}, expected one of: {}", source, SOURCES.join(", "))))
+        }
+    }
+}
+
+impl Source {
+    // `path` is a file, or the store directory for `pass`. Only
+    // `kdbx` needs a `password`, groups end up as tags like `keepass-xml`
+    pub fn read(self, path: &Path, gpg: &Gpg, password: Option<&str>) -> VaultResult<Vec<Item>> {
+        let unreadable = |err: std::io::Error| VaultError::Error(format!("Could not read {:?}: {}", path, err));
+
+        match self {
+            Source::Pass => return pass::read(path, gpg),
+            Source::Kdbx => {
+                let password = password.ok_or_else(|| VaultError::Error(String::from("A KDBX database needs its password")))?;
+
+                return Ok(Kdbx::read(path, password)?.items(true));
+            },
+            Source::OnePassword => return onepassword::parse(&fs::read(path).map_err(unreadable)?),
+            _ => {}
+        }
+
+        let contents = fs::read_to_string(path).map_err(unreadable)?;
+
+        match self {
+            Source::Bitwarden => bitwarden::parse(&contents),
+            Source::KeePass => keepass::parse(&contents),
+            Source::Chrome => browser::chrome(&contents),
+            Source::Firefox => browser::firefox(&contents),
+            Source::OnePassword | Source::Pass | Source::Kdbx => unreachable!()
+        }
+    }
+}
+
+/* Duplicates enum definition */
+
+// What to do with an account that is already in the vault
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Duplicates {
+    Skip,
+    Overwrite,
+    Rename
+}
+
+impl FromStr for Duplicates {
+    type Err = VaultError;
+
+    fn from_str(duplicates: &str) -> VaultResult<Duplicates> {
+        match duplicates {
+            "skip" => Ok(Duplicates::Skip),
+            "overwrite" => Ok(Duplicates::Overwrite),
+            "rename" => Ok(Duplicates::Rename),
+            _ => Err(VaultError::Error(format!("Unknown duplicates handling: {}", duplicates)))
+        }
+    }
+}
+
+/* Action enum definition */
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Add,
+    Skip,
+    Overwrite,
+    Rename(String)
+}
+
+impl Action {
+    pub fn to_str(&self) -> String {
+        match self {
+            Action::Add => String::from("add"),
+            Action::Skip => String::from("skip"),
+            Action::Overwrite => String::from("overwrite"),
+            Action::Rename(account) => format!("rename to {}", account)
+        }
+    }
+}
+
+/* Functions */
+
+// Builds an item, leaving out empty values and falling back
+// to `NO_ACCOUNT` when there is something to store but no username
+pub fn item(entity: &str, account: &str, password: &str, mut fields: Fields) -> Item {
+    fields.retain(|_, value| !value.trim().is_empty());
+
+    let account = match account.trim() {
+        "" if password.is_empty() && fields.is_empty() => None,
+        "" => Some(NO_ACCOUNT.to_string()),
+        account => Some(account.to_string())
+    };
+
+    Item {
+        entity: entity.trim().to_string(),
+        account,
+        password: if password.is_empty() { None } else { Some(password.to_string()) },
+        fields
+    }
+}
+
+pub fn fields(url: &str, notes: &str, tags: &[String]) -> Fields {
+    let mut fields = Fields::new();
+
+    fields.insert(URL.to_string(), url.to_string());
+    fields.insert(NOTES.to_string(), notes.to_string());
+    fields.insert(TAGS.to_string(), tags.join(","));
+
+    fields
+}
+
+// `https://www.example.com:8080/login` -> `example.com`
+pub fn host(url: &str) -> String {
+    let rest = url.split("://").nth(1).unwrap_or(url);
+    let end = rest.find(['/', ':', '?', '#']).unwrap_or(rest.len());
+    let host = &rest[..end];
+    let host = host.rsplit('@').next().unwrap_or(host);
+
+    host.trim_start_matches("www.").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_from_str() {
+        for source in SOURCES.iter() {
+            assert!(source.parse::<Source>().is_ok());
+        }
+
+        assert!("lastpass-csv".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn item_fallbacks() {
+        let mut extra = Fields::new();
+
+        extra.insert(String::from("blank"), String::from(" "));
+
+        let entity = item(" google ", "", "", extra.clone());
+        let password = item("google", "", "secret", extra);
+
+        assert_eq!(entity.entity, "google");
+        assert_eq!(entity.account, None);
+        assert!(entity.fields.is_empty());
+        assert_eq!(password.account, Some(NO_ACCOUNT.to_string()));
+        assert_eq!(password.password, Some(String::from("secret")));
+    }
+
+    #[test]
+    fn host_from_url() {
+        assert_eq!(host("https://www.example.com:8080/login?next=/"), "example.com");
+        assert_eq!(host("https://user@accounts.google.com"), "accounts.google.com");
+        assert_eq!(host("example.org/path"), "example.org");
+    }
+}
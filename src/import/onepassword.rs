@@ -0,0 +1,188 @@
+use std::io::{Cursor, Read};
+
+use serde_json::Value;
+
+use crate::archive::Item;
+use crate::vault::{VaultError, VaultResult};
+
+use super::{item, fields};
+
+/* Constants */
+
+const DATA: &str = "export.data";
+
+/* Functions */
+
+// A 1pux file is a zip holding `export.data`, a json document with
+// accounts -> vaults -> items. Archived and deleted items are left out
+pub fn parse(bytes: &[u8]) -> VaultResult<Vec<Item>> {
+    let invalid = |err: String| VaultError::Error(format!("Invalid 1Password export: {}", err));
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|err| invalid(err.to_string()))?;
+    let mut json = String::new();
+
+    zip.by_name(DATA)
+        .map_err(|err| invalid(err.to_string()))?
+        .read_to_string(&mut json)?;
+
+    let data: Value = serde_json::from_str(&json).map_err(|err| invalid(err.to_string()))?;
+    let mut items = Vec::new();
+
+    for account in list(&data["accounts"]) {
+        for vault in list(&account["vaults"]) {
+            let vault_name = string(&vault["attrs"]["name"]);
+
+            for entry in list(&vault["items"]) {
+                let state = string(&entry["state"]);
+
+                if !state.is_empty() && state != "active" {
+                    continue;
+                }
+
+                items.push(entry_item(&entry, &vault_name));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+fn entry_item(entry: &Value, vault: &str) -> Item {
+    let overview = &entry["overview"];
+    let details = &entry["details"];
+
+    let login = |designation: &str| {
+        list(&details["loginFields"])
+            .iter()
+            .find(|field| string(&field["designation"]) == designation)
+            .map(|field| string(&field["value"]))
+            .unwrap_or_default()
+    };
+
+    let mut password = login("password");
+
+    if password.is_empty() {
+        password = string(&details["password"]);
+    }
+
+    let mut tags: Vec<String> = list(&overview["tags"]).iter().map(string).collect();
+
+    if !vault.is_empty() {
+        tags.insert(0, vault.to_string());
+    }
+
+    let mut extra = fields(&string(&overview["url"]), &string(&details["notesPlain"]), &tags);
+
+    for section in list(&details["sections"]) {
+        for field in list(&section["fields"]) {
+            let name = match string(&field["title"]) {
+                title if title.is_empty() => string(&field["id"]),
+                title => title
+            };
+
+            // The value is an object keyed by its type, eg. `{ "concealed": "..." }`
+            let value = field["value"]
+                .as_object()
+                .and_then(|value| value.values().next())
+                .map(string)
+                .unwrap_or_default();
+
+            if !name.is_empty() {
+                extra.insert(name, value);
+            }
+        }
+    }
+
+    item(&string(&overview["title"]), &login("username"), &password, extra)
+}
+
+fn list(value: &Value) -> Vec<Value> {
+    value.as_array().cloned().unwrap_or_default()
+}
+
+fn string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => String::new(),
+        value => value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::fields::{URL, NOTES, TAGS};
+
+    const DATA_JSON: &str = r#"{
+        "accounts": [{
+            "attrs": { "name": "Me" },
+            "vaults": [{
+                "attrs": { "name": "Personal" },
+                "items": [
+                    {
+                        "uuid": "1",
+                        "state": "active",
+                        "categoryUuid": "001",
+                        "details": {
+                            "loginFields": [
+                                { "value": "me@gmail.com", "name": "email", "fieldType": "E", "designation": "username" },
+                                { "value": "secret", "name": "password", "fieldType": "P", "designation": "password" }
+                            ],
+                            "notesPlain": "old account",
+                            "sections": [{
+                                "title": "Security",
+                                "fields": [{ "title": "pin", "id": "p1", "value": { "concealed": "1234" } }]
+                            }]
+                        },
+                        "overview": { "title": "Google", "url": "https://google.com", "tags": ["mail"] }
+                    },
+                    {
+                        "uuid": "2",
+                        "state": "archived",
+                        "details": {},
+                        "overview": { "title": "Gone" }
+                    }
+                ]
+            }]
+        }]
+    }"#;
+
+    fn pux(data: &str) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+
+        {
+            let mut zip = zip::ZipWriter::new(&mut cursor);
+
+            zip.start_file(DATA, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+            zip.finish().unwrap();
+        }
+
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn parse_export() {
+        let items = parse(&pux(DATA_JSON)).unwrap();
+        let google = &items[0];
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(google.entity, "Google");
+        assert_eq!(google.account, Some(String::from("me@gmail.com")));
+        assert_eq!(google.password, Some(String::from("secret")));
+        assert_eq!(google.fields[URL], "https://google.com");
+        assert_eq!(google.fields[NOTES], "old account");
+        assert_eq!(google.fields[TAGS], "Personal,mail");
+        assert_eq!(google.fields["pin"], "1234");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse(b"not a zip").is_err());
+        assert!(parse(&pux("not json")).is_err());
+    }
+}
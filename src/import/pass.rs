@@ -0,0 +1,360 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::archive::Item;
+use crate::fields::{Fields, NOTES};
+use crate::vault::{VaultError, VaultResult};
+
+use super::{item, NO_ACCOUNT};
+
+/* Constants */
+
+const EXTENSION: &str = "gpg";
+const GPG_ID: &str = ".gpg-id";
+
+// Used as account by top level entries, eg. `github.gpg` with `login: me`
+const LOGINS: [&str; 3] = ["login", "username", "user"];
+
+/* Gpg struct definition */
+
+// The local gpg binary, the agent takes care of asking for the key passphrase
+#[derive(Clone, Debug)]
+pub struct Gpg {
+    program: String
+}
+
+impl Gpg {
+
+    /* Intialisers */
+
+    pub fn new(program: &str) -> Gpg {
+        Gpg { program: program.to_string() }
+    }
+
+    /* Methods */
+
+    pub fn decrypt(&self, path: &Path) -> VaultResult<String> {
+        let output = Command::new(&self.program)
+            .args(["--quiet", "--yes", "--decrypt"])
+            .arg(path)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| VaultError::Error(format!("Could not run {}: {}", self.program, err)))?;
+
+        if !output.status.success() {
+            return Err(VaultError::Error(format!("{} could not decrypt {:?}", self.program, path)));
+        }
+
+        String::from_utf8(output.stdout)
+            .map_err(|_| VaultError::Error(format!("{:?} is not valid utf-8", path)))
+    }
+
+    pub fn encrypt(&self, path: &Path, recipients: &[String], contents: &str) -> VaultResult<()> {
+        let mut command = Command::new(&self.program);
+
+        command.args(["--quiet", "--yes", "--batch", "--encrypt", "--output"]).arg(path);
+
+        for recipient in recipients {
+            command.arg("-r").arg(recipient);
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| VaultError::Error(format!("Could not run {}: {}", self.program, err)))?;
+
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.write_all(contents.as_bytes())?;
+        }
+
+        drop(child.stdin.take());
+
+        if !child.wait()?.success() {
+            return Err(VaultError::Error(format!("{} could not encrypt {:?}", self.program, path)));
+        }
+
+        Ok(())
+    }
+}
+
+/* Functions */
+
+// `<store>/<entity>/<account>.gpg`, deeper directories are kept in the
+// entity name, eg. `work/jira/me.gpg` is account `me` of `work/jira`
+pub fn read(store: &Path, gpg: &Gpg) -> VaultResult<Vec<Item>> {
+    if !store.is_dir() {
+        return Err(VaultError::Error(format!("{:?} is not a password store", store)));
+    }
+
+    let mut files = Vec::new();
+    let mut items = Vec::new();
+
+    walk(store, &mut files)?;
+    files.sort();
+
+    for file in files {
+        let relative = file.strip_prefix(store).unwrap().with_extension("");
+        let mut names: Vec<String> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect();
+
+        let stem = names.pop().unwrap_or_default();
+        let (password, mut fields) = parse(&gpg.decrypt(&file)?);
+
+        let (entity, account) = if names.is_empty() {
+            let login = LOGINS.iter().find_map(|key| fields.remove(*key)).unwrap_or_default();
+
+            (stem, login)
+        } else {
+            (names.join("/"), stem)
+        };
+
+        fields.retain(|_, value| !value.is_empty());
+        items.push(item(&entity, &account, &password, fields));
+    }
+
+    Ok(items)
+}
+
+// Refuses to touch existing entries, the recipients default to the
+// store's `.gpg-id`, which is created when `recipients` are given
+pub fn write(store: &Path, items: &[Item], recipients: &[String], gpg: &Gpg) -> VaultResult<usize> {
+    let gpg_id = store.join(GPG_ID);
+    let recipients = match recipients.is_empty() {
+        false => recipients.to_vec(),
+        true if gpg_id.is_file() => fs::read_to_string(&gpg_id)?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        true => return Err(VaultError::Error(format!("No {} in {:?}, pass the key with --gpg-id", GPG_ID, store)))
+    };
+
+    let entries: Vec<(PathBuf, &Item)> = items.iter()
+        .filter(|item| item.account.is_some())
+        .map(|item| (entry_path(store, item), item))
+        .collect();
+
+    if let Some((existing, _)) = entries.iter().find(|(path, _)| path.exists()) {
+        return Err(VaultError::Error(format!("{:?} already exists", existing)));
+    }
+
+    fs::create_dir_all(store)?;
+
+    if !gpg_id.exists() {
+        fs::write(&gpg_id, format!("{}\n", recipients.join("\n")))?;
+    }
+
+    for item in items.iter().filter(|item| item.account.is_none()) {
+        fs::create_dir_all(store.join(component(&item.entity, true)))?;
+    }
+
+    for (path, item) in entries.iter() {
+        fs::create_dir_all(path.parent().unwrap())?;
+        gpg.encrypt(path, &recipients, &format(item))?;
+    }
+
+    Ok(entries.len())
+}
+
+// First line is the password, `key: value` lines are fields
+// and everything else is kept as notes
+pub fn parse(contents: &str) -> (String, Fields) {
+    let mut lines = contents.lines();
+    let password = lines.next().unwrap_or("").to_string();
+    let mut fields = Fields::new();
+    let mut notes = Vec::new();
+
+    for line in lines {
+        match line.split_once(": ") {
+            Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                fields.insert(key.to_lowercase(), value.trim().to_string());
+            },
+            _ => notes.push(line)
+        }
+    }
+
+    let notes = notes.join("\n").trim().to_string();
+
+    if !notes.is_empty() {
+        fields.insert(NOTES.to_string(), notes);
+    }
+
+    (password, fields)
+}
+
+pub fn format(item: &Item) -> String {
+    let mut contents = format!("{}\n", item.password.as_deref().unwrap_or(""));
+
+    for (key, value) in item.fields.iter().filter(|(key, _)| key.as_str() != NOTES) {
+        contents.push_str(&format!("{}: {}\n", key, value));
+    }
+
+    if let Some(notes) = item.fields.get(NOTES) {
+        contents.push_str(&format!("{}\n", notes));
+    }
+
+    contents
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> VaultResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path.file_name()
+            .map(|name| name.to_string_lossy().starts_with('.'))
+            .unwrap_or(true);
+
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if path.extension().map(|ext| ext == EXTENSION).unwrap_or(false) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn entry_path(store: &Path, item: &Item) -> PathBuf {
+    let account = item.account.as_deref().unwrap_or(NO_ACCOUNT);
+    let mut path = store.join(component(&item.entity, true));
+
+    path.push(format!("{}.{}", component(account, false).display(), EXTENSION));
+    path
+}
+
+// Keeps names inside the store, `/` only separates directories in entities
+fn component(name: &str, nested: bool) -> PathBuf {
+    let name = if nested { name.to_string() } else { name.replace('/', "_") };
+
+    name.split('/')
+        .map(|part| match Path::new(part).components().next() {
+            Some(Component::Normal(_)) if !part.starts_with('.') => part.to_string(),
+            _ => format!("_{}", part)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+    use crate::fields::URL;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `pass.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    // Stands in for gpg, "encrypting" by copying stdin to --output
+    #[cfg(unix)]
+    fn fake_gpg(dir: &Path) -> Gpg {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = dir.join("fake-gpg");
+        let body = "#!/bin/sh\n\
+            mode=\"\"; out=\"\"; last=\"\"\n\
+            while [ $# -gt 0 ]; do\n\
+              case \"$1\" in\n\
+                --output) out=\"$2\"; shift ;;\n\
+                --decrypt) mode=decrypt ;;\n\
+                *) last=\"$1\" ;;\n\
+              esac\n\
+              shift\n\
+            done\n\
+            if [ \"$mode\" = decrypt ]; then cat \"$last\"; else cat > \"$out\"; fi\n";
+
+        fs::create_dir_all(dir).unwrap();
+        fs::write(&script, body).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        Gpg::new(script.to_str().unwrap())
+    }
+
+    #[test]
+    fn parse_contents() {
+        let (password, fields) = parse("secret\nURL: https://github.com\nlogin: me\nrecovery codes\nin the safe\n");
+
+        assert_eq!(password, "secret");
+        assert_eq!(fields[URL], "https://github.com");
+        assert_eq!(fields["login"], "me");
+        assert_eq!(fields[NOTES], "recovery codes\nin the safe");
+    }
+
+    #[test]
+    fn format_contents() {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), "https://github.com".to_string());
+        fields.insert(NOTES.to_string(), "line one\nline two".to_string());
+
+        let item = item("github", "me", "secret", fields.clone());
+        let (password, parsed) = parse(&format(&item));
+
+        assert_eq!(password, "secret");
+        assert_eq!(parsed, fields);
+    }
+
+    #[test]
+    fn component_stays_inside() {
+        assert_eq!(component("work/jira", true), PathBuf::from("work/jira"));
+        assert_eq!(component("../etc", true), PathBuf::from("_../etc"));
+        assert_eq!(component("me/you", false), PathBuf::from("me_you"));
+        assert_eq!(component(".git", false), PathBuf::from("_.git"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_and_read() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let store = locker.join("store");
+                let gpg = fake_gpg(&config);
+
+                let items = vec![
+                    item("work/jira", "me", "token", Fields::new()),
+                    item("github", "me@mail.com", "secret", Fields::new()),
+                    item("empty", "", "", Fields::new())
+                ];
+
+                let missing_id = write(&store, &items, &[], &gpg);
+                let written = write(&store, &items, &[String::from("ABCD")], &gpg).unwrap();
+                let again = write(&store, &items, &[], &gpg);
+
+                fs::write(store.join("top.gpg"), "pw\nlogin: root\n").unwrap();
+
+                let read = read(&store, &gpg).unwrap();
+
+                assert!(missing_id.is_err());
+                assert!(again.is_err());
+                assert_eq!(written, 2);
+                assert_eq!(fs::read_to_string(store.join(GPG_ID)).unwrap(), "ABCD\n");
+                assert!(store.join("empty").is_dir());
+                assert_eq!(read, vec![
+                    item("github", "me@mail.com", "secret", Fields::new()),
+                    item("top", "root", "pw", Fields::new()),
+                    item("work/jira", "me", "token", Fields::new())
+                ]);
+            }
+        };
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::archive::Item;
+use crate::fields::{is_url, URL, NOTES, TAGS};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// Chrome's columns, which Bitwarden, Firefox and `--from chrome-csv` read
+const COLUMNS: [&str; 5] = ["name", "url", "username", "password", "note"];
+
+const TOTP: &str = "totp";
+
+/* Functions */
+
+// `name,url,username,password,note` and then one column per other field,
+// entities without accounts are left out since there is nothing to log in with
+pub fn csv(items: &[Item]) -> VaultResult<String> {
+    let accounts: Vec<&Item> = items.iter().filter(|item| item.account.is_some()).collect();
+    let extra: BTreeSet<&str> = accounts.iter()
+        .flat_map(|item| item.fields.keys())
+        .map(|key| key.as_str())
+        .filter(|key| *key != URL && *key != NOTES)
+        .collect();
+
+    let invalid = |err: csv::Error| VaultError::Error(format!("Could not write csv: {}", err));
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    writer.write_record(COLUMNS.iter().copied().chain(extra.iter().copied())).map_err(invalid)?;
+
+    for item in accounts {
+        let field = |key: &str| item.fields.get(key).map(|value| value.as_str()).unwrap_or("");
+        let row = [
+            item.entity.as_str(),
+            field(URL),
+            item.account.as_deref().unwrap_or(""),
+            item.password.as_deref().unwrap_or(""),
+            field(NOTES)
+        ];
+
+        writer.write_record(row.iter().copied().chain(extra.iter().map(|key| field(key)))).map_err(invalid)?;
+    }
+
+    let bytes = writer.into_inner().map_err(|err| VaultError::Error(err.to_string()))?;
+
+    String::from_utf8(bytes).map_err(|err| VaultError::Error(err.to_string()))
+}
+
+// An unencrypted Bitwarden export, tags become the folder and every
+// field rk doesn't map onto the login is kept as a custom field
+pub fn json(items: &[Item]) -> VaultResult<String> {
+    let accounts: Vec<&Item> = items.iter().filter(|item| item.account.is_some()).collect();
+    let folders: Vec<&str> = accounts.iter()
+        .filter_map(|item| item.fields.get(TAGS))
+        .map(|tags| tags.as_str())
+        .collect::<BTreeSet<&str>>()
+        .into_iter()
+        .collect();
+
+    let folder_id = |index: usize| format!("00000000-0000-4000-8000-{:012x}", index + 1);
+
+    let entries: Vec<Value> = accounts.iter()
+        .map(|item| {
+            let mut uris: Vec<(&String, &String)> = item.fields.iter()
+                .filter(|(key, _)| is_url(key))
+                .collect();
+
+            // `url`, `url2`, `url3`... in that order
+            uris.sort_by_key(|(key, _)| key[URL.len()..].parse::<usize>().unwrap_or(1));
+
+            let fields: Vec<Value> = item.fields.iter()
+                .filter(|(key, _)| !is_url(key) && ![NOTES, TAGS, TOTP].contains(&key.as_str()))
+                .map(|(name, value)| json!({ "name": name, "value": value, "type": 0 }))
+                .collect();
+
+            let folder = item.fields.get(TAGS)
+                .and_then(|tags| folders.iter().position(|folder| folder == tags))
+                .map(folder_id);
+
+            json!({
+                "folderId": folder,
+                "type": 1,
+                "name": item.entity,
+                "notes": item.fields.get(NOTES),
+                "favorite": false,
+                "fields": fields,
+                "login": {
+                    "username": item.account,
+                    "password": item.password,
+                    "totp": item.fields.get(TOTP),
+                    "uris": uris.iter().map(|(_, uri)| json!({ "match": null, "uri": uri })).collect::<Vec<Value>>()
+                }
+            })
+        })
+        .collect();
+
+    let export = json!({
+        "encrypted": false,
+        "folders": folders.iter()
+            .enumerate()
+            .map(|(index, name)| json!({ "id": folder_id(index), "name": name }))
+            .collect::<Vec<Value>>(),
+        "items": entries
+    });
+
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|err| VaultError::Error(err.to_string()))?;
+
+    Ok(format!("{}\n", json))
+}
+
+// Never replaces an existing file and only the owner can read the new one,
+// from the moment it is created
+pub fn write(path: &Path, contents: &str) -> VaultResult<()> {
+    let mut options = OpenOptions::new();
+
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)
+        .map_err(|err| VaultError::Error(format!("Could not create {:?}: {}", path, err)))?;
+
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, remove_dir_all};
+
+    use crate::fields::Fields;
+    use crate::import::{bitwarden, browser, item};
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `plaintext.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), "https://github.com".to_string());
+        fields.insert("url2".to_string(), "https://gist.github.com".to_string());
+        fields.insert(NOTES.to_string(), "recovery codes,\nin the safe".to_string());
+        fields.insert(TAGS.to_string(), "dev,work".to_string());
+        fields.insert(TOTP.to_string(), "otpauth://totp/github".to_string());
+        fields.insert("pin".to_string(), "1234".to_string());
+
+        vec![
+            item("github", "me", "pa\"ss", fields),
+            item("gmail", "you@gmail.com", "secret", Fields::new()),
+            item("empty", "", "", Fields::new())
+        ]
+    }
+
+    #[test]
+    fn csv_columns() {
+        let csv = csv(&items()).unwrap();
+        let read = browser::chrome(&csv).unwrap();
+
+        assert!(csv.starts_with("name,url,username,password,note,pin,tags,totp,url2\n"));
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].entity, "github");
+        assert_eq!(read[0].password, Some(String::from("pa\"ss")));
+        assert_eq!(read[0].fields[NOTES], "recovery codes,\nin the safe");
+        assert_eq!(read[1], items()[1]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = json(&items()).unwrap();
+        let read = bitwarden::parse(&json).unwrap();
+
+        assert_eq!(read, items()[..2].to_vec());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_restricted() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::os::unix::fs::PermissionsExt;
+
+                let (_, locker) = this.as_path_buf();
+                let path = locker.join("export.csv");
+
+                fs::create_dir_all(&locker).unwrap();
+
+                let written = write(&path, "name\n");
+                let again = write(&path, "other\n");
+                let mode = fs::metadata(&path).unwrap().permissions().mode();
+
+                assert!(written.is_ok());
+                assert!(again.is_err());
+                assert_eq!(fs::read_to_string(&path).unwrap(), "name\n");
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        };
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::{Args, Keeper, Resolve};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+const SCHEME: &str = "rk://";
+
+// The field a reference names to get the account's password
+const PASSWORD: &str = "password";
+
+/* Reference struct definition */
+
+// `rk://entity/account/field`. The last two segments are the account and
+// the field, entity names may hold slashes, eg. `rk://work/jira/me/password`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub entity: String,
+    pub account: String,
+    pub field: String,
+    pub line: usize
+}
+
+impl Reference {
+
+    /* Intialisers */
+
+    pub fn parse(reference: &str, line: usize) -> VaultResult<Reference> {
+        let invalid = || VaultError::Error(format!("Invalid reference {:?} on line {}, expected rk://entity/account/field", reference, line));
+
+        let path = reference.strip_prefix(SCHEME).ok_or_else(invalid)?;
+        let (rest, field) = path.rsplit_once('/').ok_or_else(invalid)?;
+        let (entity, account) = rest.rsplit_once('/').ok_or_else(invalid)?;
+
+        if [entity, account, field].iter().any(|segment| segment.is_empty()) {
+            return Err(invalid());
+        }
+
+        Ok(Reference { entity: entity.to_string(), account: account.to_string(), field: field.to_string(), line })
+    }
+
+    /* Methods */
+
+    // None when the entity, account or field doesn't exist
+    fn value(&self, keeper: &mut Keeper) -> VaultResult<Option<String>> {
+        let args = Args::new(Some(&self.entity), Some(&self.account), None);
+        let resolved = match self.field.as_str() {
+            PASSWORD => keeper.read(args),
+            _ => keeper.fields(args)
+        };
+
+        match resolved {
+            Ok(Resolve::Read(password)) => Ok(Some(password)),
+            Ok(Resolve::Fields(fields)) => Ok(fields.get(&self.field).cloned()),
+            Ok(_) | Err(VaultError::MissingEntity) | Err(VaultError::MissingAccount) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}/{}/{}", SCHEME, self.entity, self.account, self.field)
+    }
+}
+
+/* Part enum definition */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Reference(Reference)
+}
+
+/* Template struct definition */
+
+// Text with `{{ rk://... }}` placeholders. Other `{{ ... }}`, eg. Helm or
+// Jinja expressions, are left as they are
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>
+}
+
+impl Template {
+
+    /* Intialisers */
+
+    pub fn parse(text: &str) -> VaultResult<Template> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        let mut literal = String::new();
+
+        while let Some(start) = rest.find(OPEN) {
+            let inner = &rest[start + OPEN.len()..];
+            let line = text[..text.len() - rest.len() + start].matches('\n').count() + 1;
+
+            if !inner.trim_start().starts_with(SCHEME) {
+                literal.push_str(&rest[..start + OPEN.len()]);
+                rest = inner;
+
+                continue;
+            }
+
+            let end = inner.find(CLOSE)
+                .ok_or_else(|| VaultError::Error(format!("Unclosed reference on line {}", line)))?;
+
+            literal.push_str(&rest[..start]);
+            parts.push(Part::Text(std::mem::take(&mut literal)));
+            parts.push(Part::Reference(Reference::parse(inner[..end].trim(), line)?));
+            rest = &inner[end + CLOSE.len()..];
+        }
+
+        literal.push_str(rest);
+        parts.push(Part::Text(literal));
+        parts.retain(|part| *part != Part::Text(String::new()));
+
+        Ok(Template { parts })
+    }
+
+    /* Methods */
+
+    pub fn references(&self) -> Vec<&Reference> {
+        self.parts.iter()
+            .filter_map(|part| match part {
+                Part::Reference(reference) => Some(reference),
+                Part::Text(_) => None
+            })
+            .collect()
+    }
+
+    // Fails listing every reference that can't be resolved, not just the
+    // first one, so a template can be fixed in one go
+    pub fn check(&self, keeper: &mut Keeper) -> VaultResult<()> {
+        self.values(keeper).map(|_| ())
+    }
+
+    pub fn render(&self, keeper: &mut Keeper) -> VaultResult<String> {
+        let mut values = self.values(keeper)?.into_iter();
+
+        let rendered = self.parts.iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Reference(_) => values.next().unwrap_or_default()
+            })
+            .collect();
+
+        Ok(rendered)
+    }
+
+    fn values(&self, keeper: &mut Keeper) -> VaultResult<Vec<String>> {
+        let mut values = Vec::new();
+        let mut missing = Vec::new();
+
+        for reference in self.references() {
+            match reference.value(keeper)? {
+                Some(value) => values.push(value),
+                None => missing.push(format!("{} (line {})", reference, reference.line))
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(VaultError::Error(format!("Missing references: {}", missing.join(", "))));
+        }
+
+        Ok(values)
+    }
+}
+
+/* Functions */
+
+// Only the owner can read the output, from the moment it exists. It is
+// written next to `path` and renamed over it, replacing an older render
+pub fn write(path: &Path, contents: &str) -> VaultResult<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+
+    tmp.push(".tmp");
+
+    let tmp = Path::new(&tmp);
+    let mut options = OpenOptions::new();
+
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    if tmp.exists() {
+        fs::remove_file(tmp)?;
+    }
+
+    let mut file = options.open(tmp)
+        .map_err(|err| VaultError::Error(format!("Could not create {:?}: {}", path, err)))?;
+
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::{Fields, Kdf};
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `inject.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn reference(entity: &str, account: &str, field: &str, line: usize) -> Reference {
+        Reference { entity: entity.to_string(), account: account.to_string(), field: field.to_string(), line }
+    }
+
+    #[test]
+    fn parse_references() {
+        let template = Template::parse("url: {{rk://prod-db/admin/url}}\nhelm: {{ .Values.host }}\ntoken: {{ rk://work/jira/me/password }}\n").unwrap();
+
+        assert_eq!(template.references(), vec![&reference("prod-db", "admin", "url", 1), &reference("work/jira", "me", "password", 3)]);
+        assert_eq!(template.references()[1].to_string(), "rk://work/jira/me/password");
+        assert!(Template::parse("{{ rk://prod-db/admin }}").is_err());
+        assert!(Template::parse("{{ rk://prod-db//password }}").is_err());
+        assert!(Template::parse("{{ rk://prod-db/admin/password").is_err());
+    }
+
+    #[test]
+    fn render_and_check() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config, locker, "master", Kdf::new(8, 1, 1)).unwrap();
+                let mut fields = Fields::new();
+
+                fields.insert(String::from("host"), String::from("db.internal"));
+                keeper.add(Args::new(Some("prod-db"), Some("admin"), Some("secret"))).unwrap();
+                keeper.annotate(Args::new(Some("prod-db"), Some("admin"), None), fields).unwrap();
+
+                let template = Template::parse("DB_HOST={{ rk://prod-db/admin/host }}\nDB_PASS={{ rk://prod-db/admin/password }}\nHELM={{ .Values }}\n").unwrap();
+                let broken = Template::parse("A={{ rk://prod-db/admin/port }}\nB={{ rk://prod-db/root/password }}\nC={{ rk://cache/admin/password }}\n").unwrap();
+
+                let rendered = template.render(&mut keeper).unwrap();
+                let missing = broken.check(&mut keeper).unwrap_err();
+
+                assert_eq!(rendered, "DB_HOST=db.internal\nDB_PASS=secret\nHELM={{ .Values }}\n");
+                assert!(template.check(&mut keeper).is_ok());
+                assert!(broken.render(&mut keeper).is_err());
+                assert_eq!(missing.to_str(), "Missing references: rk://prod-db/admin/port (line 1), rk://prod-db/root/password (line 2), rk://cache/admin/password (line 3)");
+            }
+        };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_restricted() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                use std::os::unix::fs::PermissionsExt;
+
+                let (_, locker) = this.as_path_buf();
+                let path = locker.join(".env");
+
+                fs::create_dir_all(&locker).unwrap();
+                fs::write(&path, "old\n").unwrap();
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+                write(&path, "DB_PASS=secret\n").unwrap();
+
+                let mode = fs::metadata(&path).unwrap().permissions().mode();
+
+                assert_eq!(fs::read_to_string(&path).unwrap(), "DB_PASS=secret\n");
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        };
+    }
+}
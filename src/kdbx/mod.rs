@@ -0,0 +1,688 @@
+/* Dependencies */
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use aes_soft::Aes256;
+use aes_soft::block_cipher_trait::BlockCipher;
+use aes_soft::block_cipher_trait::generic_array::GenericArray;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use block_modes::{BlockMode, Cbc};
+use block_modes::block_padding::Pkcs7;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use crypto_hash::{digest, Algorithm as Hash};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rand::{Rng, OsRng};
+
+use crate::archive::Item;
+use crate::header::{self, Kdf};
+use crate::locker::Locker;
+use crate::vault::{VaultError, VaultResult};
+
+mod variant;
+mod xml;
+
+use variant::{Dictionary, Variant};
+use xml::Document;
+
+/* Constants */
+
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+const MAJOR: u16 = 4;
+
+// Outer header fields
+const END: u8 = 0;
+const CIPHER: u8 = 2;
+const COMPRESSION: u8 = 3;
+const MASTER_SEED: u8 = 4;
+const IV: u8 = 7;
+const KDF: u8 = 11;
+const PUBLIC_DATA: u8 = 12;
+
+// Inner header fields
+const STREAM_ID: u8 = 1;
+const STREAM_KEY: u8 = 2;
+const BINARY: u8 = 3;
+
+const CHACHA20_STREAM: u32 = 3;
+
+const AES256: [u8; 16] = [0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff];
+const CHACHA20: [u8; 16] = [0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a];
+
+const AES_KDF: [u8; 16] = [0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea];
+const ARGON2D: [u8; 16] = [0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c];
+const ARGON2ID: [u8; 16] = [0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6];
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/* Custom types */
+
+type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+
+/* Cipher enum definition */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+    Aes256,
+    ChaCha20
+}
+
+impl Cipher {
+    fn from_uuid(uuid: &[u8]) -> VaultResult<Cipher> {
+        match uuid {
+            id if id == AES256 => Ok(Cipher::Aes256),
+            id if id == CHACHA20 => Ok(Cipher::ChaCha20),
+            _ => Err(VaultError::Error(String::from("Unsupported KDBX cipher, only AES-256 and ChaCha20 are")))
+        }
+    }
+
+    fn uuid(self) -> [u8; 16] {
+        match self {
+            Cipher::Aes256 => AES256,
+            Cipher::ChaCha20 => CHACHA20
+        }
+    }
+
+    fn iv_len(self) -> usize {
+        match self {
+            Cipher::Aes256 => 16,
+            Cipher::ChaCha20 => 12
+        }
+    }
+
+    fn decrypt(self, key: &[u8], iv: &[u8], data: &[u8]) -> VaultResult<Vec<u8>> {
+        match self {
+            Cipher::Aes256 => Aes256Cbc::new_var(key, iv)
+                .map_err(|_| VaultError::Error(String::from("Invalid KDBX encryption iv")))?
+                .decrypt_vec(data)
+                .map_err(|_| VaultError::Error(String::from("Damaged KDBX payload"))),
+            Cipher::ChaCha20 => {
+                let mut data = data.to_vec();
+
+                ChaCha20::new(key.into(), iv.into()).apply_keystream(&mut data);
+
+                Ok(data)
+            }
+        }
+    }
+
+    fn encrypt(self, key: &[u8], iv: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Cipher::Aes256 => Aes256Cbc::new_var(key, iv).unwrap().encrypt_vec(data),
+            Cipher::ChaCha20 => {
+                let mut data = data.to_vec();
+
+                ChaCha20::new(key.into(), iv.into()).apply_keystream(&mut data);
+                data
+            }
+        }
+    }
+}
+
+/* Kdbx struct definition */
+
+// A KeePass 2 / KeePassXC database in the KDBX 4 format. The key
+// derived from the password is kept so saving doesn't derive it again,
+// the master seed, ivs and inner stream key are new on every save
+#[derive(Debug)]
+pub struct Kdbx {
+    minor: u16,
+    cipher: Cipher,
+    compressed: bool,
+    kdf: Dictionary,
+    public_data: Option<Vec<u8>>,
+    binaries: Vec<Vec<u8>>,
+    document: Document,
+    key: Vec<u8>
+}
+
+impl Kdbx {
+
+    /* Intialisers */
+
+    // An empty database, keyed with argon2id and rk's own parameters
+    pub fn new(password: &str, kdf: &Kdf) -> VaultResult<Kdbx> {
+        let mut params = Dictionary::default();
+        let salt = hex::decode(&kdf.salt)
+            .map_err(|err| VaultError::Error(format!("Invalid key derivation salt: {}", err)))?;
+
+        params.set("$UUID", Variant::Bytes(ARGON2ID.to_vec()));
+        params.set("S", Variant::Bytes(salt));
+        params.set("P", Variant::UInt32(kdf.parallelism));
+        params.set("M", Variant::UInt64(kdf.memory as u64 * 1024));
+        params.set("I", Variant::UInt64(kdf.iterations as u64));
+        params.set("V", Variant::UInt32(0x13));
+
+        let key = transform(&params, password)?;
+
+        Ok(Kdbx {
+            minor: 0,
+            cipher: Cipher::ChaCha20,
+            compressed: true,
+            kdf: params,
+            public_data: None,
+            binaries: Vec::new(),
+            document: Document::new(),
+            key
+        })
+    }
+
+    pub fn read(path: &Path, password: &str) -> VaultResult<Kdbx> {
+        let bytes = fs::read(path)
+            .map_err(|err| VaultError::Error(format!("Could not read KDBX database {:?}: {}", path, err)))?;
+
+        Kdbx::open(&bytes, password)
+    }
+
+    // Skips the key derivation with a key kept by `rk agent`
+    pub fn read_key(path: &Path, key: &[u8]) -> VaultResult<Kdbx> {
+        let bytes = fs::read(path)
+            .map_err(|err| VaultError::Error(format!("Could not read KDBX database {:?}: {}", path, err)))?;
+
+        Kdbx::decode(&bytes, &|_| Ok(key.to_vec()))
+    }
+
+    pub fn open(bytes: &[u8], password: &str) -> VaultResult<Kdbx> {
+        Kdbx::decode(bytes, &|kdf| transform(kdf, password))
+    }
+
+    fn decode(bytes: &[u8], derive: &dyn Fn(&Dictionary) -> VaultResult<Vec<u8>>) -> VaultResult<Kdbx> {
+        let invalid = || VaultError::Error(String::from("Not a KDBX database"));
+
+        if bytes.len() < 12 || bytes[..8] != SIGNATURE {
+            return Err(invalid());
+        }
+
+        let minor = u16::from_le_bytes([bytes[8], bytes[9]]);
+        let major = u16::from_le_bytes([bytes[10], bytes[11]]);
+
+        if major != MAJOR {
+            return Err(VaultError::Error(format!("Unsupported KDBX version {}.{}, only KDBX 4 is", major, minor)));
+        }
+
+        let mut at = 12;
+        let mut cipher = None;
+        let mut compressed = false;
+        let mut seed = Vec::new();
+        let mut iv = Vec::new();
+        let mut kdf = None;
+        let mut public_data = None;
+
+        loop {
+            let (id, data) = field(bytes, &mut at).ok_or_else(invalid)?;
+
+            match id {
+                END => break,
+                CIPHER => cipher = Some(Cipher::from_uuid(data)?),
+                COMPRESSION => compressed = data.first() == Some(&1),
+                MASTER_SEED => seed = data.to_vec(),
+                IV => iv = data.to_vec(),
+                KDF => kdf = Some(Dictionary::parse(data)?),
+                PUBLIC_DATA => public_data = Some(data.to_vec()),
+                _ => {}
+            }
+        }
+
+        let cipher = cipher.ok_or_else(invalid)?;
+        let kdf = kdf.ok_or_else(invalid)?;
+
+        if seed.len() != 32 || iv.len() != cipher.iv_len() {
+            return Err(invalid());
+        }
+
+        let header = &bytes[..at];
+        let hash = bytes.get(at..at + 32).ok_or_else(invalid)?;
+        let mac = bytes.get(at + 32..at + 64).ok_or_else(invalid)?;
+
+        if digest(Hash::SHA256, header) != hash {
+            return Err(VaultError::Error(String::from("Damaged KDBX header")));
+        }
+
+        let key = derive(&kdf)?;
+        let hmac_key = hmac_base(&seed, &key);
+
+        if Locker::hmac(&block_key(u64::MAX, &hmac_key), &[header]) != mac {
+            return Err(VaultError::Error(String::from("Wrong password or damaged KDBX database")));
+        }
+
+        let encrypted = read_blocks(&bytes[at + 64..], &hmac_key)?;
+        let decrypted = cipher.decrypt(&cipher_key(&seed, &key), &iv, &encrypted)?;
+        let payload = if compressed {
+            let mut inflated = Vec::new();
+
+            GzDecoder::new(&decrypted[..]).read_to_end(&mut inflated)?;
+            inflated
+        } else {
+            decrypted
+        };
+
+        let mut at = 0;
+        let mut stream = None;
+        let mut binaries = Vec::new();
+
+        loop {
+            let (id, data) = field(&payload, &mut at).ok_or_else(invalid)?;
+
+            match id {
+                END => break,
+                STREAM_ID if data != CHACHA20_STREAM.to_le_bytes() => {
+                    return Err(VaultError::Error(String::from("Unsupported KDBX inner stream, only ChaCha20 is")));
+                },
+                STREAM_KEY => stream = Some(inner_stream(data)),
+                BINARY => binaries.push(data.to_vec()),
+                _ => {}
+            }
+        }
+
+        let mut stream = stream.ok_or_else(invalid)?;
+        let contents = std::str::from_utf8(&payload[at..])
+            .map_err(|_| VaultError::Error(String::from("KDBX xml is not valid utf-8")))?;
+
+        let plain = xml::splice(contents, &mut |value| {
+            let mut bytes = Base64::decode_vec(value.trim())
+                .map_err(|_| VaultError::Error(String::from("Invalid KDBX protected value")))?;
+
+            stream.apply_keystream(&mut bytes);
+
+            let value = String::from_utf8(bytes)
+                .map_err(|_| VaultError::Error(String::from("KDBX protected value is not valid utf-8")))?;
+
+            Ok(xml::escape(&value))
+        })?;
+
+        Ok(Kdbx {
+            minor,
+            cipher,
+            compressed,
+            kdf,
+            public_data,
+            binaries,
+            document: Document::parse(&plain)?,
+            key
+        })
+    }
+
+    /* Methods */
+
+    // The password run through the kdf, what `rk unlock` hands to the agent
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    // Flags rk keeps in the public custom data of the header, eg. that
+    // the vault keeps an audit log. The header hmac covers them, so they
+    // can't be dropped without the key
+    pub fn flag(&self, name: &str) -> bool {
+        self.public_data.as_deref()
+            .and_then(|data| Dictionary::parse(data).ok())
+            .map(|data| data.get(name) == Some(&Variant::Bool(true)))
+            .unwrap_or(false)
+    }
+
+    pub fn set_flag(&mut self, name: &str) -> VaultResult<()> {
+        let mut data = match &self.public_data {
+            Some(data) => Dictionary::parse(data)?,
+            None => Dictionary::default()
+        };
+
+        data.set(name, Variant::Bool(true));
+        self.public_data = Some(data.to_bytes());
+
+        Ok(())
+    }
+
+    // `group_tags` turns the groups an entry is in into tags
+    pub fn items(&self, group_tags: bool) -> Vec<Item> {
+        self.document.items(group_tags)
+    }
+
+    pub fn update(&mut self, items: &[Item]) {
+        self.document.update(items);
+    }
+
+    pub fn seal(&self) -> VaultResult<Vec<u8>> {
+        let mut rng = OsRng::new().expect("Could not open the OS random source");
+        let mut seed = [0u8; 32];
+        let mut iv = vec![0u8; self.cipher.iv_len()];
+        let mut stream_key = [0u8; 64];
+
+        rng.fill_bytes(&mut seed);
+        rng.fill_bytes(&mut iv);
+        rng.fill_bytes(&mut stream_key);
+
+        let mut bytes = SIGNATURE.to_vec();
+
+        bytes.extend_from_slice(&self.minor.to_le_bytes());
+        bytes.extend_from_slice(&MAJOR.to_le_bytes());
+
+        push_field(&mut bytes, CIPHER, &self.cipher.uuid());
+        push_field(&mut bytes, COMPRESSION, &(self.compressed as u32).to_le_bytes());
+        push_field(&mut bytes, MASTER_SEED, &seed);
+        push_field(&mut bytes, IV, &iv);
+        push_field(&mut bytes, KDF, &self.kdf.to_bytes());
+
+        if let Some(public_data) = &self.public_data {
+            push_field(&mut bytes, PUBLIC_DATA, public_data);
+        }
+
+        push_field(&mut bytes, END, b"\r\n\r\n");
+
+        let hmac_key = hmac_base(&seed, &self.key);
+        let hash = digest(Hash::SHA256, &bytes);
+        let mac = Locker::hmac(&block_key(u64::MAX, &hmac_key), &[&bytes]);
+
+        let mut stream = inner_stream(&stream_key);
+        let contents = xml::splice(&self.document.to_xml(), &mut |value| {
+            let mut bytes = value.as_bytes().to_vec();
+
+            stream.apply_keystream(&mut bytes);
+
+            Ok(Base64::encode_string(&bytes))
+        })?;
+
+        let mut payload = Vec::new();
+
+        push_field(&mut payload, STREAM_ID, &CHACHA20_STREAM.to_le_bytes());
+        push_field(&mut payload, STREAM_KEY, &stream_key);
+
+        for binary in self.binaries.iter() {
+            push_field(&mut payload, BINARY, binary);
+        }
+
+        push_field(&mut payload, END, &[]);
+        payload.extend_from_slice(contents.as_bytes());
+
+        if self.compressed {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+            encoder.write_all(&payload)?;
+            payload = encoder.finish()?;
+        }
+
+        let encrypted = self.cipher.encrypt(&cipher_key(&seed, &self.key), &iv, &payload);
+
+        bytes.extend_from_slice(&hash);
+        bytes.extend_from_slice(&mac);
+        write_blocks(&mut bytes, &encrypted, &hmac_key);
+
+        Ok(bytes)
+    }
+
+    // Written next to the database and renamed over it, so a
+    // failed save never leaves half a file behind
+    pub fn write(&self, path: &Path) -> VaultResult<()> {
+        let bytes = self.seal()?;
+        let mut tmp = path.as_os_str().to_os_string();
+
+        tmp.push(".tmp");
+
+        let mut file = File::create(&tmp)
+            .map_err(|err| VaultError::Error(format!("Could not write KDBX database {:?}: {}", path, err)))?;
+
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        header::restrict(Path::new(&tmp), 0o600)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+/* Functions */
+
+// The composite key of a password only database, run through the kdf
+fn transform(params: &Dictionary, password: &str) -> VaultResult<Vec<u8>> {
+    let composite = digest(Hash::SHA256, &digest(Hash::SHA256, password.as_bytes()));
+    let invalid = |err: String| VaultError::Error(format!("Invalid KDBX key derivation parameters: {}", err));
+    let uuid = params.bytes("$UUID")?;
+
+    if uuid == AES_KDF {
+        let seed = params.bytes("S")?;
+        let rounds = params.number("R")?;
+
+        if seed.len() != 32 {
+            return Err(invalid(String::from("the AES-KDF seed is not 32 bytes")));
+        }
+
+        let cipher = Aes256::new(GenericArray::from_slice(seed));
+        let mut key = composite;
+
+        for block in key.chunks_mut(16) {
+            let block = GenericArray::from_mut_slice(block);
+
+            for _ in 0..rounds {
+                cipher.encrypt_block(block);
+            }
+        }
+
+        return Ok(digest(Hash::SHA256, &key));
+    }
+
+    let algorithm = match uuid {
+        id if id == ARGON2D => Algorithm::Argon2d,
+        id if id == ARGON2ID => Algorithm::Argon2id,
+        _ => return Err(invalid(String::from("unsupported function")))
+    };
+
+    let version = match params.number("V")? {
+        0x10 => Version::V0x10,
+        0x13 => Version::V0x13,
+        version => return Err(invalid(format!("unsupported argon2 version {:#x}", version)))
+    };
+
+    let memory = (params.number("M")? / 1024).try_into().map_err(|_| invalid(String::from("memory too large")))?;
+    let iterations = params.number("I")?.try_into().map_err(|_| invalid(String::from("too many iterations")))?;
+    let parallelism = params.number("P")?.try_into().map_err(|_| invalid(String::from("parallelism too large")))?;
+    let argon = Params::new(memory, iterations, parallelism, Some(32))
+        .map_err(|err| invalid(err.to_string()))?;
+
+    let mut key = vec![0u8; 32];
+
+    Argon2::new(algorithm, version, argon)
+        .hash_password_into(&composite, params.bytes("S")?, &mut key)
+        .map_err(|err| invalid(err.to_string()))?;
+
+    Ok(key)
+}
+
+fn cipher_key(seed: &[u8], key: &[u8]) -> Vec<u8> {
+    digest(Hash::SHA256, &[seed, key].concat())
+}
+
+fn hmac_base(seed: &[u8], key: &[u8]) -> Vec<u8> {
+    digest(Hash::SHA512, &[seed, key, &[1]].concat())
+}
+
+// Every payload block has its own key, the header uses `u64::MAX`
+fn block_key(index: u64, base: &[u8]) -> Vec<u8> {
+    digest(Hash::SHA512, &[&index.to_le_bytes(), base].concat())
+}
+
+fn inner_stream(key: &[u8]) -> ChaCha20 {
+    let hash = digest(Hash::SHA512, key);
+
+    ChaCha20::new(hash[..32].into(), hash[32..44].into())
+}
+
+// One byte id and a little endian u32 size, `None` when truncated
+fn field<'b>(bytes: &'b [u8], at: &mut usize) -> Option<(u8, &'b [u8])> {
+    let id = *bytes.get(*at)?;
+    let size = u32::from_le_bytes(bytes.get(*at + 1..*at + 5)?.try_into().unwrap()) as usize;
+    let data = bytes.get(*at + 5..*at + 5 + size)?;
+
+    *at += 5 + size;
+
+    Some((id, data))
+}
+
+fn push_field(bytes: &mut Vec<u8>, id: u8, data: &[u8]) {
+    bytes.push(id);
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+fn read_blocks(bytes: &[u8], hmac_key: &[u8]) -> VaultResult<Vec<u8>> {
+    let damaged = || VaultError::Error(String::from("Damaged KDBX payload"));
+    let mut data = Vec::new();
+    let mut at = 0;
+
+    for index in 0u64.. {
+        let mac = bytes.get(at..at + 32).ok_or_else(damaged)?;
+        let size = bytes.get(at + 32..at + 36).ok_or_else(damaged)?;
+        let length = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+        let block = bytes.get(at + 36..at + 36 + length).ok_or_else(damaged)?;
+
+        if Locker::hmac(&block_key(index, hmac_key), &[&index.to_le_bytes(), size, block]) != mac {
+            return Err(damaged());
+        }
+
+        if block.is_empty() {
+            break;
+        }
+
+        data.extend_from_slice(block);
+        at += 36 + length;
+    }
+
+    Ok(data)
+}
+
+fn write_blocks(bytes: &mut Vec<u8>, data: &[u8], hmac_key: &[u8]) {
+    let blocks = data.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+
+    for (index, block) in blocks.enumerate() {
+        let index = index as u64;
+        let size = (block.len() as u32).to_le_bytes();
+
+        bytes.extend_from_slice(&Locker::hmac(&block_key(index, hmac_key), &[&index.to_le_bytes(), &size, block]));
+        bytes.extend_from_slice(&size);
+        bytes.extend_from_slice(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::fields::{Fields, URL};
+    use crate::import::item;
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `kdbx` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), String::from("https://github.com"));
+        fields.insert(String::from("otp"), String::from("JBSWY3DP"));
+
+        vec![
+            item("github", "me", "t<k&n>", fields),
+            item("google", "me@gmail.com", "secret", Fields::new())
+        ]
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let mut database = Kdbx::new("master", &Kdf::new(8, 1, 1)).unwrap();
+
+        database.update(&items());
+
+        let sealed = database.seal().unwrap();
+        let opened = Kdbx::open(&sealed, "master").unwrap();
+
+        assert_eq!(opened.items(false), items());
+        assert!(Kdbx::open(&sealed, "wrong").is_err());
+        assert!(Kdbx::open(b"not a database", "master").is_err());
+    }
+
+    #[test]
+    fn open_aes_kdf_and_cipher() {
+        let mut database = Kdbx::new("master", &Kdf::new(8, 1, 1)).unwrap();
+        let mut params = Dictionary::default();
+
+        params.set("$UUID", Variant::Bytes(AES_KDF.to_vec()));
+        params.set("S", Variant::Bytes(vec![7; 32]));
+        params.set("R", Variant::UInt64(10));
+
+        database.key = transform(&params, "master").unwrap();
+        database.kdf = params;
+        database.cipher = Cipher::Aes256;
+        database.compressed = false;
+        database.update(&items());
+
+        let opened = Kdbx::open(&database.seal().unwrap(), "master").unwrap();
+
+        assert_eq!(opened.cipher, Cipher::Aes256);
+        assert_eq!(opened.items(false), items());
+    }
+
+    #[test]
+    fn open_tampered() {
+        let mut database = Kdbx::new("master", &Kdf::new(8, 1, 1)).unwrap();
+
+        database.update(&items());
+
+        let mut sealed = database.seal().unwrap();
+        let last = sealed.len() - 40;
+
+        sealed[last] ^= 1;
+
+        assert!(Kdbx::open(&sealed, "master").is_err());
+    }
+
+    #[test]
+    fn keep_flags() {
+        let mut database = Kdbx::new("master", &Kdf::new(8, 1, 1)).unwrap();
+
+        assert!(!database.flag("rk.audit"));
+
+        database.set_flag("rk.audit").unwrap();
+
+        let opened = Kdbx::open(&database.seal().unwrap(), "master").unwrap();
+
+        assert!(opened.flag("rk.audit"));
+        assert!(!opened.flag("rk.other"));
+    }
+
+    #[test]
+    fn write_and_read() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (_, locker) = this.as_path_buf();
+                let path = locker.join("vault.kdbx");
+                let mut database = Kdbx::new("master", &Kdf::new(8, 1, 1)).unwrap();
+
+                header::private_dir(&locker).unwrap();
+                database.update(&items());
+                database.write(&path).unwrap();
+
+                let mut read = Kdbx::read(&path, "master").unwrap();
+
+                read.update(&items()[..1]);
+                read.write(&path).unwrap();
+
+                assert_eq!(Kdbx::read(&path, "master").unwrap().items(false), items()[..1].to_vec());
+                assert_eq!(Kdbx::read_key(&path, read.key()).unwrap().items(false), items()[..1].to_vec());
+                assert!(Kdbx::read_key(&path, &[0; 32]).is_err());
+            }
+        };
+    }
+}
@@ -0,0 +1,190 @@
+use std::convert::TryInto;
+
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// Only the major byte has to match, minor versions stay readable
+const VERSION: u16 = 0x0100;
+const CRITICAL: u16 = 0xFF00;
+
+const END: u8 = 0x00;
+const UINT32: u8 = 0x04;
+const UINT64: u8 = 0x05;
+const BOOL: u8 = 0x08;
+const INT32: u8 = 0x0C;
+const INT64: u8 = 0x0D;
+const STRING: u8 = 0x18;
+const BYTES: u8 = 0x42;
+
+/* Variant enum definition */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Variant {
+    UInt32(u32),
+    UInt64(u64),
+    Bool(bool),
+    Int32(i32),
+    Int64(i64),
+    String(String),
+    Bytes(Vec<u8>)
+}
+
+/* Dictionary struct definition */
+
+// KeePass' VariantDictionary, used for the key derivation parameters.
+// Entries keep the order they were read in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dictionary(Vec<(String, Variant)>);
+
+impl Dictionary {
+
+    /* Intialisers */
+
+    pub fn parse(bytes: &[u8]) -> VaultResult<Dictionary> {
+        let invalid = || VaultError::Error(String::from("Invalid KDBX key derivation parameters"));
+        let mut dictionary = Dictionary::default();
+        let mut at = 2;
+
+        if bytes.len() < 2 {
+            return Err(invalid());
+        }
+
+        if u16::from_le_bytes([bytes[0], bytes[1]]) & CRITICAL > VERSION & CRITICAL {
+            return Err(VaultError::Error(String::from("Unsupported KDBX parameters version")));
+        }
+
+        loop {
+            let kind = *bytes.get(at).ok_or_else(invalid)?;
+
+            at += 1;
+
+            if kind == END {
+                return Ok(dictionary);
+            }
+
+            let key = read_sized(bytes, &mut at).ok_or_else(invalid)?;
+            let key = String::from_utf8(key.to_vec()).map_err(|_| invalid())?;
+            let value = read_sized(bytes, &mut at).ok_or_else(invalid)?;
+
+            let variant = match (kind, value.len()) {
+                (UINT32, 4) => Variant::UInt32(u32::from_le_bytes(value.try_into().unwrap())),
+                (UINT64, 8) => Variant::UInt64(u64::from_le_bytes(value.try_into().unwrap())),
+                (BOOL, 1) => Variant::Bool(value[0] != 0),
+                (INT32, 4) => Variant::Int32(i32::from_le_bytes(value.try_into().unwrap())),
+                (INT64, 8) => Variant::Int64(i64::from_le_bytes(value.try_into().unwrap())),
+                (STRING, _) => Variant::String(String::from_utf8(value.to_vec()).map_err(|_| invalid())?),
+                (BYTES, _) => Variant::Bytes(value.to_vec()),
+                _ => return Err(invalid())
+            };
+
+            dictionary.0.push((key, variant));
+        }
+    }
+
+    /* Methods */
+
+    pub fn get(&self, key: &str) -> Option<&Variant> {
+        self.0.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn set(&mut self, key: &str, value: Variant) {
+        match self.0.iter_mut().find(|(name, _)| name == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((key.to_string(), value))
+        }
+    }
+
+    // Accepts both widths, writers disagree on which one they use
+    pub fn number(&self, key: &str) -> VaultResult<u64> {
+        match self.get(key) {
+            Some(Variant::UInt32(value)) => Ok(*value as u64),
+            Some(Variant::UInt64(value)) => Ok(*value),
+            _ => Err(VaultError::Error(format!("Missing KDBX key derivation parameter {:?}", key)))
+        }
+    }
+
+    pub fn bytes(&self, key: &str) -> VaultResult<&[u8]> {
+        match self.get(key) {
+            Some(Variant::Bytes(value)) => Ok(value),
+            _ => Err(VaultError::Error(format!("Missing KDBX key derivation parameter {:?}", key)))
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = VERSION.to_le_bytes().to_vec();
+
+        for (key, value) in self.0.iter() {
+            let (kind, value) = match value {
+                Variant::UInt32(value) => (UINT32, value.to_le_bytes().to_vec()),
+                Variant::UInt64(value) => (UINT64, value.to_le_bytes().to_vec()),
+                Variant::Bool(value) => (BOOL, vec![*value as u8]),
+                Variant::Int32(value) => (INT32, value.to_le_bytes().to_vec()),
+                Variant::Int64(value) => (INT64, value.to_le_bytes().to_vec()),
+                Variant::String(value) => (STRING, value.as_bytes().to_vec()),
+                Variant::Bytes(value) => (BYTES, value.clone())
+            };
+
+            bytes.push(kind);
+            bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&value);
+        }
+
+        bytes.push(END);
+        bytes
+    }
+}
+
+/* Functions */
+
+// A little endian u32 length followed by that many bytes
+fn read_sized<'b>(bytes: &'b [u8], at: &mut usize) -> Option<&'b [u8]> {
+    let size = bytes.get(*at..*at + 4)?;
+    let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+    let value = bytes.get(*at + 4..*at + 4 + size)?;
+
+    *at += 4 + size;
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut dictionary = Dictionary::default();
+
+        dictionary.set("$UUID", Variant::Bytes(vec![1, 2, 3]));
+        dictionary.set("I", Variant::UInt64(2));
+        dictionary.set("P", Variant::UInt32(1));
+        dictionary.set("name", Variant::String(String::from("argon2")));
+        dictionary.set("I", Variant::UInt64(4));
+
+        let parsed = Dictionary::parse(&dictionary.to_bytes()).unwrap();
+
+        assert_eq!(parsed, dictionary);
+        assert_eq!(parsed.number("I").unwrap(), 4);
+        assert_eq!(parsed.number("P").unwrap(), 1);
+        assert_eq!(parsed.bytes("$UUID").unwrap(), &[1, 2, 3]);
+        assert!(parsed.number("M").is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let mut truncated = Dictionary::default();
+
+        truncated.set("S", Variant::Bytes(vec![0; 32]));
+
+        let bytes = truncated.to_bytes();
+
+        assert!(Dictionary::parse(&bytes[..bytes.len() - 4]).is_err());
+        assert!(Dictionary::parse(&[0x00, 0x02, 0x00]).is_err());
+        assert!(Dictionary::parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64ct::{Base64, Encoding};
+use rand::{Rng, OsRng};
+use regex::Regex;
+use roxmltree::{Document as Xml, Node};
+
+use crate::archive::Item;
+use crate::fields::{self, Fields, URL, NOTES, TAGS};
+use crate::import::keepass::{self, STANDARD};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+const TITLE: &str = "Title";
+const USERNAME: &str = "UserName";
+const PASSWORD: &str = "Password";
+
+// Seconds between 0001-01-01, where KDBX 4 times start, and the unix epoch
+const EPOCH_OFFSET: i64 = 62_135_596_800;
+
+// KeePass writes an all zero uuid when there is no recycle bin yet
+const NO_UUID: &str = "AAAAAAAAAAAAAAAAAAAAAA==";
+
+/* Custom types */
+
+// What rk knows an entry by, its entity and account
+type Key = (String, Option<String>);
+
+/* Text struct definition */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Text {
+    pub key: String,
+    pub value: String,
+    pub protected: bool
+}
+
+/* Entry struct definition */
+
+// Everything rk doesn't map, eg. history, attachments and
+// icons, is kept as raw xml in `extra` and written back as is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    uuid: String,
+    tags: String,
+    times: String,
+    strings: Vec<Text>,
+    extra: String
+}
+
+impl Entry {
+
+    /* Intialisers */
+
+    fn new(item: &Item) -> Entry {
+        let mut entry = Entry {
+            uuid: uuid(),
+            tags: String::new(),
+            times: times(&now()),
+            strings: Vec::new(),
+            extra: String::new()
+        };
+
+        entry.set(TITLE, &item.entity);
+        entry.set(USERNAME, item.account.as_deref().unwrap_or(""));
+        entry.apply(item);
+        entry
+    }
+
+    fn parse(node: Node, xml: &str) -> Entry {
+        let mut strings = Vec::new();
+        let mut times = String::new();
+        let mut extra = String::new();
+
+        for child in node.children().filter(|child| child.is_element()) {
+            match child.tag_name().name() {
+                "UUID" | "Tags" => {},
+                "Times" => times = raw(child, xml),
+                "String" => {
+                    let value = child.children().find(|value| value.has_tag_name("Value"));
+
+                    strings.push(Text {
+                        key: text(child, "Key"),
+                        value: value.and_then(|value| value.text()).unwrap_or("").to_string(),
+                        protected: value.and_then(|value| value.attribute("Protected")) == Some("True")
+                    });
+                },
+                _ => extra.push_str(&raw(child, xml))
+            }
+        }
+
+        Entry {
+            uuid: text(node, "UUID"),
+            tags: text(node, "Tags"),
+            times,
+            strings,
+            extra
+        }
+    }
+
+    /* Methods */
+
+    pub fn get(&self, key: &str) -> &str {
+        self.strings.iter()
+            .find(|string| string.key == key)
+            .map(|string| string.value.as_str())
+            .unwrap_or("")
+    }
+
+    // Custom strings are dropped once they are empty, the standard
+    // ones are always there. Passwords are protected unless the
+    // entry already says otherwise
+    fn set(&mut self, key: &str, value: &str) {
+        if value.is_empty() && !STANDARD.contains(&key) {
+            self.strings.retain(|string| string.key != key);
+
+            return;
+        }
+
+        match self.strings.iter_mut().find(|string| string.key == key) {
+            Some(string) => string.value = value.to_string(),
+            None => self.strings.push(Text {
+                key: key.to_string(),
+                value: value.to_string(),
+                protected: key == PASSWORD
+            })
+        }
+    }
+
+    pub fn item(&self, path: &[String]) -> Item {
+        let strings: Fields = self.strings.iter()
+            .map(|string| (string.key.clone(), string.value.clone()))
+            .collect();
+
+        keepass::entry(&strings, &self.tags, path)
+    }
+
+    // Title and username are what the entry is matched by, so
+    // they are left alone
+    fn apply(&mut self, item: &Item) {
+        let get = |key: &str| item.fields.get(key).map(|value| value.as_str()).unwrap_or("");
+
+        self.set(PASSWORD, item.password.as_deref().unwrap_or(""));
+        self.set("URL", get(URL));
+        self.set("Notes", get(NOTES));
+        self.tags = fields::tags(&item.fields).join(";");
+
+        let custom: Vec<String> = self.strings.iter()
+            .map(|string| string.key.clone())
+            .filter(|key| !STANDARD.contains(&key.as_str()))
+            .collect();
+
+        for key in custom.iter().filter(|key| !item.fields.contains_key(*key)) {
+            self.set(key, "");
+        }
+
+        for (key, value) in item.fields.iter().filter(|(key, _)| ![URL, NOTES, TAGS].contains(&key.as_str())) {
+            self.set(key, value);
+        }
+
+        self.touch();
+    }
+
+    fn touch(&mut self) {
+        let modified = Regex::new(r"<LastModificationTime>[^<]*</LastModificationTime>").unwrap();
+        let now = now();
+
+        if modified.is_match(&self.times) {
+            let replaced = format!("<LastModificationTime>{}</LastModificationTime>", now);
+
+            self.times = modified.replace(&self.times, replaced.as_str()).to_string();
+        } else {
+            self.times = times(&now);
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = format!("<Entry><UUID>{}</UUID>", self.uuid);
+
+        if !self.tags.is_empty() {
+            xml.push_str(&format!("<Tags>{}</Tags>", escape(&self.tags)));
+        }
+
+        xml.push_str(&self.times);
+
+        for string in self.strings.iter() {
+            let protected = if string.protected { " Protected=\"True\"" } else { "" };
+
+            xml.push_str(&format!(
+                "<String><Key>{}</Key><Value{}>{}</Value></String>",
+                escape(&string.key),
+                protected,
+                escape(&string.value)
+            ));
+        }
+
+        xml.push_str(&self.extra);
+        xml.push_str("</Entry>");
+        xml
+    }
+}
+
+/* Group struct definition */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    uuid: String,
+    name: String,
+    extra: String,
+    entries: Vec<Entry>,
+    groups: Vec<Group>
+}
+
+impl Group {
+
+    /* Intialisers */
+
+    fn new(name: &str) -> Group {
+        Group {
+            uuid: uuid(),
+            name: name.to_string(),
+            extra: times(&now()),
+            entries: Vec::new(),
+            groups: Vec::new()
+        }
+    }
+
+    fn parse(node: Node, xml: &str) -> Group {
+        let mut group = Group {
+            uuid: text(node, "UUID"),
+            name: text(node, "Name"),
+            extra: String::new(),
+            entries: Vec::new(),
+            groups: Vec::new()
+        };
+
+        for child in node.children().filter(|child| child.is_element()) {
+            match child.tag_name().name() {
+                "UUID" | "Name" => {},
+                "Entry" => group.entries.push(Entry::parse(child, xml)),
+                "Group" => group.groups.push(Group::parse(child, xml)),
+                _ => group.extra.push_str(&raw(child, xml))
+            }
+        }
+
+        group
+    }
+
+    /* Methods */
+
+    // Walks every entry outside the recycle bin that has a title,
+    // with the names of the groups below the root one
+    fn walk<'g>(&'g self, path: &[String], recycle_bin: Option<&str>, found: &mut Vec<(&'g Entry, Vec<String>)>) {
+        if recycle_bin == Some(self.uuid.as_str()) {
+            return;
+        }
+
+        for entry in self.entries.iter().filter(|entry| !entry.get(TITLE).trim().is_empty()) {
+            found.push((entry, path.to_vec()));
+        }
+
+        for group in self.groups.iter() {
+            let mut sub_path = path.to_vec();
+
+            sub_path.push(group.name.clone());
+            group.walk(&sub_path, recycle_bin, found);
+        }
+    }
+
+    fn find_mut(&mut self, key: &Key, recycle_bin: Option<&str>) -> Option<&mut Entry> {
+        if recycle_bin == Some(self.uuid.as_str()) {
+            return None;
+        }
+
+        if let Some(index) = self.entries.iter().position(|entry| self::key(&entry.item(&[])) == *key) {
+            return Some(&mut self.entries[index]);
+        }
+
+        self.groups.iter_mut().find_map(|group| group.find_mut(key, recycle_bin))
+    }
+
+    fn retain(&mut self, recycle_bin: Option<&str>, keep: &mut dyn FnMut(&Entry) -> bool) {
+        if recycle_bin == Some(self.uuid.as_str()) {
+            return;
+        }
+
+        self.entries.retain(|entry| keep(entry));
+
+        for group in self.groups.iter_mut() {
+            group.retain(recycle_bin, keep);
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = format!("<Group><UUID>{}</UUID><Name>{}</Name>{}", self.uuid, escape(&self.name), self.extra);
+
+        for entry in self.entries.iter() {
+            xml.push_str(&entry.to_xml());
+        }
+
+        for group in self.groups.iter() {
+            xml.push_str(&group.to_xml());
+        }
+
+        xml.push_str("</Group>");
+        xml
+    }
+}
+
+/* Document struct definition */
+
+// The inner xml of a database, with protected values in the clear
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    meta: String,
+    root: Group,
+    deleted: Vec<String>,
+    recycle_bin: Option<String>
+}
+
+impl Document {
+
+    /* Intialisers */
+
+    pub fn new() -> Document {
+        Document {
+            meta: String::from("<Meta><Generator>rk</Generator><DatabaseName>rk</DatabaseName></Meta>"),
+            root: Group::new("Root"),
+            deleted: Vec::new(),
+            recycle_bin: None
+        }
+    }
+
+    pub fn parse(xml: &str) -> VaultResult<Document> {
+        let invalid = |msg: String| VaultError::Error(format!("Invalid KDBX xml: {}", msg));
+        let document = Xml::parse(xml).map_err(|err| invalid(err.to_string()))?;
+        let file = document.root_element();
+
+        if !file.has_tag_name("KeePassFile") {
+            return Err(invalid(String::from("not a KeePass file")));
+        }
+
+        let root = child(file, "Root").ok_or_else(|| invalid(String::from("no root")))?;
+        let group = child(root, "Group").ok_or_else(|| invalid(String::from("no root group")))?;
+        let meta = child(file, "Meta");
+
+        let recycle_bin = meta
+            .filter(|meta| text(*meta, "RecycleBinEnabled") != "False")
+            .map(|meta| text(meta, "RecycleBinUUID"))
+            .filter(|uuid| !uuid.is_empty() && uuid != NO_UUID);
+
+        let deleted = child(root, "DeletedObjects")
+            .map(|deleted| {
+                deleted.children()
+                    .filter(|object| object.is_element())
+                    .map(|object| raw(object, xml))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Document {
+            meta: meta.map(|meta| raw(meta, xml)).unwrap_or_else(|| Document::new().meta),
+            root: Group::parse(group, xml),
+            deleted,
+            recycle_bin
+        })
+    }
+
+    /* Methods */
+
+    // `group_tags` tags entries with the groups they are in, like
+    // the xml import does
+    pub fn items(&self, group_tags: bool) -> Vec<Item> {
+        let mut found = Vec::new();
+
+        self.root.walk(&[], self.recycle_bin.as_deref(), &mut found);
+
+        found.iter()
+            .map(|(entry, path)| entry.item(if group_tags { path } else { &[] }))
+            .collect()
+    }
+
+    // Brings the entries in line with `items`. Entries are matched
+    // by entity and account so they keep their uuid, group, history
+    // and attachments, new ones land in the root group and the ones
+    // rk no longer has are recorded as deleted
+    pub fn update(&mut self, items: &[Item]) {
+        let recycle_bin = self.recycle_bin.clone();
+        let wanted: HashSet<Key> = items.iter().map(key).collect();
+        let mut removed = Vec::new();
+        let mut seen = HashSet::new();
+
+        // Entries without a title can't be held by rk, so rk never drops them
+        self.root.retain(recycle_bin.as_deref(), &mut |entry| {
+            let key = key(&entry.item(&[]));
+            let keep = key.0.is_empty() || wanted.contains(&key);
+
+            if !keep { removed.push(entry.uuid.clone()); }
+
+            keep
+        });
+
+        for item in items.iter().filter(|item| seen.insert(key(item))) {
+            match self.root.find_mut(&key(item), recycle_bin.as_deref()) {
+                Some(entry) if entry.item(&[]) != *item => entry.apply(item),
+                Some(_) => {},
+                None => self.root.entries.push(Entry::new(item))
+            }
+        }
+
+        let now = now();
+
+        for uuid in removed {
+            self.deleted.push(format!("<DeletedObject><UUID>{}</UUID><DeletionTime>{}</DeletionTime></DeletedObject>", uuid, now));
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>{}<Root>{}<DeletedObjects>{}</DeletedObjects></Root></KeePassFile>",
+            self.meta,
+            self.root.to_xml(),
+            self.deleted.concat()
+        )
+    }
+}
+
+/* Functions */
+
+// Replaces the contents of every protected value, in document
+// order since that is the order the inner stream runs in
+pub fn splice(xml: &str, transform: &mut dyn FnMut(&str) -> VaultResult<String>) -> VaultResult<String> {
+    let document = Xml::parse(xml)
+        .map_err(|err| VaultError::Error(format!("Invalid KDBX xml: {}", err)))?;
+
+    let mut spliced = String::with_capacity(xml.len());
+    let mut copied = 0;
+
+    let protected = document.descendants()
+        .filter(|node| node.has_tag_name("Value") && node.attribute("Protected") == Some("True"));
+
+    for value in protected {
+        let (first, last) = match (value.first_child(), value.last_child()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue
+        };
+
+        let start = first.range().start;
+
+        spliced.push_str(&xml[copied..start]);
+        spliced.push_str(&transform(value.text().unwrap_or(""))?);
+        copied = last.range().end;
+    }
+
+    spliced.push_str(&xml[copied..]);
+
+    Ok(spliced)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn key(item: &Item) -> Key {
+    (item.entity.clone(), item.account.clone())
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn text(node: Node, name: &str) -> String {
+    child(node, name)
+        .and_then(|child| child.text())
+        .unwrap_or("")
+        .to_string()
+}
+
+fn raw(node: Node, xml: &str) -> String {
+    xml[node.range()].to_string()
+}
+
+fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+    let mut rng = OsRng::new().expect("Could not open the OS random source");
+
+    rng.fill_bytes(&mut bytes);
+
+    Base64::encode_string(&bytes)
+}
+
+fn now() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+
+    Base64::encode_string(&(seconds + EPOCH_OFFSET).to_le_bytes())
+}
+
+fn times(now: &str) -> String {
+    format!(
+        "<Times><CreationTime>{0}</CreationTime><LastModificationTime>{0}</LastModificationTime>\
+        <LastAccessTime>{0}</LastAccessTime><ExpiryTime>{0}</ExpiryTime><Expires>False</Expires>\
+        <UsageCount>0</UsageCount><LocationChanged>{0}</LocationChanged></Times>",
+        now
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::import::item;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <Generator>KeePassXC</Generator>
+        <RecycleBinEnabled>True</RecycleBinEnabled>
+        <RecycleBinUUID>YmlufQ==</RecycleBinUUID>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdA==</UUID>
+            <Name>Database</Name>
+            <IsExpanded>True</IsExpanded>
+            <Entry>
+                <UUID>ZW50cnk=</UUID>
+                <IconID>1</IconID>
+                <Times><LastModificationTime>old</LastModificationTime></Times>
+                <String><Key>Title</Key><Value>Google</Value></String>
+                <String><Key>UserName</Key><Value>me@gmail.com</Value></String>
+                <String><Key>Password</Key><Value Protected="True">s3cr&amp;t</Value></String>
+                <String><Key>pin</Key><Value>0000</Value></String>
+                <History><Entry><String><Key>Password</Key><Value Protected="True">old</Value></String></Entry></History>
+            </Entry>
+            <Group>
+                <UUID>d29yaw==</UUID>
+                <Name>Work</Name>
+                <Entry>
+                    <UUID>amlyYQ==</UUID>
+                    <Tags>jira</Tags>
+                    <String><Key>Title</Key><Value>Jira</Value></String>
+                    <String><Key>UserName</Key><Value>me</Value></String>
+                    <String><Key>Password</Key><Value Protected="True"></Value></String>
+                </Entry>
+            </Group>
+            <Group>
+                <UUID>YmlufQ==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                </Entry>
+            </Group>
+            <Entry>
+                <UUID>bm90aXRsZQ==</UUID>
+                <String><Key>UserName</Key><Value>untitled</Value></String>
+            </Entry>
+        </Group>
+        <DeletedObjects/>
+    </Root>
+</KeePassFile>"#;
+
+    #[test]
+    fn parse_items() {
+        let document = Document::parse(XML).unwrap();
+        let tagged = document.items(true);
+        let items = document.items(false);
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].entity, "Google");
+        assert_eq!(items[0].password, Some(String::from("s3cr&t")));
+        assert_eq!(items[0].fields["pin"], "0000");
+        assert_eq!(items[1].fields[TAGS], "jira");
+        assert_eq!(items[1].password, None);
+        assert_eq!(tagged[1].fields[TAGS], "Work,jira");
+    }
+
+    #[test]
+    fn update_keeps_unknown_data() {
+        let mut document = Document::parse(XML).unwrap();
+        let mut items = document.items(false);
+
+        items[0].password = Some(String::from("new"));
+        items.remove(1);
+        items.push(item("github", "me", "token", Fields::new()));
+
+        document.update(&items);
+
+        let xml = document.to_xml();
+        let parsed = Document::parse(&xml).unwrap();
+
+        assert_eq!(parsed.items(false), items);
+        assert!(xml.contains("<UUID>ZW50cnk=</UUID>"));
+        assert!(xml.contains("<IconID>1</IconID>"));
+        assert!(xml.contains("<Value Protected=\"True\">old</Value>"));
+        assert!(!xml.contains("<LastModificationTime>old</LastModificationTime>"));
+        assert!(xml.contains("<DeletedObject><UUID>amlyYQ==</UUID>"));
+        assert!(xml.contains("<Name>Work</Name>"));
+        assert!(xml.contains("Recycle Bin"));
+        assert!(xml.contains("<UUID>bm90aXRsZQ==</UUID>"));
+    }
+
+    #[test]
+    fn update_new_document() {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), String::from("https://github.com"));
+        fields.insert(TAGS.to_string(), String::from("code,work"));
+
+        let items = vec![
+            item("github", "me", "token", fields),
+            item("empty", "", "", Fields::new())
+        ];
+
+        let mut document = Document::new();
+
+        document.update(&items);
+
+        assert_eq!(Document::parse(&document.to_xml()).unwrap().items(false), items);
+    }
+
+    #[test]
+    fn splice_protected() {
+        let spliced = splice(XML, &mut |value| Ok(format!("[{}]", escape(value)))).unwrap();
+
+        assert!(spliced.contains("<Value Protected=\"True\">[s3cr&amp;t]</Value>"));
+        assert!(spliced.contains("<Value Protected=\"True\">[old]</Value>"));
+        assert!(spliced.contains("<Value Protected=\"True\"></Value>"));
+        assert!(spliced.contains("<Value>0000</Value>"));
+    }
+}
@@ -1,17 +1,49 @@
+mod archive;
 mod args;
+mod fields;
+mod header;
+mod import;
+mod kdbx;
 mod locker;
 mod managers;
 mod mocks;
+mod search;
 mod vault;
 mod settings;
 mod tables;
 
-use std::path::PathBuf;
+pub mod agent;
+pub mod audit;
+pub mod credential;
+pub mod git;
+pub mod inject;
+pub mod merge;
+pub mod native;
+pub mod ssh;
+pub mod clipboard;
+pub mod output;
+pub mod run;
+pub mod server;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use audit::{Audit, Event};
+use git::Git;
+use merge::{Change, Snapshot};
+use search::Entry;
 
 pub use tables::*;
 pub use args::Args;
+pub use fields::{Fields, URL, TAGS, NOTES};
+pub use output::{Format, Record};
 pub use vault::{Vault, VaultResult, VaultError};
-pub use locker::{Locker, Distinguished, Encrypted};
+pub use settings::{Settings, SettingsOpts, Origin, Profile, GitHost};
+pub use locker::{Locker, Distinguished, Encrypted, Seal};
+pub use header::{Header, Kdf};
+pub use archive::{Archive, Item, Mode};
+pub use import::{pass, plaintext, Source, Duplicates, Action, Gpg};
+pub use kdbx::Kdbx;
 
 #[derive(Debug, PartialEq)]
 pub enum Resolve {
@@ -19,7 +51,11 @@ pub enum Resolve {
     Failure,
     Read(String),
     Find(Vec<(String, String)>),
-    List(Vec<String>)
+    List(Vec<String>),
+    Fields(Fields),
+    Search(Vec<(String, String)>),
+    // A child's exit code, rk exits with it once done
+    Exit(i32)
 }
 
 impl Resolve {
@@ -37,31 +73,302 @@ impl Resolve {
         if let Resolve::Read(string) = self { return string; }
         panic!("to_string should be called on a Resolve::Read only");
     }
+
+    pub fn to_fields(self) -> Fields {
+        if let Resolve::Fields(fields) = self { return fields; }
+        panic!("to_fields should be called on a Resolve::Fields only");
+    }
+
+    pub fn to_matches(self) -> Vec<(String, String)> {
+        if let Resolve::Search(vec) = self { return vec; }
+        panic!("to_matches should be called on a Resolve::Search only");
+    }
 }
 
-pub struct Keeper { vault: Vault }
+pub struct Keeper {
+    vault: Vault,
+    kdbx: Option<(PathBuf, Kdbx)>,
+    audit: Option<(Audit, String)>,
+    git: Option<Git>,
+    key: Option<Vec<u8>>,
+    locker: Option<PathBuf>
+}
 
 impl Keeper {
     pub fn new(config: PathBuf, locker: PathBuf) -> VaultResult<Keeper> {
         let vault = Vault::new(&config, &locker)?;
-        let keeper = Keeper { vault };
+        let keeper = Keeper { vault, kdbx: None, audit: None, git: Git::open(&locker), key: None, locker: None };
+
+        Ok(keeper)
+    }
+
+    // A vault kept in a KDBX database instead of a locker, every change
+    // is written back to it. Only the first of several entries with the
+    // same entity and account is used, the others are left untouched
+    pub fn open_kdbx(path: PathBuf, password: &str) -> VaultResult<Keeper> {
+        if !path.is_file() {
+            return Err(VaultError::MissingVault(path.to_string_lossy().to_string()));
+        }
+
+        let database = Kdbx::read(&path, password)?;
+
+        Keeper::from_kdbx(path, database)
+    }
+
+    // Opens a KDBX backed vault with the key a running agent keeps for it,
+    // `password` is only asked for when there is none or it no longer fits
+    pub fn unlock_kdbx<F>(path: PathBuf, socket: &Path, password: F) -> VaultResult<Keeper>
+    where F: FnOnce() -> VaultResult<String> {
+        if !path.is_file() {
+            return Err(VaultError::MissingVault(path.to_string_lossy().to_string()));
+        }
+
+        let cached = agent::key(socket, &path)
+            .and_then(|key| Kdbx::read_key(&path, &key).ok());
+
+        match cached {
+            Some(database) => Keeper::from_kdbx(path, database),
+            None => Keeper::open_kdbx(path, &password()?)
+        }
+    }
+
+    fn from_kdbx(path: PathBuf, database: Kdbx) -> VaultResult<Keeper> {
+        let mut seen = HashSet::new();
+        let items: Vec<Item> = database.items(false)
+            .into_iter()
+            .filter(|item| seen.insert((item.entity.clone(), item.account.clone())))
+            .collect();
+
+        let mut keeper = Keeper { vault: Vault::memory(), kdbx: None, audit: None, git: None, key: None, locker: None };
+
+        keeper.import(Archive::new(items), Mode::Merge)?;
+        keeper.kdbx = Some((path, database));
+
+        Ok(keeper)
+    }
+
+    pub fn init_kdbx(path: PathBuf, password: &str, kdf: Kdf) -> VaultResult<Keeper> {
+        if path.exists() {
+            return Err(VaultError::ExistingVault(path.to_string_lossy().to_string()));
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            header::shared_dir(dir)?;
+        }
+
+        let database = Kdbx::new(password, &kdf)?;
+
+        database.write(&path)?;
+
+        Ok(Keeper { vault: Vault::memory(), kdbx: Some((path, database)), audit: None, git: None, key: None, locker: None })
+    }
+
+    // Every add, find, read, remove and export is recorded in `audit` as
+    // done by `actor`, before its result is handed out. The vault notes
+    // that it keeps a log, so that one removed along with its head fails
+    // here instead of starting over
+    pub fn with_audit(mut self, audit: Audit, actor: &str) -> VaultResult<Keeper> {
+        let id = audit.id();
+        let kept = self.keeps_audit(&id)?;
+
+        audit.begin(kept)?;
+
+        if !kept {
+            self.keep_audit(&id)?;
+        }
+
+        self.audit = Some((audit, actor.to_string()));
+
+        Ok(self)
+    }
+
+    // Hands the audit log over, eg. to a server recording each request
+    // itself
+    pub fn take_audit(&mut self) -> Option<Audit> {
+        self.audit.take().map(|(audit, _)| audit)
+    }
+
+    fn keeps_audit(&self, id: &str) -> VaultResult<bool> {
+        match (&self.kdbx, &self.locker) {
+            (Some((_, database)), _) => Ok(database.flag(&format!("{}{}", audit::FLAG, id))),
+            (None, Some(locker)) => Ok(Keeper::header(locker)?.audits.iter().any(|kept| kept == id)),
+            (None, None) => Ok(false)
+        }
+    }
+
+    // Lockers opened with `new` have no header to note it in
+    fn keep_audit(&mut self, id: &str) -> VaultResult<()> {
+        if let Some((path, database)) = self.kdbx.as_mut() {
+            database.set_flag(&format!("{}{}", audit::FLAG, id))?;
+
+            return database.write(path);
+        }
+
+        if let (Some(locker), Some(key)) = (&self.locker, &self.key) {
+            let mut header = Keeper::header(locker)?;
+
+            header.keep_audit(key, id);
+            header.write(locker)?;
+
+            if let Some(git) = &self.git {
+                git.commit("Keep an audit log")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // An operation that can't be recorded fails, whatever its result
+    fn audited<T>(&mut self, action: &str, args: &Args, result: VaultResult<T>) -> VaultResult<T> {
+        if let Some((audit, actor)) = self.audit.as_mut() {
+            let entity = Locker::from_encrypted(&args.entity).decrypt();
+            let account = Locker::from_encrypted(&args.account).decrypt();
+            let named = |name: &str| Some(name.to_string()).filter(|name| !name.is_empty());
+            let event = Event::new(
+                actor,
+                action,
+                named(&entity).as_deref(),
+                named(&account).as_deref(),
+                audit::outcome(&result)
+            );
+
+            audit.record(&event)?;
+        }
+
+        result
+    }
+
+    // What an agent has to keep for `rk unlock`
+    pub fn key(&self) -> Option<&[u8]> {
+        match &self.kdbx {
+            Some((_, database)) => Some(database.key()),
+            None => self.key.as_deref()
+        }
+    }
+
+    // What the entries of a locker are sealed under, `None` for lockers
+    // opened with `new` and KDBX backed vaults
+    pub fn seal(&self) -> Option<&Seal> {
+        self.vault.seal()
+    }
+
+    // Writes a KDBX backed vault back to its database, and commits the
+    // change to a locker kept in git. `change` never names an entry
+    fn sync(&mut self, change: &str) -> VaultResult<()> {
+        if let Some(git) = &self.git {
+            git.commit(change)?;
+        }
+
+        if self.kdbx.is_none() {
+            return Ok(());
+        }
+
+        let items = self.archive()?.items;
+        let (path, database) = self.kdbx.as_mut().unwrap();
+
+        database.update(&items);
+        database.write(path)
+    }
+
+    // Like `new`, but refuses lockers that `init` never set up instead
+    // of creating them on the fly, and only opens them with their master
+    // password
+    pub fn open(config: PathBuf, locker: PathBuf, password: &str) -> VaultResult<Keeper> {
+        let header = Keeper::header(&locker)?;
+        let key = header.unlock(password)?;
+
+        Keeper::sealed(config, locker, header, key)
+    }
+
+    // Like `open`, with the key a running agent keeps for the locker.
+    // `password` is only asked for when there is none or it no longer fits
+    pub fn unlock<F>(config: PathBuf, locker: PathBuf, socket: &Path, password: F) -> VaultResult<Keeper>
+    where F: FnOnce() -> VaultResult<String> {
+        let header = Keeper::header(&locker)?;
+        let cached = agent::key(socket, &locker).filter(|key| header.fits(key));
+        let key = match cached {
+            Some(key) => key,
+            None => header.unlock(&password()?)?
+        };
+
+        Keeper::sealed(config, locker, header, key)
+    }
+
+    fn header(locker: &Path) -> VaultResult<Header> {
+        Header::read(locker)?.ok_or_else(|| VaultError::MissingVault(locker.to_string_lossy().to_string()))
+    }
+
+    // Lockers from an older header are sealed in place the first time
+    // they are opened, and committed if they are kept in git
+    fn sealed(config: PathBuf, locker: PathBuf, mut header: Header, key: Vec<u8>) -> VaultResult<Keeper> {
+        let current = header.version == header::VERSION;
+        let vault = match current {
+            true => Vault::sealed(&config, &locker, Seal::new(&key))?,
+            false => Vault::adopt(&config, &locker, Seal::new(&key))?
+        };
+        let mut keeper = Keeper { vault, kdbx: None, audit: None, git: Git::open(&locker), key: Some(key), locker: Some(locker.clone()) };
+
+        if !current {
+            header.upgrade(keeper.key.as_ref().unwrap());
+            header.write(&locker)?;
+            keeper.sync("Seal the vault under its master password")?;
+        }
 
         Ok(keeper)
     }
 
+    // Existing lockers without a header are adopted, and sealed in place
+    pub fn init(config: PathBuf, locker: PathBuf, password: &str, kdf: Kdf) -> VaultResult<Keeper> {
+        if Header::read(&locker)?.is_some() {
+            return Err(VaultError::ExistingVault(locker.to_string_lossy().to_string()));
+        }
+
+        let key = kdf.derive(password)?;
+        let header = Header::for_key(&key, kdf);
+        let vault = Vault::adopt(&config, &locker, Seal::new(&key))?;
+
+        header::private_dir(&config)?;
+        header::private_dir(&locker)?;
+        header.write(&locker)?;
+
+        Ok(Keeper { vault, kdbx: None, audit: None, git: Git::open(&locker), key: Some(key), locker: Some(locker) })
+    }
+
     pub fn add(&mut self, args: Args) -> VaultResult<Resolve> {
+        let names = args.clone();
+        let result = self.add_entry(args);
+
+        self.audited("add", &names, result)
+    }
+
+    fn add_entry(&mut self, args: Args) -> VaultResult<Resolve> {
         let Args {
             entity,
             account,
             password
         } = args;
 
+        let change = match (account.is_empty(), password.is_empty()) {
+            (true, _) => "Add an entity",
+            (false, true) => "Add an account",
+            (false, false) => "Save the password of an account"
+        };
+
         self.vault.set(&entity, &account, &password)?;
+        self.sync(change)?;
 
         Ok(Resolve::Done)
     }
 
     pub fn find(&mut self, args: Args) -> VaultResult<Resolve> {
+        let names = args.clone();
+        let result = self.find_entry(args);
+
+        self.audited("find", &names, result)
+    }
+
+    // Not recorded, for rk's own lookups such as api tokens
+    pub(crate) fn find_entry(&mut self, args: Args) -> VaultResult<Resolve> {
         let Args {
             entity,
             account,
@@ -128,6 +435,13 @@ impl Keeper {
     }
 
     pub fn read(&mut self, args: Args) -> VaultResult<Resolve> {
+        let names = args.clone();
+        let result = self.read_entry(args);
+
+        self.audited("read", &names, result)
+    }
+
+    fn read_entry(&mut self, args: Args) -> VaultResult<Resolve> {
         let Args {
             entity,
             account,
@@ -148,7 +462,14 @@ impl Keeper {
         Ok(Resolve::Read(decrypted))
     }
 
-    pub fn remove(&mut self, args: Args ) -> VaultResult<Resolve> {
+    pub fn remove(&mut self, args: Args) -> VaultResult<Resolve> {
+        let names = args.clone();
+        let result = self.remove_entry(args);
+
+        self.audited("remove", &names, result)
+    }
+
+    fn remove_entry(&mut self, args: Args ) -> VaultResult<Resolve> {
         let Args {
             entity,
             account,
@@ -163,11 +484,347 @@ impl Keeper {
 
         if !entity.is_empty() && account.is_empty() {
             self.vault.remove_entity(&entity)?;
+            self.sync("Remove an entity")?;
+        } else {
+            self.vault.remove_account(&entity, &account)?;
+            self.sync("Remove an account")?;
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    pub fn annotate(&mut self, args: Args, fields: Fields) -> VaultResult<Resolve> {
+        self.merge_fields(args, fields)?;
+        self.sync("Change the fields of an account")?;
 
-            return Ok(Resolve::Done);
+        Ok(Resolve::Done)
+    }
+
+    fn merge_fields(&mut self, args: Args, fields: Fields) -> VaultResult<()> {
+        let Args {
+            entity,
+            account,
+            ..
+        } = args;
+
+        if entity.is_empty() || account.is_empty() {
+            let err = VaultError::Error("Entity and account must be provided".to_string());
+
+            return Err(err);
+        }
+
+        let stored = self.vault.get_fields(&entity, &account)?;
+        let mut merged = fields::open(&stored)?;
+
+        for (name, value) in fields {
+            if value.is_empty() {
+                merged.remove(&name);
+            } else {
+                merged.insert(name, value);
+            }
         }
 
-        self.vault.remove_account(&entity, &account)?;
+        self.vault.set_fields(&entity, &account, &fields::seal(&merged)?)?;
+
+        Ok(())
+    }
+
+    pub fn fields(&mut self, args: Args) -> VaultResult<Resolve> {
+        let Args {
+            entity,
+            account,
+            ..
+        } = args;
+
+        let stored = self.vault.get_fields(&entity, &account)?;
+
+        Ok(Resolve::Fields(fields::open(&stored)?))
+    }
+
+    pub fn search(&mut self, query: &str) -> VaultResult<Resolve> {
+        let mut entries = Vec::new();
+
+        for entity in self.vault.list()? {
+            let entity_name = Locker::from_encrypted(&entity).decrypt();
+
+            for account in self.vault.list_accounts(&entity)? {
+                let stored = self.vault.get_fields(&entity, &account)?;
+
+                entries.push(Entry {
+                    entity: entity_name.clone(),
+                    account: Locker::from_encrypted(&account).decrypt(),
+                    fields: fields::open(&stored)?
+                });
+            }
+        }
+
+        Ok(Resolve::Search(search::rank(entries, query)))
+    }
+
+    pub fn export(&mut self) -> VaultResult<Archive> {
+        let result = self.archive();
+
+        self.audited("export", &Args::new(None, None, None), result)
+    }
+
+    // Every entry in the clear, without being recorded
+    fn archive(&mut self) -> VaultResult<Archive> {
+        let mut items = Vec::new();
+
+        for entity in self.vault.list()? {
+            let entity_name = Locker::from_encrypted(&entity).decrypt();
+            let accounts = self.vault.list_accounts(&entity)?;
+
+            if accounts.is_empty() {
+                items.push(Item {
+                    entity: entity_name.clone(),
+                    account: None,
+                    password: None,
+                    fields: Fields::new()
+                });
+            }
+
+            for account in accounts {
+                let stored_password = self.vault.get_account(&entity, &account)?;
+                let password = match stored_password.is_empty() {
+                    true => None,
+                    false => Some(Locker::from_encrypted(stored_password).decrypt())
+                };
+                let stored = self.vault.get_fields(&entity, &account)?;
+
+                items.push(Item {
+                    entity: entity_name.clone(),
+                    account: Some(Locker::from_encrypted(&account).decrypt()),
+                    password,
+                    fields: fields::open(&stored)?
+                });
+            }
+        }
+
+        Ok(Archive::new(items))
+    }
+
+    // Merging keeps what is not in the archive and lets the
+    // archive win for accounts found in both
+    pub fn import(&mut self, archive: Archive, mode: Mode) -> VaultResult<Resolve> {
+        let change = format!("Import {} items", archive.items.len());
+
+        if mode == Mode::Replace {
+            for entity in self.vault.list()? {
+                self.vault.remove_entity(&entity)?;
+            }
+        }
+
+        for item in archive.items {
+            let args = Args::new(
+                Some(&item.entity),
+                item.account.as_deref(),
+                item.password.as_deref()
+            );
+
+            self.vault.set(&args.entity, &args.account, &args.password)?;
+
+            if item.account.is_some() && !item.fields.is_empty() {
+                self.merge_fields(args, item.fields)?;
+            }
+        }
+
+        self.sync(&change)?;
+
+        Ok(Resolve::Done)
+    }
+
+    // Decides what happens to every item without touching the vault,
+    // duplicates are looked up in the vault and earlier in `items`
+    pub fn plan(&mut self, items: &[Item], duplicates: Duplicates) -> VaultResult<Vec<Action>> {
+        let mut entities = HashSet::new();
+        let mut accounts = HashSet::new();
+
+        for entity in self.vault.list()? {
+            let entity_name = Locker::from_encrypted(&entity).decrypt();
+
+            for account in self.vault.list_accounts(&entity)? {
+                accounts.insert((entity_name.clone(), Locker::from_encrypted(&account).decrypt()));
+            }
+
+            entities.insert(entity_name);
+        }
+
+        let actions = items.iter()
+            .map(|item| {
+                let new_entity = entities.insert(item.entity.clone());
+                let account = match &item.account {
+                    Some(account) => account,
+                    None if new_entity => return Action::Add,
+                    None => return Action::Skip
+                };
+
+                if accounts.insert((item.entity.clone(), account.clone())) {
+                    return Action::Add;
+                }
+
+                match duplicates {
+                    Duplicates::Skip => Action::Skip,
+                    Duplicates::Overwrite => Action::Overwrite,
+                    Duplicates::Rename => {
+                        let renamed = (2..)
+                            .map(|n| format!("{} ({})", account, n))
+                            .find(|renamed| accounts.insert((item.entity.clone(), renamed.clone())))
+                            .unwrap();
+
+                        Action::Rename(renamed)
+                    }
+                }
+            })
+            .collect();
+
+        Ok(actions)
+    }
+
+    pub fn import_items(&mut self, items: Vec<Item>, actions: &[Action]) -> VaultResult<Resolve> {
+        let imported = actions.iter().filter(|action| **action != Action::Skip).count();
+
+        for (item, action) in items.into_iter().zip(actions) {
+            let account = match action {
+                Action::Skip => continue,
+                Action::Rename(account) => Some(account.clone()),
+                Action::Add | Action::Overwrite => item.account.clone()
+            };
+
+            let args = Args::new(Some(&item.entity), account.as_deref(), item.password.as_deref());
+
+            self.vault.set(&args.entity, &args.account, &args.password)?;
+
+            if account.is_none() {
+                continue;
+            }
+
+            let stored = self.vault.get_fields(&args.entity, &args.account)?;
+
+            if !stored.is_empty() || !item.fields.is_empty() {
+                self.vault.set_fields(&args.entity, &args.account, &fields::seal(&item.fields)?)?;
+            }
+        }
+
+        self.sync(&format!("Import {} items", imported))?;
+
+        Ok(Resolve::Done)
+    }
+
+    // Every entry with the time it last changed, for `rk merge`
+    pub fn snapshot(&mut self) -> VaultResult<Snapshot> {
+        let mut items = Vec::new();
+
+        for item in self.archive()?.items {
+            let args = Args::new(Some(&item.entity), item.account.as_deref(), None);
+            let modified = match item.account {
+                Some(_) => self.vault.get_modified(&args.entity, &args.account)?,
+                None => 0
+            };
+
+            items.push((item, modified));
+        }
+
+        Ok(Snapshot::new(items))
+    }
+
+    // Applies what `rk merge` decided as a single change, keeping the
+    // modification times of the entries taken from the other vault. Each
+    // entry written is recorded as a merge
+    pub fn apply(&mut self, changes: &[Change]) -> VaultResult<Resolve> {
+        for change in changes {
+            let names = match change {
+                Change::AddEntity(entity) | Change::RemoveEntity(entity) => Args::new(Some(entity), None, None),
+                Change::RemoveAccount(entity, account) | Change::Set(entity, account, _) => Args::new(Some(entity), Some(account), None)
+            };
+            let result = self.apply_change(change);
+
+            self.audited("merge", &names, result)?;
+        }
+
+        if !changes.is_empty() {
+            self.sync(&format!("Merge {} changes", changes.len()))?;
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    fn apply_change(&mut self, change: &Change) -> VaultResult<()> {
+        match change {
+            Change::AddEntity(entity) => {
+                self.vault.set_entity(&Args::new(Some(entity), None, None).entity)?;
+            },
+            Change::RemoveEntity(entity) => {
+                self.vault.remove_entity(&Args::new(Some(entity), None, None).entity)?;
+            },
+            Change::RemoveAccount(entity, account) => {
+                let args = Args::new(Some(entity), Some(account), None);
+
+                self.vault.remove_account(&args.entity, &args.account)?;
+            },
+            Change::Set(entity, account, entry) => {
+                let args = Args::new(Some(entity), Some(account), entry.password.as_deref());
+
+                self.vault.set(&args.entity, &args.account, &args.password)?;
+
+                let stored = self.vault.get_fields(&args.entity, &args.account)?;
+
+                if !stored.is_empty() || !entry.fields.is_empty() {
+                    self.vault.set_fields(&args.entity, &args.account, &fields::seal(&entry.fields)?)?;
+                }
+
+                self.vault.set_modified(&args.entity, &args.account, entry.modified)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn rename(&mut self, args: Args, to: Encrypted) -> VaultResult<Resolve> {
+        let Args {
+            entity,
+            account,
+            ..
+        } = args;
+
+        if entity.is_empty() {
+            let err = VaultError::Error("Entity must be provided".to_string());
+
+            return Err(err);
+        }
+
+        if to.is_empty() {
+            let err = VaultError::Error("New name must be provided".to_string());
+
+            return Err(err);
+        }
+
+        if account.is_empty() {
+            self.vault.rename_entity(&entity, &to)?;
+            self.sync("Rename an entity")?;
+        } else {
+            self.vault.rename_account(&entity, &account, &to)?;
+            self.sync("Rename an account")?;
+        }
+
+        Ok(Resolve::Done)
+    }
+
+    pub fn move_account(&mut self, args: Args, to_entity: Encrypted) -> VaultResult<Resolve> {
+        let Args {
+            entity,
+            account,
+            ..
+        } = args;
+
+        if entity.is_empty() || account.is_empty() || to_entity.is_empty() {
+            let err = VaultError::Error("Entity, account and target entity must be provided".to_string());
+
+            return Err(err);
+        }
+
+        self.vault.move_account(&entity, &account, &to_entity)?;
+        self.sync("Move an account to another entity")?;
 
         Ok(Resolve::Done)
     }
@@ -180,7 +837,7 @@ mod keeper {
     use mocks::Setup;
 
     use std::path::Path;
-    use std::fs::{remove_dir_all, remove_file};
+    use std::fs::{self, remove_dir_all, remove_file};
     use std::panic::{AssertUnwindSafe, catch_unwind};
 
     fn after_each(this: &mut Setup) {
@@ -638,4 +1295,401 @@ mod keeper {
             }
         };
     }
+
+    #[test]
+    fn open_uninitialised() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let keeper = Keeper::open(config, locker.clone(), "master");
+
+                assert!(keeper.is_err());
+                assert!(!locker.exists());
+            }
+        };
+    }
+
+    #[test]
+    fn init() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+                keeper.add(Args::new(Some("entity"), None, None)).unwrap();
+
+                let mut opened = Keeper::open(config.clone(), locker.clone(), "master").unwrap();
+                let wrong = Keeper::open(config.clone(), locker.clone(), "wrong");
+                let again = Keeper::init(config, locker.clone(), "master", Kdf::new(8, 1, 1));
+                let list = opened.list(None).unwrap();
+                let stored = fs::read_dir(&locker).unwrap()
+                    .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                    .find(|name| !name.starts_with('.'))
+                    .unwrap();
+                let stored = Encrypted::from(&stored).unwrap();
+
+                assert_eq!(list, Resolve::List(vec![String::from("entity")]));
+                assert!(wrong.is_err());
+                assert_eq!(again.err().unwrap().exit_code(), 4);
+                assert!(Header::read(&locker).unwrap().unwrap().unlock("master").is_ok());
+                assert_ne!(Locker::from_encrypted(&stored).try_decrypt(), Some(String::from("entity")));
+                assert_ne!(stored.hash(), Locker::hash("entity"));
+            }
+        };
+    }
+
+    #[test]
+    fn seal_older_lockers() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut unsealed = Keeper::new(config.clone(), locker.clone()).unwrap();
+                let mut fields = Fields::new();
+
+                fields.insert(URL.to_string(), String::from("https://github.com"));
+                unsealed.add(Args::new(Some("entity"), Some("account"), Some("password"))).unwrap();
+                unsealed.annotate(Args::new(Some("entity"), Some("account"), None), fields.clone()).unwrap();
+
+                // A header as the first version wrote it
+                let header = Header::new("master", Kdf::new(8, 1, 1)).unwrap();
+                let key = header.unlock("master").unwrap();
+                let yaml: Vec<String> = serde_yaml::to_string(&header).unwrap()
+                    .lines()
+                    .map(|line| match line.split(':').next().unwrap() {
+                        "version" => String::from("version: 1"),
+                        "check" => format!("check: {}", crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, &key)),
+                        _ => line.to_string()
+                    })
+                    .collect();
+
+                fs::write(Header::path(&locker), yaml.join("\n")).unwrap();
+
+                let mut keeper = Keeper::open(config.clone(), locker.clone(), "master").unwrap();
+                let password = keeper.read(Args::new(Some("entity"), Some("account"), None)).unwrap();
+                let annotated = keeper.fields(Args::new(Some("entity"), Some("account"), None)).unwrap();
+                let mut reopened = Keeper::open(config, locker.clone(), "master").unwrap();
+
+                assert_eq!(password, Resolve::Read(String::from("password")));
+                assert_eq!(annotated, Resolve::Fields(fields));
+                assert_eq!(reopened.list(None).unwrap(), Resolve::List(vec![String::from("entity")]));
+                assert_eq!(Header::read(&locker).unwrap().unwrap().version, 2);
+            }
+        };
+    }
+
+    #[test]
+    fn export_and_import() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+                let mut fields = Fields::new();
+
+                fields.insert(URL.to_string(), "https://mail.google.com".to_string());
+
+                keeper.add(Args::new(Some("google"), Some("me@gmail.com"), Some("secret"))).unwrap();
+                keeper.add(Args::new(Some("empty"), None, None)).unwrap();
+                keeper.annotate(Args::new(Some("google"), Some("me@gmail.com"), None), fields.clone()).unwrap();
+
+                let archive = keeper.export().unwrap();
+                let google = archive.items.iter().find(|item| item.entity == "google").unwrap();
+
+                assert_eq!(archive.items.len(), 2);
+                assert_eq!(google.password, Some(String::from("secret")));
+                assert_eq!(google.fields, fields);
+
+                keeper.add(Args::new(Some("google"), Some("me@gmail.com"), Some("changed"))).unwrap();
+                keeper.add(Args::new(Some("github"), Some("me"), Some("token"))).unwrap();
+                keeper.import(archive.clone(), Mode::Merge).unwrap();
+
+                let password = keeper.read(Args::new(Some("google"), Some("me@gmail.com"), None)).unwrap();
+                let merged = keeper.list(None).unwrap().to_list();
+
+                assert_eq!(password, Resolve::Read(String::from("secret")));
+                assert_eq!(merged.len(), 3);
+
+                keeper.import(archive.clone(), Mode::Replace).unwrap();
+
+                let mut replaced = keeper.list(None).unwrap().to_list();
+                let restored = keeper.fields(Args::new(Some("google"), Some("me@gmail.com"), None)).unwrap();
+
+                replaced.sort();
+
+                assert_eq!(replaced, vec![String::from("empty"), String::from("google")]);
+                assert_eq!(restored, Resolve::Fields(fields.clone()));
+            }
+        };
+    }
+
+    #[test]
+    fn plan_and_import_items() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+                let mut fields = Fields::new();
+
+                fields.insert(NOTES.to_string(), "imported".to_string());
+
+                keeper.add(Args::new(Some("github"), Some("me"), Some("old"))).unwrap();
+
+                let items = vec![
+                    Item { entity: "github".to_string(), account: Some("me".to_string()), password: Some("new".to_string()), fields },
+                    Item { entity: "github".to_string(), account: Some("other".to_string()), password: Some("other".to_string()), fields: Fields::new() },
+                    Item { entity: "github".to_string(), account: None, password: None, fields: Fields::new() }
+                ];
+
+                let skip = keeper.plan(&items, Duplicates::Skip).unwrap();
+                let rename = keeper.plan(&items, Duplicates::Rename).unwrap();
+                let overwrite = keeper.plan(&items, Duplicates::Overwrite).unwrap();
+
+                assert_eq!(skip, vec![Action::Skip, Action::Add, Action::Skip]);
+                assert_eq!(rename[0], Action::Rename(String::from("me (2)")));
+                assert_eq!(overwrite[0], Action::Overwrite);
+
+                keeper.import_items(items.clone(), &rename).unwrap();
+
+                let renamed = keeper.read(Args::new(Some("github"), Some("me (2)"), None)).unwrap();
+                let kept = keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+
+                assert_eq!(renamed, Resolve::Read(String::from("new")));
+                assert_eq!(kept, Resolve::Read(String::from("old")));
+
+                keeper.import_items(items.clone(), &overwrite).unwrap();
+
+                let overwritten = keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+                let fields = keeper.fields(Args::new(Some("github"), Some("me"), None)).unwrap().to_fields();
+                let accounts = keeper.list(Some(Args::new(Some("github"), None, None))).unwrap().to_list();
+
+                assert_eq!(overwritten, Resolve::Read(String::from("new")));
+                assert_eq!(fields[NOTES], "imported");
+                assert_eq!(accounts.len(), 3);
+            }
+        };
+    }
+
+    #[test]
+    fn rename_entity() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+
+                let args = Args::new(
+                    Some("rename_entity"),
+                    Some("rename_entity_account"),
+                    Some("rename_entity_password")
+                );
+
+                keeper.add(args.clone()).unwrap();
+
+                let to = Locker::new().encrypt("renamed_entity");
+                let rename = keeper.rename(Args::new(Some("rename_entity"), None, None), to);
+
+                assert!(rename.is_ok());
+                assert!(keeper.find(Args::new(Some("rename_entity"), None, None)).is_err());
+
+                let read = keeper.read(Args::new(
+                    Some("renamed_entity"),
+                    Some("rename_entity_account"),
+                    None
+                ));
+
+                assert_eq!(read.unwrap().to_string(), "rename_entity_password");
+            }
+        };
+    }
+
+    #[test]
+    fn rename_account() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+
+                let args = Args::new(
+                    Some("rename_account"),
+                    Some("rename_account_old"),
+                    Some("rename_account_password")
+                );
+
+                keeper.add(args.clone()).unwrap();
+
+                let to = Locker::new().encrypt("rename_account_new");
+                let rename = keeper.rename(args, to);
+
+                assert!(rename.is_ok());
+
+                let found = keeper.find(Args::new(Some("rename_account"), None, None)).unwrap();
+
+                assert_eq!(
+                    found.to_vec(), 
+                    vec![(String::from("rename_account_new"), String::from("rename_account_password"))]
+                );
+            }
+        };
+    }
+
+    #[test]
+    fn move_account() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+
+                let args = Args::new(
+                    Some("move_account_from"),
+                    Some("move_account"),
+                    Some("move_account_password")
+                );
+
+                keeper.add(args.clone()).unwrap();
+
+                let to_entity = Locker::new().encrypt("move_account_to");
+                let moved = keeper.move_account(args, to_entity);
+
+                assert!(moved.is_ok());
+
+                let from = keeper.find(Args::new(Some("move_account_from"), None, None)).unwrap();
+                let read = keeper.read(Args::new(Some("move_account_to"), Some("move_account"), None));
+
+                assert_eq!(from.to_vec().len(), 0);
+                assert_eq!(read.unwrap().to_string(), "move_account_password");
+            }
+        };
+    }
+
+    #[test]
+    fn annotate() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+
+                let args = Args::new(
+                    Some("annotate_entity"),
+                    Some("annotate_account"),
+                    Some("annotate_password")
+                );
+
+                keeper.add(args.clone()).unwrap();
+
+                let mut fields = Fields::new();
+
+                fields.insert(URL.to_string(), "https://example.com".to_string());
+                fields.insert(TAGS.to_string(), "work".to_string());
+
+                assert!(keeper.annotate(args.clone(), fields.clone()).is_ok());
+                assert_eq!(keeper.fields(args.clone()).unwrap().to_fields(), fields);
+
+                let mut update = Fields::new();
+
+                update.insert(TAGS.to_string(), String::new());
+
+                assert!(keeper.annotate(args.clone(), update).is_ok());
+
+                let stored = keeper.fields(args.clone()).unwrap().to_fields();
+
+                assert_eq!(stored.get(URL), Some(&String::from("https://example.com")));
+                assert_eq!(stored.get(TAGS), None);
+                assert_eq!(keeper.read(args).unwrap().to_string(), "annotate_password");
+            }
+        };
+    }
+
+    #[test]
+    fn search() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::new(config, locker).unwrap();
+
+                let gmail = Args::new(Some("gmail"), Some("me@gmail.com"), Some("pass"));
+                let bank = Args::new(Some("bank"), Some("savings"), Some("pass"));
+                let router = Args::new(Some("home"), Some("admin"), Some("pass"));
+
+                keeper.add(gmail).unwrap();
+                keeper.add(bank).unwrap();
+                keeper.add(router.clone()).unwrap();
+
+                let mut fields = Fields::new();
+
+                fields.insert(URL.to_string(), "http://192.168.0.1".to_string());
+                keeper.annotate(router, fields).unwrap();
+
+                let by_name = keeper.search("gmal").unwrap().to_matches();
+                let by_url = keeper.search("192.168").unwrap().to_matches();
+
+                assert_eq!(by_name[0], (String::from("gmail"), String::from("me@gmail.com")));
+                assert_eq!(by_url, vec![(String::from("home"), String::from("admin"))]);
+            }
+        };
+    }
+
+    #[test]
+    fn audit_access() {
+        Setup {
+            paths: Vec::new(), 
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let keeper = Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+                let (log, key) = (audit::path(&config, &locker), keeper.key().unwrap().to_vec());
+                let mut keeper = keeper.with_audit(Audit::new(log.clone(), &key), "alice").unwrap();
+
+                keeper.add(Args::new(Some("github"), Some("me"), Some("pass"))).unwrap();
+                keeper.find(Args::new(Some("github"), None, None)).unwrap();
+                keeper.read(Args::new(Some("github"), Some("me"), None)).unwrap();
+                keeper.list(None).unwrap();
+                assert!(keeper.read(Args::new(Some("github"), Some("work"), None)).is_err());
+                keeper.export().unwrap();
+                keeper.remove(Args::new(Some("github"), None, None)).unwrap();
+
+                let events: Vec<String> = Audit::new(log.clone(), &key)
+                    .events()
+                    .unwrap()
+                    .into_iter()
+                    .map(|event| format!("{} {} {:?} {:?} {}", event.actor, event.action, event.entity, event.account, event.outcome))
+                    .collect();
+
+                assert_eq!(events, vec![
+                    r#"alice add Some("github") Some("me") ok"#,
+                    r#"alice find Some("github") None ok"#,
+                    r#"alice read Some("github") Some("me") ok"#,
+                    r#"alice read Some("github") Some("work") missing"#,
+                    r#"alice export None None ok"#,
+                    r#"alice remove Some("github") None ok"#
+                ]);
+                assert_eq!(Audit::new(log.clone(), &key).verify().unwrap(), 6);
+                assert_eq!(Header::read(&locker).unwrap().unwrap().audits, vec![Audit::new(log.clone(), &key).id()]);
+
+                fs::remove_file(&log).unwrap();
+                fs::remove_file(log.with_extension("head")).unwrap();
+
+                let reopened = Keeper::open(config, locker, "master").unwrap().with_audit(Audit::new(log, &key), "alice");
+
+                assert!(reopened.is_err());
+            }
+        };
+    }
 }
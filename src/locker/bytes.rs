@@ -8,7 +8,8 @@ pub enum ByteSize {
     E,
     U16,
     U32,
-    U64
+    U64,
+    Blocks(usize)
 }
 
 #[derive(Debug)]
@@ -28,6 +29,7 @@ impl Bytes {
             ByteSize::U16 => Bytes::random_u16(),
             ByteSize::U32 => Bytes::random_u32(),
             ByteSize::U64 => Bytes::random_u64(),
+            ByteSize::Blocks(n) => Bytes::random_blocks(n),
         };
 
         let hex = Bytes::bin_to_hex(&binary);
@@ -45,6 +47,7 @@ impl Bytes {
             16 => ByteSize::U16,
             32 => ByteSize::U32,
             64 => ByteSize::U64,
+            len if len % 16 == 0 => ByteSize::Blocks(len / 16),
             _ => panic!("Invalid vec length!"),
         };
 
@@ -62,10 +65,9 @@ impl Bytes {
             0 => ByteSize::E,
             32 => ByteSize::U16,
             64 => ByteSize::U32,
-            // TODO: encryption can be other
-            // multiples of 16 bytes, currently
-            96 => ByteSize::U32,
             128 => ByteSize::U64,
+            // encrypted data can be any multiple of 16 bytes
+            len if len % 32 == 0 => ByteSize::Blocks(len / 32),
             _ => panic!("Invalid hex length!"),
         };
         
@@ -133,6 +135,15 @@ impl Bytes {
         random_bytes.to_vec()
     }
 
+    fn random_blocks(blocks: usize) -> Vec<u8> {
+        let mut rng = OsRng::new().ok().unwrap();
+        let mut random_bytes = vec![0; blocks * 16];
+
+        rng.fill_bytes(&mut random_bytes);
+
+        random_bytes
+    }
+
     pub fn bytes_string(string: &[u8]) -> String {
         str::from_utf8(&string)
             .unwrap()
@@ -224,6 +235,16 @@ mod tests {
         assert_eq!(byte.hex(), hex);
     }
 
+    #[test]
+    fn from_hex_blocks() {
+        let hex = format!("0x{}", "00".repeat(48));
+        let byte = Bytes::from_hex(hex.clone());
+
+        assert_eq!(byte.size(), &Blocks(3));
+        assert_eq!(byte.raw().len(), 48);
+        assert_eq!(byte.hex(), hex);
+    }
+
     #[test]
     #[should_panic(expected = "Invalid hex length!")]
     fn from_hex_panic() {
@@ -9,7 +9,7 @@ use aes::Aes128;
 use regex::Regex;
 use block_modes::{BlockMode, Cbc};
 use block_modes::block_padding::Pkcs7;
-use crypto_hash::{Algorithm, hex_digest};
+use crypto_hash::{Algorithm, digest, hex_digest};
 
 use crate::locker::{Bytes, ByteSize};
 
@@ -19,6 +19,10 @@ use ByteSize::*;
 
 type Aes128Cbc = Cbc<Aes128, Pkcs7>;
 
+/* Constants */
+
+const HMAC_BLOCK: usize = 64;
+
 /* Distinguished struct */
 
 #[derive(Clone, Debug)]
@@ -38,7 +42,11 @@ pub struct Encrypted(String);
 
 impl PartialEq for Encrypted {
     fn eq(&self, other: &Self) -> bool {
-        self.is_empty() == other.is_empty() || self.hash() == other.hash()
+        if self.is_empty() || other.is_empty() {
+            return self.is_empty() == other.is_empty();
+        }
+
+        self.hash() == other.hash()
     }
 }
 
@@ -223,6 +231,24 @@ impl Locker {
         Bytes::bytes_string(&decrypted)
     }
 
+    // Like `decrypt`, for data that may have been tampered with
+    pub fn try_decrypt(&self) -> Option<String> {
+        if self.dat.size() == &E {
+            return Some(String::new());
+        }
+
+        let iv = self.iv.raw();
+        let key = self.key.raw();
+        let dat = self.dat.raw();
+
+        let decrypted = Aes128Cbc::new_var(&key[..], &iv[..])
+           .ok()?
+           .decrypt_vec(&dat[..])
+           .ok()?;
+
+        String::from_utf8(decrypted).ok()
+    }
+
     /* Associated functions */
     
     pub fn hash(string: &str) -> String {
@@ -231,6 +257,19 @@ impl Locker {
             string.as_bytes()
         )
     }
+
+    // HMAC-SHA256 of `parts` one after the other
+    pub fn hmac(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        let mut padded = if key.len() > HMAC_BLOCK { digest(Algorithm::SHA256, key) } else { key.to_vec() };
+
+        padded.resize(HMAC_BLOCK, 0);
+
+        let inner: Vec<u8> = padded.iter().map(|byte| byte ^ 0x36).collect();
+        let outer: Vec<u8> = padded.iter().map(|byte| byte ^ 0x5c).collect();
+        let inner = digest(Algorithm::SHA256, &[&inner[..], &parts.concat()].concat());
+
+        digest(Algorithm::SHA256, &[outer, inner].concat())
+    }
 }
 
 /* Locker tests */
@@ -322,6 +361,28 @@ mod locker_tests {
         assert_eq!(decrypted, String::from("encrypt me!"));
     }
 
+    #[test]
+    fn decrypt_long() {
+        let mut locker = Locker::new();
+        let to_encrypt = "encrypt me! ".repeat(20);
+        let encrypted = locker.encrypt(&to_encrypt);
+        let decrypted = Locker::from_encrypted(&encrypted).decrypt();
+
+        assert_eq!(decrypted, to_encrypt);
+    }
+
+    #[test]
+    fn try_decrypt_tampered() {
+        let iv = String::from("0x00000000000000000000000000000000");
+        let key = String::from("0x00000000000000000000000000000001");
+        let encrypted = Locker::from(iv.clone(), key, String::from("0x")).encrypt("encrypt me!");
+        let Distinguished { dat, .. } = encrypted.distinguish();
+        let wrong_key = String::from("0x00000000000000000000000000000002");
+
+        assert_eq!(Locker::from_encrypted(&encrypted).try_decrypt(), Some(String::from("encrypt me!")));
+        assert_eq!(Locker::from(iv, wrong_key, dat).try_decrypt(), None);
+    }
+
     #[test]
     fn hash() {
         let string = String::from("hash this");
@@ -330,6 +391,14 @@ mod locker_tests {
 
         assert_eq!(hash, hashed);
     }
+
+    #[test]
+    fn hmac_rfc4231() {
+        let key = [0x0b; 20];
+        let mac = Locker::hmac(&key, &[b"Hi ", b"There"]);
+
+        assert_eq!(hex::encode(mac), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+    }
 }
 
 /* Encrypted tests */
@@ -388,4 +457,14 @@ mod encrypted_tests {
 
         assert_eq!(foo, bar);
     }
+
+    #[test]
+    fn partial_eq_different_hash() {
+        let foo = Encrypted::new("foo", "bar", "biz", "fred");
+        let bar = Encrypted::new("foo", "bar", "biz", "quux");
+
+        assert_ne!(foo, bar);
+        assert_ne!(foo, Encrypted::empty());
+        assert_eq!(Encrypted::empty(), Encrypted::empty());
+    }
 }
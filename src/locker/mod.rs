@@ -1,5 +1,7 @@
 mod bytes;
 mod locker;
+mod seal;
 
 pub use locker::{Locker, Encrypted, Distinguished};
+pub use seal::Seal;
 pub use bytes::{Bytes, ByteSize};
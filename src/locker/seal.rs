@@ -0,0 +1,142 @@
+/* Dependencies */
+
+use std::fmt;
+
+use aes_soft as aes;
+use aes::Aes128;
+use aes::block_cipher_trait::BlockCipher;
+use aes::block_cipher_trait::generic_array::GenericArray;
+
+use crate::locker::{Bytes, Locker, Encrypted, Distinguished};
+
+/* Seal struct definition */
+
+// Ties what a locker keeps on disk to the key derived from its master
+// password. The `Locker` key of every value is wrapped under it, and the
+// hash that names are compared by is keyed with it, so without the master
+// password a stored value can neither be decrypted nor guessed from its hash
+#[derive(Clone)]
+pub struct Seal {
+    wrap: Vec<u8>,
+    mac: Vec<u8>
+}
+
+impl Seal {
+
+    /* Intialisers */
+
+    pub fn new(key: &[u8]) -> Seal {
+        let wrap = Locker::hmac(key, &[b"rk wrap"])[..16].to_vec();
+        let mac = Locker::hmac(key, &[b"rk hash"]);
+
+        Seal { wrap, mac }
+    }
+
+    /* Methods */
+
+    pub fn hash(&self, string: &str) -> String {
+        hex::encode(Locker::hmac(&self.mac, &[string.as_bytes()]))
+    }
+
+    // The form a value `Locker` encrypted is stored in
+    pub fn seal(&self, encrypted: &Encrypted) -> Encrypted {
+        if encrypted.is_empty() {
+            return Encrypted::empty();
+        }
+
+        let Distinguished { iv, key, dat, .. } = encrypted.distinguish();
+        let data = Locker::from_encrypted(encrypted).decrypt();
+        let mut block = GenericArray::clone_from_slice(&Bytes::hex_to_bin(&key));
+
+        self.cipher().encrypt_block(&mut block);
+
+        Encrypted::new(&iv, &Bytes::bin_to_hex(&block.to_vec()), &dat, &self.hash(&data))
+    }
+
+    // Back to a value `Locker` can decrypt, `None` when it was sealed
+    // under another master password or altered since
+    pub fn open(&self, encrypted: &Encrypted) -> Option<Encrypted> {
+        if encrypted.is_empty() {
+            return Some(Encrypted::empty());
+        }
+
+        let Distinguished { iv, key, dat, hash } = encrypted.distinguish();
+        let wrapped = hex(&key).filter(|wrapped| wrapped.len() == 16)?;
+        let mut block = GenericArray::clone_from_slice(&wrapped);
+
+        hex(&iv).filter(|iv| iv.len() == 16)?;
+        hex(&dat).filter(|dat| !dat.is_empty() && dat.len() % 16 == 0)?;
+        self.cipher().decrypt_block(&mut block);
+
+        let key = Bytes::bin_to_hex(&block.to_vec());
+        let data = Locker::from(iv.clone(), key.clone(), dat.clone()).try_decrypt()?;
+
+        if self.hash(&data) != hash {
+            return None;
+        }
+
+        Some(Encrypted::new(&iv, &key, &dat, &Locker::hash(&data)))
+    }
+
+    pub fn encrypt(&self, data: &str) -> Encrypted {
+        self.seal(&Locker::new().encrypt(data))
+    }
+
+    pub fn decrypt(&self, encrypted: &Encrypted) -> Option<String> {
+        self.open(encrypted).map(|opened| Locker::from_encrypted(&opened).decrypt())
+    }
+
+    fn cipher(&self) -> Aes128 {
+        Aes128::new(GenericArray::from_slice(&self.wrap))
+    }
+}
+
+/* Seal Debug behaviour */
+
+// Never prints the keys
+impl fmt::Debug for Seal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Seal")
+    }
+}
+
+/* Functions */
+
+// `0x` prefixed hex as `Bytes` writes it
+fn hex(string: &str) -> Option<Vec<u8>> {
+    hex::decode(string.strip_prefix("0x")?).ok()
+}
+
+/* Seal tests */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let seal = Seal::new(&[1; 32]);
+        let encrypted = Locker::new().encrypt("encrypt me!");
+        let sealed = seal.seal(&encrypted);
+        let Distinguished { key, hash, .. } = sealed.distinguish();
+
+        assert_ne!(key, encrypted.distinguish().key);
+        assert_ne!(hash, Locker::hash("encrypt me!"));
+        assert_eq!(hash, seal.hash("encrypt me!"));
+        assert_eq!(seal.seal(&encrypted).path(), sealed.path());
+        assert_eq!(seal.open(&sealed).unwrap().path(), encrypted.path());
+        assert_eq!(seal.decrypt(&seal.encrypt("encrypt me!")), Some(String::from("encrypt me!")));
+        assert_eq!(seal.open(&Encrypted::empty()), Some(Encrypted::empty()));
+    }
+
+    #[test]
+    fn open_with_another_key() {
+        let sealed = Seal::new(&[1; 32]).encrypt("encrypt me!");
+        let Distinguished { iv, key, dat, .. } = sealed.distinguish();
+        let forged = Encrypted::new(&iv, &key, &dat, &Locker::hash("encrypt me!"));
+
+        assert_eq!(Seal::new(&[2; 32]).open(&sealed), None);
+        assert_eq!(Seal::new(&[1; 32]).open(&forged), None);
+        assert_eq!(Seal::new(&[1; 32]).open(&Encrypted::from("foo$bar$biz$fred").unwrap()), None);
+    }
+}
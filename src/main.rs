@@ -10,27 +10,51 @@ mod vault;
 mod settings;
 mod tables;
 
+use std::process;
+
 use cli::*;
 use args::*;
 use locker::*;
 use tables::*;
-use settings::{
+use rk::{
+    Resolve,
     Settings, 
     SettingsOpts::*
 };
 
-// TODO:
-// extract cmd -> encrypt/zip all passwords and send it to a specific path
-// set config cmd -> allow for custom config settings (eg. locker location, config location, cypto algo, etc...)
-
 fn main() { 
     let args = app::execute();
-    let settings = Settings::default();
-    
-    let config = settings.get(Config);
-    let locker = settings.get(Locker);
-
-    CLI::start(config, locker)
-        .operation(args)
-        .expect("Error on app operation");
+    let flags = overrides(&args);
+    let path = Settings::path(&flags);
+    let settings = match Settings::load(&flags) {
+        Ok(settings) => settings,
+        // `rk config` has to be able to fix a broken settings file
+        Err(_) if args.subcommand_name() == Some("config") => Settings::default(),
+        Err(err) => exit(err.exit_code(), err.to_str())
+    };
+
+    let paths = path.and_then(|path| {
+        Ok((path, settings.config_dir()?, settings.get(Locker)?))
+    });
+
+    let (path, config, locker) = match paths {
+        Ok(paths) => paths,
+        Err(err) => exit(err.exit_code(), err.to_str())
+    };
+
+    let operation = CLI::start(config, locker)
+        .with_settings(path)
+        .with_backend(&settings.backend())
+        .operation(args);
+
+    match operation {
+        Ok(Resolve::Exit(code)) => process::exit(code),
+        Err(err) => exit(err.exit_code(), err.to_str()),
+        Ok(_) => {}
+    }
+}
+
+fn exit(code: i32, msg: String) -> ! {
+    eprintln!("rk: {}", msg);
+    process::exit(code);
 }
@@ -44,6 +44,13 @@ impl DirManager {
         )
     }
 
+    pub fn rename_locker(&mut self, from: &str, to: &str) -> io::Result<()> {
+        self.rename(
+            &self.gen_path(Locker, from),
+            &self.gen_path(Locker, to)
+        )
+    }
+
     pub fn create_config(&mut self, path: &str) -> io::Result<()> {
         self.create(
             &self.gen_path(Config, path)
@@ -257,6 +264,24 @@ mod test {
         }; 
     }
 
+    #[test]
+    fn rename_locker() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (.., config, locker) = this.as_path_buf();
+                let mut dm = DirManager::new(&config, &locker);
+
+                dm.create_locker("from").unwrap();
+                dm.rename_locker("from", "to").unwrap();
+
+                assert!(!locker.join("from").exists());
+                assert!(locker.join("to").exists());
+            },
+        }; 
+    }
+
     #[test]
     fn remove_config() {
         Setup {
@@ -1,4 +1,5 @@
 use std::io;
+use std::fs;
 use std::path::{PathBuf};
 
 #[derive(Debug)]
@@ -20,6 +21,10 @@ pub trait Manager {
 
     fn write(&mut self, path: &str, content: &str) -> io::Result<()> { Ok(()) }
 
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
     fn pb_to_str(path: &PathBuf) -> String {
         path
             .as_path()
@@ -0,0 +1,548 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::archive::Item;
+use crate::fields::Fields;
+use crate::inject;
+use crate::locker::{Encrypted, Locker, Seal};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// Kept in the locker, next to the vault header
+pub const LOG: &str = ".sync";
+
+// Merges remembered, enough for two lockers to find one in common
+const HISTORY: usize = 16;
+
+/* Custom types */
+
+// An entity and one of its accounts
+type Key = (String, String);
+
+// Hashes of what a vault held: entities map to nothing, accounts
+// (`<entity hash>/<account hash>`) to the digest of their entry
+type Digests = BTreeMap<String, String>;
+
+/* Base struct definition */
+
+// What two vaults held when they were last merged, nothing if never
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Base(Digests);
+
+/* Entry struct definition */
+
+// What an account holds, and when it last changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub password: Option<String>,
+    pub fields: Fields,
+    pub modified: u64
+}
+
+impl Entry {
+
+    /* Methods */
+
+    // Two entries with the same digest hold the same, whenever they changed
+    fn digest(&self) -> String {
+        let fields = serde_json::to_string(&self.fields).unwrap_or_default();
+
+        Locker::hash(&format!("{}\u{0}{}", self.password.as_deref().unwrap_or(""), fields))
+    }
+}
+
+/* Snapshot struct definition */
+
+// Every entity and account of a vault
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub entities: BTreeSet<String>,
+    pub accounts: BTreeMap<Key, Entry>
+}
+
+impl Snapshot {
+
+    /* Intialisers */
+
+    // Items as `Keeper::export` lists them, with the modification time of each
+    pub fn new(items: Vec<(Item, u64)>) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+
+        for (item, modified) in items {
+            snapshot.entities.insert(item.entity.clone());
+
+            if let Some(account) = item.account {
+                let entry = Entry { password: item.password, fields: item.fields, modified };
+
+                snapshot.accounts.insert((item.entity, account), entry);
+            }
+        }
+
+        snapshot
+    }
+
+    /* Methods */
+
+    // What turns `from` into this snapshot, removals first
+    pub fn changes(&self, from: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        for (entity, account) in from.accounts.keys().filter(|key| !self.accounts.contains_key(*key)) {
+            changes.push(Change::RemoveAccount(entity.clone(), account.clone()));
+        }
+
+        for entity in from.entities.difference(&self.entities) {
+            changes.push(Change::RemoveEntity(entity.clone()));
+        }
+
+        for entity in self.entities.difference(&from.entities) {
+            changes.push(Change::AddEntity(entity.clone()));
+        }
+
+        for ((entity, account), entry) in self.accounts.iter() {
+            let current = from.accounts.get(&(entity.clone(), account.clone())).map(Entry::digest);
+
+            if current != Some(entry.digest()) {
+                changes.push(Change::Set(entity.clone(), account.clone(), entry.clone()));
+            }
+        }
+
+        changes
+    }
+
+    fn digests(&self) -> Digests {
+        let mut digests = Digests::new();
+
+        for entity in self.entities.iter() {
+            digests.insert(Locker::hash(entity), String::new());
+        }
+
+        for ((entity, account), entry) in self.accounts.iter() {
+            digests.insert(account_key(entity, account), entry.digest());
+        }
+
+        digests
+    }
+}
+
+/* Change enum definition */
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    AddEntity(String),
+    RemoveEntity(String),
+    Set(String, String, Entry),
+    RemoveAccount(String, String)
+}
+
+/* Side enum definition */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs
+}
+
+/* Conflict struct definition */
+
+// An account both vaults changed differently since they were last merged,
+// `None` on the side that removed it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub entity: String,
+    pub account: String,
+    pub ours: Option<Entry>,
+    pub theirs: Option<Entry>
+}
+
+impl Conflict {
+
+    /* Methods */
+
+    // The side changed last. Removals carry no time and lose
+    pub fn newest(&self) -> Side {
+        let modified = |entry: &Option<Entry>| entry.as_ref().map(|entry| entry.modified);
+
+        match modified(&self.ours) >= modified(&self.theirs) {
+            true => Side::Ours,
+            false => Side::Theirs
+        }
+    }
+
+    // Names what differs, never the values
+    pub fn differs(&self) -> Vec<String> {
+        let (ours, theirs) = match (&self.ours, &self.theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            _ => return vec![String::from("removed")]
+        };
+
+        let mut differs = Vec::new();
+
+        if ours.password != theirs.password {
+            differs.push(String::from("password"));
+        }
+
+        let names: BTreeSet<&String> = ours.fields.keys().chain(theirs.fields.keys()).collect();
+
+        for name in names {
+            if ours.fields.get(name) != theirs.fields.get(name) {
+                differs.push(name.clone());
+            }
+        }
+
+        differs
+    }
+}
+
+/* Merge struct definition */
+
+// A three-way merge of two vaults against what they held when last merged.
+// What changed on one side only is taken, what changed on both is left for
+// `resolve`
+#[derive(Clone, Debug)]
+pub struct Merge {
+    merged: Snapshot,
+    pub conflicts: Vec<Conflict>
+}
+
+impl Merge {
+
+    /* Intialisers */
+
+    pub fn plan(ours: &Snapshot, theirs: &Snapshot, base: &Base) -> Merge {
+        let Base(base) = base;
+        let mut merged = Snapshot::default();
+        let mut conflicts = Vec::new();
+
+        let keys: BTreeSet<&Key> = ours.accounts.keys().chain(theirs.accounts.keys()).collect();
+
+        for key in keys {
+            let (entity, account) = key;
+            let before = base.get(&account_key(entity, account)).cloned();
+            let (left, right) = (ours.accounts.get(key), theirs.accounts.get(key));
+            let (left_digest, right_digest) = (left.map(Entry::digest), right.map(Entry::digest));
+
+            let taken = if left_digest == right_digest || right_digest == before {
+                left
+            } else if left_digest == before {
+                right
+            } else {
+                conflicts.push(Conflict { entity: entity.clone(), account: account.clone(), ours: left.cloned(), theirs: right.cloned() });
+
+                continue;
+            };
+
+            if let Some(entry) = taken {
+                merged.accounts.insert(key.clone(), entry.clone());
+            }
+        }
+
+        let entities: BTreeSet<&String> = ours.entities.iter().chain(theirs.entities.iter()).collect();
+
+        for entity in entities {
+            let before = base.contains_key(&Locker::hash(entity));
+            let (left, right) = (ours.entities.contains(entity), theirs.entities.contains(entity));
+
+            // Whichever side differs from the base changed it
+            let present = match left == right || left != before {
+                true => left,
+                false => right
+            };
+
+            if present {
+                merged.entities.insert(entity.clone());
+            }
+        }
+
+        Merge { merged, conflicts }
+    }
+
+    /* Methods */
+
+    // One side for each conflict, in order. Entities keep their accounts
+    pub fn resolve(self, sides: &[Side]) -> VaultResult<Snapshot> {
+        if sides.len() != self.conflicts.len() {
+            return Err(VaultError::Error(format!("Expected a side for each of the {} conflicts", self.conflicts.len())));
+        }
+
+        let mut merged = self.merged;
+
+        for (conflict, side) in self.conflicts.into_iter().zip(sides) {
+            let taken = match side {
+                Side::Ours => conflict.ours,
+                Side::Theirs => conflict.theirs
+            };
+
+            if let Some(entry) = taken {
+                merged.accounts.insert((conflict.entity, conflict.account), entry);
+            }
+        }
+
+        let entities: Vec<String> = merged.accounts.keys().map(|(entity, _)| entity.clone()).collect();
+
+        merged.entities.extend(entities);
+
+        Ok(merged)
+    }
+}
+
+/* Record struct definition */
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Record {
+    time: u64,
+    state: String,
+    entries: Digests
+}
+
+/* SyncLog struct definition */
+
+// What a locker held after each merge, a record per line sealed like the
+// locker itself. Both lockers of a merge get the same record, the newest
+// they share is the base of the next merge
+#[derive(Clone, Debug)]
+pub struct SyncLog {
+    path: PathBuf,
+    seal: Seal
+}
+
+impl SyncLog {
+
+    /* Intialisers */
+
+    pub fn new(locker: &Path, seal: Seal) -> SyncLog {
+        SyncLog { path: locker.join(LOG), seal }
+    }
+
+    /* Methods */
+
+    pub fn record(&self, snapshot: &Snapshot) -> VaultResult<()> {
+        let entries = snapshot.digests();
+        let state = Locker::hash(&serde_json::to_string(&entries).map_err(|err| VaultError::Error(err.to_string()))?);
+        let mut records = self.records()?;
+
+        if records.last().map(|record| &record.state) == Some(&state) {
+            return Ok(());
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        records.push(Record { time, state, entries });
+
+        let skipped = records.len().saturating_sub(HISTORY);
+        let mut lines = String::new();
+
+        for record in records.iter().skip(skipped) {
+            let json = serde_json::to_string(record).map_err(|err| VaultError::Error(err.to_string()))?;
+
+            lines.push_str(&self.seal.encrypt(&json).path());
+            lines.push('\n');
+        }
+
+        inject::write(&self.path, &lines)
+    }
+
+    // The newest record both logs hold
+    pub fn base(&self, other: &SyncLog) -> VaultResult<Base> {
+        let theirs: BTreeSet<String> = other.records()?.into_iter().map(|record| record.state).collect();
+        let shared = self.records()?.into_iter().rev().find(|record| theirs.contains(&record.state));
+
+        Ok(Base(shared.map(|record| record.entries).unwrap_or_default()))
+    }
+
+    fn records(&self) -> VaultResult<Vec<Record>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        fs::read_to_string(&self.path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| self.open(line.trim()).ok_or_else(|| VaultError::Error(format!("The sync log {:?} is damaged", self.path))))
+            .collect()
+    }
+
+    fn open(&self, line: &str) -> Option<Record> {
+        let json = self.seal.decrypt(&Encrypted::from(line).ok()?)?;
+
+        serde_json::from_str(&json).ok()
+    }
+}
+
+/* Functions */
+
+fn account_key(entity: &str, account: &str) -> String {
+    format!("{}/{}", Locker::hash(entity), Locker::hash(account))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `merge.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn item(entity: &str, account: Option<&str>, password: &str, modified: u64) -> (Item, u64) {
+        let item = Item {
+            entity: entity.to_string(),
+            account: account.map(String::from),
+            password: Some(password.to_string()),
+            fields: Fields::new()
+        };
+
+        (item, modified)
+    }
+
+    fn base() -> Snapshot {
+        Snapshot::new(vec![
+            item("github", Some("me"), "one", 1),
+            item("github", Some("work"), "one", 1),
+            item("gitlab", Some("me"), "one", 1),
+            item("mail", None, "", 0)
+        ])
+    }
+
+    fn digests(snapshot: &Snapshot) -> Base {
+        Base(snapshot.digests())
+    }
+
+    #[test]
+    fn merge_changes() {
+        let ours = Snapshot::new(vec![
+            item("github", Some("me"), "two", 2),
+            item("github", Some("work"), "one", 1),
+            item("gitlab", Some("me"), "one", 1),
+            item("aws", Some("root"), "one", 2)
+        ]);
+        let theirs = Snapshot::new(vec![
+            item("github", Some("me"), "one", 1),
+            item("gitlab", Some("me"), "one", 1),
+            item("gitlab", Some("bot"), "one", 3),
+            item("mail", None, "", 0)
+        ]);
+
+        let plan = Merge::plan(&ours, &theirs, &digests(&base()));
+        let merged = plan.resolve(&[]).unwrap();
+        let accounts: Vec<(&str, &str, Option<&str>)> = merged.accounts.iter()
+            .map(|((entity, account), entry)| (entity.as_str(), account.as_str(), entry.password.as_deref()))
+            .collect();
+
+        assert_eq!(accounts, vec![
+            ("aws", "root", Some("one")),
+            ("github", "me", Some("two")),
+            ("gitlab", "bot", Some("one")),
+            ("gitlab", "me", Some("one"))
+        ]);
+        assert_eq!(merged.entities.iter().collect::<Vec<&String>>(), vec!["aws", "github", "gitlab"]);
+        assert_eq!(merged.changes(&ours), vec![
+            Change::RemoveAccount(String::from("github"), String::from("work")),
+            Change::Set(String::from("gitlab"), String::from("bot"), merged.accounts[&(String::from("gitlab"), String::from("bot"))].clone())
+        ]);
+        assert_eq!(merged.changes(&theirs).len(), 4);
+        assert!(merged.changes(&merged).is_empty());
+    }
+
+    #[test]
+    fn merge_conflicts() {
+        let ours = Snapshot::new(vec![
+            item("github", Some("me"), "two", 2),
+            item("gitlab", Some("me"), "two", 4),
+            item("mail", None, "", 0)
+        ]);
+        let theirs = Snapshot::new(vec![
+            item("github", Some("me"), "three", 3),
+            item("github", Some("work"), "two", 3),
+            item("mail", None, "", 0)
+        ]);
+
+        let plan = Merge::plan(&ours, &theirs, &digests(&base()));
+        let conflicts: Vec<(&str, &str, Side, Vec<String>)> = plan.conflicts.iter()
+            .map(|conflict| (conflict.entity.as_str(), conflict.account.as_str(), conflict.newest(), conflict.differs()))
+            .collect();
+
+        assert_eq!(conflicts, vec![
+            ("github", "me", Side::Theirs, vec![String::from("password")]),
+            ("github", "work", Side::Theirs, vec![String::from("removed")]),
+            ("gitlab", "me", Side::Ours, vec![String::from("removed")])
+        ]);
+        assert!(plan.clone().resolve(&[Side::Ours]).is_err());
+
+        let merged = plan.resolve(&[Side::Ours, Side::Ours, Side::Theirs]).unwrap();
+        let accounts: Vec<(&str, &str, Option<&str>)> = merged.accounts.iter()
+            .map(|((entity, account), entry)| (entity.as_str(), account.as_str(), entry.password.as_deref()))
+            .collect();
+
+        assert_eq!(accounts, vec![("github", "me", Some("two"))]);
+        assert_eq!(merged.entities.iter().collect::<Vec<&String>>(), vec!["github", "mail"]);
+    }
+
+    #[test]
+    fn first_merge() {
+        let ours = Snapshot::new(vec![item("github", Some("me"), "one", 1), item("mail", None, "", 0)]);
+        let theirs = Snapshot::new(vec![item("github", Some("me"), "two", 2), item("gitlab", Some("me"), "one", 1)]);
+
+        let plan = Merge::plan(&ours, &theirs, &Base::default());
+
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.resolve(&[Side::Theirs]).unwrap().entities.len(), 3);
+    }
+
+    #[test]
+    fn sync_log() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (ours, theirs) = this.as_path_buf();
+                let (log, other) = (SyncLog::new(&ours, Seal::new(&[1; 32])), SyncLog::new(&theirs, Seal::new(&[2; 32])));
+                let merged = base();
+
+                fs::create_dir_all(&ours).unwrap();
+                fs::create_dir_all(&theirs).unwrap();
+
+                assert_eq!(log.base(&other).unwrap(), Base::default());
+
+                log.record(&merged).unwrap();
+                other.record(&merged).unwrap();
+                log.record(&Snapshot::default()).unwrap();
+                log.record(&Snapshot::default()).unwrap();
+
+                assert_eq!(log.records().unwrap().len(), 2);
+                assert_eq!(log.base(&other).unwrap(), digests(&merged));
+                assert_eq!(other.base(&log).unwrap(), digests(&merged));
+
+                for _ in 0..HISTORY {
+                    log.record(&Snapshot::new(vec![item("github", None, "", 0)])).unwrap();
+                    log.record(&Snapshot::default()).unwrap();
+                }
+
+                assert_eq!(log.records().unwrap().len(), HISTORY);
+                assert_eq!(log.base(&other).unwrap(), Base::default());
+                assert!(!fs::read_to_string(ours.join(LOG)).unwrap().contains("github"));
+                assert!(SyncLog::new(&ours, Seal::new(&[2; 32])).records().is_err());
+
+                fs::write(ours.join(LOG), "damaged\n").unwrap();
+
+                assert!(log.base(&other).is_err());
+            }
+        };
+    }
+}
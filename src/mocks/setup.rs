@@ -1,6 +1,7 @@
 use rand::{Rng, OsRng};
 
 use std::env;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 
 // https://stackoverflow.com/questions/16946888/is-it-possible-to-make-a-recursive-closure-in-rust
@@ -80,9 +81,59 @@ impl<'s> Setup<'s> {
 } 
 
 // RAII https://stackoverflow.com/questions/38253321/what-is-a-good-way-of-cleaning-up-after-a-unit-test-in-rust
+// `after_each` also runs when the test panics, so a failing test doesn't
+// leave its directories behind in `dump/`
 impl<'s> Drop for Setup<'s> {
     fn drop(&mut self) {
-        (self.test)(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (self.test)(self)));
+
         (self.after_each)(self); 
+
+        if let Err(err) = result {
+            panic::resume_unwind(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::sync::Mutex;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `setup.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    #[test]
+    fn cleanup_after_panic() {
+        let created = Mutex::new(PathBuf::new());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Setup {
+                paths: Vec::new(),
+                after_each: &after_each,
+                test: &|this| {
+                    let (config, _) = this.as_path_buf();
+
+                    create_dir_all(&config).unwrap();
+                    *created.lock().unwrap() = config;
+
+                    panic!("failing test");
+                }
+            };
+        }));
+
+        let created = created.lock().unwrap();
+
+        assert!(result.is_err());
+        assert!(!created.as_os_str().is_empty());
+        assert!(!created.exists());
     }
 }
@@ -0,0 +1,443 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{Args, Fields, Keeper, URL};
+use crate::fields::is_url;
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// The name extensions connect to, eg. `browser.runtime.connectNative("rk")`
+pub const HOST: &str = "rk";
+
+// Browsers drop the host for anything bigger it sends them
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+// Way above what a login weighs, only there to refuse garbage lengths
+const MAX_REQUEST: usize = 64 * 1024 * 1024;
+
+/* Request enum definition */
+
+// One json message from the extension. Accounts are only ever handed
+// out for the origin the extension says it is filling in
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Request {
+    Find { url: String },
+    Get { url: String, entity: String, account: String },
+    Save { url: String, username: String, password: String, #[serde(default)] entity: Option<String> }
+}
+
+/* Browser enum definition */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+    Chromium
+}
+
+impl Browser {
+
+    /* Intialisers */
+
+    pub fn parse(name: &str) -> VaultResult<Browser> {
+        match name {
+            "firefox" => Ok(Browser::Firefox),
+            "chrome" => Ok(Browser::Chrome),
+            "chromium" => Ok(Browser::Chromium),
+            _ => Err(VaultError::Error(format!("Unknown browser {:?}, expected firefox, chrome or chromium", name)))
+        }
+    }
+
+    /* Methods */
+
+    // Where the browser looks for the manifests of the current user
+    pub fn manifest_dir(&self, home: &Path) -> PathBuf {
+        let mac = cfg!(target_os = "macos");
+
+        match (self, mac) {
+            (Browser::Firefox, false) => home.join(".mozilla/native-messaging-hosts"),
+            (Browser::Firefox, true) => home.join("Library/Application Support/Mozilla/NativeMessagingHosts"),
+            (Browser::Chrome, false) => home.join(".config/google-chrome/NativeMessagingHosts"),
+            (Browser::Chrome, true) => home.join("Library/Application Support/Google/Chrome/NativeMessagingHosts"),
+            (Browser::Chromium, false) => home.join(".config/chromium/NativeMessagingHosts"),
+            (Browser::Chromium, true) => home.join("Library/Application Support/Chromium/NativeMessagingHosts")
+        }
+    }
+
+    // Firefox names extensions by id, Chromium based browsers by origin
+    pub fn manifest(&self, launcher: &Path, extension: &str) -> Value {
+        let mut manifest = json!({
+            "name": HOST,
+            "description": "rk password manager",
+            "path": launcher,
+            "type": "stdio"
+        });
+
+        match self {
+            Browser::Firefox => manifest["allowed_extensions"] = json!([extension]),
+            _ => manifest["allowed_origins"] = json!([format!("chrome-extension://{}/", extension)])
+        }
+
+        manifest
+    }
+}
+
+/* Functions */
+
+// Answers messages until the browser closes stdin, which it does once
+// the extension disconnects
+pub fn serve<R: Read, W: Write>(keeper: &mut Keeper, mut input: R, mut output: W) -> VaultResult<()> {
+    while let Some(message) = read_message(&mut input)? {
+        let response = match serde_json::from_value::<Request>(message) {
+            Ok(request) => answer(keeper, request),
+            Err(err) => failure(&format!("Invalid request: {}", err))
+        };
+
+        write_message(&mut output, &response)?;
+    }
+
+    Ok(())
+}
+
+pub fn answer(keeper: &mut Keeper, request: Request) -> Value {
+    let answered = match request {
+        Request::Find { url } => find(keeper, &url).map(|logins| {
+            let accounts: Vec<Value> = logins.iter()
+                .map(|(entity, account)| json!({ "entity": entity, "account": account }))
+                .collect();
+
+            json!({ "ok": true, "accounts": accounts })
+        }),
+        Request::Get { url, entity, account } => get(keeper, &url, &entity, &account)
+            .map(|password| json!({ "ok": true, "username": account, "password": password })),
+        Request::Save { url, username, password, entity } => save(keeper, &url, &username, &password, entity)
+            .map(|entity| json!({ "ok": true, "entity": entity, "account": username }))
+    };
+
+    answered.unwrap_or_else(|err| failure(&err.to_str()))
+}
+
+// Entities and accounts with a url of the same scheme, host and port.
+// Only names and fields are decrypted, never a password
+pub fn find(keeper: &mut Keeper, url: &str) -> VaultResult<Vec<(String, String)>> {
+    let wanted = origin(url).ok_or_else(|| VaultError::Error(format!("Invalid url {:?}", url)))?;
+    let mut logins = Vec::new();
+
+    for entity in keeper.list(None)?.to_list() {
+        for account in keeper.list(Some(Args::new(Some(&entity), None, None)))?.to_list() {
+            if serves(keeper, &wanted, &entity, &account)? {
+                logins.push((entity.clone(), account));
+            }
+        }
+    }
+
+    Ok(logins)
+}
+
+// The password of one account, only when it is for the site. Read like
+// `rk read` does, so it is the only entry decrypted and the access is
+// recorded
+fn get(keeper: &mut Keeper, url: &str, entity: &str, account: &str) -> VaultResult<String> {
+    let wanted = origin(url).ok_or_else(|| VaultError::Error(format!("Invalid url {:?}", url)))?;
+
+    if !serves(keeper, &wanted, entity, account).unwrap_or(false) {
+        return Err(VaultError::Error(format!("No account {:?} of {:?} for this site", account, entity)));
+    }
+
+    Ok(keeper.read(Args::new(Some(entity), Some(account), None))?.to_string())
+}
+
+fn serves(keeper: &mut Keeper, wanted: &str, entity: &str, account: &str) -> VaultResult<bool> {
+    let fields = keeper.fields(Args::new(Some(entity), Some(account), None))?.to_fields();
+
+    Ok(fields.iter()
+        .filter(|(key, _)| is_url(key))
+        .any(|(_, value)| origin(value).as_deref() == Some(wanted)))
+}
+
+// A new login is kept under the site's host unless told otherwise, an
+// existing account gets the new password. The url is only added when the
+// account has none, not to change what it already matches
+fn save(keeper: &mut Keeper, url: &str, username: &str, password: &str, entity: Option<String>) -> VaultResult<String> {
+    let origin = origin(url).ok_or_else(|| VaultError::Error(format!("Invalid url {:?}", url)))?;
+    let entity = match entity.filter(|entity| !entity.is_empty()) {
+        Some(entity) => entity,
+        None => origin.split_once("://").map(|(_, host)| host.to_string()).unwrap_or_default()
+    };
+
+    if username.is_empty() || password.is_empty() {
+        return Err(VaultError::Error(String::from("A login needs a username and a password")));
+    }
+
+    keeper.add(Args::new(Some(&entity), Some(username), Some(password)))?;
+
+    let stored = keeper.fields(Args::new(Some(&entity), Some(username), None))?.to_fields();
+
+    if !stored.keys().any(|key| is_url(key)) {
+        let mut fields = Fields::new();
+
+        fields.insert(URL.to_string(), origin);
+        keeper.annotate(Args::new(Some(&entity), Some(username), None), fields)?;
+    }
+
+    Ok(entity)
+}
+
+// `scheme://host[:port]` lowercased, without the port a scheme defaults to.
+// Urls stored without a scheme, eg. `github.com`, are taken as https
+pub fn origin(url: &str) -> Option<String> {
+    let url = url.trim();
+    let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit_once('@').map(|(_, host)| host).unwrap_or(authority);
+
+    // Bracketed IPv6 addresses hold colons of their own
+    let (host, port) = match host_port.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((address, rest)) => (&host_port[..address.len() + 2], rest.strip_prefix(':')),
+        None => match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None)
+        }
+    };
+
+    let scheme = scheme.to_lowercase();
+    let default = match scheme.as_str() {
+        "http" => Some("80"),
+        "https" => Some("443"),
+        _ => None
+    };
+
+    if host.is_empty() || scheme.is_empty() {
+        return None;
+    }
+
+    match port.filter(|port| Some(*port) != default) {
+        Some(port) => Some(format!("{}://{}:{}", scheme, host.to_lowercase(), port)),
+        None => Some(format!("{}://{}", scheme, host.to_lowercase()))
+    }
+}
+
+// Writes the manifest, and a launcher next to it since browsers call the
+// host with arguments of their own and without rk's flags
+pub fn install(browser: Browser, extension: &str, home: &Path, command: &str) -> VaultResult<PathBuf> {
+    let dir = browser.manifest_dir(home);
+    let launcher = dir.join(format!("{}-native-host", HOST));
+    let manifest = dir.join(format!("{}.json", HOST));
+
+    fs::create_dir_all(&dir)?;
+    fs::write(&launcher, format!("#!/bin/sh\nexec {} native-messaging \"$@\"\n", command))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(&launcher, fs::Permissions::from_mode(0o700))?;
+    }
+
+    let json = serde_json::to_string_pretty(&browser.manifest(&launcher, extension))
+        .map_err(|err| VaultError::Error(err.to_string()))?;
+
+    fs::write(&manifest, format!("{}\n", json))?;
+
+    Ok(manifest)
+}
+
+// A u32 length in the machine's byte order and then the json, `None`
+// once the browser has closed the pipe
+pub fn read_message<R: Read>(input: &mut R) -> VaultResult<Option<Value>> {
+    let mut length = [0u8; 4];
+
+    match input.read_exact(&mut length) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+
+    if length > MAX_REQUEST {
+        return Err(VaultError::Error(format!("Message of {} bytes refused", length)));
+    }
+
+    let mut message = vec![0u8; length];
+
+    input.read_exact(&mut message)?;
+
+    serde_json::from_slice(&message)
+        .map(Some)
+        .map_err(|err| VaultError::Error(format!("Invalid message: {}", err)))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> VaultResult<()> {
+    let mut json = serde_json::to_vec(message).map_err(|err| VaultError::Error(err.to_string()))?;
+
+    if json.len() > MAX_RESPONSE {
+        json = serde_json::to_vec(&failure("Too many accounts to send")).map_err(|err| VaultError::Error(err.to_string()))?;
+    }
+
+    output.write_all(&(json.len() as u32).to_ne_bytes())?;
+    output.write_all(&json)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+fn failure(error: &str) -> Value {
+    json!({ "ok": false, "error": error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+
+    use crate::Kdf;
+    use crate::audit::{self, Audit};
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `native.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn message(value: Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        write_message(&mut bytes, &value).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn origins() {
+        assert_eq!(origin("https://GitHub.com/login?next=/"), Some(String::from("https://github.com")));
+        assert_eq!(origin("https://github.com:443"), Some(String::from("https://github.com")));
+        assert_eq!(origin("http://localhost:8080/admin"), Some(String::from("http://localhost:8080")));
+        assert_eq!(origin("https://me@[::1]:8443/"), Some(String::from("https://[::1]:8443")));
+        assert_eq!(origin("https://[::1]/"), Some(String::from("https://[::1]")));
+        assert_eq!(origin("github.com"), Some(String::from("https://github.com")));
+        assert_eq!(origin("https:///path"), None);
+    }
+
+    #[test]
+    fn messages() {
+        let mut input: &[u8] = &[message(json!({ "action": "find" })), message(json!([1, 2]))].concat();
+
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "action": "find" })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!([1, 2])));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        assert!(read_message(&mut &u32::MAX.to_ne_bytes()[..]).is_err());
+    }
+
+    #[test]
+    fn find_get_save() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let keeper = Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+                let (path, key) = (audit::path(&config, &locker), keeper.key().unwrap().to_vec());
+                let log = Audit::new(path.clone(), &key);
+                let mut keeper = keeper.with_audit(Audit::new(path, &key), "alice").unwrap();
+                let mut fields = Fields::new();
+
+                fields.insert(URL.to_string(), String::from("https://github.com/login"));
+                keeper.add(Args::new(Some("github"), Some("me"), Some("secret"))).unwrap();
+                keeper.annotate(Args::new(Some("github"), Some("me"), None), fields).unwrap();
+                keeper.add(Args::new(Some("gitlab"), Some("me"), Some("other"))).unwrap();
+
+                let found = answer(&mut keeper, Request::Find { url: String::from("https://github.com/settings") });
+                let elsewhere = answer(&mut keeper, Request::Find { url: String::from("https://github.com.evil.com/") });
+                let get = |keeper: &mut Keeper, url: &str, entity: &str, account: &str| answer(keeper, Request::Get {
+                    url: url.to_string(),
+                    entity: entity.to_string(),
+                    account: account.to_string()
+                });
+
+                let credentials = get(&mut keeper, "https://github.com/", "github", "me");
+                let wrong_site = get(&mut keeper, "https://gitlab.com/", "github", "me");
+                let unmatched = get(&mut keeper, "https://github.com/", "gitlab", "me");
+
+                let saved = answer(&mut keeper, Request::Save {
+                    url: String::from("https://news.ycombinator.com/login?goto=news"),
+                    username: String::from("pg"),
+                    password: String::from("hunter2"),
+                    entity: None
+                });
+                let saved_credentials = get(&mut keeper, "https://news.ycombinator.com/", "news.ycombinator.com", "pg");
+                let reads: Vec<String> = log.events().unwrap().into_iter()
+                    .filter(|event| event.action != "add")
+                    .map(|event| format!("{} {:?} {:?}", event.action, event.entity, event.account))
+                    .collect();
+
+                assert_eq!(found, json!({ "ok": true, "accounts": [{ "entity": "github", "account": "me" }] }));
+                assert_eq!(elsewhere, json!({ "ok": true, "accounts": [] }));
+                assert_eq!(credentials, json!({ "ok": true, "username": "me", "password": "secret" }));
+                assert_eq!(wrong_site["ok"], json!(false));
+                assert_eq!(unmatched["ok"], json!(false));
+                assert_eq!(saved, json!({ "ok": true, "entity": "news.ycombinator.com", "account": "pg" }));
+                assert_eq!(saved_credentials["password"], json!("hunter2"));
+                assert_eq!(reads, vec![r#"read Some("github") Some("me")"#, r#"read Some("news.ycombinator.com") Some("pg")"#]);
+            }
+        };
+    }
+
+    #[test]
+    fn serve_requests() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config, locker, "master", Kdf::new(8, 1, 1)).unwrap();
+                let input = [
+                    message(json!({ "action": "find", "url": "https://github.com" })),
+                    message(json!({ "action": "delete" }))
+                ].concat();
+                let mut output = Vec::new();
+
+                serve(&mut keeper, &input[..], &mut output).unwrap();
+
+                let mut responses = &output[..];
+
+                assert_eq!(read_message(&mut responses).unwrap(), Some(json!({ "ok": true, "accounts": [] })));
+                assert_eq!(read_message(&mut responses).unwrap().unwrap()["ok"], json!(false));
+                assert_eq!(read_message(&mut responses).unwrap(), None);
+            }
+        };
+    }
+
+    #[test]
+    fn install_manifests() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (home, _) = this.as_path_buf();
+
+                let firefox = install(Browser::Firefox, "rk@example.com", &home, "/usr/bin/rk").unwrap();
+                let chrome = install(Browser::Chrome, "abcdefghijklmnop", &home, "/usr/bin/rk --vault work").unwrap();
+
+                let manifest: Value = serde_json::from_str(&fs::read_to_string(&chrome).unwrap()).unwrap();
+                let launcher = PathBuf::from(manifest["path"].as_str().unwrap());
+                let firefox: Value = serde_json::from_str(&fs::read_to_string(&firefox).unwrap()).unwrap();
+
+                assert_eq!(manifest["name"], json!(HOST));
+                assert_eq!(manifest["allowed_origins"], json!(["chrome-extension://abcdefghijklmnop/"]));
+                assert_eq!(firefox["allowed_extensions"], json!(["rk@example.com"]));
+                assert!(launcher.starts_with(Browser::Chrome.manifest_dir(&home)));
+                assert_eq!(fs::read_to_string(&launcher).unwrap(), "#!/bin/sh\nexec /usr/bin/rk --vault work native-messaging \"$@\"\n");
+            }
+        };
+    }
+}
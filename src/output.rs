@@ -0,0 +1,205 @@
+use std::str::FromStr;
+use std::collections::BTreeMap;
+
+use crate::vault::{VaultError, VaultResult};
+
+/* Custom types */
+
+pub type Record = BTreeMap<String, String>;
+
+/* Constants */
+
+// TSV columns that always come first, in this order,
+// any other field follows alphabetically
+const COLUMNS: [&str; 4] = ["entity", "account", "password", "status"];
+
+/* Format enum */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Yaml,
+    Tsv,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = VaultError;
+
+    fn from_str(format: &str) -> VaultResult<Format> {
+        match format {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            "tsv" => Ok(Format::Tsv),
+            "csv" => Ok(Format::Csv),
+            _ => Err(VaultError::Error(format!("Unknown output format: {}", format)))
+        }
+    }
+}
+
+/* Functions */
+
+pub fn record(pairs: &[(&str, &str)]) -> Record {
+    pairs.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+// Renders records for scripts. Tables are printed by the `tables`
+// functions instead since each command lays them out differently.
+pub fn render(records: &[Record], format: Format) -> VaultResult<String> {
+    match format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(records)
+                .map_err(|err| VaultError::Error(err.to_string()))?;
+
+            Ok(format!("{}\n", json))
+        },
+        Format::Yaml => {
+            serde_yaml::to_string(records)
+                .map_err(|err| VaultError::Error(err.to_string()))
+        },
+        Format::Tsv => Ok(tsv(records)),
+        Format::Csv => csv(records),
+        Format::Table => Err(VaultError::Error("Tables are not rendered as records".to_string()))
+    }
+}
+
+pub fn field(records: &[Record], name: &str) -> VaultResult<String> {
+    let values: Vec<&str> = records.iter()
+        .filter_map(|record| record.get(name))
+        .map(|value| &value[..])
+        .collect();
+
+    if values.is_empty() {
+        return Err(VaultError::MissingField(name.to_string()));
+    }
+
+    Ok(format!("{}\n", values.join("\n")))
+}
+
+fn columns(records: &[Record]) -> Vec<String> {
+    let mut columns: Vec<String> = COLUMNS.iter()
+        .filter(|column| records.iter().any(|record| record.contains_key(**column)))
+        .map(|column| column.to_string())
+        .collect();
+
+    for record in records {
+        for name in record.keys() {
+            if !columns.contains(name) {
+                columns.push(name.to_owned());
+            }
+        }
+    }
+
+    let fixed = columns.iter().filter(|column| COLUMNS.contains(&&column[..])).count();
+
+    columns[fixed..].sort();
+    columns
+}
+
+fn tsv(records: &[Record]) -> String {
+    let columns = columns(records);
+    let escape = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+
+    let mut lines = vec![columns.join("\t")];
+
+    for record in records {
+        let row: Vec<String> = columns.iter()
+            .map(|column| record.get(column).map(|value| escape(value)).unwrap_or_default())
+            .collect();
+
+        lines.push(row.join("\t"));
+    }
+
+    format!("{}\n", lines.join("\n"))
+}
+
+// Same columns as TSV, quoted where needed instead of escaped
+fn csv(records: &[Record]) -> VaultResult<String> {
+    let columns = columns(records);
+    let invalid = |err: csv::Error| VaultError::Error(err.to_string());
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    writer.write_record(&columns).map_err(invalid)?;
+
+    for record in records {
+        let row = columns.iter().map(|column| record.get(column).map(|value| value.as_str()).unwrap_or(""));
+
+        writer.write_record(row).map_err(invalid)?;
+    }
+
+    let bytes = writer.into_inner().map_err(|err| VaultError::Error(err.to_string()))?;
+
+    String::from_utf8(bytes).map_err(|err| VaultError::Error(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            record(&[("entity", "gmail"), ("account", "me"), ("password", "p\tw"), ("url", "https://gmail.com")]),
+            record(&[("entity", "gmail"), ("account", "you"), ("password", "pw2")]),
+        ]
+    }
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!("json".parse::<Format>().unwrap(), Format::Json);
+        assert_eq!("tsv".parse::<Format>().unwrap(), Format::Tsv);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn render_json() {
+        let json = render(&records(), Format::Json).unwrap();
+        let parsed: Vec<Record> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed, records());
+    }
+
+    #[test]
+    fn render_yaml() {
+        let yaml = render(&records(), Format::Yaml).unwrap();
+        let parsed: Vec<Record> = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(parsed, records());
+    }
+
+    #[test]
+    fn render_tsv() {
+        let tsv = render(&records(), Format::Tsv).unwrap();
+
+        assert_eq!(
+            tsv,
+            "entity\taccount\tpassword\turl\ngmail\tme\tp\\tw\thttps://gmail.com\ngmail\tyou\tpw2\t\n"
+        );
+    }
+
+    #[test]
+    fn render_csv() {
+        let csv = render(&records(), Format::Csv).unwrap();
+
+        assert_eq!(
+            csv,
+            "entity,account,password,url\ngmail,me,p\tw,https://gmail.com\ngmail,you,pw2,\n"
+        );
+    }
+
+    #[test]
+    fn field_values() {
+        assert_eq!(field(&records(), "password").unwrap(), "p\tw\npw2\n");
+        assert_eq!(field(&records(), "url").unwrap(), "https://gmail.com\n");
+        assert!(field(&records(), "notes").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+use crate::{Args, Keeper, Resolve};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+const MASK: &[u8] = b"********";
+
+/* Variable struct definition */
+
+// `NAME=entity/account`, the account being what follows the last slash
+// since entity names may hold some, eg. `work/jira/me`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub entity: String,
+    pub account: String
+}
+
+impl Variable {
+
+    /* Intialisers */
+
+    pub fn parse(spec: &str) -> VaultResult<Variable> {
+        let invalid = || VaultError::Error(format!("Invalid --env {:?}, expected NAME=entity/account", spec));
+
+        let (name, reference) = spec.split_once('=').ok_or_else(invalid)?;
+        let (entity, account) = reference.rsplit_once('/').ok_or_else(invalid)?;
+
+        let valid_name = name.chars().next().map(|first| first.is_ascii_alphabetic() || first == '_').unwrap_or(false)
+            && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_');
+
+        if !valid_name || entity.is_empty() || account.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Variable { name: name.to_string(), entity: entity.to_string(), account: account.to_string() })
+    }
+}
+
+/* Functions */
+
+// Every variable with its password, failing on the first missing account
+// so the command never runs with part of its environment
+pub fn resolve(keeper: &mut Keeper, variables: &[Variable]) -> VaultResult<Vec<(String, String)>> {
+    variables.iter()
+        .map(|variable| {
+            let args = Args::new(Some(&variable.entity), Some(&variable.account), None);
+
+            match keeper.read(args)? {
+                Resolve::Read(password) => Ok((variable.name.clone(), password)),
+                _ => Err(VaultError::MissingAccount)
+            }
+        })
+        .collect()
+}
+
+// Runs `command` with the variables added to this process' environment
+// and returns its exit code. With `mask` its output goes through rk, line
+// by line, so the values are replaced before reaching the terminal
+pub fn exec(command: &[String], env: &[(String, String)], mask: bool) -> VaultResult<i32> {
+    let (program, args) = command.split_first()
+        .ok_or_else(|| VaultError::Error(String::from("No command to run")))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(env.iter().cloned())
+        .stdout(if mask { Stdio::piped() } else { Stdio::inherit() })
+        .stderr(if mask { Stdio::piped() } else { Stdio::inherit() })
+        .spawn()
+        .map_err(|err| VaultError::Error(format!("Could not run {}: {}", program, err)))?;
+
+    let status = match mask {
+        true => {
+            let secrets: Vec<Vec<u8>> = env.iter().map(|(_, value)| value.as_bytes().to_vec()).collect();
+            let stdout = child.stdout.take().map(|out| {
+                let secrets = secrets.clone();
+
+                thread::spawn(move || copy_masked(out, io::stdout(), &secrets))
+            });
+            let stderr = child.stderr.take().map(|err| {
+                thread::spawn(move || copy_masked(err, io::stderr(), &secrets))
+            });
+
+            for copy in stdout.into_iter().chain(stderr) {
+                let _ = copy.join();
+            }
+
+            child.wait()?
+        },
+        false => child.wait()?
+    };
+
+    Ok(exit_code(status))
+}
+
+// What a shell reports: the code, or 128 and the signal that killed it
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
+}
+
+fn copy_masked<R: Read, W: Write>(reader: R, mut writer: W, secrets: &[Vec<u8>]) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        writer.write_all(&masked(&line, secrets))?;
+        writer.flush()?;
+        line.clear();
+    }
+
+    Ok(())
+}
+
+// Longer values first, so one holding another is masked as a whole
+fn masked(line: &[u8], secrets: &[Vec<u8>]) -> Vec<u8> {
+    let mut secrets: Vec<&Vec<u8>> = secrets.iter().filter(|secret| !secret.is_empty()).collect();
+    let mut output = Vec::with_capacity(line.len());
+    let mut rest = line;
+
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+    'outer: while !rest.is_empty() {
+        for secret in secrets.iter() {
+            if rest.starts_with(secret) {
+                output.extend_from_slice(MASK);
+                rest = &rest[secret.len()..];
+
+                continue 'outer;
+            }
+        }
+
+        output.push(rest[0]);
+        rest = &rest[1..];
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use crate::Kdf;
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `run.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn command(script: &str) -> Vec<String> {
+        vec![String::from("sh"), String::from("-c"), script.to_string()]
+    }
+
+    #[test]
+    fn parse_variables() {
+        let nested = Variable::parse("JIRA_TOKEN=work/jira/me").unwrap();
+
+        assert_eq!(Variable::parse("DB_PASS=prod-db/admin").unwrap(), Variable {
+            name: String::from("DB_PASS"),
+            entity: String::from("prod-db"),
+            account: String::from("admin")
+        });
+        assert_eq!((nested.entity.as_str(), nested.account.as_str()), ("work/jira", "me"));
+        assert!(Variable::parse("DB_PASS").is_err());
+        assert!(Variable::parse("DB_PASS=prod-db").is_err());
+        assert!(Variable::parse("DB_PASS=prod-db/").is_err());
+        assert!(Variable::parse("1PASS=prod-db/admin").is_err());
+        assert!(Variable::parse("DB-PASS=prod-db/admin").is_err());
+    }
+
+    #[test]
+    fn mask_values() {
+        let secrets = [b"hunter2".to_vec(), b"hunter22".to_vec(), Vec::new()];
+        let mut output = Vec::new();
+
+        copy_masked(&b"login hunter22 ok\npartial hunter\nhunter2"[..], &mut output, &secrets).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "login ******** ok\npartial hunter\n********");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_and_exec() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config, locker, "master", Kdf::new(8, 1, 1)).unwrap();
+
+                keeper.add(Args::new(Some("prod-db"), Some("admin"), Some("secret"))).unwrap();
+
+                let env = resolve(&mut keeper, &[Variable::parse("DB_PASS=prod-db/admin").unwrap()]).unwrap();
+                let missing = resolve(&mut keeper, &[Variable::parse("DB_PASS=prod-db/root").unwrap()]);
+
+                assert_eq!(env, vec![(String::from("DB_PASS"), String::from("secret"))]);
+                assert!(missing.is_err());
+                assert_eq!(exec(&command("test \"$DB_PASS\" = secret"), &env, false).unwrap(), 0);
+                assert_eq!(exec(&command("test \"$DB_PASS\" = secret"), &env, true).unwrap(), 0);
+                assert_eq!(exec(&command("exit 7"), &env, true).unwrap(), 7);
+                assert_eq!(exec(&command("kill -9 $$"), &env, false).unwrap(), 137);
+                assert!(exec(&[String::from("rk-missing-command")], &env, false).is_err());
+                assert!(exec(&[], &env, false).is_err());
+            }
+        };
+    }
+}
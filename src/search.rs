@@ -0,0 +1,121 @@
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+
+use crate::fields::{self, Fields, URL};
+
+/* Entry struct */
+
+// Decrypted view of one account, only ever kept in memory
+#[derive(Debug)]
+pub struct Entry {
+    pub entity: String,
+    pub account: String,
+    pub fields: Fields,
+}
+
+impl Entry {
+    fn candidates(&self) -> Vec<&str> {
+        let mut candidates = vec![&self.entity[..], &self.account[..]];
+
+        if let Some(url) = self.fields.get(URL) {
+            candidates.push(url);
+        }
+
+        candidates
+    }
+}
+
+/* Functions */
+
+// Ranks entries by their best fuzzy score over entity, account,
+// url and tags. Entries that match nothing are left out.
+pub fn rank(entries: Vec<Entry>, query: &str) -> Vec<(String, String)> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut scored: Vec<(i64, Entry)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let tags = fields::tags(&entry.fields);
+            let best = entry.candidates()
+                .into_iter()
+                .chain(tags.iter().map(|tag| &tag[..]))
+                .filter_map(|candidate| matcher.fuzzy_match(candidate, query))
+                .max();
+
+            best.map(|score| (score, entry))
+        })
+        .collect();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score)
+            .then_with(|| a.entity.cmp(&b.entity))
+            .then_with(|| a.account.cmp(&b.account))
+    });
+
+    scored
+        .into_iter()
+        .map(|(_, entry)| (entry.entity, entry.account))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fields::TAGS;
+
+    fn entry(entity: &str, account: &str, url: Option<&str>, tags: Option<&str>) -> Entry {
+        let mut fields = Fields::new();
+
+        if let Some(url) = url { fields.insert(URL.to_string(), url.to_string()); }
+        if let Some(tags) = tags { fields.insert(TAGS.to_string(), tags.to_string()); }
+
+        Entry {
+            entity: entity.to_string(),
+            account: account.to_string(),
+            fields
+        }
+    }
+
+    #[test]
+    fn rank_by_score() {
+        let entries = vec![
+            entry("github", "me", None, None),
+            entry("gmail", "me@gmail.com", None, None),
+            entry("bank", "savings", None, None),
+        ];
+
+        let ranked = rank(entries, "gml");
+
+        assert_eq!(ranked[0], (String::from("gmail"), String::from("me@gmail.com")));
+        assert!(!ranked.contains(&(String::from("bank"), String::from("savings"))));
+    }
+
+    #[test]
+    fn rank_matches_url_and_tags() {
+        let entries = vec![
+            entry("work", "deploy", Some("https://ci.example.com"), None),
+            entry("home", "router", None, Some("network,admin")),
+            entry("bank", "savings", None, None),
+        ];
+
+        let by_url = rank(entries, "ci.example");
+
+        assert_eq!(by_url, vec![(String::from("work"), String::from("deploy"))]);
+
+        let entries = vec![
+            entry("home", "router", None, Some("network,admin")),
+            entry("bank", "savings", None, None),
+        ];
+
+        let by_tag = rank(entries, "network");
+
+        assert_eq!(by_tag, vec![(String::from("home"), String::from("router"))]);
+    }
+
+    #[test]
+    fn rank_no_matches() {
+        let entries = vec![ entry("bank", "savings", None, None) ];
+
+        assert!(rank(entries, "zzz").is_empty());
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::time::Duration;
+
+use rand::{Rng, OsRng};
+use serde_json::{json, Value};
+
+use crate::{Args, Fields, Keeper, Resolve};
+use crate::audit::{Audit, Event};
+use crate::vault::{VaultError, VaultResult};
+
+/* Constants */
+
+// The entity holding one account per client, its password being the
+// client's token. The api never hands it out
+pub const TOKENS: &str = "rk-serve";
+
+pub const SCOPE: &str = "scope";
+
+const MAX_HEAD: usize = 64 * 1024;
+const MAX_BODY: usize = 1024 * 1024;
+
+// A client that stops sending doesn't hold up the others for long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* Scope enum definition */
+
+// What a client's token allows, `write` includes `read`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Read,
+    Write
+}
+
+impl Scope {
+
+    /* Intialisers */
+
+    pub fn parse(scope: &str) -> VaultResult<Scope> {
+        match scope {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            _ => Err(VaultError::Error(format!("Unknown scope {:?}, expected read or write", scope)))
+        }
+    }
+
+    /* Methods */
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write"
+        }
+    }
+}
+
+/* Request struct definition */
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: Vec<String>,
+    pub query: BTreeMap<String, String>,
+    pub token: Option<String>,
+    pub body: Vec<u8>
+}
+
+impl Request {
+
+    /* Intialisers */
+
+    // An HTTP/1.1 request, its body sized by Content-Length. Path segments
+    // are percent decoded, so `/` in names comes as `%2F`
+    pub fn read<R: Read>(reader: R) -> VaultResult<Request> {
+        let invalid = |msg: &str| VaultError::Error(msg.to_string());
+        let mut reader = BufReader::new(reader.take((MAX_HEAD + MAX_BODY) as u64));
+        let mut line = String::new();
+        let mut head = 0;
+
+        reader.read_line(&mut line)?;
+
+        let mut parts = line.split_whitespace();
+        let method = parts.next().ok_or_else(|| invalid("Empty request"))?.to_string();
+        let target = parts.next().ok_or_else(|| invalid("Request without a path"))?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let path = path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(decode)
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| invalid("Invalid path encoding"))?;
+
+        let query = query.split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .filter(|(key, _)| !key.is_empty())
+            .map(|(key, value)| Some((decode(key)?, decode(&value.replace('+', " "))?)))
+            .collect::<Option<BTreeMap<String, String>>>()
+            .ok_or_else(|| invalid("Invalid query encoding"))?;
+
+        let mut token = None;
+        let mut length = 0;
+
+        loop {
+            let mut header = String::new();
+
+            head += reader.read_line(&mut header)?;
+
+            if head > MAX_HEAD {
+                return Err(invalid("Headers too large"));
+            }
+
+            let header = header.trim_end();
+
+            if header.is_empty() {
+                break;
+            }
+
+            let (name, value) = header.split_once(':').ok_or_else(|| invalid("Invalid header"))?;
+            let value = value.trim();
+
+            match name.trim().to_lowercase().as_str() {
+                "authorization" => token = value.strip_prefix("Bearer ").map(|token| token.trim().to_string()),
+                "content-length" => length = value.parse().map_err(|_| invalid("Invalid Content-Length"))?,
+                "transfer-encoding" => return Err(invalid("Chunked bodies are not supported")),
+                _ => {}
+            }
+        }
+
+        if length > MAX_BODY {
+            return Err(invalid("Body too large"));
+        }
+
+        let mut body = vec![0u8; length];
+
+        reader.read_exact(&mut body)?;
+
+        Ok(Request { method, path, query, token, body })
+    }
+}
+
+/* Response struct definition */
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value
+}
+
+impl Response {
+
+    /* Intialisers */
+
+    pub fn new(status: u16, body: Value) -> Response {
+        Response { status, body }
+    }
+
+    pub fn error(status: u16, message: &str) -> Response {
+        Response { status, body: json!({ "error": message }) }
+    }
+
+    /* Methods */
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let body = format!("{}\n", self.body);
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error"
+        };
+
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            self.status, reason, body.len(), body
+        )?;
+
+        writer.flush()
+    }
+}
+
+/* Server struct definition */
+
+// Answers one request per connection, one connection at a time, and
+// writes every request to the audit log, refused ones included. A request
+// that can't be recorded is answered with a 500 instead
+pub struct Server<'k> {
+    keeper: &'k mut Keeper,
+    audit: Audit
+}
+
+impl<'k> Server<'k> {
+
+    /* Intialisers */
+
+    // Takes the audit log over from `keeper`, so that each request is
+    // recorded once, with its status
+    pub fn new(keeper: &'k mut Keeper) -> VaultResult<Server<'k>> {
+        let audit = keeper.take_audit()
+            .ok_or_else(|| VaultError::Error(String::from("The api is only served with an audit log")))?;
+
+        Ok(Server { keeper, audit })
+    }
+
+    /* Methods */
+
+    // Only loopback addresses, the api is for tools on this machine
+    pub fn listen(&mut self, address: &str) -> VaultResult<()> {
+        let address: SocketAddr = address.parse()
+            .map_err(|_| VaultError::Error(format!("Invalid address {:?}, expected eg. 127.0.0.1:8750", address)))?;
+
+        if !address.ip().is_loopback() {
+            return Err(VaultError::Error(format!("Refusing to listen on {}, only loopback addresses are allowed", address.ip())));
+        }
+
+        let listener = TcpListener::bind(address)
+            .map_err(|err| VaultError::Error(format!("Could not listen on {}: {}", address, err)))?;
+
+        for stream in listener.incoming() {
+            // A client that is gone before being accepted only loses its
+            // own connection
+            match stream.and_then(|stream| stream.set_read_timeout(Some(TIMEOUT)).map(|_| stream)) {
+                Ok(stream) => self.serve(stream),
+                Err(err) => eprintln!("rk: could not accept an api client: {}", err)
+            }
+        }
+
+        Ok(())
+    }
+
+    // Only the user can connect to the socket
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, socket: &Path) -> VaultResult<()> {
+        use std::fs;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        use crate::header;
+
+        if UnixStream::connect(socket).is_ok() {
+            return Err(VaultError::Error(format!("A server is already listening on {:?}", socket)));
+        }
+
+        if socket.exists() {
+            fs::remove_file(socket)?;
+        }
+
+        let listener = UnixListener::bind(socket)
+            .map_err(|err| VaultError::Error(format!("Could not listen on {:?}: {}", socket, err)))?;
+
+        header::restrict(socket, 0o600)?;
+
+        for stream in listener.incoming() {
+            // A client that is gone before being accepted only loses its
+            // own connection
+            match stream.and_then(|stream| stream.set_read_timeout(Some(TIMEOUT)).map(|_| stream)) {
+                Ok(stream) => self.serve(stream),
+                Err(err) => eprintln!("rk: could not accept an api client: {}", err)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen_unix(&mut self, _socket: &Path) -> VaultResult<()> {
+        Err(VaultError::Error("Unix sockets are only supported on unix".to_string()))
+    }
+
+    // A client hanging up early only loses its own answer
+    fn serve<S: Read + Write>(&mut self, mut stream: S) {
+        let response = match Request::read(&mut stream) {
+            Ok(request) => self.answer(&request),
+            Err(err) => Response::error(400, &err.to_str())
+        };
+
+        let _ = response.write(&mut stream);
+    }
+
+    pub fn answer(&mut self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.iter().map(|segment| segment.as_str()).collect();
+        let (action, scope) = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["v1", "entities"]) | ("GET", ["v1", "entities", _]) => ("list", Scope::Read),
+            ("GET", ["v1", "find"]) => ("find", Scope::Read),
+            ("GET", ["v1", "entities", _, _]) => ("read", Scope::Read),
+            ("PUT", ["v1", "entities", _, _]) => ("add", Scope::Write),
+            ("DELETE", ["v1", "entities", _]) | ("DELETE", ["v1", "entities", _, _]) => ("remove", Scope::Write),
+            _ => ("unknown", Scope::Write)
+        };
+
+        let entity = segments.get(2).copied();
+        let account = segments.get(3).copied();
+        let client = request.token.as_deref().and_then(|token| authenticate(self.keeper, token).ok().flatten());
+        let actor = match &client {
+            Some((name, _)) => format!("api:{}", name),
+            None => String::from("api")
+        };
+
+        let response = match &client {
+            None => Response::error(401, "Missing or unknown bearer token"),
+            Some((_, granted)) if *granted < scope => Response::error(403, "The token doesn't allow this"),
+            Some(_) if entity == Some(TOKENS) => Response::error(403, "Tokens can't be read or changed through the api"),
+            Some(_) if action == "unknown" => Response::error(404, "Unknown route"),
+            Some(_) => self.route(action, entity, account, request).unwrap_or_else(failure)
+        };
+
+        match self.audit.record(&Event::new(&actor, action, entity, account, &response.status.to_string())) {
+            Ok(()) => response,
+            Err(err) => Response::error(500, &format!("The request couldn't be recorded: {}", err.to_str()))
+        }
+    }
+
+    fn route(&mut self, action: &str, entity: Option<&str>, account: Option<&str>, request: &Request) -> VaultResult<Response> {
+        let args = Args::new(entity, account, None);
+
+        match (action, entity, account) {
+            ("list", None, _) => {
+                let entities: Vec<String> = self.keeper.list(None)?.to_list()
+                    .into_iter()
+                    .filter(|entity| entity != TOKENS)
+                    .collect();
+
+                Ok(Response::new(200, json!({ "entities": entities })))
+            },
+            ("list", Some(entity), _) => {
+                let accounts = self.keeper.list(Some(args))?.to_list();
+
+                Ok(Response::new(200, json!({ "entity": entity, "accounts": accounts })))
+            },
+            ("find", _, _) => {
+                let query = request.query.get("q").map(|query| query.as_str()).unwrap_or("");
+                let matches: Vec<Value> = self.keeper.search(query)?.to_matches()
+                    .into_iter()
+                    .filter(|(entity, _)| entity != TOKENS)
+                    .map(|(entity, account)| json!({ "entity": entity, "account": account }))
+                    .collect();
+
+                Ok(Response::new(200, json!({ "matches": matches })))
+            },
+            ("read", Some(entity), Some(account)) => {
+                let password = self.keeper.read(args.clone())?.to_string();
+                let fields = self.keeper.fields(args)?.to_fields();
+
+                Ok(Response::new(200, json!({ "entity": entity, "account": account, "password": password, "fields": fields })))
+            },
+            ("add", Some(entity), Some(account)) => {
+                let body: Value = serde_json::from_slice(&request.body)
+                    .map_err(|err| VaultError::Error(format!("Invalid json body: {}", err)))?;
+                let password = body["password"].as_str()
+                    .ok_or_else(|| VaultError::Error(String::from("The body needs a \"password\"")))?;
+                let fields: Fields = serde_json::from_value(body.get("fields").cloned().unwrap_or_else(|| json!({})))
+                    .map_err(|_| VaultError::Error(String::from("\"fields\" has to map names to strings")))?;
+
+                self.keeper.add(Args::new(Some(entity), Some(account), Some(password)))?;
+
+                if !fields.is_empty() {
+                    self.keeper.annotate(Args::new(Some(entity), Some(account), None), fields)?;
+                }
+
+                Ok(Response::new(200, json!({ "status": "done" })))
+            },
+            ("remove", Some(_), _) => {
+                self.keeper.remove(args)?;
+
+                Ok(Response::new(200, json!({ "status": "done" })))
+            },
+            _ => Ok(Response::error(404, "Unknown route"))
+        }
+    }
+}
+
+/* Functions */
+
+// A new client with a random 128 bit token, which is only shown this
+// once. Adding an existing client replaces its token
+pub fn add_client(keeper: &mut Keeper, name: &str, scope: Scope) -> VaultResult<String> {
+    let mut bytes = [0u8; 16];
+    let mut rng = OsRng::new().expect("Could not open the OS random source");
+
+    rng.fill_bytes(&mut bytes);
+
+    let token = hex::encode(bytes);
+    let mut fields = Fields::new();
+
+    fields.insert(SCOPE.to_string(), scope.as_str().to_string());
+    keeper.add(Args::new(Some(TOKENS), Some(name), Some(&token)))?;
+    keeper.annotate(Args::new(Some(TOKENS), Some(name), None), fields)?;
+
+    Ok(token)
+}
+
+pub fn remove_client(keeper: &mut Keeper, name: &str) -> VaultResult<()> {
+    keeper.remove(Args::new(Some(TOKENS), Some(name), None))?;
+
+    Ok(())
+}
+
+// The client a token belongs to and its scope, `None` for unknown tokens
+fn authenticate(keeper: &mut Keeper, token: &str) -> VaultResult<Option<(String, Scope)>> {
+    let clients = match keeper.find_entry(Args::new(Some(TOKENS), None, None)) {
+        Ok(Resolve::Find(clients)) => clients,
+        _ => return Ok(None)
+    };
+
+    for (name, stored) in clients {
+        if !stored.is_empty() && same(stored.as_bytes(), token.as_bytes()) {
+            let fields = keeper.fields(Args::new(Some(TOKENS), Some(&name), None))?.to_fields();
+            let scope = fields.get(SCOPE).map(|scope| Scope::parse(scope)).unwrap_or(Ok(Scope::Read))?;
+
+            return Ok(Some((name, scope)));
+        }
+    }
+
+    Ok(None)
+}
+
+// Compares every byte, so how long it takes says nothing about the token
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn failure(err: VaultError) -> Response {
+    let status = match &err {
+        VaultError::MissingEntity | VaultError::MissingAccount | VaultError::MissingField(_) => 404,
+        VaultError::ExistingEntity | VaultError::ExistingAccount => 409,
+        VaultError::Io(_) => 500,
+        _ => 400
+    };
+
+    Response::error(status, &err.to_str())
+}
+
+fn decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let hex = segment.get(index + 1..index + 3)?;
+
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 3;
+            },
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, remove_dir_all};
+
+    use crate::Kdf;
+    use crate::audit;
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `server.rs` test", path);
+
+            if exists { remove_dir_all(path).expect(&msg); }
+        }
+    }
+
+    fn request(method: &str, path: &str, token: &str, body: &str) -> Request {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}", method, path, token, body.len(), body);
+
+        Request::read(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn read_requests() {
+        let read = request("PUT", "/v1/entities/work%2Fjira/me?q=git+hub&x", "abc", "{}");
+
+        assert_eq!(read.method, "PUT");
+        assert_eq!(read.path, vec!["v1", "entities", "work/jira", "me"]);
+        assert_eq!(read.query.get("q").map(|q| q.as_str()), Some("git hub"));
+        assert_eq!(read.token.as_deref(), Some("abc"));
+        assert_eq!(read.body, b"{}");
+        assert!(Request::read(&b"GET /v1/%zz HTTP/1.1\r\n\r\n"[..]).is_err());
+        assert!(Request::read(&b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"[..]).is_err());
+        assert!(Request::read(&b""[..]).is_err());
+    }
+
+    #[test]
+    fn write_responses() {
+        let mut bytes = Vec::new();
+
+        Response::error(404, "Unknown route").write(&mut bytes).unwrap();
+
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(text.contains("Content-Length: 26\r\n"));
+        assert!(text.ends_with("\r\n\r\n{\"error\":\"Unknown route\"}\n"));
+    }
+
+    #[test]
+    fn scopes_and_routes() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+                fs::create_dir_all(&config).unwrap();
+
+                let reader = add_client(&mut keeper, "dashboard", Scope::Read).unwrap();
+                let writer = add_client(&mut keeper, "ci", Scope::Write).unwrap();
+                let (path, key) = (audit::path(&config, &locker), keeper.key().unwrap().to_vec());
+                let mut keeper = keeper.with_audit(Audit::new(path.clone(), &key), "alice").unwrap();
+                let mut server = Server::new(&mut keeper).unwrap();
+
+                let added = server.answer(&request("PUT", "/v1/entities/github/me", &writer, r#"{"password":"secret","fields":{"url":"https://github.com"}}"#));
+                let refused = server.answer(&request("PUT", "/v1/entities/github/me", &reader, r#"{"password":"other"}"#));
+                let unknown = server.answer(&request("GET", "/v1/entities", "nope", ""));
+                let list = server.answer(&request("GET", "/v1/entities", &reader, ""));
+                let accounts = server.answer(&request("GET", "/v1/entities/github", &reader, ""));
+                let read = server.answer(&request("GET", "/v1/entities/github/me", &reader, ""));
+                let found = server.answer(&request("GET", "/v1/find?q=gthub", &reader, ""));
+                let tokens = server.answer(&request("GET", "/v1/entities/rk-serve/ci", &writer, ""));
+                let missing = server.answer(&request("GET", "/v1/entities/gitlab/me", &reader, ""));
+                let removed = server.answer(&request("DELETE", "/v1/entities/github/me", &writer, ""));
+                let route = server.answer(&request("POST", "/v1/entities", &writer, ""));
+
+                let log = Audit::new(path.clone(), &key);
+                let events = log.events().unwrap();
+                let github: Vec<String> = events.iter()
+                    .filter(|event| event.entity.as_deref() == Some("github"))
+                    .map(|event| format!("{} {} {}", event.actor, event.action, event.outcome))
+                    .collect();
+
+                assert_eq!(added.status, 200);
+                assert_eq!(refused.status, 403);
+                assert_eq!(unknown.status, 401);
+                assert_eq!(list.body, json!({ "entities": ["github"] }));
+                assert_eq!(accounts.body, json!({ "entity": "github", "accounts": ["me"] }));
+                assert_eq!(read.body["password"], json!("secret"));
+                assert_eq!(read.body["fields"]["url"], json!("https://github.com"));
+                assert_eq!(found.body["matches"], json!([{ "entity": "github", "account": "me" }]));
+                assert_eq!(tokens.status, 403);
+                assert_eq!(missing.status, 404);
+                assert_eq!(removed.status, 200);
+                assert_eq!(route.status, 404);
+                assert_eq!(events.len(), 11);
+                assert_eq!(events[2], Event { time: events[2].time, ..Event::new("api", "list", None, None, "401") });
+                assert_eq!(github, vec![
+                    "api:ci add 200",
+                    "api:dashboard add 403",
+                    "api:dashboard list 200",
+                    "api:dashboard read 200",
+                    "api:ci remove 200"
+                ]);
+                assert_eq!(log.verify().unwrap(), 11);
+
+                fs::remove_file(path.with_extension("head")).unwrap();
+
+                let unrecorded = server.answer(&request("GET", "/v1/entities", &reader, ""));
+
+                assert_eq!(unrecorded.status, 500);
+                assert_eq!(unrecorded.body.get("entities"), None);
+            }
+        };
+    }
+
+    #[test]
+    fn serve_stream() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let mut keeper = Keeper::init(config.clone(), locker.clone(), "master", Kdf::new(8, 1, 1)).unwrap();
+
+                fs::create_dir_all(&config).unwrap();
+
+                let token = add_client(&mut keeper, "ci", Scope::Read).unwrap();
+                let audit = Audit::new(audit::path(&config, &locker), keeper.key().unwrap());
+                let mut keeper = keeper.with_audit(audit, "alice").unwrap();
+                let mut server = Server::new(&mut keeper).unwrap();
+                let raw = format!("GET /v1/entities HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n", token);
+                let mut stream = io::Cursor::new(raw.into_bytes());
+
+                server.serve(&mut stream);
+
+                let written = String::from_utf8(stream.into_inner()).unwrap();
+
+                assert!(written.contains("HTTP/1.1 200 OK\r\n"));
+                assert!(written.ends_with("{\"entities\":[]}\n"));
+            }
+        };
+    }
+}
@@ -1,18 +1,99 @@
-use std::path::PathBuf;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::default::Default;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde_yaml:: Value;
 use serde::{Serialize, Deserialize};
 
+use crate::vault::{VaultError, VaultResult};
+
+/* Schema */
+
+// Every setting as `section.name`, in the order `rk config list` shows them
+pub const KEYS: [&str; 4] = ["paths.locker", "paths.config", "crypto.algorithm", "vault.default"];
+
+pub const ALGORITHMS: [&str; 1] = ["aes-128-cbc"];
+
+pub const BACKENDS: [&str; 2] = ["directory", "kdbx"];
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
-    paths: HashMap<String, Value>
+    #[serde(default)]
+    paths: HashMap<String, Value>,
+    #[serde(default)]
+    crypto: HashMap<String, Value>,
+    #[serde(default)]
+    vault: HashMap<String, Value>,
+    #[serde(default)]
+    vaults: BTreeMap<String, Profile>,
+    #[serde(default)]
+    git: BTreeMap<String, GitHost>,
+    #[serde(skip)]
+    origins: HashMap<String, Origin>
+}
+
+// A named vault, anything it leaves out comes from
+// the top level settings
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub locker: String,
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    #[serde(default)]
+    pub algorithm: Option<String>
 }
 
+// Where `rk git-credential` keeps the credentials of a host, hosts
+// without one use the host as entity and the username as account
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GitHost {
+    pub entity: String,
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub usernames: BTreeMap<String, String>
+}
+
+fn default_backend() -> String {
+    BACKENDS[0].to_string()
+}
+
+// Where an effective value came from, keys missing from
+// `Settings.origins` are defaults
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File,
+    Env(String),
+    Flag(String),
+    Vault(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File => write!(f, "file"),
+            Origin::Env(name) => write!(f, "env {}", name),
+            Origin::Flag(name) => write!(f, "flag {}", name),
+            Origin::Vault(name) => write!(f, "vault {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SettingsOpts {
     Locker,
     Config,
+    Algorithm,
+    Vault,
 }
 
 impl SettingsOpts {
@@ -20,8 +101,42 @@ impl SettingsOpts {
         match self {
             SettingsOpts::Locker => "locker",
             SettingsOpts::Config => "config",
+            SettingsOpts::Algorithm => "algorithm",
+            SettingsOpts::Vault => "default",
         }
     }
+
+    fn section<'a>(self) -> &'a str {
+        match self {
+            SettingsOpts::Locker | SettingsOpts::Config => "paths",
+            SettingsOpts::Algorithm => "crypto",
+            SettingsOpts::Vault => "vault",
+        }
+    }
+
+    // Name used by the environment variable and the flag
+    fn name<'a>(self) -> &'a str {
+        match self {
+            SettingsOpts::Vault => "vault",
+            opt => opt.to_str(),
+        }
+    }
+
+    pub fn key(self) -> String {
+        format!("{}.{}", self.section(), self.to_str())
+    }
+
+    pub fn all() -> [SettingsOpts; 4] {
+        [SettingsOpts::Locker, SettingsOpts::Config, SettingsOpts::Algorithm, SettingsOpts::Vault]
+    }
+
+    pub fn env(self) -> String {
+        format!("RK_{}", self.name().to_uppercase())
+    }
+
+    pub fn flag(self) -> String {
+        format!("--{}", self.name())
+    }
 }
 
 impl Settings {
@@ -32,37 +147,667 @@ impl Settings {
         serde_yaml::from_str(yaml)
     }
 
+    // Loads the settings file and layers `RK_*` variables and then
+    // flags over it, so flag > env > file > default
+    pub fn load(flags: &[(SettingsOpts, String)]) -> VaultResult<Settings> {
+        Settings::load_from(&Settings::path(flags)?, flags)
+    }
+
+    pub fn load_from(path: &Path, flags: &[(SettingsOpts, String)]) -> VaultResult<Settings> {
+        let mut settings = Settings::from_path(path)?;
+
+        for opt in SettingsOpts::all().iter() {
+            if let Some(value) = env_value(*opt) {
+                settings.apply(*opt, &value, Origin::Env(opt.env()))?;
+            }
+        }
+
+        for (opt, value) in flags {
+            settings.apply(*opt, value, Origin::Flag(opt.flag()))?;
+        }
+
+        settings.select()?;
+
+        Ok(settings)
+    }
+
+    // The picked vault's settings win over the file and defaults,
+    // but not over the environment or flags
+    fn select(&mut self) -> VaultResult<()> {
+        let (name, profile) = match self.vault()? {
+            Some((name, profile)) => (name.to_string(), profile.clone()),
+            None => return Ok(())
+        };
+
+        let mut values = vec![(SettingsOpts::Locker, profile.locker)];
+
+        if let Some(algorithm) = profile.algorithm {
+            values.push((SettingsOpts::Algorithm, algorithm));
+        }
+
+        for (opt, value) in values {
+            match self.origin(&opt.key()) {
+                Origin::Env(_) | Origin::Flag(_) => continue,
+                _ => self.apply(opt, &value, Origin::Vault(name.clone()))?
+            }
+        }
+
+        Ok(())
+    }
+
+    // The settings file itself can only be moved by a flag or `RK_CONFIG`
+    pub fn path(flags: &[(SettingsOpts, String)]) -> VaultResult<PathBuf> {
+        let flag = flags.iter()
+            .find(|(opt, _)| *opt == SettingsOpts::Config)
+            .map(|(_, value)| value.to_string());
+
+        let mut settings = Settings::default();
+
+        if let Some(value) = flag {
+            settings.apply(SettingsOpts::Config, &value, Origin::Flag(SettingsOpts::Config.flag()))?;
+        } else if let Some(value) = env_value(SettingsOpts::Config) {
+            settings.apply(SettingsOpts::Config, &value, Origin::Env(SettingsOpts::Config.env()))?;
+        }
+
+        settings.get(SettingsOpts::Config)
+    }
+
+    // Missing files and keys fall back to `Settings::default`
+    pub fn from_path(path: &Path) -> VaultResult<Settings> {
+        let yaml = read(path)?;
+
+        Settings::parse(&yaml, path)
+    }
+
+    fn parse(yaml: &str, path: &Path) -> VaultResult<Settings> {
+        let mut settings = Settings::default();
+
+        if yaml.trim().is_empty() {
+            return Ok(settings);
+        }
+
+        let file = Settings::from_yaml(yaml)
+            .map_err(|err| format!("Invalid settings file {:?}: {}", path, err))?;
+
+        for (name, profile) in file.vaults.iter() {
+            check_profile(name, profile)?;
+        }
+
+        if let Some((host, _)) = file.git.iter().find(|(_, mapping)| mapping.entity.trim().is_empty()) {
+            return Err(VaultError::Error(format!("Missing entity for git host {:?}", host)));
+        }
+
+        settings.vaults = file.vaults;
+        settings.git = file.git;
+
+        let sections = vec![
+            ("paths", file.paths),
+            ("crypto", file.crypto),
+            ("vault", file.vault)
+        ];
+
+        for (section, values) in sections {
+            for (name, value) in values {
+                let value = validate(&format!("{}.{}", section, name), &value)?;
+
+                settings.origins.insert(format!("{}.{}", section, name), Origin::File);
+                settings.section_mut(section).insert(name, Value::String(value));
+            }
+        }
+
+        settings.vault()?;
+
+        Ok(settings)
+    }
+
     /* Methods */
 
     fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
-        serde_yaml::to_string(self) 
+        serde_yaml::to_string(self)
+    }
+
+    fn section(&self, name: &str) -> Option<&HashMap<String, Value>> {
+        match name {
+            "paths" => Some(&self.paths),
+            "crypto" => Some(&self.crypto),
+            "vault" => Some(&self.vault),
+            _ => None
+        }
+    }
+
+    fn section_mut(&mut self, name: &str) -> &mut HashMap<String, Value> {
+        match name {
+            "crypto" => &mut self.crypto,
+            "vault" => &mut self.vault,
+            _ => &mut self.paths
+        }
+    }
+
+    pub fn value(&self, key: &str) -> VaultResult<String> {
+        let (section, name) = split(key);
+
+        if !KEYS.contains(&key) {
+            return Err(unknown(key));
+        }
+
+        let value = self.section(section)
+            .and_then(|values| values.get(name))
+            .and_then(|value| value.as_str())
+            .ok_or_else(|| format!("Missing setting \"{}\"", key))?;
+
+        Ok(value.to_string())
+    }
+
+    pub fn vaults(&self) -> &BTreeMap<String, Profile> {
+        &self.vaults
+    }
+
+    // The vault picked by `vault.default`, `RK_VAULT` or `--vault`
+    pub fn vault(&self) -> VaultResult<Option<(&String, &Profile)>> {
+        let name = match self.value("vault.default") {
+            Ok(name) => name,
+            Err(_) => return Ok(None)
+        };
+
+        let vault = self.vaults
+            .get_key_value(&name)
+            .ok_or_else(|| format!("Unknown vault {:?} ({})", name, self.origin("vault.default")))?;
+
+        Ok(Some(vault))
+    }
+
+    pub fn git(&self, host: &str) -> Option<&GitHost> {
+        self.git.get(host)
+    }
+
+    // Lockers outside of a named vault are always directories
+    pub fn backend(&self) -> String {
+        match self.vault() {
+            Ok(Some((_, profile))) => profile.backend.clone(),
+            _ => default_backend()
+        }
+    }
+
+    pub fn origin(&self, key: &str) -> Origin {
+        self.origins.get(key).cloned().unwrap_or(Origin::Default)
+    }
+
+    // Paths from the environment or flags may be relative to
+    // the current directory, unlike the ones in the file
+    fn apply(&mut self, opt: SettingsOpts, value: &str, origin: Origin) -> VaultResult<()> {
+        let key = opt.key();
+        let mut value = value.to_string();
+
+        if opt.section() == "paths" && !value.starts_with('~') && !value.starts_with('$') && Path::new(&value).is_relative() {
+            value = env::current_dir()?.join(value).to_string_lossy().to_string();
+        }
+
+        let checked = validate(&key, &Value::String(value))
+            .map_err(|err| VaultError::Error(format!("{} (from {})", err.to_str(), origin)))?;
+
+        self.section_mut(opt.section()).insert(opt.to_str().to_string(), Value::String(checked));
+        self.origins.insert(key, origin);
+
+        Ok(())
+    }
+
+    pub fn get(&self, path: SettingsOpts) -> VaultResult<PathBuf> {
+        let path = self.value(&path.key())?;
+
+        Ok(PathBuf::from(path))
+    }
+
+    // Effective value of every setting that has one
+    pub fn entries(&self) -> Vec<(String, String)> {
+        KEYS.iter()
+            .filter_map(|key| self.value(key).ok().map(|value| (key.to_string(), value)))
+            .collect()
+    }
+
+    // The managers treat the config path as a directory, so they
+    // get the one holding the settings file
+    pub fn config_dir(&self) -> VaultResult<PathBuf> {
+        let config = self.get(SettingsOpts::Config)?;
+        let dir = config.parent()
+            .ok_or_else(|| format!("Invalid value for \"paths.config\": {:?} has no parent", config))?;
+
+        Ok(dir.to_path_buf())
+    }
+
+    /* Writing */
+
+    pub fn set(path: &Path, key: &str, value: &str) -> VaultResult<()> {
+        let checked = validate(key, &Value::String(value.to_string()))?;
+
+        if key.starts_with("paths.") {
+            writable(key, &checked)?;
+        }
+
+        let yaml = edit(&read(path)?, key, Some(value));
+
+        Settings::replace(path, &yaml)
+    }
+
+    pub fn unset(path: &Path, key: &str) -> VaultResult<()> {
+        if !KEYS.contains(&key) {
+            return Err(unknown(key));
+        }
+
+        let current = read(path)?;
+        let yaml = edit(&current, key, None);
+
+        if yaml == current {
+            return Ok(());
+        }
+
+        write_atomic(path, &yaml)
+    }
+
+    pub fn create_vault(path: &Path, name: &str, profile: &Profile) -> VaultResult<()> {
+        check_profile(name, profile)?;
+        writable("paths.locker", &validate("paths.locker", &Value::String(profile.locker.clone()))?)?;
+
+        let current = read(path)?;
+
+        if Settings::parse(&current, path)?.vaults.contains_key(name) {
+            return Err(VaultError::Error(format!("Vault {:?} already exists", name)));
+        }
+
+        let mut block = vec![
+            format!("locker: {}", quote(&profile.locker)),
+            format!("backend: {}", quote(&profile.backend))
+        ];
+
+        if let Some(algorithm) = &profile.algorithm {
+            block.push(format!("algorithm: {}", quote(algorithm)));
+        }
+
+        Settings::replace(path, &edit_vault(&current, name, Some(block)))
+    }
+
+    pub fn remove_vault(path: &Path, name: &str) -> VaultResult<()> {
+        let current = read(path)?;
+        let settings = Settings::parse(&current, path)?;
+
+        if !settings.vaults.contains_key(name) {
+            return Err(VaultError::Error(format!("Unknown vault {:?}", name)));
+        }
+
+        if settings.value("vault.default").ok().as_deref() == Some(name) {
+            let msg = format!("Vault {:?} is the default, unset or change `vault.default` first", name);
+
+            return Err(VaultError::Error(msg));
+        }
+
+        Settings::replace(path, &edit_vault(&current, name, None))
+    }
+
+    // Writes a whole settings file, as long as it is valid
+    pub fn replace(path: &Path, yaml: &str) -> VaultResult<()> {
+        Settings::parse(yaml, path)?;
+
+        write_atomic(path, yaml)
+    }
+}
+
+/* Validation */
+
+fn env_value(opt: SettingsOpts) -> Option<String> {
+    env::var(opt.env()).ok().filter(|value| !value.is_empty())
+}
+
+fn split(key: &str) -> (&str, &str) {
+    let mut parts = key.splitn(2, '.');
+
+    (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+}
+
+fn unknown(key: &str) -> VaultError {
+    VaultError::Error(format!("Unknown setting \"{}\", expected one of: {}", key, KEYS.join(", ")))
+}
+
+fn validate(key: &str, value: &Value) -> VaultResult<String> {
+    if !KEYS.contains(&key) {
+        return Err(unknown(key));
+    }
+
+    let text = match value {
+        Value::String(text) if !text.trim().is_empty() => text,
+        _ => return Err(VaultError::Error(format!("Invalid value for \"{}\": expected a string", key)))
+    };
+
+    if key == "vault.default" {
+        return check_name(text).map(|_| text.to_string());
+    }
+
+    if key == "crypto.algorithm" {
+        if !ALGORITHMS.contains(&text.as_str()) {
+            let msg = format!("Unsupported algorithm {:?} for \"{}\", expected one of: {}", text, key, ALGORITHMS.join(", "));
+
+            return Err(VaultError::Error(msg));
+        }
+
+        return Ok(text.to_string());
+    }
+
+    let expanded = expand(text)
+        .map_err(|var| format!("Undefined variable ${} in \"{}\"", var, key))?;
+
+    if !Path::new(&expanded).is_absolute() {
+        let msg = format!("Invalid value for \"{}\": {:?} is not an absolute path", key, expanded);
+
+        return Err(VaultError::Error(msg));
+    }
+
+    Ok(expanded)
+}
+
+fn check_name(name: &str) -> VaultResult<()> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        let msg = format!("Invalid vault name {:?}, use letters, digits, `-` and `_`", name);
+
+        return Err(VaultError::Error(msg));
+    }
+
+    Ok(())
+}
+
+fn check_profile(name: &str, profile: &Profile) -> VaultResult<()> {
+    let context = |err: VaultError| VaultError::Error(format!("{} in vault {:?}", err.to_str(), name));
+
+    check_name(name)?;
+    validate("paths.locker", &Value::String(profile.locker.clone())).map_err(context)?;
+
+    if let Some(algorithm) = &profile.algorithm {
+        validate("crypto.algorithm", &Value::String(algorithm.clone())).map_err(context)?;
+    }
+
+    if !BACKENDS.contains(&profile.backend.as_str()) {
+        let msg = format!("Unsupported backend {:?} in vault {:?}, expected one of: {}", profile.backend, name, BACKENDS.join(", "));
+
+        return Err(VaultError::Error(msg));
+    }
+
+    Ok(())
+}
+
+// The closest existing directory has to accept new files. For the
+// config path that is checked on the directory holding the file.
+fn writable(key: &str, path: &str) -> VaultResult<()> {
+    let path = Path::new(path);
+    let target = if key == "paths.config" { path.parent().unwrap_or(path) } else { path };
+    let unwritable = || VaultError::Error(format!("Invalid value for \"{}\": {:?} is not writable", key, path));
+
+    let mut dir = target
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or_else(unwritable)?;
+
+    // The locker of a kdbx vault is the database itself
+    if key == "paths.locker" && dir == path && dir.is_file() {
+        dir = dir.parent().unwrap_or(dir);
+    }
+
+    if !dir.is_dir() {
+        return Err(unwritable());
+    }
+
+    let probe = dir.join(format!(".rk-{}", std::process::id()));
+
+    File::create(&probe).map_err(|_| unwritable())?;
+    fs::remove_file(&probe)?;
+
+    Ok(())
+}
+
+// Expands a leading `~` and `$VAR`/`${VAR}`, returning the name
+// of the first variable that is not set
+fn expand(path: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs::home_dir().ok_or_else(|| String::from("HOME"))?;
+
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let (name, len) = if rest.starts_with('{') {
+            match rest.find('}') {
+                Some(end) => (&rest[1..end], end + 1),
+                None => return Err(rest.to_string())
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+
+            (&rest[..end], end)
+        };
+
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+
+        let value = env::var(name).map_err(|_| name.to_string())?;
+
+        expanded.push_str(&value);
+        rest = &rest[len..];
     }
 
-    pub fn get(&self, path: SettingsOpts) -> PathBuf {
-        let option = path.to_str();
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
 
-        let path = self.paths
-            .get(option)
-            .unwrap()
-            .as_str()
-            .unwrap();
+/* Editing */
 
-        PathBuf::from(path)
+fn read(path: &Path) -> VaultResult<String> {
+    if !path.is_file() {
+        return Ok(String::new());
     }
+
+    Ok(fs::read_to_string(path)?)
+}
+
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+fn is_entry(line: &str) -> bool {
+    !line.trim().is_empty() && !is_comment(line)
+}
+
+fn is_top_level(line: &str) -> bool {
+    is_entry(line) && !line.starts_with(' ') && !line.starts_with('\t')
+}
+
+fn indent(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// Changes the single line holding `section.name` so comments and
+// everything else in the file stay as they are. `None` removes it.
+fn edit(yaml: &str, key: &str, value: Option<&str>) -> String {
+    let (section, name) = split(key);
+    let header = format!("{}:", section);
+    let entry = format!("{}:", name);
+    let mut lines: Vec<String> = yaml.lines().map(String::from).collect();
+
+    let start = lines.iter().position(|line| {
+        is_top_level(line) && line.split('#').next().unwrap_or("").trim_end() == header
+    });
+
+    let start = match (start, value) {
+        (Some(start), _) => start,
+        (None, Some(value)) => {
+            lines.push(header);
+            lines.push(format!("  {}: {}", name, quote(value)));
+
+            return join(lines);
+        },
+        (None, None) => return join(lines)
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| is_top_level(line))
+        .map(|index| start + 1 + index)
+        .unwrap_or(lines.len());
+
+    let found = (start + 1..end).find(|&index| {
+        is_entry(&lines[index]) && lines[index].trim_start().starts_with(&entry)
+    });
+
+    match (found, value) {
+        (Some(index), Some(value)) => {
+            lines[index] = format!("{}{}: {}", indent(&lines[index]), name, quote(value));
+        },
+        (Some(index), None) => {
+            lines.remove(index);
+
+            // An empty section would be read back as null
+            if !(start + 1..end - 1).any(|index| is_entry(&lines[index])) {
+                lines.remove(start);
+            }
+        },
+        (None, Some(value)) => {
+            let last = (start + 1..end)
+                .rev()
+                .find(|&index| is_entry(&lines[index]));
+
+            let (at, indent) = match last {
+                Some(index) => (index + 1, indent(&lines[index]).to_string()),
+                None => (start + 1, String::from("  "))
+            };
+
+            lines.insert(at, format!("{}{}: {}", indent, name, quote(value)));
+        },
+        (None, None) => {}
+    }
+
+    join(lines)
+}
+
+// Adds or removes a whole `vaults.<name>` block, along
+// with any comment lines inside it
+fn edit_vault(yaml: &str, name: &str, block: Option<Vec<String>>) -> String {
+    let header = "vaults:";
+    let entry = format!("{}:", name);
+    let mut lines: Vec<String> = yaml.lines().map(String::from).collect();
+
+    let start = lines.iter().position(|line| {
+        is_top_level(line) && line.split('#').next().unwrap_or("").trim_end() == header
+    });
+
+    let start = match (start, &block) {
+        (Some(start), _) => start,
+        (None, Some(block)) => {
+            lines.push(header.to_string());
+            lines.push(format!("  {}", entry));
+            lines.extend(block.iter().map(|line| format!("    {}", line)));
+
+            return join(lines);
+        },
+        (None, None) => return join(lines)
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| is_top_level(line))
+        .map(|index| start + 1 + index)
+        .unwrap_or(lines.len());
+
+    let found = (start + 1..end).find(|&index| {
+        is_entry(&lines[index]) && lines[index].trim_start() == entry
+    });
+
+    match (found, block) {
+        (Some(index), None) => {
+            let depth = indent(&lines[index]).len();
+            let last = (index + 1..end)
+                .take_while(|&next| lines[next].trim().is_empty() || indent(&lines[next]).len() > depth)
+                .last()
+                .unwrap_or(index);
+
+            lines.drain(index..=last);
+
+            if !(start + 1..end - (last + 1 - index)).any(|index| is_entry(&lines[index])) {
+                lines.remove(start);
+            }
+        },
+        (None, Some(block)) => {
+            let at = (start + 1..end)
+                .rev()
+                .find(|&index| is_entry(&lines[index]))
+                .map(|index| index + 1)
+                .unwrap_or(start + 1);
+
+            let mut added = vec![format!("  {}", entry)];
+
+            added.extend(block.iter().map(|line| format!("    {}", line)));
+            lines.splice(at..at, added);
+        },
+        _ => {}
+    }
+
+    join(lines)
+}
+
+fn join(lines: Vec<String>) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    format!("{}\n", lines.join("\n"))
+}
+
+fn write_atomic(path: &Path, contents: &str) -> VaultResult<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+
+    name.push(".tmp");
+
+    let tmp = dir.join(name);
+
+    fs::create_dir_all(dir)?;
+
+    let mut file = File::create(&tmp)?;
+
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&tmp, path)?;
+
+    Ok(())
 }
 
 impl Default for Settings {
     fn default() -> Self {
         let mut paths: HashMap<String, Value> = HashMap::new();
+        let mut crypto: HashMap<String, Value> = HashMap::new();
 
         let locker = String::from("locker");
         let config = String::from("config");
+        let algorithm = String::from("algorithm");
 
         let mut locker_dir = dirs::home_dir().unwrap();
         let mut config_dir = dirs::config_dir().unwrap();
 
         locker_dir.push(".rk");
-        
+
         config_dir.push("rk");
         config_dir.push("settings.yml");
 
@@ -71,12 +816,19 @@ impl Default for Settings {
 
         let config_value = Value::String(config_location);
         let locker_value = Value::String(locker_location);
+        let algorithm_value = Value::String(ALGORITHMS[0].to_string());
 
         paths.insert(config, config_value);
         paths.insert(locker, locker_value);
+        crypto.insert(algorithm, algorithm_value);
 
         Settings {
-            paths
+            paths,
+            crypto,
+            vault: HashMap::new(),
+            vaults: BTreeMap::new(),
+            git: BTreeMap::new(),
+            origins: HashMap::new()
         }
     }
 }
@@ -86,9 +838,20 @@ mod test {
     use super::*;
    
     use std::env;
-    use std::fs::File;
+    use std::fs::{File, remove_file};
     use std::io::Read;
 
+    use crate::mocks::Setup;
+
+    fn after_each(this: &mut Setup) {
+        for path in this.paths.iter() {
+            let exists = Path::new(&path).exists();
+            let msg = format!("Could not remove {} in `settings.rs` test", path);
+
+            if exists { remove_file(path).expect(&msg); }
+        }
+    }
+
     #[test]
     fn default() {
         let mut paths: HashMap<String, Value> = HashMap::new();
@@ -113,7 +876,11 @@ mod test {
         paths.insert(config, config_value);
         paths.insert(locker, locker_value);
 
-        let settings = Settings { paths };
+        let mut crypto = HashMap::new();
+
+        crypto.insert(String::from("algorithm"), Value::String(String::from("aes-128-cbc")));
+
+        let settings = Settings { paths, crypto, vault: HashMap::new(), vaults: BTreeMap::new(), git: BTreeMap::new(), origins: HashMap::new() };
         let default_settings: Settings = Default::default();
 
         assert_eq!(settings, default_settings);
@@ -143,8 +910,17 @@ mod test {
         default_paths.insert(config, config_value);
         default_paths.insert(locker, locker_value);
 
+        let mut default_crypto: HashMap<String, Value> = HashMap::new();
+
+        default_crypto.insert(String::from("algorithm"), Value::String(String::from("aes-128-cbc")));
+
         let default_config = Settings {
-            paths: default_paths
+            paths: default_paths,
+            crypto: default_crypto,
+            vault: HashMap::new(),
+            vaults: BTreeMap::new(),
+            git: BTreeMap::new(),
+            origins: HashMap::new()
         };
 
         assert_eq!(deserialized, default_config);
@@ -163,14 +939,119 @@ mod test {
         paths.insert(locker, locker_value);
         paths.insert(config, config_value);
 
-        let settings = Settings { paths };
-        let get_locker = settings.get(SettingsOpts::Locker);
-        let get_config = settings.get(SettingsOpts::Config);
+        let settings = Settings { paths, crypto: HashMap::new(), vault: HashMap::new(), vaults: BTreeMap::new(), git: BTreeMap::new(), origins: HashMap::new() };
+        let get_locker = settings.get(SettingsOpts::Locker).unwrap();
+        let get_config = settings.get(SettingsOpts::Config).unwrap();
 
         assert_eq!(get_locker, PathBuf::from("locker"));
         assert_eq!(get_config, PathBuf::from("config"));
     }
 
+    #[test]
+    fn get_missing() {
+        let settings = Settings { paths: HashMap::new(), crypto: HashMap::new(), vault: HashMap::new(), vaults: BTreeMap::new(), git: BTreeMap::new(), origins: HashMap::new() };
+
+        assert!(settings.get(SettingsOpts::Locker).is_err());
+    }
+
+    #[test]
+    fn config_dir() {
+        let mut paths = HashMap::new();
+        
+        let config = String::from("config");
+        let config_value = Value::String("/rk/settings.yml".to_string());
+        
+        paths.insert(config, config_value);
+
+        let settings = Settings { paths, crypto: HashMap::new(), vault: HashMap::new(), vaults: BTreeMap::new(), git: BTreeMap::new(), origins: HashMap::new() };
+
+        assert_eq!(settings.config_dir().unwrap(), PathBuf::from("/rk"));
+    }
+
+    #[test]
+    fn from_path_missing() {
+        let path = PathBuf::from("dump/missing_settings.yml");
+        let settings = Settings::from_path(&path).unwrap();
+
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn from_path_expands() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+                let home = dirs::home_dir().unwrap();
+
+                fs::write(&config, "paths:\n  locker: '$HOME/.rk_test'\n").unwrap();
+
+                let settings = Settings::from_path(&config).unwrap();
+                let default_config = Settings::default().get(SettingsOpts::Config).unwrap();
+
+                assert_eq!(settings.get(SettingsOpts::Locker).unwrap(), home.join(".rk_test"));
+                assert_eq!(settings.get(SettingsOpts::Config).unwrap(), default_config);
+            }
+        };
+    }
+
+    #[test]
+    fn from_path_unknown_key() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "paths:\n  lockr: /tmp/rk\n").unwrap();
+                let unknown_path = Settings::from_path(&config).unwrap_err();
+
+                fs::write(&config, "path:\n  locker: /tmp/rk\n").unwrap();
+                let unknown_section = Settings::from_path(&config);
+
+                assert!(unknown_path.to_str().contains("paths.lockr"));
+                assert!(unknown_section.is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn from_path_invalid_value() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "paths:\n  locker: 42\n").unwrap();
+                let number = Settings::from_path(&config);
+
+                fs::write(&config, "paths:\n  locker: relative/rk\n").unwrap();
+                let relative = Settings::from_path(&config);
+
+                fs::write(&config, "paths:\n  locker: $RK_UNDEFINED_VARIABLE/rk\n").unwrap();
+                let undefined = Settings::from_path(&config).unwrap_err();
+
+                assert!(number.is_err());
+                assert!(relative.is_err());
+                assert!(undefined.to_str().contains("RK_UNDEFINED_VARIABLE"));
+            }
+        };
+    }
+
+    #[test]
+    fn expand_variables() {
+        let home = dirs::home_dir().unwrap();
+        let home = home.to_str().unwrap();
+        let path = env::var("PATH").unwrap();
+
+        assert_eq!(expand("~/.rk").unwrap(), format!("{}/.rk", home));
+        assert_eq!(expand("${PATH}/rk").unwrap(), format!("{}/rk", path));
+        assert_eq!(expand("/a$/b").unwrap(), "/a$/b");
+        assert_eq!(expand("/a/$RK_UNDEFINED_VARIABLE").unwrap_err(), "RK_UNDEFINED_VARIABLE");
+    }
+
     #[test]
     fn settings_opts_to_str() {
         let locker_option = SettingsOpts::Locker;
@@ -178,5 +1059,291 @@ mod test {
 
         assert_eq!(locker_option.to_str(), "locker");
         assert_eq!(config_option.to_str(), "config");
+        assert_eq!(SettingsOpts::Algorithm.key(), "crypto.algorithm");
+    }
+
+    #[test]
+    fn settings_opts_env_and_flag() {
+        assert_eq!(SettingsOpts::Locker.env(), "RK_LOCKER");
+        assert_eq!(SettingsOpts::Algorithm.env(), "RK_ALGORITHM");
+        assert_eq!(SettingsOpts::Config.flag(), "--config");
+        assert_eq!(SettingsOpts::Vault.env(), "RK_VAULT");
+        assert_eq!(SettingsOpts::Vault.key(), "vault.default");
+    }
+
+    #[test]
+    fn apply_origins() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "paths:\n  locker: '/from/file'\n").unwrap();
+
+                let mut settings = Settings::from_path(&config).unwrap();
+
+                assert_eq!(settings.origin("paths.locker"), Origin::File);
+                assert_eq!(settings.origin("crypto.algorithm"), Origin::Default);
+
+                settings.apply(SettingsOpts::Locker, "/from/env", Origin::Env(SettingsOpts::Locker.env())).unwrap();
+                settings.apply(SettingsOpts::Locker, "relative", Origin::Flag(SettingsOpts::Locker.flag())).unwrap();
+
+                let relative = env::current_dir().unwrap().join("relative");
+                let invalid = settings.apply(SettingsOpts::Algorithm, "rot13", Origin::Env(SettingsOpts::Algorithm.env()));
+
+                assert_eq!(settings.get(SettingsOpts::Locker).unwrap(), relative);
+                assert_eq!(settings.origin("paths.locker").to_string(), "flag --locker");
+                assert!(invalid.unwrap_err().to_str().contains("RK_ALGORITHM"));
+            }
+        };
+    }
+
+    #[test]
+    fn load_flags() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+                let path = config.to_str().unwrap().to_string();
+
+                fs::write(&config, "paths:\n  locker: '/from/file'\n").unwrap();
+
+                let flags = vec![(SettingsOpts::Config, path)];
+                let settings = Settings::load(&flags).unwrap();
+
+                assert_eq!(Settings::path(&flags).unwrap(), config);
+                assert_eq!(settings.get(SettingsOpts::Config).unwrap(), config);
+                assert_eq!(settings.origin("paths.config"), Origin::Flag(String::from("--config")));
+                assert_eq!(settings.get(SettingsOpts::Locker).unwrap(), PathBuf::from("/from/file"));
+            }
+        };
+    }
+
+    #[test]
+    fn vault_selection() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "vault:\n  default: 'team'\nvaults:\n  team:\n    locker: '/team'\n  prod:\n    locker: '/prod'\n").unwrap();
+
+                let team = Settings::load_from(&config, &[]).unwrap();
+                let prod = Settings::load_from(&config, &[(SettingsOpts::Vault, String::from("prod"))]).unwrap();
+                let pinned = Settings::load_from(&config, &[(SettingsOpts::Locker, String::from("/pinned"))]).unwrap();
+                let unknown = Settings::load_from(&config, &[(SettingsOpts::Vault, String::from("nope"))]);
+
+                assert_eq!(team.get(SettingsOpts::Locker).unwrap(), PathBuf::from("/team"));
+                assert_eq!(team.origin("paths.locker"), Origin::Vault(String::from("team")));
+                assert_eq!(prod.get(SettingsOpts::Locker).unwrap(), PathBuf::from("/prod"));
+                assert_eq!(pinned.get(SettingsOpts::Locker).unwrap(), PathBuf::from("/pinned"));
+                assert!(unknown.is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn vault_invalid() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "vaults:\n  team:\n    locker: '/team'\n    backend: 's3'\n").unwrap();
+                let backend = Settings::from_path(&config);
+
+                fs::write(&config, "vault:\n  default: 'team'\n").unwrap();
+                let missing = Settings::from_path(&config);
+
+                assert!(backend.unwrap_err().to_str().contains("s3"));
+                assert!(missing.is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn git_hosts() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "git:\n  forge.internal:\n    entity: 'forge'\n    usernames:\n      alice: 'alice@corp.com'\n").unwrap();
+                let settings = Settings::from_path(&config).unwrap();
+
+                fs::write(&config, "git:\n  forge.internal:\n    entity: ''\n").unwrap();
+                let empty = Settings::from_path(&config);
+
+                let forge = settings.git("forge.internal").unwrap();
+
+                assert_eq!(forge.entity, "forge");
+                assert_eq!(forge.account, None);
+                assert_eq!(forge.usernames["alice"], "alice@corp.com");
+                assert!(settings.git("github.com").is_none());
+                assert!(empty.is_err());
+            }
+        };
+    }
+
+    #[test]
+    fn create_and_remove_vault() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let profile = Profile {
+                    locker: locker.to_str().unwrap().to_string(),
+                    backend: default_backend(),
+                    algorithm: None
+                };
+
+                fs::write(&config, "# vaults\npaths:\n  locker: '/tmp'\n").unwrap();
+
+                Settings::create_vault(&config, "team", &profile).unwrap();
+                Settings::create_vault(&config, "prod", &profile).unwrap();
+
+                let existing = Settings::create_vault(&config, "team", &profile);
+                let settings = Settings::from_path(&config).unwrap();
+
+                assert!(existing.is_err());
+                assert_eq!(settings.vaults().len(), 2);
+                assert_eq!(settings.vaults()["team"], profile);
+
+                Settings::set(&config, "vault.default", "prod").unwrap();
+
+                let default = Settings::remove_vault(&config, "prod");
+
+                Settings::remove_vault(&config, "team").unwrap();
+
+                let settings = Settings::from_path(&config).unwrap();
+
+                assert!(default.is_err());
+                assert_eq!(settings.vaults().keys().collect::<Vec<_>>(), vec!["prod"]);
+                assert!(fs::read_to_string(&config).unwrap().starts_with("# vaults\n"));
+            }
+        };
+    }
+
+    #[test]
+    fn edit_vault_blocks() {
+        let yaml = "vaults:\n  team:\n    # shared\n    locker: '/team'\n  prod:\n    locker: '/prod'\npaths:\n  locker: '/tmp'\n";
+
+        let removed = edit_vault(yaml, "team", None);
+        let emptied = edit_vault(&edit_vault(yaml, "team", None), "prod", None);
+        let added = edit_vault(yaml, "dev", Some(vec![String::from("locker: '/dev'")]));
+
+        assert_eq!(removed, "vaults:\n  prod:\n    locker: '/prod'\npaths:\n  locker: '/tmp'\n");
+        assert_eq!(emptied, "paths:\n  locker: '/tmp'\n");
+        assert_eq!(added, "vaults:\n  team:\n    # shared\n    locker: '/team'\n  prod:\n    locker: '/prod'\n  dev:\n    locker: '/dev'\npaths:\n  locker: '/tmp'\n");
+    }
+
+    #[test]
+    fn value_and_entries() {
+        let settings = Settings::default();
+        let entries = settings.entries();
+
+        assert_eq!(settings.value("crypto.algorithm").unwrap(), "aes-128-cbc");
+        assert!(settings.value("crypto.cipher").is_err());
+        // `vault.default` has no default
+        assert_eq!(entries.len(), KEYS.len() - 1);
+        assert_eq!(entries[0].0, "paths.locker");
+    }
+
+    #[test]
+    fn edit_keeps_comments() {
+        let yaml = "# rk settings\npaths: # where things live\n  # the vault\n  locker: '/old'\n\ncrypto:\n  algorithm: 'aes-128-cbc'\n";
+
+        let replaced = edit(yaml, "paths.locker", Some("/new"));
+        let added = edit(yaml, "paths.config", Some("/rk/settings.yml"));
+        let removed = edit(yaml, "crypto.algorithm", None);
+        let section = edit("", "paths.locker", Some("/it's"));
+
+        assert_eq!(replaced, "# rk settings\npaths: # where things live\n  # the vault\n  locker: '/new'\n\ncrypto:\n  algorithm: 'aes-128-cbc'\n");
+        assert_eq!(added, "# rk settings\npaths: # where things live\n  # the vault\n  locker: '/old'\n  config: '/rk/settings.yml'\n\ncrypto:\n  algorithm: 'aes-128-cbc'\n");
+        assert_eq!(removed, "# rk settings\npaths: # where things live\n  # the vault\n  locker: '/old'\n\n");
+        assert_eq!(section, "paths:\n  locker: '/it''s'\n");
+    }
+
+    #[test]
+    fn set_and_unset() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+                let locker = locker.to_str().unwrap();
+
+                fs::write(&config, "# keep me\npaths:\n  locker: '/tmp'\n").unwrap();
+
+                Settings::set(&config, "paths.locker", locker).unwrap();
+                Settings::set(&config, "crypto.algorithm", "aes-128-cbc").unwrap();
+
+                let settings = Settings::from_path(&config).unwrap();
+                let yaml = fs::read_to_string(&config).unwrap();
+
+                assert_eq!(settings.get(SettingsOpts::Locker).unwrap(), PathBuf::from(locker));
+                assert!(yaml.starts_with("# keep me\n"));
+
+                Settings::unset(&config, "paths.locker").unwrap();
+
+                let settings = Settings::from_path(&config).unwrap();
+                let misspelled = Settings::unset(&config, "crypto.algoritm");
+
+                assert_eq!(settings.get(SettingsOpts::Locker).unwrap(), Settings::default().get(SettingsOpts::Locker).unwrap());
+                assert!(misspelled.unwrap_err().to_str().starts_with("Unknown setting \"crypto.algoritm\""));
+            }
+        };
+    }
+
+    #[test]
+    fn set_invalid() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+                let mut file = config.clone();
+
+                file.push("locker");
+
+                let algorithm = Settings::set(&config, "crypto.algorithm", "rot13");
+                let unknown = Settings::set(&config, "paths.lockr", "/tmp");
+                let relative = Settings::set(&config, "paths.locker", "relative");
+
+                fs::write(&config, "").unwrap();
+
+                let unwritable = Settings::set(&config, "paths.locker", file.to_str().unwrap());
+
+                assert!(algorithm.is_err());
+                assert!(unknown.is_err());
+                assert!(relative.is_err());
+                assert!(unwritable.is_err());
+                assert_eq!(fs::read_to_string(&config).unwrap(), "");
+            }
+        };
+    }
+
+    #[test]
+    fn replace_invalid() {
+        Setup {
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, _) = this.as_path_buf();
+
+                fs::write(&config, "paths:\n  locker: '/tmp'\n").unwrap();
+
+                let replaced = Settings::replace(&config, "paths:\n  locker: 42\n");
+
+                assert!(replaced.is_err());
+                assert_eq!(fs::read_to_string(&config).unwrap(), "paths:\n  locker: '/tmp'\n");
+            }
+        };
     }
 }
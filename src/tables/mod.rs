@@ -42,3 +42,32 @@ pub fn find_table(password: String) -> io::Result<()> {
 
     table.print_stdout()
 }
+
+pub fn settings_table(headers: &[&str], entries: Vec<Vec<String>>) -> io::Result<()> {
+    let bold = CellFormat::builder().bold(true).build();
+    let header = headers.iter()
+        .map(|header| {
+            let mut title = header.to_string();
+
+            title[..1].make_ascii_uppercase();
+
+            Cell::new(&title, bold)
+        })
+        .collect();
+
+    let mut rows = vec![Row::new(header)];
+
+    for entry in entries {
+        let row = Row::new(
+            entry.iter()
+                .map(|value| Cell::new(value, Default::default()))
+                .collect()
+        );
+
+        rows.push(row);
+    }
+
+    let table = Table::new(rows, Default::default()).unwrap();
+
+    table.print_stdout()
+}
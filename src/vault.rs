@@ -2,12 +2,20 @@
 /* Dependencies */
 
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::locker::{Locker, Encrypted};
+use crate::locker::{Encrypted, Seal};
 use crate::managers::{Manager, DirManager, FileManager};
 
+/* Constants */
+
+const FIELDS: &str = ".fields";
+
+// Unix seconds of the last change to an account, for `rk merge`
+const MODIFIED: &str = ".modified";
+
 /* Custom types */
 
 type Account = HashMap<Encrypted, Encrypted>;
@@ -22,6 +30,11 @@ pub enum VaultError {
     Io(io::Error),
     MissingEntity,
     MissingAccount,
+    ExistingEntity,
+    ExistingAccount,
+    MissingField(String),
+    MissingVault(String),
+    ExistingVault(String),
 }
 
 impl VaultError {
@@ -31,6 +44,26 @@ impl VaultError {
             VaultError::Io(e) => format!("{:?}", e),
             VaultError::MissingEntity => format!("Missing entity on operation"),
             VaultError::MissingAccount => format!("Missing account on operation"),
+            VaultError::ExistingEntity => String::from("Entity already exists"),
+            VaultError::ExistingAccount => String::from("Account already exists"),
+            VaultError::MissingField(name) => format!("Missing field {:?}", name),
+            VaultError::MissingVault(path) => format!("No vault initialised at {:?}, run `rk init` first", path),
+            VaultError::ExistingVault(path) => format!("A vault is already initialised at {:?}", path),
+        }
+    }
+
+    // Process exit codes, documented in the README
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            VaultError::Error(_) => 1,
+            VaultError::Io(_) => 2,
+            VaultError::MissingEntity => 3,
+            VaultError::MissingAccount => 3,
+            VaultError::MissingField(_) => 3,
+            VaultError::MissingVault(_) => 3,
+            VaultError::ExistingEntity => 4,
+            VaultError::ExistingAccount => 4,
+            VaultError::ExistingVault(_) => 4,
         }
     }
 }
@@ -55,13 +88,114 @@ impl From<&str> for VaultError {
     }
 }
 
+/* Storage enum definition */
+
+// Where the vault is persisted. `Memory` is for backends that save
+// the whole vault at once, eg. a KDBX database, and only keeps the
+// fields files since everything else is in the structure
+#[derive(Debug)]
+enum Storage {
+    Directory { files: FileManager, directories: DirManager },
+    Memory { fields: HashMap<PathBuf, String> }
+}
+
+impl Storage {
+    fn create_dir(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { directories, .. } => directories.create_locker(path),
+            Storage::Memory { .. } => Ok(())
+        }
+    }
+
+    fn remove_dir(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { directories, .. } => directories.remove_locker(path),
+            Storage::Memory { fields } => {
+                fields.retain(|file, _| !file.starts_with(path));
+
+                Ok(())
+            }
+        }
+    }
+
+    fn rename_dir(&mut self, from: &str, to: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { directories, .. } => directories.rename_locker(from, to),
+            Storage::Memory { fields } => {
+                let moved: Vec<PathBuf> = fields.keys()
+                    .filter(|file| file.starts_with(from))
+                    .cloned()
+                    .collect();
+
+                for file in moved {
+                    let contents = fields.remove(&file).unwrap();
+                    let renamed = Path::new(to).join(file.strip_prefix(from).unwrap());
+
+                    fields.insert(renamed, contents);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn create_file(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { files, .. } => files.create_locker(path),
+            Storage::Memory { .. } => Ok(())
+        }
+    }
+
+    fn remove_file(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { files, .. } => files.remove_locker(path),
+            Storage::Memory { fields } => {
+                fields.remove(Path::new(path));
+
+                Ok(())
+            }
+        }
+    }
+
+    fn read_file(&mut self, directory: &str, name: &str) -> io::Result<Option<String>> {
+        let path = DirManager::append_path(directory, name);
+
+        match self {
+            Storage::Directory { files, directories } => {
+                let exists = directories
+                    .read_locker(directory)?
+                    .iter()
+                    .any(|file| Vault::filename(file) == name);
+
+                if !exists {
+                    return Ok(None);
+                }
+
+                Ok(Some(files.read_locker(&path)?))
+            },
+            Storage::Memory { fields } => Ok(fields.get(Path::new(&path)).cloned())
+        }
+    }
+
+    fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()> {
+        match self {
+            Storage::Directory { files, .. } => files.write_locker(path, contents),
+            Storage::Memory { fields } => {
+                fields.insert(PathBuf::from(path), contents.to_string());
+
+                Ok(())
+            }
+        }
+    }
+}
+
 /* Vault struct definition */
 
 #[derive(Debug)]
 pub struct Vault {
    structure: Structure,
-   files: FileManager,
-   directories: DirManager,
+   storage: Storage,
+   seal: Option<Seal>
 }
 
 /* Vault struct behavior */
@@ -71,27 +205,79 @@ impl Vault {
     /* Intialisers */
 
     pub fn new(config: &PathBuf, locker: &PathBuf) -> VaultResult<Vault> {
+        Vault::load(config, locker, None, false)
+    }
+
+    // A locker whose names and fields are sealed under its master key
+    pub fn sealed(config: &PathBuf, locker: &PathBuf, seal: Seal) -> VaultResult<Vault> {
+        Vault::load(config, locker, Some(seal), false)
+    }
+
+    // Like `sealed`, for lockers from before they were sealed. Whatever is
+    // still unsealed gets sealed in place, so an interrupted run is finished
+    // by the next one
+    pub fn adopt(config: &PathBuf, locker: &PathBuf, seal: Seal) -> VaultResult<Vault> {
+        Vault::load(config, locker, Some(seal), true)
+    }
+
+    fn load(config: &PathBuf, locker: &PathBuf, seal: Option<Seal>, adopt: bool) -> VaultResult<Vault> {
         let mut dm = DirManager::new(config, locker);
         let mut fm = FileManager::new(config, locker);
         let mut structure = Structure::new();
         let entities = dm.read_locker("")?;
 
-        for entity in entities.iter() {
+        // Dotfiles such as the vault header or the placeholders of a locker
+        // kept in git are not entries
+        for entity in entities.iter().filter(|entity| !Self::filename(entity).starts_with('.')) {
             let mut accounts = Vec::new();
-            let entity_name = Self::filename(&entity);
-            let encrypted_entity = Encrypted::from(&entity_name)?;
+            let mut entity_name = Self::filename(entity);
+            let (encrypted_entity, sealed) = Self::open_name(seal.as_ref(), &entity_name, adopt)?;
+
+            if let Some(sealed) = sealed {
+                dm.rename_locker(&entity_name, &sealed)?;
+                entity_name = sealed;
+            }
+
             let entity_dir = dm.read_locker(&entity_name)?;
 
-            for account in entity_dir.iter() {
-                let account_name = Self::filename(&account);
-                let encrypted_account = Encrypted::from(&account_name)?;
+            for account in entity_dir.iter().filter(|account| !Self::filename(account).starts_with('.')) {
+                let mut account_name = Self::filename(account);
+                let (encrypted_account, sealed) = Self::open_name(seal.as_ref(), &account_name, adopt)?;
+
+                if let Some(sealed) = sealed {
+                    let from = DirManager::append_path(&entity_name, &account_name);
+                    let to = DirManager::append_path(&entity_name, &sealed);
+
+                    dm.rename_locker(&from, &to)?;
+                    account_name = sealed;
+                }
+
                 let path = DirManager::append_path(&entity_name, &account_name);
                 let account_dir = dm.read_locker(&path)?;
+                let password_files: Vec<&PathBuf> = account_dir.iter()
+                    .filter(|file| !Self::filename(file).starts_with('.'))
+                    .collect();
+
+                if let (Some(seal), true) = (&seal, adopt) {
+                    if account_dir.iter().any(|file| Self::filename(file) == FIELDS) {
+                        let fields_path = DirManager::append_path(&path, FIELDS);
+                        let fields = Encrypted::from(fm.read_locker(&fields_path)?.trim())?;
+
+                        if seal.open(&fields).is_none() {
+                            fm.write_locker(&fields_path, &seal.seal(&fields).path())?;
+                        }
+                    }
+                }
 
-                if account_dir.len() == 1 {
-                    let password_file = &account_dir[0];
+                if password_files.len() == 1 {
+                    let password_file = password_files[0];
                     let password_name = Self::filename(&password_file);
-                    let encrypted_password = Encrypted::from(&password_name)?;
+                    let (encrypted_password, sealed) = Self::open_name(seal.as_ref(), &password_name, adopt)?;
+
+                    if let Some(sealed) = sealed {
+                        fm.remove_locker(&DirManager::append_path(&path, &password_name))?;
+                        fm.create_locker(&DirManager::append_path(&path, &sealed))?;
+                    }
                     
                     accounts.push((encrypted_account, encrypted_password));
                 } else {
@@ -121,11 +307,20 @@ impl Vault {
 
         Ok(Vault {
             structure,
-            files: fm,
-            directories: dm
+            storage: Storage::Directory { files: fm, directories: dm },
+            seal
         })
     }
 
+    // An empty vault that is never written to disk
+    pub fn memory() -> Vault {
+        Vault {
+            structure: Structure::new(),
+            storage: Storage::Memory { fields: HashMap::new() },
+            seal: None
+        }
+    }
+
     /* Methods */
 
     pub fn set(&mut self, entity: &Encrypted, account: &Encrypted, password: &Encrypted) -> VaultResult<()> {
@@ -198,6 +393,10 @@ impl Vault {
             .ok_or(VaultError::MissingAccount)
     }
 
+    pub fn seal(&self) -> Option<&Seal> {
+        self.seal.as_ref()
+    }
+
     pub fn list(&self) -> VaultResult<Vec<Encrypted>> {
         Ok(self.structure.keys().cloned().collect())
     }
@@ -213,9 +412,9 @@ impl Vault {
             return Ok(());
         }
 
-        let path = entity.path();
+        let path = self.stored(entity);
 
-        self.directories.create_locker(&path)?;
+        self.storage.create_dir(&path)?;
         self.structure.insert(entity.to_owned(), Account::new());
 
         Ok(())
@@ -227,21 +426,21 @@ impl Vault {
         }
 
         let vault_entity = self.get_entity_key(entity)?;
-        let entity_path = vault_entity.path();
-        let account_path = account.path();
+        let entity_path = self.stored(&vault_entity);
+        let account_path = self.stored(account);
         let path = DirManager::append_path(&entity_path, &account_path);
         let structure_entity = self.structure
             .get_mut(&vault_entity)
             .ok_or(VaultError::MissingEntity)?;
 
-        self.directories.create_locker(&path)?;
+        self.storage.create_dir(&path)?;
 
         structure_entity.insert(
             account.to_owned(), 
             Encrypted::empty()
         );
 
-        Ok(())
+        self.touch(entity, account)
     }
 
     pub fn set_password(&mut self, entity: &Encrypted, account: &Encrypted, password: &Encrypted) -> VaultResult<()> {
@@ -250,9 +449,9 @@ impl Vault {
         let vault_entity = self.get_entity_key(entity)?;
         let vault_account = self.get_account_key(entity, account)?;
 
-        let entity_path = vault_entity.path();
-        let account_path = vault_account.path();
-        let password_path = password.path();
+        let entity_path = self.stored(&vault_entity);
+        let account_path = self.stored(&vault_account);
+        let password_path = self.stored(password);
 
         path.push(entity_path);
         path.push(account_path);
@@ -260,17 +459,17 @@ impl Vault {
         if self.has_password(entity, account)? {
             let old_password = self.get_account(entity, account)?;
 
-            path.push(old_password.path());
+            path.push(self.stored(old_password));
 
             let old_password_path = DirManager::pb_to_str(&path);
-            self.files.remove_locker(&old_password_path)?;
+            self.storage.remove_file(&old_password_path)?;
 
             path.pop();
         }
 
         path.push(password_path);
         let password_locker = DirManager::pb_to_str(&path);
-        self.files.create_locker(&password_locker)?;
+        self.storage.create_file(&password_locker)?;
 
         let structure_entity = self.structure
             .get_mut(&vault_entity)
@@ -281,15 +480,15 @@ impl Vault {
             password.to_owned()
         );
 
-        Ok(())
+        self.touch(entity, account)
     }
 
     pub fn remove_entity(&mut self, entity: &Encrypted) -> VaultResult<()> {
         let directory = self.get_entity_key(entity)?;
-        let locker = directory.path();
+        let locker = self.stored(&directory);
 
         self.structure.remove(entity);
-        self.directories.remove_locker(&locker)?;
+        self.storage.remove_dir(&locker)?;
 
         Ok(())
     }
@@ -297,18 +496,191 @@ impl Vault {
     pub fn remove_account(&mut self, entity: &Encrypted, account: &Encrypted) -> VaultResult<()> {
         let ent = self.get_entity_key(entity)?;
         let acc = self.get_account_key(entity, account)?;
-        let path = DirManager::append_path(&ent.path(), &acc.path());
+        let path = DirManager::append_path(&self.stored(&ent), &self.stored(&acc));
         // TODO: abstract this so unwrap does not need to be called
         let structure_entity = self.structure.get_mut(entity).unwrap();
 
         structure_entity.remove(account);
-        self.directories.remove_locker(&path)?;
+        self.storage.remove_dir(&path)?;
+
+        Ok(())
+    }
+
+    pub fn get_fields(&mut self, entity: &Encrypted, account: &Encrypted) -> VaultResult<Encrypted> {
+        let directory = self.account_path(entity, account)?;
+
+        match self.storage.read_file(&directory, FIELDS)? {
+            Some(contents) => self.read_name(contents.trim()),
+            None => Ok(Encrypted::empty())
+        }
+    }
+
+    pub fn set_fields(&mut self, entity: &Encrypted, account: &Encrypted, fields: &Encrypted) -> VaultResult<()> {
+        let directory = self.account_path(entity, account)?;
+        let path = DirManager::append_path(&directory, FIELDS);
+
+        if fields.is_empty() {
+            self.storage.remove_file(&path)?;
+        } else {
+            self.storage.write_file(&path, &self.stored(fields))?;
+        }
+
+        self.touch(entity, account)
+    }
+
+    // 0 for accounts saved before modification times were kept
+    pub fn get_modified(&mut self, entity: &Encrypted, account: &Encrypted) -> VaultResult<u64> {
+        let directory = self.account_path(entity, account)?;
+        let modified = self.storage.read_file(&directory, MODIFIED)?;
+
+        Ok(modified.and_then(|time| time.trim().parse().ok()).unwrap_or(0))
+    }
+
+    pub fn set_modified(&mut self, entity: &Encrypted, account: &Encrypted, time: u64) -> VaultResult<()> {
+        let directory = self.account_path(entity, account)?;
+        let path = DirManager::append_path(&directory, MODIFIED);
+
+        self.storage.write_file(&path, &time.to_string())?;
+
+        Ok(())
+    }
+
+    fn touch(&mut self, entity: &Encrypted, account: &Encrypted) -> VaultResult<()> {
+        self.set_modified(entity, account, Self::now())
+    }
+
+    // What `encrypted` is kept as in the locker
+    fn stored(&self, encrypted: &Encrypted) -> String {
+        match &self.seal {
+            Some(seal) => seal.seal(encrypted).path(),
+            None => encrypted.path()
+        }
+    }
+
+    fn read_name(&self, name: &str) -> VaultResult<Encrypted> {
+        let (encrypted, _) = Self::open_name(self.seal.as_ref(), name, false)?;
+
+        Ok(encrypted)
+    }
+
+    fn account_path(&self, entity: &Encrypted, account: &Encrypted) -> VaultResult<String> {
+        let ent = self.get_entity_key(entity)?;
+        let acc = self.get_account_key(entity, account)?;
+
+        Ok(DirManager::append_path(&self.stored(&ent), &self.stored(&acc)))
+    }
+
+    pub fn rename_entity(&mut self, entity: &Encrypted, to: &Encrypted) -> VaultResult<()> {
+        let from = self.get_entity_key(entity)?;
+
+        if self.has_entity(to) {
+            return Err(VaultError::ExistingEntity);
+        }
+
+        self.storage.rename_dir(&self.stored(&from), &self.stored(to))?;
+
+        let accounts = self.structure
+            .remove(entity)
+            .ok_or(VaultError::MissingEntity)?;
+
+        self.structure.insert(to.to_owned(), accounts);
+
+        Ok(())
+    }
+
+    pub fn rename_account(&mut self, entity: &Encrypted, account: &Encrypted, to: &Encrypted) -> VaultResult<()> {
+        let ent = self.get_entity_key(entity)?;
+        let acc = self.get_account_key(entity, account)?;
+
+        if self.has_account(entity, to)? {
+            return Err(VaultError::ExistingAccount);
+        }
+
+        let from = DirManager::append_path(&self.stored(&ent), &self.stored(&acc));
+        let dest = DirManager::append_path(&self.stored(&ent), &self.stored(to));
+
+        self.storage.rename_dir(&from, &dest)?;
+
+        let structure_entity = self.structure
+            .get_mut(entity)
+            .ok_or(VaultError::MissingEntity)?;
+        let password = structure_entity
+            .remove(account)
+            .ok_or(VaultError::MissingAccount)?;
+
+        structure_entity.insert(to.to_owned(), password);
+
+        Ok(())
+    }
+
+    pub fn move_account(&mut self, entity: &Encrypted, account: &Encrypted, to_entity: &Encrypted) -> VaultResult<()> {
+        let ent = self.get_entity_key(entity)?;
+        let acc = self.get_account_key(entity, account)?;
+        let created = !self.has_entity(to_entity);
+
+        // Checked before creating anything, a failed move changes nothing
+        if !created && self.has_account(to_entity, account)? {
+            return Err(VaultError::ExistingAccount);
+        }
+
+        self.set_entity(to_entity)?;
+
+        let target = self.get_entity_key(to_entity)?;
+        let from = DirManager::append_path(&self.stored(&ent), &self.stored(&acc));
+        let dest = DirManager::append_path(&self.stored(&target), &self.stored(&acc));
+
+        if let Err(err) = self.storage.rename_dir(&from, &dest) {
+            if created {
+                self.remove_entity(to_entity)?;
+            }
+
+            return Err(err.into());
+        }
+
+        let password = self.structure
+            .get_mut(entity)
+            .and_then(|accounts| accounts.remove(account))
+            .ok_or(VaultError::MissingAccount)?;
+
+        self.structure
+            .get_mut(to_entity)
+            .ok_or(VaultError::MissingEntity)?
+            .insert(acc, password);
 
         Ok(())
     }
 
     /* Associated functions */
 
+    // The name kept in memory for one in the locker, and for an adopted
+    // locker that still holds it unsealed, the name to seal it under
+    fn open_name(seal: Option<&Seal>, name: &str, adopt: bool) -> VaultResult<(Encrypted, Option<String>)> {
+        let encrypted = Encrypted::from(name)?;
+        let seal = match seal {
+            Some(seal) => seal,
+            None => return Ok((encrypted, None))
+        };
+
+        if let Some(opened) = seal.open(&encrypted) {
+            return Ok((opened, None));
+        }
+
+        if !adopt {
+            return Err(VaultError::Error(format!("{:?} is not sealed under this master password", name)));
+        }
+
+        let sealed = seal.seal(&encrypted).path();
+
+        Ok((encrypted, Some(sealed)))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+
     fn filename(path_string: &PathBuf) -> String {
         path_string.file_name()
             .unwrap()
@@ -324,6 +696,7 @@ impl Vault {
 mod tests {
     use super::*;
 
+    use crate::locker::Locker;
     use crate::mocks::Setup;
 
     use std::path::Path;
@@ -603,7 +976,10 @@ mod tests {
                 assert!(vault.set_password(&ent, &acc, &pass).is_ok());
 
                 let account = vault.get_account(&ent, &acc);
-                let password_location = dm.read_locker(&path).unwrap();
+                let password_location: Vec<PathBuf> = dm.read_locker(&path).unwrap()
+                    .into_iter()
+                    .filter(|file| !Vault::filename(file).starts_with('.'))
+                    .collect();
 
                 assert!(account.is_ok());
                 assert_eq!(*account.unwrap(), pass);
@@ -633,10 +1009,13 @@ mod tests {
                 assert!(vault.set_password(&ent, &acc, &other_pass).is_ok());
 
                 let account = vault.get_account(&ent, &acc);
-                let password_location = dm.read_locker(&path).unwrap();
+                let password_location: Vec<PathBuf> = dm.read_locker(&path).unwrap()
+                    .into_iter()
+                    .filter(|file| !Vault::filename(file).starts_with('.'))
+                    .collect();
 
                 assert!(account.is_ok());
-                assert_eq!(*account.unwrap(), pass);
+                assert_eq!(*account.unwrap(), other_pass);
                 assert_eq!(password_location.len(), 1);
             }
         }; 
@@ -713,4 +1092,225 @@ mod tests {
             }
         }; 
     }
+
+    #[test]
+    fn rename_entity() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut dm = DirManager::new(&config, &locker);
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let password = Encrypted::from("biz$fred$bar$corge").unwrap();
+                let to = Encrypted::from("foo$foo$foo$foo").unwrap();
+
+                assert!(vault.rename_entity(&entity, &to).is_ok());
+                assert!(vault.get_entity(&entity).is_err());
+                assert_eq!(*vault.get_account(&to, &account).unwrap(), password);
+
+                let path = DirManager::append_path(&to.path(), &account.path());
+                let password_location = dm.read_locker(&path).unwrap();
+
+                assert_eq!(dm.read_locker("").unwrap().len(), 1);
+                assert_eq!(password_location.len(), 1);
+            }
+        }; 
+    }
+
+    #[test]
+    fn rename_entity_existing() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let other = Encrypted::from("foo$foo$foo$foo").unwrap();
+
+                assert!(vault.set_entity(&other).is_ok());
+
+                let renamed = vault.rename_entity(&entity, &other);
+
+                assert_eq!(renamed.unwrap_err().to_str(), "Entity already exists");
+                assert!(vault.get_entity(&entity).is_ok());
+            }
+        }; 
+    }
+
+    #[test]
+    fn rename_account() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut dm = DirManager::new(&config, &locker);
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let password = Encrypted::from("biz$fred$bar$corge").unwrap();
+                let to = Encrypted::from("bar$bar$bar$bar").unwrap();
+
+                assert!(vault.rename_account(&entity, &account, &to).is_ok());
+                assert!(vault.get_account(&entity, &account).is_err());
+                assert_eq!(*vault.get_account(&entity, &to).unwrap(), password);
+
+                let path = DirManager::append_path(&entity.path(), &to.path());
+
+                assert_eq!(dm.read_locker(&path).unwrap().len(), 1);
+                assert_eq!(dm.read_locker(&entity.path()).unwrap().len(), 1);
+            }
+        }; 
+    }
+
+    #[test]
+    fn move_account() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut dm = DirManager::new(&config, &locker);
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let password = Encrypted::from("biz$fred$bar$corge").unwrap();
+                let to_entity = Encrypted::from("foo$foo$foo$foo").unwrap();
+
+                assert!(vault.move_account(&entity, &account, &to_entity).is_ok());
+                assert_eq!(vault.get_entity(&entity).unwrap().len(), 0);
+                assert_eq!(*vault.get_account(&to_entity, &account).unwrap(), password);
+
+                let path = DirManager::append_path(&to_entity.path(), &account.path());
+
+                assert_eq!(dm.read_locker(&path).unwrap().len(), 1);
+                assert_eq!(dm.read_locker(&entity.path()).unwrap().len(), 0);
+            }
+        }; 
+    }
+
+    #[test]
+    fn move_account_existing() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let to_entity = Encrypted::from("foo$foo$foo$foo").unwrap();
+                let missing = Encrypted::from("missing$missing$missing$missing").unwrap();
+
+                assert!(vault.set(&to_entity, &account, &Encrypted::empty()).is_ok());
+
+                let entities = vault.list().unwrap().len();
+
+                assert!(matches!(vault.move_account(&entity, &account, &to_entity), Err(VaultError::ExistingAccount)));
+                assert!(matches!(vault.move_account(&entity, &missing, &missing), Err(VaultError::MissingAccount)));
+                assert_eq!(vault.list().unwrap().len(), entities);
+                assert!(vault.get_entity(&missing).is_err());
+                assert!(vault.get_account(&entity, &account).is_ok());
+            }
+        }; 
+    }
+
+    #[test]
+    fn set_fields() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let password = Encrypted::from("biz$fred$bar$corge").unwrap();
+                let fields = Encrypted::from("fields$fields$fields$fields").unwrap();
+
+                assert!(vault.get_fields(&entity, &account).unwrap().is_empty());
+                assert!(vault.set_fields(&entity, &account, &fields).is_ok());
+                assert_eq!(vault.get_fields(&entity, &account).unwrap(), fields);
+
+                let reloaded = Vault::new(&config, &locker).unwrap();
+
+                assert_eq!(*reloaded.get_account(&entity, &account).unwrap(), password);
+
+                assert!(vault.set_fields(&entity, &account, &Encrypted::empty()).is_ok());
+                assert!(vault.get_fields(&entity, &account).unwrap().is_empty());
+            }
+        }; 
+    }
+
+    #[test]
+    fn modified() {
+        Setup { 
+            paths: Vec::new(),
+            after_each: &after_each,
+            test: &|this| {
+                let (config, locker) = this.as_path_buf();
+
+                fill_locker(&config, &locker);
+
+                let mut vault = Vault::new(&config, &locker).unwrap();
+                let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+                let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+                let other = Encrypted::from("other$other$other$other").unwrap();
+                let fields = Encrypted::from("fields$fields$fields$fields").unwrap();
+
+                assert_eq!(vault.get_modified(&entity, &account).unwrap(), 0);
+                assert!(vault.set_fields(&entity, &account, &fields).is_ok());
+                assert!(vault.get_modified(&entity, &account).unwrap() > 0);
+                assert!(vault.set_modified(&entity, &account, 42).is_ok());
+                assert_eq!(Vault::new(&config, &locker).unwrap().get_modified(&entity, &account).unwrap(), 42);
+                assert!(vault.get_modified(&entity, &other).is_err());
+            }
+        }; 
+    }
+
+    #[test]
+    fn memory() {
+        let mut vault = Vault::memory();
+        let entity = Encrypted::from("foo$bar$biz$fred").unwrap();
+        let account = Encrypted::from("quux$foo$bar$biz").unwrap();
+        let password = Encrypted::from("biz$fred$bar$corge").unwrap();
+        let fields = Encrypted::from("fields$fields$fields$fields").unwrap();
+        let renamed = Encrypted::from("renamed$renamed$renamed$renamed").unwrap();
+        let other = Encrypted::from("other$other$other$other").unwrap();
+
+        vault.set(&entity, &account, &password).unwrap();
+        vault.set_fields(&entity, &account, &fields).unwrap();
+        vault.rename_entity(&entity, &renamed).unwrap();
+        vault.move_account(&renamed, &account, &other).unwrap();
+
+        assert_eq!(*vault.get_account(&other, &account).unwrap(), password);
+        assert_eq!(vault.get_fields(&other, &account).unwrap(), fields);
+
+        vault.remove_entity(&other).unwrap();
+        vault.set(&other, &account, &Encrypted::empty()).unwrap();
+
+        assert!(vault.get_fields(&other, &account).unwrap().is_empty());
+    }
 }